@@ -1,44 +1,298 @@
 use serde_json::Value;
-use std::{collections::HashSet, hash::BuildHasher};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::BuildHasher,
+};
 
-pub fn cache_tags<S: BuildHasher>(value: &Value, tags: &mut HashSet<String, S>) {
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum TagKind {
+    /// The object itself, `type:{typename}` or `type:{typename}:{field}:{value}`
+    #[default]
+    Entity,
+    /// The object appeared inside a list, `type:{typename}:list`
+    List,
+    /// The object is nested under a parent with a key, `type:{typename}:{id}:{child}`
+    Child { typename: String, id: String },
+}
+
+/// A cache tag before formatting; `field` and `value` are unset for the bare `type:{typename}` tag.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tag {
+    pub typename: String,
+    pub field: Option<String>,
+    pub value: Option<String>,
+    pub kind: TagKind,
+}
+
+pub struct TagConfig {
+    /// Fields that identify an object, e.g. `id`
+    pub key_fields: Vec<String>,
+    /// Field suffixes that mark a foreign key, e.g. `_id`
+    pub key_suffixes: Vec<String>,
+    /// Renders a tag, `type:{typename}:{field}:{value}` by default
+    pub formatter: Box<dyn Fn(&Tag) -> String + Send + Sync>,
+    /// Stop collecting once this many tags have been gathered
+    pub max_tags: Option<usize>,
+    /// Emit `type:{typename}:list` for objects found in arrays
+    pub list_tags: bool,
+    /// Emit `type:{parent}:{id}:{typename}` for objects nested under a keyed parent
+    pub child_tags: bool,
+}
+
+impl Default for TagConfig {
+    fn default() -> Self {
+        Self {
+            key_fields: vec!["id".to_string(), "key".to_string(), "email".to_string()],
+            key_suffixes: vec!["_id".to_string()],
+            formatter: Box::new(format_tag),
+            max_tags: None,
+            list_tags: false,
+            child_tags: false,
+        }
+    }
+}
+
+impl TagConfig {
+    fn is_key(&self, field: &str) -> bool {
+        self.key_fields.iter().any(|k| k == field)
+            || self
+                .key_suffixes
+                .iter()
+                .any(|s| field.ends_with(s.as_str()))
+    }
+}
+
+fn format_tag(tag: &Tag) -> String {
+    match (&tag.kind, &tag.field, &tag.value) {
+        (TagKind::List, _, _) => format!("type:{}:list", tag.typename),
+        (TagKind::Child { typename, id }, _, _) => format!("type:{typename}:{id}:{}", tag.typename),
+        (TagKind::Entity, Some(field), Some(value)) => {
+            format!("type:{}:{field}:{value}", tag.typename)
+        }
+        (TagKind::Entity, _, _) => format!("type:{}", tag.typename),
+    }
+}
+
+fn bare_tag(typename: &str, kind: TagKind) -> Tag {
+    Tag {
+        typename: typename.to_string(),
+        field: None,
+        value: None,
+        kind,
+    }
+}
+
+// `parent` is the nearest enclosing typed object with a key field; `insert` returns false
+// once no more tags should be collected
+fn walk(
+    value: &Value,
+    config: &TagConfig,
+    parent: Option<(&str, &str)>,
+    in_list: bool,
+    insert: &mut dyn FnMut(Tag) -> bool,
+) -> bool {
     match value {
         Value::Object(map) => {
             if let Some(Value::String(typename)) = map.get("__typename") {
+                if config.list_tags && in_list && !insert(bare_tag(typename, TagKind::List)) {
+                    return false;
+                }
+                if let Some((parent, id)) = parent.filter(|_| config.child_tags) {
+                    let kind = TagKind::Child {
+                        typename: parent.to_string(),
+                        id: id.to_string(),
+                    };
+                    if !insert(bare_tag(typename, kind)) {
+                        return false;
+                    }
+                }
+                let own_key = config.key_fields.iter().find_map(|k| match map.get(k) {
+                    Some(Value::String(id)) => Some((typename.as_str(), id.as_str())),
+                    _ => None,
+                });
+                let child_parent = own_key.or(parent);
                 let mut has_tag = false;
                 for (key, value) in map {
-                    match (key.as_str(), value) {
-                        ("id" | "key" | "email", Value::String(id)) => {
-                            tags.insert(format!("type:{typename}:{key}:{id}"));
-                            has_tag = true;
-                        }
-                        (_, Value::String(id)) => {
-                            if key.ends_with("_id") {
-                                tags.insert(format!("type:{typename}:{key}:{id}"));
+                    match value {
+                        Value::String(id) => {
+                            if config.is_key(key) {
                                 has_tag = true;
+                                if !insert(Tag {
+                                    typename: typename.clone(),
+                                    field: Some(key.clone()),
+                                    value: Some(id.clone()),
+                                    kind: TagKind::Entity,
+                                }) {
+                                    return false;
+                                }
                             }
                         }
                         _ => {
-                            cache_tags(value, tags);
+                            if !walk(value, config, child_parent, false, insert) {
+                                return false;
+                            }
                         }
                     }
                 }
                 if !has_tag {
-                    tags.insert(format!("type:{typename}"));
+                    return insert(bare_tag(typename, TagKind::Entity));
                 }
             } else {
                 for (_, value) in map {
-                    cache_tags(value, tags);
+                    if !walk(value, config, parent, false, insert) {
+                        return false;
+                    }
                 }
             }
         }
         Value::Array(array) => {
             for item in array {
-                cache_tags(item, tags);
+                if !walk(item, config, parent, true, insert) {
+                    return false;
+                }
             }
         }
         _ => {}
     }
+    true
+}
+
+pub fn cache_tags<S: BuildHasher>(value: &Value, tags: &mut HashSet<String, S>) {
+    cache_tags_with_config(value, &TagConfig::default(), tags);
+}
+
+pub fn cache_tags_with_config<S: BuildHasher>(
+    value: &Value,
+    config: &TagConfig,
+    tags: &mut HashSet<String, S>,
+) {
+    walk(value, config, None, false, &mut |tag| {
+        if config.max_tags.is_some_and(|max| tags.len() >= max) {
+            return false;
+        }
+        tags.insert((config.formatter)(&tag));
+        true
+    });
+}
+
+/// Same as `cache_tags_with_config` but returns the unformatted tags.
+pub fn structured_cache_tags<S: BuildHasher>(
+    value: &Value,
+    config: &TagConfig,
+    tags: &mut HashSet<Tag, S>,
+) {
+    walk(value, config, None, false, &mut |tag| {
+        if config.max_tags.is_some_and(|max| tags.len() >= max) {
+            return false;
+        }
+        tags.insert(tag);
+        true
+    });
+}
+
+/// A cache key for a translated statement and its bound params, stable across builds and
+/// Rust releases (FNV-1a over the SQL text and the JSON of each param).
+pub fn cache_key(sql: &str, params: &[Value]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut write = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    write(sql.as_bytes());
+    for param in params {
+        write(&[0]);
+        write(param.to_string().as_bytes());
+    }
+    format!("{hash:016x}")
+}
+
+// `type:{typename}` for any tag of the default format
+fn type_tag(tag: &str) -> Option<&str> {
+    let rest = tag.strip_prefix("type:")?;
+    let end = rest.find(':').map_or(tag.len(), |i| i + 5);
+    Some(&tag[..end])
+}
+
+/// Cached response keys by tag, so a mutation can find the entries it makes stale.
+#[derive(Debug, Clone, Default)]
+pub struct TagIndex {
+    keys: HashMap<String, HashSet<String>>,
+    // keys by `type:{typename}` of any of their tags
+    types: HashMap<String, HashSet<String>>,
+    tags: HashMap<String, HashSet<String>>,
+}
+
+fn unlink(map: &mut HashMap<String, HashSet<String>>, tag: &str, key: &str) {
+    if let Some(keys) = map.get_mut(tag) {
+        keys.remove(key);
+        if keys.is_empty() {
+            map.remove(tag);
+        }
+    }
+}
+
+impl TagIndex {
+    /// Records the tags of the response cached under `key`, replacing earlier ones.
+    pub fn insert(&mut self, key: impl Into<String>, tags: impl IntoIterator<Item = String>) {
+        let key = key.into();
+        self.remove(&key);
+        let tags: HashSet<String> = tags.into_iter().collect();
+        for tag in &tags {
+            self.keys
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.clone());
+            if let Some(type_tag) = type_tag(tag) {
+                let keys = self.types.entry(type_tag.to_string()).or_default();
+                keys.insert(key.clone());
+            }
+        }
+        self.tags.insert(key, tags);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        for tag in self.tags.remove(key).into_iter().flatten() {
+            unlink(&mut self.keys, &tag, key);
+            if let Some(type_tag) = type_tag(&tag) {
+                unlink(&mut self.types, type_tag, key);
+            }
+        }
+    }
+
+    /// Removes and returns the keys affected by a mutation whose response has `tags`. A keyed
+    /// tag hits entries sharing it plus entries that only know the type (`type:{typename}` and
+    /// `type:{typename}:list`), since the row may enter or leave those results; a bare
+    /// `type:{typename}` hits every entry of the type.
+    pub fn invalidate<'a>(&mut self, tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let mut stale = HashSet::new();
+        for tag in tags {
+            let type_tag = type_tag(tag);
+            if type_tag == Some(tag) {
+                stale.extend(self.types.get(tag).into_iter().flatten().cloned());
+                continue;
+            }
+            let mut affected = vec![tag.to_string()];
+            if let Some(type_tag) = type_tag {
+                affected.push(type_tag.to_string());
+                affected.push(format!("{type_tag}:list"));
+            }
+            for tag in &affected {
+                stale.extend(self.keys.get(tag).into_iter().flatten().cloned());
+            }
+        }
+        let mut stale: Vec<String> = stale.into_iter().collect();
+        stale.sort();
+        for key in &stale {
+            self.remove(key);
+        }
+        stale
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
 }
 
 #[cfg(test)]
@@ -87,4 +341,115 @@ mod tests {
         println!("{:?}", tags.clone());
         assert_eq!(tags.len(), 4);
     }
+
+    #[test]
+    fn custom_config() {
+        let value = json!({
+            "data": {
+                "users": [
+                    { "__typename": "User", "uuid": "u1", "orgRef": "o1", "id": "ignored" },
+                    { "__typename": "User", "uuid": "u2", "orgRef": "o1" },
+                    { "__typename": "Org", "name": "Acme" }
+                ]
+            }
+        });
+        let config = TagConfig {
+            key_fields: vec!["uuid".to_string()],
+            key_suffixes: vec!["Ref".to_string()],
+            formatter: Box::new(|tag: &Tag| match &tag.value {
+                Some(value) => format!("{}-{}", tag.typename, value),
+                None => tag.typename.clone(),
+            }),
+            max_tags: None,
+            ..TagConfig::default()
+        };
+        let mut tags = HashSet::new();
+        cache_tags_with_config(&value, &config, &mut tags);
+        let mut tags: Vec<_> = tags.into_iter().collect();
+        tags.sort();
+        assert_eq!(tags, vec!["Org", "User-o1", "User-u1", "User-u2"]);
+
+        let mut structured = HashSet::new();
+        structured_cache_tags(&value, &config, &mut structured);
+        assert!(structured.contains(&Tag {
+            typename: "User".to_string(),
+            field: Some("orgRef".to_string()),
+            value: Some("o1".to_string()),
+            kind: TagKind::Entity,
+        }));
+
+        let limited = TagConfig {
+            max_tags: Some(2),
+            ..TagConfig::default()
+        };
+        let mut tags = HashSet::new();
+        cache_tags_with_config(&value, &limited, &mut tags);
+        assert_eq!(tags.len(), 2);
+    }
+
+    #[test]
+    fn list_and_child_tags() {
+        let config = TagConfig {
+            list_tags: true,
+            child_tags: true,
+            ..TagConfig::default()
+        };
+        let mut tags = HashSet::new();
+        cache_tags_with_config(
+            &json!({
+                "data": {
+                    "app": {
+                        "__typename": "App",
+                        "id": "123",
+                        "settings": { "__typename": "Settings", "theme": "dark" },
+                        "components": [
+                            { "__typename": "Component", "id": "c1" },
+                            { "__typename": "Component", "id": "c2" }
+                        ]
+                    }
+                }
+            }),
+            &config,
+            &mut tags,
+        );
+        let mut tags: Vec<_> = tags.into_iter().collect();
+        tags.sort();
+        assert_eq!(
+            tags,
+            vec![
+                "type:App:123:Component",
+                "type:App:123:Settings",
+                "type:App:id:123",
+                "type:Component:id:c1",
+                "type:Component:id:c2",
+                "type:Component:list",
+                "type:Settings",
+            ]
+        );
+    }
+
+    #[test]
+    fn tag_index_invalidation() {
+        let key = cache_key("SELECT $1", &[json!("1")]);
+        assert_eq!(key, cache_key("SELECT $1", &[json!("1")]));
+        assert_ne!(key, cache_key("SELECT $1", &[json!("2")]));
+
+        let mut index = TagIndex::default();
+        index.insert("app-1", ["type:App:id:1".to_string()]);
+        index.insert("app-2", ["type:App:id:2".to_string()]);
+        index.insert("apps", ["type:App".to_string()]);
+        index.insert("users", ["type:User".to_string()]);
+
+        let mut mutation = HashSet::new();
+        cache_tags(
+            &json!({ "data": { "update_App": { "__typename": "App", "id": "1" } } }),
+            &mut mutation,
+        );
+        let stale = index.invalidate(mutation.iter().map(String::as_str));
+        assert_eq!(stale, vec!["app-1", "apps"]);
+        assert!(index.invalidate(["type:App:id:1"]).is_empty());
+        assert_eq!(index.invalidate(["type:App"]), vec!["app-2"]);
+        assert_eq!(index.invalidate(["type:User"]), vec!["users"]);
+        assert!(index.is_empty());
+    }
 }
@@ -1,66 +1,257 @@
 mod utils;
 
-use async_graphql_parser::parse_query;
-use gql2sql::{detect_date, gql2sql as gql2sql_rs};
+use gql2sql::{
+    dry_run, get_cache_policy, get_local_settings, get_operation_directives, get_operation_name,
+    get_referenced_objects, get_timeouts, gql2sql_roots, gql2sql_with_options, parse, parse_js_ast,
+    shape_response, to_commented_sql, CachePolicy, CacheTag, Options, Param, ReferencedObjects,
+};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use utils::set_panic_hook;
 use wasm_bindgen::prelude::*;
 
-#[cfg(feature = "wee_alloc")]
+#[cfg(all(feature = "wee_alloc", not(feature = "memory-budget")))]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-#[derive(Deserialize)]
-pub struct Args {
-    pub query: String,
-    pub variables: Option<Value>,
-    pub operation_name: Option<String>,
-}
+// counts what the isolate holds, so `memoryBudget` can stop a translation before its limit
+#[cfg(all(feature = "wee_alloc", feature = "memory-budget"))]
+#[global_allocator]
+static ALLOC: gql2sql::BudgetAllocator<wee_alloc::WeeAlloc> =
+    gql2sql::BudgetAllocator::new(wee_alloc::WeeAlloc::INIT);
+
+#[cfg(all(not(feature = "wee_alloc"), feature = "memory-budget"))]
+#[global_allocator]
+static ALLOC: gql2sql::BudgetAllocator = gql2sql::BudgetAllocator::system();
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct GqlResult {
     pub sql: String,
     pub params: Option<Vec<Value>>,
+    pub param_types: Option<Vec<String>>,
+    pub param_names: Option<Vec<String>>,
     pub tags: Option<Vec<String>>,
-    #[serde(rename = "isMutation")]
+    pub cache_tags: Option<Vec<CacheTag>>,
     pub is_mutation: bool,
+    pub timeouts: Option<IndexMap<String, u64>>,
+    pub directives: Option<IndexMap<String, Value>>,
+    pub cache_policy: Option<CachePolicy>,
+    pub settings: Option<Vec<String>>,
+    pub referenced_objects: ReferencedObjects,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roots: Option<Vec<RootResult>>,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RootResult {
+    pub key: String,
+    pub sql: String,
+    pub params: Option<Vec<Value>>,
+    pub param_types: Option<Vec<String>>,
+    pub param_names: Option<Vec<String>>,
+    pub settings: Vec<String>,
+    pub timeout_ms: Option<u64>,
+}
+
+fn js_error(error: impl std::fmt::Display) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+fn to_param(value: Value, sql_type: &str) -> Result<Value, serde_json::Error> {
+    Ok(match value {
+        Value::Object(obj) => Value::String(serde_json::to_string(&obj)?),
+        Value::Array(list) if sql_type.ends_with("[]") => Value::Array(list),
+        Value::Array(list) => Value::String(serde_json::to_string(&list)?),
+        other => other,
+    })
+}
+
+// the values of `params` as bound, with their types and names
+type BoundParams = (Option<Vec<Value>>, Option<Vec<String>>, Option<Vec<String>>);
+
+fn bind_params(params: Option<Vec<Param>>) -> Result<BoundParams, serde_json::Error> {
+    let param_types = params
+        .as_ref()
+        .map(|p| p.iter().map(|p| p.sql_type.clone()).collect());
+    let param_names = params
+        .as_ref()
+        .map(|p| p.iter().map(|p| p.name.clone()).collect());
+    let params = params
+        .map(|p| {
+            p.into_iter()
+                .map(|p| to_param(p.value, &p.sql_type))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    Ok((params, param_types, param_names))
+}
+
+/// `query` is either the query text or a parsed JS document; `variables` and `options` are
+/// plain objects. Returns
+/// `{sql, params, paramTypes, paramNames, tags, cacheTags, isMutation, timeouts, directives,
+/// cachePolicy, settings, referencedObjects, roots}`, where `tags` are the formatted `cacheTags`,
+/// `referencedObjects` is `{schemas, tables: [{schema, table, columns}]}` and `roots`, with the
+/// `splitRoots` option, is each root field of a query compiled on its own as
+/// `{key, sql, params, paramTypes, paramNames, settings, timeoutMs}`.
 #[wasm_bindgen]
-pub fn gql2sql(args: String) -> Result<String, JsError> {
+pub fn gql2sql(
+    query: JsValue,
+    variables: JsValue,
+    #[wasm_bindgen(js_name = operationName)] operation_name: Option<String>,
+    options: JsValue,
+) -> Result<JsValue, JsError> {
     set_panic_hook();
-    let Args {
-        query,
-        variables,
-        operation_name,
-    } = serde_json::from_str(&args)?;
-    let ast = parse_query(query)?;
+    let ast = match query.as_string() {
+        Some(query) => parse(&query),
+        None => parse_js_ast(&serde_wasm_bindgen::from_value(query)?),
+    }
+    .map_err(js_error)?;
+    let variables: Option<Value> = serde_wasm_bindgen::from_value(variables)?;
+    let options: Option<Options> = serde_wasm_bindgen::from_value(options)?;
+    let timeouts = get_timeouts(&ast, operation_name.as_deref()).map_err(js_error)?;
+    let directives =
+        get_operation_directives(&ast, &variables, operation_name.as_deref()).map_err(js_error)?;
+    let cache_policy =
+        get_cache_policy(&ast, &variables, operation_name.as_deref()).map_err(js_error)?;
+    let options = options.unwrap_or_default();
+    let settings = get_local_settings(&ast, &variables, operation_name.as_deref(), &options)
+        .map_err(js_error)?;
+    let name = get_operation_name(&ast, operation_name.as_deref());
+    let roots = if options.split_roots {
+        let roots = gql2sql_roots(ast.clone(), &variables, operation_name.clone(), &options)
+            .map_err(js_error)?;
+        let mut results = vec![];
+        for root in roots {
+            let sql = to_commented_sql(
+                &root.statement,
+                name.as_deref(),
+                &variables,
+                root.tags.as_deref(),
+                &options,
+            );
+            let (params, param_types, param_names) = bind_params(root.params)?;
+            results.push(RootResult {
+                key: root.key,
+                sql,
+                params,
+                param_types,
+                param_names,
+                settings: root.settings.iter().map(ToString::to_string).collect(),
+                timeout_ms: root.timeout_ms,
+            });
+        }
+        Some(results)
+    } else {
+        None
+    };
     let (sql, params, tags, is_mutation) =
-        gql2sql_rs(ast, &variables, operation_name).map_err(|e| JsError::new(&e.to_string()))?;
-    let params = params.map(|o| {
-        o.into_iter()
-            .map(|a| match a {
-                Value::String(s) => {
-                    if let Some(date) = detect_date(&s) {
-                        Value::String(date)
-                    } else {
-                        Value::String(s)
-                    }
-                }
-                Value::Null => Value::Null,
-                Value::Number(s) => Value::Number(s),
-                Value::Bool(s) => Value::Bool(s),
-                Value::Object(obj) => Value::String(serde_json::to_string(&obj).unwrap()),
-                Value::Array(list) => Value::String(serde_json::to_string(&list).unwrap()),
-            })
-            .collect()
-    });
+        gql2sql_with_options(ast, &variables, operation_name, &options).map_err(js_error)?;
+    let (params, param_types, param_names) = bind_params(params)?;
     let result = GqlResult {
-        sql: sql.to_string(),
+        sql: to_commented_sql(&sql, name.as_deref(), &variables, tags.as_deref(), &options),
         params,
-        tags,
+        param_types,
+        param_names,
+        tags: tags
+            .as_ref()
+            .map(|t| t.iter().map(ToString::to_string).collect()),
+        cache_tags: tags,
         is_mutation,
+        timeouts,
+        directives,
+        cache_policy,
+        settings: (!settings.is_empty())
+            .then(|| settings.iter().map(ToString::to_string).collect()),
+        referenced_objects: get_referenced_objects(&sql),
+        roots,
     };
-    Ok(serde_json::to_string(&result)?)
+    Ok(result.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
+
+/// Compiles without running, for checking persisted documents in CI. Takes the arguments of
+/// `gql2sql` and returns `{sql, params, tags, isMutation, complexity, tables, warnings}`, where
+/// `params` are `{name, value, type}` in binding order, `tables` are `{table, columns}` and
+/// `warnings` are `{message, line, column}`.
+#[wasm_bindgen]
+pub fn explain(
+    query: JsValue,
+    variables: JsValue,
+    #[wasm_bindgen(js_name = operationName)] operation_name: Option<String>,
+    options: JsValue,
+) -> Result<JsValue, JsError> {
+    set_panic_hook();
+    let ast = match query.as_string() {
+        Some(query) => parse(&query),
+        None => parse_js_ast(&serde_wasm_bindgen::from_value(query)?),
+    }
+    .map_err(js_error)?;
+    let variables: Option<Value> = serde_wasm_bindgen::from_value(variables)?;
+    let options: Option<Options> = serde_wasm_bindgen::from_value(options)?;
+    let result = dry_run(
+        ast,
+        &variables,
+        operation_name,
+        &options.unwrap_or_default(),
+    )
+    .map_err(js_error)?;
+    Ok(result.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
+
+#[wasm_bindgen(typescript_custom_section)]
+const SHAPE_TYPES: &str = r#"
+export interface ShapeArgs {
+  query?: string;
+  document?: any;
+  variables?: Record<string, any>;
+  operationName?: string;
+  data?: any;
+  errors?: string[];
+}
+
+export interface ShapeResponse {
+  data: any;
+  errors?: { message: string }[];
+}
+"#;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShapeArgs {
+    #[serde(default)]
+    pub query: String,
+    pub document: Option<Value>,
+    pub variables: Option<Value>,
+    pub operation_name: Option<String>,
+    pub data: Option<Value>,
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+/// Turns the `data` column of a generated statement into a GraphQL response `{data, errors}`.
+/// `args` is `{query, document, variables, operationName, data, errors}`, where `document` is a
+/// parsed JS document to use instead of the `query` text.
+#[wasm_bindgen(unchecked_return_type = "ShapeResponse")]
+pub fn shape(
+    #[wasm_bindgen(unchecked_param_type = "ShapeArgs")] args: JsValue,
+) -> Result<JsValue, JsError> {
+    set_panic_hook();
+    let ShapeArgs {
+        query,
+        document,
+        variables,
+        operation_name,
+        data,
+        errors,
+    } = serde_wasm_bindgen::from_value(args)?;
+    let ast = match document {
+        Some(document) => parse_js_ast(&document),
+        None => parse(&query),
+    }
+    .map_err(js_error)?;
+    let response = shape_response(&ast, &variables, operation_name.as_deref(), data, errors)
+        .map_err(js_error)?;
+    Ok(response.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
 }
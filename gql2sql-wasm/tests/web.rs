@@ -3,8 +3,8 @@
 #![cfg(target_arch = "wasm32")]
 
 extern crate wasm_bindgen_test;
-use gql2sql_wasm::gql2sql;
-use wasm_bindgen::JsError;
+use gql2sql_wasm::{explain, gql2sql, shape};
+use wasm_bindgen::{JsError, JsValue};
 use wasm_bindgen_test::*;
 
 wasm_bindgen_test_configure!(run_in_browser);
@@ -13,3 +13,59 @@ wasm_bindgen_test_configure!(run_in_browser);
 fn pass() {
     assert_eq!(1 + 1, 2);
 }
+
+#[wasm_bindgen_test]
+fn structured_result() {
+    let variables = serde_wasm_bindgen::to_value(&serde_json::json!({ "id": "1" })).unwrap();
+    let result: Result<JsValue, JsError> = gql2sql(
+        JsValue::from_str(r#"query App($id: String!) { App(id: $id) { id } }"#),
+        variables,
+        None,
+        JsValue::UNDEFINED,
+    );
+    let Ok(result) = result else {
+        panic!("translation failed");
+    };
+    let result: serde_json::Value = serde_wasm_bindgen::from_value(result).unwrap();
+    assert_eq!(result["params"], serde_json::json!(["1"]));
+    assert_eq!(result["isMutation"], serde_json::json!(false));
+}
+
+#[wasm_bindgen_test]
+fn explain_lists_tables() {
+    let result: Result<JsValue, JsError> = explain(
+        JsValue::from_str(r#"query App { App(id: "1") { id name } }"#),
+        JsValue::UNDEFINED,
+        None,
+        JsValue::UNDEFINED,
+    );
+    let Ok(result) = result else {
+        panic!("explain failed");
+    };
+    let result: serde_json::Value = serde_wasm_bindgen::from_value(result).unwrap();
+    assert_eq!(
+        result["tables"],
+        serde_json::json!([{ "schema": null, "table": "App", "columns": ["id", "name"] }])
+    );
+}
+
+#[wasm_bindgen_test]
+fn shape_fills_the_response() {
+    let args = serde_wasm_bindgen::to_value(&serde_json::json!({
+        "query": r#"query App { App(id: "1") { id name } }"#,
+        "data": { "App": { "id": "1" } },
+        "errors": ["partial"],
+    }))
+    .unwrap();
+    let Ok(result) = shape(args) else {
+        panic!("shape failed");
+    };
+    let result: serde_json::Value = serde_wasm_bindgen::from_value(result).unwrap();
+    assert_eq!(
+        result,
+        serde_json::json!({
+            "data": { "App": { "id": "1", "name": null } },
+            "errors": [{ "message": "partial" }],
+        })
+    );
+}
@@ -7,6 +7,7 @@
 use async_graphql_parser::parse_query;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use gql2sql::gql2sql;
+use serde_json::{json, Value};
 
 pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("parse", |b| {
@@ -37,6 +38,35 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("transform", |b| {
         b.iter(|| gql2sql(black_box(gqlast.clone()), &None, Some("App".to_string())));
     });
+    let nested = parse_query(include_str!("fixtures/nested_playground.graphql")).unwrap();
+    let nested_variables: Option<Value> =
+        serde_json::from_str(include_str!("fixtures/nested_playground.json")).unwrap();
+    c.bench_function("transform_nested", |b| {
+        b.iter(|| gql2sql(black_box(nested.clone()), &nested_variables, None));
+    });
+    let mutation = parse_query(
+        r#"mutation CreateComponents($data: [Component_insert_input!]!) {
+                insert_Component(data: $data) {
+                    id
+                    appId
+                }
+            }"#,
+    )
+    .unwrap();
+    let mutation_variables = Some(json!({
+        "data": (0..50)
+            .map(|i| json!({ "id": format!("c{i}"), "appId": "a1", "order": i }))
+            .collect::<Vec<_>>()
+    }));
+    c.bench_function("transform_mutation", |b| {
+        b.iter(|| gql2sql(black_box(mutation.clone()), &mutation_variables, None));
+    });
+    c.bench_function("transform_string", |b| {
+        b.iter(|| {
+            gql2sql(black_box(nested.clone()), &nested_variables, None)
+                .map(|(statement, ..)| statement.to_string())
+        });
+    });
 }
 
 criterion_group!(benches, criterion_benchmark);
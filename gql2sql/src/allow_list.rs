@@ -0,0 +1,147 @@
+use anyhow::{anyhow, Result as AnyResult};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt::{self, Write};
+
+type Hash = [u8; 32];
+
+/// A document that is not on the `AllowList` it was checked against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotAllowed {
+    /// The hash of the document, as `get_document_hash` writes it
+    pub hash: String,
+}
+
+impl fmt::Display for NotAllowed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Document {} is not on the allow list", self.hash)
+    }
+}
+
+impl std::error::Error for NotAllowed {}
+
+fn hash(document: &str) -> Hash {
+    Sha256::digest(document.as_bytes()).into()
+}
+
+fn to_hex(hash: &Hash) -> String {
+    hash.iter()
+        .fold(String::with_capacity(64), |mut text, byte| {
+            let _ = write!(text, "{byte:02x}");
+            text
+        })
+}
+
+fn parse_hash(text: &str) -> AnyResult<Hash> {
+    let invalid = || anyhow!("Invalid document hash: {}", text);
+    let digits = text.as_bytes();
+    if digits.len() != 64 {
+        return Err(invalid());
+    }
+    let mut hash = [0; 32];
+    for (byte, pair) in hash.iter_mut().zip(digits.chunks(2)) {
+        let digit = |d: u8| char::from(d).to_digit(16).ok_or_else(invalid);
+        *byte = u8::try_from(digit(pair[0])? * 16 + digit(pair[1])?)?;
+    }
+    Ok(hash)
+}
+
+/// The SHA-256 of a document's text as lowercase hex, the hash of Apollo's persisted queries.
+/// The text is hashed exactly as sent, so reformatting a document changes its hash.
+#[must_use]
+pub fn get_document_hash(document: &str) -> String {
+    to_hex(&hash(document))
+}
+
+/// Compares two byte strings in time that depends only on their lengths, not on where they
+/// first differ.
+#[must_use]
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// The approved documents, by hash. `gql2sql_checked` refuses to compile any other, so a
+/// deployment only runs the operations it was built with. Deserializes from a list of hashes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct AllowList {
+    hashes: Vec<Hash>,
+}
+
+impl TryFrom<Vec<String>> for AllowList {
+    type Error = anyhow::Error;
+
+    fn try_from(hashes: Vec<String>) -> AnyResult<Self> {
+        Self::new(hashes)
+    }
+}
+
+impl AllowList {
+    /// An allow list of hashes written as `get_document_hash` writes them.
+    pub fn new(hashes: impl IntoIterator<Item = impl AsRef<str>>) -> AnyResult<Self> {
+        let mut allow_list = Self::default();
+        for hash in hashes {
+            allow_list.insert_hash(hash.as_ref())?;
+        }
+        Ok(allow_list)
+    }
+
+    /// An allow list of the hashes of documents.
+    pub fn from_documents(documents: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let mut allow_list = Self::default();
+        for document in documents {
+            allow_list.insert_document(document.as_ref());
+        }
+        allow_list
+    }
+
+    pub fn insert_hash(&mut self, hash: &str) -> AnyResult<()> {
+        let hash = parse_hash(&hash.to_ascii_lowercase())?;
+        if !self.contains(&hash) {
+            self.hashes.push(hash);
+        }
+        Ok(())
+    }
+
+    pub fn insert_document(&mut self, document: &str) {
+        let hash = hash(document);
+        if !self.contains(&hash) {
+            self.hashes.push(hash);
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    // compares against every hash, so the time taken doesn't tell how close a document came
+    fn contains(&self, hash: &Hash) -> bool {
+        self.hashes.iter().fold(false, |found, allowed| {
+            found | constant_time_eq(allowed, hash)
+        })
+    }
+
+    /// Whether the document is on the list.
+    #[must_use]
+    pub fn allows(&self, document: &str) -> bool {
+        self.contains(&hash(document))
+    }
+
+    /// Fails with `NotAllowed` when the document is not on the list.
+    pub fn check(&self, document: &str) -> Result<(), NotAllowed> {
+        let hash = hash(document);
+        if self.contains(&hash) {
+            Ok(())
+        } else {
+            Err(NotAllowed {
+                hash: to_hex(&hash),
+            })
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use crate::consts::{BYTEA, BYTES};
+use crate::dates::Retype;
+use crate::QueryPlan;
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{
+    types::{BaseType, VariableDefinition},
+    Positioned,
+};
+use async_graphql_value::{
+    indexmap::{IndexMap, IndexSet},
+    Name,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sqlparser::ast::{
+    Expr, Function, FunctionArg, FunctionArgExpr, FunctionArgumentList, FunctionArguments, Ident,
+    ObjectName, Value, VisitMut,
+};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+type JsonValue = serde_json::Value;
+
+// the encoding and text of a `{ "base64": ".." }` or `{ "hex": ".." }` variable
+fn get_encoded(value: &JsonValue) -> Option<(&str, &str)> {
+    let JsonValue::Object(object) = value else {
+        return None;
+    };
+    match object.iter().next() {
+        Some((encoding, JsonValue::String(text)))
+            if object.len() == 1 && (encoding == "base64" || encoding == "hex") =>
+        {
+            Some((encoding, text))
+        }
+        _ => None,
+    }
+}
+
+/// The variables declared as `Bytes`, whose values are `{ "base64": ".." }` or `{ "hex": ".." }`
+/// and bound as `bytea`; an object in any other variable is JSON.
+pub(crate) fn get_binary_variables(
+    definitions: &[Positioned<VariableDefinition>],
+) -> IndexSet<Name> {
+    definitions
+        .iter()
+        .filter(|definition| {
+            matches!(&definition.node.var_type.node.base, BaseType::Named(name) if name == BYTES)
+        })
+        .map(|definition| definition.node.name.node.clone())
+        .collect()
+}
+
+fn decode_hex(text: &str) -> AnyResult<Vec<u8>> {
+    let digits = text.strip_prefix("\\x").unwrap_or(text);
+    digits
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => match (
+                char::from(*high).to_digit(16),
+                char::from(*low).to_digit(16),
+            ) {
+                (Some(high), Some(low)) => Ok(u8::try_from(high * 16 + low)?),
+                _ => Err(anyhow!("hex must only contain the digits 0-9 and a-f")),
+            },
+            _ => Err(anyhow!("hex must have an even number of digits")),
+        })
+        .collect()
+}
+
+fn decode(value: &JsonValue) -> Option<AnyResult<Vec<u8>>> {
+    let (encoding, text) = get_encoded(value)?;
+    Some(if encoding == "hex" {
+        decode_hex(text)
+    } else {
+        STANDARD.decode(text).map_err(Into::into)
+    })
+}
+
+// the Postgres hex format of bytes, `\x0a1b`, which `bytea` accepts as text
+fn to_bytea(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::from("\\x"), |mut text, byte| {
+        let _ = write!(text, "{byte:02x}");
+        text
+    })
+}
+
+/// Checks the encoding of the binary variables, so their parameters can be written as `bytea`.
+pub(crate) fn check_binary(
+    sql_vars: &IndexMap<Name, JsonValue>,
+    binary: &IndexSet<Name>,
+) -> AnyResult<()> {
+    for name in binary {
+        match sql_vars.get(name).map(|value| (value, decode(value))) {
+            None | Some((JsonValue::Null, _) | (_, Some(Ok(_)))) => {}
+            Some((_, Some(Err(error)))) => {
+                return Err(anyhow!("variable ${} is not valid binary: {}", name, error));
+            }
+            Some((_, None)) => {
+                return Err(anyhow!(
+                    "variable ${} of type {} must be {{ base64: \"..\" }} or {{ hex: \"..\" }}",
+                    name,
+                    BYTES
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// the value a binary variable is bound as, `\x..` text; other values are unchanged
+fn get_binary_param(value: JsonValue) -> JsonValue {
+    match decode(&value) {
+        Some(Ok(bytes)) => JsonValue::String(to_bytea(&bytes)),
+        _ => value,
+    }
+}
+
+impl QueryPlan {
+    /// Binds the variables declared as `Bytes`, which are translated as JSON objects, as `bytea`.
+    pub(crate) fn apply_binary(&mut self, binary: &IndexSet<Name>) {
+        let Some(params) = &mut self.params else {
+            return;
+        };
+        let mut retyped = HashMap::new();
+        for (i, param) in params.iter_mut().enumerate() {
+            if param.sql_type == "jsonb" && binary.iter().any(|name| name == param.name.as_str()) {
+                retyped.insert(format!("${}::jsonb", i + 1), format!("${}::{BYTEA}", i + 1));
+                param.value = get_binary_param(std::mem::take(&mut param.value));
+                BYTEA.clone_into(&mut param.sql_type);
+            }
+        }
+        if !retyped.is_empty() {
+            let _ = self.edit(|statement| statement.visit(&mut Retype(retyped)));
+        }
+    }
+}
+
+// a binary literal of the AST, which the parser never produces, as `decode('0aff', 'hex')` so
+// it reads the same whatever `standard_conforming_strings` is
+pub(crate) fn get_binary_literal(bytes: &[u8]) -> Expr {
+    let digits = to_bytea(bytes).split_off(2);
+    Expr::Function(Function {
+        within_group: vec![],
+        name: ObjectName(vec![Ident::new("decode")]),
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment: None,
+            clauses: vec![],
+            args: [digits, "hex".to_string()]
+                .into_iter()
+                .map(|text| {
+                    FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                        Value::SingleQuotedString(text),
+                    )))
+                })
+                .collect(),
+        }),
+        over: None,
+        filter: None,
+        null_treatment: None,
+    })
+}
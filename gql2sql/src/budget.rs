@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result as AnyResult};
+#[cfg(feature = "memory-budget")]
+use std::alloc::{GlobalAlloc, Layout, System};
+#[cfg(feature = "memory-budget")]
+use std::cell::Cell;
+
+#[cfg(feature = "memory-budget")]
+thread_local! {
+    // the bytes this thread has allocated and not freed, and the most of them at once since the
+    // budget started
+    static LIVE: Cell<usize> = const { Cell::new(0) };
+    static PEAK: Cell<usize> = const { Cell::new(0) };
+    // the live bytes when the budget started, and the budget
+    static BUDGET: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+#[cfg(feature = "memory-budget")]
+fn record(allocated: usize, freed: usize) {
+    // the thread locals are gone while the thread exits
+    let _ = LIVE.try_with(|live| {
+        let now = live.get().saturating_add(allocated).saturating_sub(freed);
+        live.set(now);
+        let _ = PEAK.try_with(|peak| peak.set(peak.get().max(now)));
+    });
+}
+
+/// A global allocator that counts the bytes each thread holds, which `Options::memory_budget`
+/// needs to limit a translation:
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOC: gql2sql::BudgetAllocator = gql2sql::BudgetAllocator::system();
+/// ```
+#[cfg(feature = "memory-budget")]
+pub struct BudgetAllocator<A = System>(A);
+
+#[cfg(feature = "memory-budget")]
+impl BudgetAllocator<System> {
+    #[must_use]
+    pub const fn system() -> Self {
+        Self(System)
+    }
+}
+
+#[cfg(feature = "memory-budget")]
+impl<A> BudgetAllocator<A> {
+    /// Counts the allocations of another allocator, like `wee_alloc`.
+    pub const fn new(inner: A) -> Self {
+        Self(inner)
+    }
+}
+
+#[cfg(feature = "memory-budget")]
+unsafe impl<A: GlobalAlloc> GlobalAlloc for BudgetAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0.alloc(layout);
+        if !ptr.is_null() {
+            record(layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.0.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record(layout.size(), 0);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.dealloc(ptr, layout);
+        record(0, layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.0.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            record(new_size, layout.size());
+        }
+        new_ptr
+    }
+}
+
+/// Ends the budget of a translation when dropped, restoring the one it replaced.
+pub(crate) struct BudgetGuard {
+    #[cfg(feature = "memory-budget")]
+    previous: Option<(usize, usize)>,
+}
+
+#[cfg(feature = "memory-budget")]
+impl Drop for BudgetGuard {
+    fn drop(&mut self) {
+        BUDGET.set(self.previous);
+    }
+}
+
+/// Starts limiting the bytes the translations of this thread hold at once to `limit`, from
+/// the bytes held now.
+#[cfg(feature = "memory-budget")]
+pub(crate) fn start_budget(limit: Option<usize>) -> AnyResult<BudgetGuard> {
+    let previous = BUDGET.get();
+    let Some(limit) = limit else {
+        return Ok(BudgetGuard { previous });
+    };
+    let start = LIVE.get();
+    let probe = std::hint::black_box(Box::new(0u64));
+    let counted = LIVE.get() != start;
+    drop(probe);
+    if !counted {
+        return Err(anyhow!(
+            "memoryBudget needs BudgetAllocator as the global allocator"
+        ));
+    }
+    let start = LIVE.get();
+    PEAK.set(start);
+    BUDGET.set(Some((start, limit)));
+    Ok(BudgetGuard { previous })
+}
+
+#[cfg(not(feature = "memory-budget"))]
+pub(crate) fn start_budget(limit: Option<usize>) -> AnyResult<BudgetGuard> {
+    match limit {
+        Some(_) => Err(anyhow!(
+            "memoryBudget needs gql2sql to be built with the memory-budget feature"
+        )),
+        None => Ok(BudgetGuard {}),
+    }
+}
+
+/// Fails when the translation has held more bytes at once than its budget, checked before each
+/// root field and relation and after the passes over the plan.
+pub(crate) fn check_memory() -> AnyResult<()> {
+    #[cfg(feature = "memory-budget")]
+    if let Some((start, limit)) = BUDGET.get() {
+        let used = PEAK.get().saturating_sub(start);
+        if used > limit {
+            return Err(anyhow!(
+                "Translation held {} bytes which exceeds the memory budget of {}",
+                used,
+                limit
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use crate::{get_directive_arguments, get_operation};
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{
+    types::{Directive, ExecutableDocument, OperationDefinition, OperationType, Selection},
+    Positioned,
+};
+use serde::Serialize;
+
+type JsonValue = serde_json::Value;
+
+/// How long a query result may be cached, from `@cached(ttl:, staleWhileRevalidate:)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CachePolicy {
+    /// Seconds
+    pub ttl: u64,
+    /// Seconds a stale result may be served while it is refreshed
+    pub stale_while_revalidate: Option<u64>,
+}
+
+impl CachePolicy {
+    /// The matching `Cache-Control` header value.
+    #[must_use]
+    pub fn cache_control(&self) -> String {
+        match self.stale_while_revalidate {
+            Some(swr) => format!("max-age={}, stale-while-revalidate={swr}", self.ttl),
+            None => format!("max-age={}", self.ttl),
+        }
+    }
+
+    // the stricter of two policies, as a response is only as fresh as its least cacheable field
+    fn min(self, other: Self) -> Self {
+        Self {
+            ttl: self.ttl.min(other.ttl),
+            stale_while_revalidate: self
+                .stale_while_revalidate
+                .zip(other.stale_while_revalidate)
+                .map(|(a, b)| a.min(b)),
+        }
+    }
+}
+
+fn get_seconds(arguments: &serde_json::Map<String, JsonValue>, name: &str) -> Option<u64> {
+    arguments.get(name).and_then(JsonValue::as_u64)
+}
+
+fn parse_cached(
+    operation: &OperationDefinition,
+    variables: &Option<JsonValue>,
+    directives: &[Positioned<Directive>],
+) -> AnyResult<Option<CachePolicy>> {
+    let Some(directive) = directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == "cached")
+    else {
+        return Ok(None);
+    };
+    let arguments = get_directive_arguments(operation, variables, &directive.node)?;
+    let ttl = get_seconds(&arguments, "ttl").ok_or_else(|| {
+        anyhow!(
+            "@cached argument \"ttl\" must be a non-negative integer at {}",
+            directive.pos
+        )
+    })?;
+    let stale_while_revalidate = match arguments.get("staleWhileRevalidate") {
+        None | Some(JsonValue::Null) => None,
+        Some(_) => Some(
+            get_seconds(&arguments, "staleWhileRevalidate").ok_or_else(|| {
+                anyhow!(
+                    "@cached argument \"staleWhileRevalidate\" must be a non-negative integer at {}",
+                    directive.pos
+                )
+            })?,
+        ),
+    };
+    Ok(Some(CachePolicy {
+        ttl,
+        stale_while_revalidate,
+    }))
+}
+
+/// The cache policy of a query: `@cached` on a root field overrides the one on the operation,
+/// and the result is the strictest over all root fields. Mutations, and queries with a root
+/// field that has no policy, are not cacheable.
+pub fn get_cache_policy(
+    ast: &ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<&str>,
+) -> AnyResult<Option<CachePolicy>> {
+    let operation = get_operation(ast, operation_name)?;
+    let default = parse_cached(operation, variables, &operation.directives)?;
+    if operation.ty == OperationType::Mutation {
+        return Ok(None);
+    }
+    let mut policy: Option<CachePolicy> = None;
+    for selection in &operation.selection_set.node.items {
+        let Selection::Field(field) = &selection.node else {
+            continue;
+        };
+        let Some(field_policy) =
+            parse_cached(operation, variables, &field.node.directives)?.or(default)
+        else {
+            return Ok(None);
+        };
+        policy = Some(policy.map_or(field_policy, |policy| policy.min(field_policy)));
+    }
+    Ok(policy)
+}
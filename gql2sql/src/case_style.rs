@@ -0,0 +1,107 @@
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{
+    types::{Field, Selection},
+    Positioned,
+};
+use async_graphql_value::{Name, Value as GqlValue};
+use serde::Deserialize;
+
+/// Case of the JSON keys in the result, applied to every field without an explicit alias.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CaseStyle {
+    CamelCase,
+    SnakeCase,
+}
+
+impl CaseStyle {
+    fn parse(value: &str) -> AnyResult<Self> {
+        match value {
+            "camelCase" => Ok(Self::CamelCase),
+            "snakeCase" | "snake_case" => Ok(Self::SnakeCase),
+            _ => Err(anyhow!(
+                "caseStyle must be one of camelCase or snakeCase, got {}",
+                value
+            )),
+        }
+    }
+
+    fn apply(self, name: &str) -> String {
+        // keep leading underscores so `_id` stays distinct from `id`
+        let trimmed = name.trim_start_matches('_');
+        let prefix = &name[..name.len() - trimmed.len()];
+        let mut output = prefix.to_string();
+        match self {
+            Self::CamelCase => {
+                let mut upper = false;
+                for c in trimmed.chars() {
+                    if c == '_' {
+                        upper = true;
+                    } else if upper {
+                        output.extend(c.to_uppercase());
+                        upper = false;
+                    } else {
+                        output.push(c);
+                    }
+                }
+            }
+            Self::SnakeCase => {
+                let mut previous: Option<char> = None;
+                for c in trimmed.chars() {
+                    if c.is_uppercase()
+                        && previous.is_some_and(|p| p.is_lowercase() || p.is_ascii_digit())
+                    {
+                        output.push('_');
+                    }
+                    output.extend(c.to_lowercase());
+                    previous = Some(c);
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Reads `@meta(caseStyle:)` from a root field.
+pub(crate) fn get_meta_case_style(field: &Field) -> AnyResult<Option<CaseStyle>> {
+    let Some(value) = field
+        .directives
+        .iter()
+        .filter(|d| d.node.name.node.as_str() == "meta")
+        .flat_map(|d| d.node.arguments.iter())
+        .find(|(name, _)| name.node.as_str() == "caseStyle")
+        .map(|(_, value)| &value.node)
+    else {
+        return Ok(None);
+    };
+    match value {
+        GqlValue::String(style) => CaseStyle::parse(style).map(Some),
+        GqlValue::Enum(style) => CaseStyle::parse(style.as_str()).map(Some),
+        _ => Err(anyhow!("caseStyle must be a string")),
+    }
+}
+
+/// Aliases a field whose key would change, and every field nested in it, so the translator
+/// emits the styled keys.
+pub(crate) fn apply_field_case_style(field: &mut Field, style: CaseStyle) {
+    let name = field.name.node.as_str();
+    if field.alias.is_none() && !name.starts_with("__") {
+        let key = style.apply(name);
+        if key != name {
+            field.alias = Some(Positioned::new(Name::new(key), field.name.pos));
+        }
+    }
+    apply_case_style(&mut field.selection_set.node.items, style);
+}
+
+fn apply_case_style(items: &mut [Positioned<Selection>], style: CaseStyle) {
+    for selection in items {
+        match &mut selection.node {
+            Selection::Field(field) => apply_field_case_style(&mut field.node, style),
+            Selection::InlineFragment(fragment) => {
+                apply_case_style(&mut fragment.node.selection_set.node.items, style);
+            }
+            Selection::FragmentSpread(_) => {}
+        }
+    }
+}
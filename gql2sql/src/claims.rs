@@ -0,0 +1,75 @@
+use anyhow::{anyhow, Result as AnyResult};
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use std::collections::HashMap;
+
+/// Where a claim lives in the token payload: a dotted path like `org.id`, or explicit segments
+/// for claim names that contain dots, e.g. `["https://example.com/claims", "org_id"]`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ClaimPath {
+    Dotted(String),
+    Segments(Vec<String>),
+}
+
+impl ClaimPath {
+    fn lookup<'a>(&self, claims: &'a JsonValue) -> Option<&'a JsonValue> {
+        match self {
+            Self::Dotted(path) => path
+                .split('.')
+                .try_fold(claims, |value, key| value.get(key)),
+            Self::Segments(path) => path.iter().try_fold(claims, |value, key| value.get(key)),
+        }
+    }
+}
+
+impl std::fmt::Display for ClaimPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dotted(path) => write!(f, "{path}"),
+            Self::Segments(path) => write!(f, "{}", path.join(" > ")),
+        }
+    }
+}
+
+/// GraphQL variable name to the claim it is read from, e.g.
+/// `{"currentUserId": "sub", "orgId": "org.id"}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct ClaimMapping {
+    variables: HashMap<String, ClaimPath>,
+}
+
+impl ClaimMapping {
+    pub fn insert(&mut self, variable: impl Into<String>, path: ClaimPath) {
+        self.variables.insert(variable.into(), path);
+    }
+}
+
+/// Sets every mapped variable from the verified claims of the request, overwriting anything the
+/// client sent under the same name. A mapped claim missing from the token is an error.
+pub fn inject_claims(
+    variables: &mut Option<JsonValue>,
+    claims: &JsonValue,
+    mapping: &ClaimMapping,
+) -> AnyResult<()> {
+    if mapping.variables.is_empty() {
+        return Ok(());
+    }
+    if variables.as_ref().is_some_and(JsonValue::is_null) {
+        *variables = None;
+    }
+    let JsonValue::Object(variables) =
+        variables.get_or_insert_with(|| JsonValue::Object(Map::new()))
+    else {
+        return Err(anyhow!("variables must be an object"));
+    };
+    for (name, path) in &mapping.variables {
+        let value = path
+            .lookup(claims)
+            .filter(|value| !value.is_null())
+            .ok_or_else(|| anyhow!("claim {} for variable ${} is missing", path, name))?;
+        variables.insert(name.clone(), value.clone());
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use crate::identifiers::fnv1a;
+use crate::{to_sql, CacheTag, Options};
+use async_graphql_parser::types::{DocumentOperations, ExecutableDocument};
+use serde::Deserialize;
+use sqlparser::ast::Statement;
+use std::collections::BTreeMap;
+
+type JsonValue = serde_json::Value;
+
+/// What `to_commented_sql` writes into the comment before a statement, besides the operation
+/// name and the fingerprint of the statement.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SqlComment {
+    /// Include the cache tags of the result
+    pub tags: bool,
+    /// More `key:value` pairs, like the tenant; a value of `$name` is replaced with the
+    /// variable `name`, and the pair is left out when that is not a string, number or boolean
+    pub values: BTreeMap<String, String>,
+}
+
+// only characters that cannot end the comment, start a nested one or look like a placeholder
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "_.:,-=@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// the text of a comment value, with a `$name` template replaced by the variable
+fn get_value(value: &str, variables: &Option<JsonValue>) -> Option<String> {
+    let Some(name) = value.strip_prefix('$') else {
+        return Some(value.to_owned());
+    };
+    match variables.as_ref()?.get(name)? {
+        JsonValue::String(s) => Some(s.clone()),
+        value @ (JsonValue::Number(_) | JsonValue::Bool(_)) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// The name of the operation to run: `operation_name` when it is given, otherwise the name the
+/// document declares for the operation `gql2sql` picks, if it has one.
+#[must_use]
+pub fn get_operation_name(
+    ast: &ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Option<String> {
+    if let Some(name) = operation_name {
+        return Some(name.to_owned());
+    }
+    match &ast.operations {
+        DocumentOperations::Single(_) => None,
+        DocumentOperations::Multiple(map) => map.keys().next().map(ToString::to_string),
+    }
+}
+
+/// `/* op:GetApp hash:9f2c1d0e4b7a6c35 tags:type:App */`, where the hash is the same for every
+/// run of the operation with the same shape, so slow query logs and `pg_stat_statements` can be
+/// traced back to it.
+#[must_use]
+pub fn get_sql_comment(
+    statement: &Statement,
+    operation_name: Option<&str>,
+    variables: &Option<JsonValue>,
+    tags: Option<&[CacheTag]>,
+    comment: &SqlComment,
+) -> String {
+    let mut parts = vec![];
+    if let Some(name) = operation_name {
+        parts.push(format!("op:{}", sanitize(name)));
+    }
+    parts.push(format!("hash:{:016x}", fnv1a(&statement.to_string())));
+    if let Some(tags) = tags.filter(|tags| comment.tags && !tags.is_empty()) {
+        let tags = tags.iter().map(ToString::to_string).collect::<Vec<_>>();
+        parts.push(format!("tags:{}", sanitize(&tags.join(","))));
+    }
+    for (key, value) in &comment.values {
+        if let Some(value) = get_value(value, variables) {
+            parts.push(format!("{}:{}", sanitize(key), sanitize(&value)));
+        }
+    }
+    format!("/* {} */", parts.join(" "))
+}
+
+/// `to_sql`, preceded by the comment of `options.comment` when it is set. `operation_name` is
+/// the one `get_operation_name` finds in the document, before it is translated.
+#[must_use]
+pub fn to_commented_sql(
+    statement: &Statement,
+    operation_name: Option<&str>,
+    variables: &Option<JsonValue>,
+    tags: Option<&[CacheTag]>,
+    options: &Options,
+) -> String {
+    let sql = to_sql(statement, options);
+    let Some(comment) = &options.comment else {
+        return sql;
+    };
+    let comment = get_sql_comment(statement, operation_name, variables, tags, comment);
+    if options.pretty {
+        format!("{comment}\n{sql}")
+    } else {
+        format!("{comment} {sql}")
+    }
+}
@@ -8,4 +8,90 @@ pub const TO_JSONB: &str = "to_jsonb";
 pub const JSONB_AGG: &str = "jsonb_agg";
 pub const ON: &str = "ON";
 pub const TYPENAME: &str = "__typename";
+pub const AFFECTED_ROWS: &str = "affected_rows";
+pub const ALLOW_ALL: &str = "allowAll";
+pub const CLAIM: &str = "claim";
 pub const ID: &str = "id";
+pub const DEFAULT_SENTINEL: &str = "__default";
+pub const DEFAULT_KEYWORD: &str = "DEFAULT";
+pub const COLUMN_ARG_PREFIX: &str = "col_";
+// the arguments of queries and mutations that a column of the same name would clash with
+pub const RESERVED_ARGS: [&str; 21] = [
+    "filter",
+    "where",
+    "order",
+    "first",
+    "limit",
+    "after",
+    "offset",
+    "distinct",
+    "group_by",
+    "groupBy",
+    "lock",
+    "sample",
+    "data",
+    "from",
+    "set",
+    "inc",
+    "increment",
+    "append",
+    "prepend",
+    "delete_key",
+    "delete_at_path",
+];
+pub const KEY_COLUMNS: [&str; 4] = ["id", "email", "A", "B"];
+pub const VALUE_TYPES: [&str; 3] = ["boolean", "integer", "numeric"];
+pub const INTERVAL: &str = "interval";
+pub const VECTOR: &str = "vector";
+pub const VECTOR_DISTANCE: &str = "<->";
+pub const CLONE_SOURCE: &str = "src";
+pub const CLONE_MAP: &str = "map";
+pub const CLONE_PARENT: &str = "parent";
+pub const CLONE_ID_FUNCTION: &str = "gen_random_uuid";
+pub const DATE_TRUNC: &str = "date_trunc";
+pub const TO_CHAR: &str = "to_char";
+pub const REGEXP_REPLACE: &str = "regexp_replace";
+// the width numbers are zero padded to for a natural sort
+pub const NATURAL_SORT_DIGITS: usize = 20;
+pub const ISO8601_FORMAT: &str = r#"YYYY-MM-DD"T"HH24:MI:SS.MS"#;
+pub const DATE_TRUNC_UNITS: [&str; 13] = [
+    "microseconds",
+    "milliseconds",
+    "second",
+    "minute",
+    "hour",
+    "day",
+    "week",
+    "month",
+    "quarter",
+    "year",
+    "decade",
+    "century",
+    "millennium",
+];
+// the fields of an aggregate selection besides `value`
+pub const AGGREGATE_FIELDS: [&str; 12] = [
+    "__typename",
+    "count",
+    "min",
+    "max",
+    "avg",
+    "sum",
+    "stddev",
+    "variance",
+    "array_agg",
+    "string_agg",
+    "percentile_cont",
+    "count_distinct",
+];
+pub const GROUPING: &str = "grouping";
+pub const BYTEA: &str = "bytea";
+pub const BYTES: &str = "Bytes";
+pub const STRING: &str = "String";
+pub const TABLESAMPLE: &str = "TABLESAMPLE";
+// the default comes first
+pub const SAMPLE_METHODS: [&str; 2] = ["SYSTEM", "BERNOULLI"];
+// the queries of a plan are referred to as `TABLE #<index>`
+pub const QUERY_REFERENCE: &str = "#";
+// the variables that literals of registered enums are rewritten to, numbered from 0
+pub const ENUM_VARIABLE_PREFIX: &str = "__enum_";
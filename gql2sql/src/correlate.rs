@@ -0,0 +1,52 @@
+use async_graphql_value::indexmap::IndexMap;
+use sqlparser::ast::{Expr, Ident, Query, VisitMut, VisitorMut};
+use std::ops::ControlFlow;
+
+struct Correlate<'a> {
+    alias: &'a Ident,
+    columns: &'a IndexMap<String, Expr>,
+    depth: usize,
+}
+
+impl VisitorMut for Correlate<'_> {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, _query: &mut Query) -> ControlFlow<()> {
+        self.depth += 1;
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &mut Query) -> ControlFlow<()> {
+        self.depth -= 1;
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<()> {
+        if self.depth > 0 {
+            return ControlFlow::Continue(());
+        }
+        if let Expr::Identifier(ident) = expr {
+            let column = match self.columns.get(&ident.value) {
+                Some(Expr::Identifier(name)) => name.clone(),
+                Some(computed) => {
+                    *expr = computed.clone();
+                    return ControlFlow::Continue(());
+                }
+                None => ident.clone(),
+            };
+            *expr = Expr::CompoundIdentifier(vec![self.alias.clone(), column]);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Qualifies the unqualified columns of a relation filter with the alias of the related table,
+/// mapping GraphQL names of its selection onto `@column` names and `@static` values. Subqueries
+/// of nested relation filters already qualify their own columns and are left alone.
+pub(crate) fn correlate(expr: &mut Expr, alias: &Ident, columns: &IndexMap<String, Expr>) {
+    let _ = expr.visit(&mut Correlate {
+        alias,
+        columns,
+        depth: 0,
+    });
+}
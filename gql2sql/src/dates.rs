@@ -0,0 +1,338 @@
+use crate::consts::{INTERVAL, STRING};
+use crate::{get_string_or_variable, QueryPlan};
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{
+    types::{BaseType, VariableDefinition},
+    Positioned,
+};
+use async_graphql_value::{
+    indexmap::{IndexMap, IndexSet},
+    Name, Value as GqlValue,
+};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+use sqlparser::ast::{
+    BinaryOperator, Expr, Function, FunctionArgumentList, FunctionArguments, Ident, Interval,
+    ObjectName, Value, VisitMut, VisitorMut,
+};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+type JsonValue = serde_json::Value;
+
+lazy_static! {
+    static ref TIMESTAMP: Regex =
+        Regex::new(r"^(\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(\.\d+)?)(Z|[\+-]\d{2}:\d{2})?$")
+            .expect("Failed to compile regex");
+    static ref DATE: Regex = Regex::new(r"^\d{4}-\d{2}-\d{2}$").expect("Failed to compile regex");
+    static ref TIME: Regex = Regex::new(r"^\d{2}:\d{2}:\d{2}(?:\.\d+)?(Z|[\+-]\d{2}:\d{2})?$")
+        .expect("Failed to compile regex");
+    static ref RELATIVE: Regex =
+        Regex::new(r"^now(?:([\+-])(\d+)([smhdwMy]))?$").expect("Failed to compile regex");
+    static ref OFFSET: Regex =
+        Regex::new(r"^(Z|[\+-]\d{2}:\d{2})$").expect("Failed to compile regex");
+}
+
+/// How timestamps without an offset are bound.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TimestampPolicy {
+    /// As `timestamptz`, as written, so Postgres reads them in the time zone of the session (the
+    /// default).
+    #[default]
+    AsWritten,
+    /// They are UTC: bound as `timestamptz` with `Z` appended.
+    AssumeUtc,
+    /// They are at this offset, `Z` or `+HH:MM`: bound as `timestamptz` with it appended.
+    AssumeOffset(String),
+    /// They are bound as `timestamp`, as written.
+    Passthrough,
+}
+
+/// How date and time variables are typed and normalized. By default timestamps are bound as
+/// `timestamptz` without changing their values, and everything else as `text`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DatePolicy {
+    pub timestamps: TimestampPolicy,
+    /// Binds dates (`YYYY-MM-DD`) as `date` and times (`HH:MM:SS`) as `time` or `timetz`
+    /// instead of `text`, except for variables declared `String`
+    pub dates_and_times: bool,
+}
+
+impl DatePolicy {
+    pub(crate) fn check(&self) -> AnyResult<()> {
+        match &self.timestamps {
+            TimestampPolicy::AssumeOffset(offset) if !OFFSET.is_match(offset) => Err(anyhow!(
+                "datePolicy offset must be Z or +HH:MM, got {}",
+                offset
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Recognises timestamps in a string, and dates (`YYYY-MM-DD`) and times (`HH:MM:SS`) when
+/// `policy` types them, returning the text to bind, with the policy applied to timestamps
+/// without an offset, and its type.
+#[must_use]
+pub fn detect_date_with(text: &str, policy: &DatePolicy) -> Option<(String, &'static str)> {
+    if let Some(captures) = TIMESTAMP.captures(text) {
+        if captures.get(3).is_some() {
+            return Some((text.to_owned(), "timestamptz"));
+        }
+        return Some(match &policy.timestamps {
+            TimestampPolicy::AsWritten => (text.to_owned(), "timestamptz"),
+            TimestampPolicy::AssumeUtc if captures.get(2).is_some() => {
+                (text.to_owned() + "Z", "timestamptz")
+            }
+            TimestampPolicy::AssumeUtc => (text.to_owned() + ".000Z", "timestamptz"),
+            TimestampPolicy::AssumeOffset(offset) => (text.to_owned() + offset, "timestamptz"),
+            TimestampPolicy::Passthrough => (text.to_owned(), "timestamp"),
+        });
+    }
+    if !policy.dates_and_times {
+        return None;
+    }
+    if DATE.is_match(text) {
+        return Some((text.to_owned(), "date"));
+    }
+    let captures = TIME.captures(text)?;
+    let sql_type = if captures.get(1).is_some() {
+        "timetz"
+    } else {
+        "time"
+    };
+    Some((text.to_owned(), sql_type))
+}
+
+/// A timestamp with `Z` appended when it has no offset, as `TimestampPolicy::AssumeUtc` binds it.
+#[must_use]
+pub fn detect_date(text: &str) -> Option<String> {
+    let policy = DatePolicy {
+        timestamps: TimestampPolicy::AssumeUtc,
+        dates_and_times: false,
+    };
+    detect_date_with(text, &policy).map(|(text, _)| text)
+}
+
+pub(crate) fn is_timestamp(text: &str) -> bool {
+    TIMESTAMP.is_match(text)
+}
+
+// the text and type `policy` gives a string bound as `sql_type`: timestamps without an offset
+// as the policy says, and text that is a date or time as one when the policy types them
+fn apply_to_text(
+    text: &str,
+    sql_type: &str,
+    policy: &DatePolicy,
+) -> Option<(String, &'static str)> {
+    match sql_type {
+        "timestamptz" => TIMESTAMP
+            .captures(text)
+            .filter(|captures| captures.get(3).is_none())
+            .and_then(|_| detect_date_with(text, policy)),
+        "text" => detect_date_with(text, policy)
+            .filter(|(_, sql_type)| ["date", "time", "timetz"].contains(sql_type)),
+        _ => None,
+    }
+}
+
+// the value and type a parameter is bound as under `policy`; a list is retyped when all its
+// elements are, to the same type
+fn get_binding(
+    value: &JsonValue,
+    sql_type: &str,
+    policy: &DatePolicy,
+) -> Option<(JsonValue, String)> {
+    match value {
+        JsonValue::String(s) => apply_to_text(s, sql_type, policy)
+            .map(|(text, sql_type)| (JsonValue::String(text), sql_type.to_string())),
+        JsonValue::Array(list) => {
+            let element_type = sql_type.strip_suffix("[]")?;
+            let mut types = vec![];
+            let list = list
+                .iter()
+                .map(|value| {
+                    let binding = match value {
+                        JsonValue::Null => return JsonValue::Null,
+                        JsonValue::String(s) => apply_to_text(s, element_type, policy),
+                        _ => None,
+                    };
+                    match binding {
+                        Some((text, sql_type)) => {
+                            types.push(sql_type);
+                            JsonValue::String(text)
+                        }
+                        None => {
+                            types.push(element_type);
+                            value.clone()
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+            let retyped = match types.split_first() {
+                Some((first, rest)) if rest.iter().all(|t| t == first) => *first,
+                _ => element_type,
+            };
+            Some((JsonValue::Array(list), format!("{retyped}[]")))
+        }
+        _ => None,
+    }
+}
+
+// `$n::type` placeholders replaced by the retyped ones
+pub(crate) struct Retype(pub(crate) HashMap<String, String>);
+
+impl VisitorMut for Retype {
+    type Break = ();
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<()> {
+        if let Expr::Value(Value::Placeholder(placeholder)) = expr {
+            if let Some(retyped) = self.0.get(placeholder) {
+                placeholder.clone_from(retyped);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+impl QueryPlan {
+    /// Binds the date and time parameters as `policy` says: timestamps without an offset with
+    /// `Z` or the offset appended under `AssumeUtc` and `AssumeOffset`, as `timestamp` under
+    /// `Passthrough`, and dates and times as `date`, `time` or `timetz` when it types them.
+    /// `strings` are the variables declared `String`, which stay `text`. Under the default
+    /// policy nothing changes.
+    pub fn apply_date_policy(&mut self, policy: &DatePolicy, strings: &IndexSet<Name>) {
+        let Some(params) = &mut self.params else {
+            return;
+        };
+        let mut retyped = HashMap::new();
+        for (i, param) in params.iter_mut().enumerate() {
+            if param.sql_type.starts_with("text") && strings.contains(param.name.as_str()) {
+                continue;
+            }
+            let Some((value, sql_type)) = get_binding(&param.value, &param.sql_type, policy) else {
+                continue;
+            };
+            param.value = value;
+            if sql_type != param.sql_type {
+                retyped.insert(
+                    format!("${}::{}", i + 1, param.sql_type),
+                    format!("${}::{sql_type}", i + 1),
+                );
+                param.sql_type = sql_type;
+            }
+        }
+        if !retyped.is_empty() {
+            let _ = self.edit(|statement| statement.visit(&mut Retype(retyped)));
+        }
+    }
+}
+
+/// The variables declared `String`, which are never bound as dates or times.
+pub(crate) fn get_string_variables(
+    definitions: &[Positioned<VariableDefinition>],
+) -> IndexSet<Name> {
+    definitions
+        .iter()
+        .filter(|definition| match &definition.node.var_type.node.base {
+            BaseType::Named(name) => name == STRING,
+            BaseType::List(element) => {
+                matches!(&element.base, BaseType::Named(name) if name == STRING)
+            }
+        })
+        .map(|definition| definition.node.name.node.clone())
+        .collect()
+}
+
+// the start of the current transaction
+pub(crate) fn get_now() -> Expr {
+    Expr::Function(Function {
+        within_group: vec![],
+        name: ObjectName(vec![Ident::new("now")]),
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment: None,
+            clauses: vec![],
+            args: vec![],
+        }),
+        over: None,
+        filter: None,
+        null_treatment: None,
+    })
+}
+
+fn get_interval_literal(interval: String) -> Expr {
+    Expr::Interval(Interval {
+        value: Box::new(Expr::Value(Value::SingleQuotedString(interval))),
+        leading_field: None,
+        leading_precision: None,
+        last_field: None,
+        fractional_seconds_precision: None,
+    })
+}
+
+// `interval: "7 days"` is written as a literal, a variable is bound as `$n::interval`
+pub(crate) fn get_interval(
+    value: &GqlValue,
+    sql_vars: &IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Expr> {
+    match value {
+        GqlValue::Variable(v) if matches!(sql_vars.get(v), Some(JsonValue::String(_))) => {
+            let (i, _) = final_vars.insert_full(v.clone(), INTERVAL.to_owned());
+            Ok(Expr::Value(Value::Placeholder(format!(
+                "${}::{INTERVAL}",
+                i + 1
+            ))))
+        }
+        GqlValue::String(s) => Ok(get_interval_literal(s.clone())),
+        _ => Err(anyhow!("interval must be a string")),
+    }
+}
+
+// `now`, `now-7d` or `now+1h`: the current time, shifted by a number of seconds (s), minutes
+// (m), hours (h), days (d), weeks (w), months (M) or years (y)
+fn parse_relative_date(relative: &str) -> AnyResult<Expr> {
+    let captures = RELATIVE
+        .captures(relative)
+        .ok_or_else(|| anyhow!("relative date must look like now-7d, got {}", relative))?;
+    let (Some(sign), Some(amount), Some(unit)) =
+        (captures.get(1), captures.get(2), captures.get(3))
+    else {
+        return Ok(get_now());
+    };
+    let unit = match unit.as_str() {
+        "s" => "seconds",
+        "m" => "minutes",
+        "h" => "hours",
+        "d" => "days",
+        "w" => "weeks",
+        "M" => "months",
+        _ => "years",
+    };
+    Ok(Expr::BinaryOp {
+        left: Box::new(get_now()),
+        op: if sign.as_str() == "+" {
+            BinaryOperator::Plus
+        } else {
+            BinaryOperator::Minus
+        },
+        right: Box::new(get_interval_literal(format!("{} {unit}", amount.as_str()))),
+    })
+}
+
+// a filter value of `{ relative: "now-7d" }`, also when a filter variable holds it
+pub(crate) fn get_relative_date(
+    value: &GqlValue,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<Option<Expr>> {
+    let GqlValue::Object(object) = value else {
+        return Ok(None);
+    };
+    let Some(relative) = object.get("relative") else {
+        return Ok(None);
+    };
+    parse_relative_date(&get_string_or_variable(relative, sql_vars)?).map(Some)
+}
@@ -0,0 +1,105 @@
+use crate::consts::QUOTE_CHAR;
+use sqlparser::ast::{
+    visit_expressions_mut, Expr, Ident, Join, Query, SelectItem, SetExpr, TableFactor, VisitMut,
+    VisitorMut,
+};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+// blanks the aliases of a lateral join in place so it compares equal to the joins that differ
+// only in them, returning its alias and the column it produces
+fn blank(join: &mut Join) -> Option<(Ident, Ident)> {
+    let TableFactor::Derived {
+        lateral: true,
+        subquery,
+        alias: Some(alias),
+    } = &mut join.relation
+    else {
+        return None;
+    };
+    let SetExpr::Select(select) = subquery.body.as_mut() else {
+        return None;
+    };
+    let [SelectItem::ExprWithAlias { alias: column, .. }] = select.projection.as_mut_slice() else {
+        return None;
+    };
+    let column = std::mem::replace(column, Ident::new(""));
+    let alias = std::mem::replace(&mut alias.name, Ident::new(""));
+    Some((alias, column))
+}
+
+// puts back what `blank` took
+fn restore(join: &mut Join, (name, column): (Ident, Ident)) {
+    if let TableFactor::Derived {
+        subquery,
+        alias: Some(alias),
+        ..
+    } = &mut join.relation
+    {
+        alias.name = name;
+        if let SetExpr::Select(select) = subquery.body.as_mut() {
+            if let [SelectItem::ExprWithAlias { alias, .. }] = select.projection.as_mut_slice() {
+                *alias = column;
+            }
+        }
+    }
+}
+
+struct Dedupe;
+
+impl VisitorMut for Dedupe {
+    type Break = ();
+
+    // children first, so parents whose relations select the same nested ones compare equal
+    fn post_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        let SetExpr::Select(select) = query.body.as_mut() else {
+            return ControlFlow::Continue(());
+        };
+        let mut kept: Vec<(usize, Join, Option<(Ident, Ident)>)> =
+            Vec::with_capacity(select.from.iter().map(|table| table.joins.len()).sum());
+        let mut renames = HashMap::new();
+        for (index, table) in select.from.iter_mut().enumerate() {
+            for mut join in std::mem::take(&mut table.joins) {
+                let names = blank(&mut join);
+                let seen = names.as_ref().and_then(|_| {
+                    kept.iter().find_map(|(_, seen, names)| match names {
+                        Some((_, column)) if *seen == join => Some(column),
+                        _ => None,
+                    })
+                });
+                match (seen, names) {
+                    (Some(seen), Some((_, column))) => {
+                        renames.insert(column.value, seen.value.clone());
+                    }
+                    (_, names) => kept.push((index, join, names)),
+                }
+            }
+        }
+        for (index, mut join, names) in kept {
+            if let Some(names) = names {
+                restore(&mut join, names);
+            }
+            select.from[index].joins.push(join);
+        }
+        if !renames.is_empty() {
+            let _ = visit_expressions_mut(&mut select.projection, |expr| {
+                if let Expr::Identifier(ident) = expr {
+                    if ident.quote_style == Some(QUOTE_CHAR) {
+                        if let Some(kept) = renames.get(&ident.value) {
+                            ident.value.clone_from(kept);
+                        }
+                    }
+                }
+                ControlFlow::<()>::Continue(())
+            });
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Keeps one of the lateral joins of a query that differ only in their aliases, as sibling
+/// fields selecting the same relation with the same arguments and fields compile to, and points
+/// the fields of the others at its column.
+pub(crate) fn dedupe_joins<T: VisitMut>(node: &mut T) {
+    let _ = VisitMut::visit(node, &mut Dedupe);
+}
@@ -0,0 +1,49 @@
+use crate::sample::count_samples;
+use anyhow::{anyhow, Result as AnyResult};
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{Expr, Statement, Visit, Visitor};
+use std::ops::ControlFlow;
+
+/// The database `render` writes a plan for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Dialect {
+    #[default]
+    Postgres,
+    /// CockroachDB, which runs the lateral joins and jsonb functions the statements are built
+    /// from, but has no table samples or grouping sets
+    CockroachDb,
+}
+
+struct GroupingSets;
+
+impl Visitor for GroupingSets {
+    type Break = &'static str;
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<&'static str> {
+        match expr {
+            Expr::GroupingSets(_) => ControlFlow::Break("GROUPING SETS"),
+            Expr::Rollup(_) => ControlFlow::Break("ROLLUP"),
+            Expr::Cube(_) => ControlFlow::Break("CUBE"),
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+impl Dialect {
+    /// Fails for a statement that uses what the database lacks.
+    pub(crate) fn check(self, statement: &Statement) -> AnyResult<()> {
+        match self {
+            Self::Postgres => Ok(()),
+            Self::CockroachDb => {
+                if count_samples(statement) > 0 {
+                    return Err(anyhow!("CockroachDB does not support TABLESAMPLE"));
+                }
+                if let ControlFlow::Break(clause) = Visit::visit(statement, &mut GroupingSets) {
+                    return Err(anyhow!("CockroachDB does not support {}", clause));
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{
+    types::{Directive, Field},
+    Positioned,
+};
+use async_graphql_value::{indexmap::IndexMap, ConstValue, Name};
+use sqlparser::ast::{BinaryOperator, Cte, Expr, Query, Statement, With};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+type JsonValue = serde_json::Value;
+
+/// A field carrying a custom directive, as passed to its `DirectiveHandler`.
+pub struct DirectiveContext<'a> {
+    /// The name of the field, not its alias
+    pub field: &'a str,
+    /// The table the field reads, or for a scalar field the table of its column
+    pub table: &'a str,
+    pub directive: &'a Directive,
+    /// The arguments of the directive, with variables replaced by their values
+    pub arguments: IndexMap<String, JsonValue>,
+}
+
+/// Compiles a directive of your own, like `@mask` or `@audit`, registered in
+/// `Options::directives`. Every hook defaults to leaving the statement unchanged.
+pub trait DirectiveHandler: Send + Sync {
+    /// The name of the directive, without the `@`
+    fn name(&self) -> &str;
+
+    /// Rewrites the column a scalar field selects; `expr` is the column, qualified as the query
+    /// reads it.
+    fn project(&self, _context: &DirectiveContext, expr: Expr) -> AnyResult<Expr> {
+        Ok(expr)
+    }
+
+    /// A condition on the columns of the table of a root or relation field, added to its filter.
+    /// Updates, deletes and claims only write the rows that pass it; an insert fails when there
+    /// is one.
+    fn filter(&self, _context: &DirectiveContext) -> AnyResult<Option<Expr>> {
+        Ok(None)
+    }
+
+    /// Common table expressions added to the statement for a root field, like an insert into an
+    /// audit log. Postgres runs data-modifying ones even when nothing reads them.
+    fn ctes(&self, _context: &DirectiveContext) -> AnyResult<Vec<Cte>> {
+        Ok(vec![])
+    }
+}
+
+/// The custom directive handlers by directive name. They are registered in code, so options
+/// deserialized from JSON have none.
+#[derive(Clone, Default)]
+pub struct DirectiveRegistry {
+    handlers: HashMap<String, Arc<dyn DirectiveHandler>>,
+}
+
+impl fmt::Debug for DirectiveRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.handlers.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_set().entries(names).finish()
+    }
+}
+
+impl DirectiveRegistry {
+    /// Adds a handler, replacing any registered for the same directive.
+    pub fn register(&mut self, handler: impl DirectiveHandler + 'static) {
+        self.handlers
+            .insert(handler.name().to_string(), Arc::new(handler));
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    // the directives of a field that have a handler, in the order they are written
+    fn get<'a>(
+        &'a self,
+        directives: &'a [Positioned<Directive>],
+    ) -> impl Iterator<Item = (&'a dyn DirectiveHandler, &'a Directive)> {
+        directives.iter().filter_map(|directive| {
+            self.handlers
+                .get(directive.node.name.node.as_str())
+                .map(|handler| (handler.as_ref(), &directive.node))
+        })
+    }
+
+    pub(crate) fn has_handler(&self, directives: &[Positioned<Directive>]) -> bool {
+        self.get(directives).next().is_some()
+    }
+
+    // runs `apply` for the context of every handled directive of a field
+    fn each<T>(
+        &self,
+        field: &str,
+        table: &str,
+        directives: &[Positioned<Directive>],
+        sql_vars: &IndexMap<Name, JsonValue>,
+        mut apply: impl FnMut(&dyn DirectiveHandler, &DirectiveContext) -> AnyResult<T>,
+    ) -> AnyResult<Vec<T>> {
+        self.get(directives)
+            .map(|(handler, directive)| {
+                let context = DirectiveContext {
+                    field,
+                    table,
+                    directive,
+                    arguments: get_arguments(directive, sql_vars)?,
+                };
+                apply(handler, &context)
+            })
+            .collect()
+    }
+
+    /// The column of a scalar field after the `project` hooks of its directives.
+    pub(crate) fn project(
+        &self,
+        field: &Field,
+        table: &str,
+        mut expr: Expr,
+        sql_vars: &IndexMap<Name, JsonValue>,
+    ) -> AnyResult<Expr> {
+        let name = field.name.node.as_str();
+        for (handler, directive) in self.get(&field.directives) {
+            let context = DirectiveContext {
+                field: name,
+                table,
+                directive,
+                arguments: get_arguments(directive, sql_vars)?,
+            };
+            expr = handler.project(&context, expr)?;
+        }
+        Ok(expr)
+    }
+
+    /// Adds the conditions of the `filter` hooks of a field's directives to its filter.
+    pub(crate) fn filter(
+        &self,
+        field: &str,
+        table: &str,
+        directives: &[Positioned<Directive>],
+        sql_vars: &IndexMap<Name, JsonValue>,
+        selection: &mut Option<Expr>,
+    ) -> AnyResult<()> {
+        let conditions = self.each(field, table, directives, sql_vars, |handler, context| {
+            handler.filter(context)
+        })?;
+        for condition in conditions.into_iter().flatten() {
+            *selection = Some(match selection.take() {
+                Some(selection) => Expr::BinaryOp {
+                    left: Box::new(Expr::Nested(Box::new(selection))),
+                    op: BinaryOperator::And,
+                    right: Box::new(Expr::Nested(Box::new(condition))),
+                },
+                None => condition,
+            });
+        }
+        Ok(())
+    }
+
+    /// The common table expressions of the `ctes` hooks of a root field's directives.
+    pub(crate) fn ctes(
+        &self,
+        field: &Field,
+        table: &str,
+        sql_vars: &IndexMap<Name, JsonValue>,
+    ) -> AnyResult<Vec<Cte>> {
+        let ctes = self.each(
+            field.name.node.as_str(),
+            table,
+            &field.directives,
+            sql_vars,
+            |handler, context| handler.ctes(context),
+        )?;
+        Ok(ctes.into_iter().flatten().collect())
+    }
+}
+
+fn get_arguments(
+    directive: &Directive,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<IndexMap<String, JsonValue>> {
+    directive
+        .arguments
+        .iter()
+        .map(|(name, value)| {
+            let value = value.node.clone().into_const_with(|variable| {
+                sql_vars
+                    .get(&variable)
+                    .cloned()
+                    .map_or(Ok(ConstValue::Null), ConstValue::from_json)
+            })?;
+            Ok((name.node.to_string(), value.into_json()?))
+        })
+        .collect()
+}
+
+/// Puts common table expressions ahead of those of the statement.
+pub(crate) fn add_ctes(statement: &mut Statement, ctes: Vec<Cte>) -> AnyResult<()> {
+    if ctes.is_empty() {
+        return Ok(());
+    }
+    let Statement::Query(query) = statement else {
+        return Err(anyhow!("directive CTEs can only be added to a query"));
+    };
+    let Query { with, .. } = query.as_mut();
+    match with {
+        Some(with) => {
+            with.cte_tables.splice(0..0, ctes);
+        }
+        None => {
+            *with = Some(With {
+                recursive: false,
+                cte_tables: ctes,
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use crate::referenced::{get_referenced_objects, TableColumns};
+use crate::{
+    build_plan, count_operation_nodes, get_operation, render, to_sql, CacheTag, Diagnostic,
+    Options, Param,
+};
+use anyhow::Result as AnyResult;
+use async_graphql_parser::types::ExecutableDocument;
+use serde::Serialize;
+
+type JsonValue = serde_json::Value;
+
+/// What an operation compiles to, without running it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRun {
+    pub sql: String,
+    /// In binding order, with their Postgres types
+    pub params: Vec<Param>,
+    pub tags: Vec<CacheTag>,
+    pub is_mutation: bool,
+    /// The size of the operation and its variables, as `Options::node_budget` counts it
+    pub complexity: usize,
+    /// In order of first reference
+    pub tables: Vec<TableColumns>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// Compiles an operation without a database, for checking persisted documents in CI: the SQL
+/// `to_sql` writes, its parameters and cache tags, the size of the operation, the tables and
+/// columns the statement references, and the warnings of the translation.
+pub fn dry_run(
+    ast: ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+    options: &Options,
+) -> AnyResult<DryRun> {
+    let complexity =
+        count_operation_nodes(get_operation(&ast, operation_name.as_deref())?, variables);
+    let mut plan = build_plan(ast, variables, operation_name, options)?;
+    let warnings = std::mem::take(&mut plan.warnings);
+    let (statement, params, tags, is_mutation) = render(plan, options.dialect, options)?;
+    let tables = get_referenced_objects(&statement).tables;
+    Ok(DryRun {
+        sql: to_sql(&statement, options),
+        params: params.unwrap_or_default(),
+        tags: tags.unwrap_or_default(),
+        is_mutation,
+        complexity,
+        tables,
+        warnings,
+    })
+}
@@ -0,0 +1,301 @@
+use crate::{consts::ENUM_VARIABLE_PREFIX, get_operation_mut, QueryPlan};
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{
+    types::{BaseType, ExecutableDocument, Field, Selection, Type, VariableDefinition},
+    Pos, Positioned,
+};
+use async_graphql_value::{Name, Value as GqlValue};
+use serde::Deserialize;
+use sqlparser::ast::{CastKind, DataType, Expr, Ident, ObjectName, Value, VisitMut, VisitorMut};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+type JsonValue = serde_json::Value;
+
+/// The Postgres enum a GraphQL enum is stored as.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EnumMapping {
+    /// The Postgres type, e.g. `post_status`
+    pub sql_type: String,
+    /// The Postgres label of every GraphQL value, so literals of the enum can be recognised;
+    /// variables with values missing here are bound as given
+    pub labels: HashMap<String, String>,
+}
+
+impl EnumMapping {
+    fn get_label(&self, value: &str) -> String {
+        self.labels
+            .get(value)
+            .cloned()
+            .unwrap_or_else(|| value.to_string())
+    }
+
+    fn get_param(&self, value: &JsonValue) -> JsonValue {
+        match value {
+            JsonValue::String(value) => JsonValue::String(self.get_label(value)),
+            JsonValue::Array(list) => list.iter().map(|v| self.get_param(v)).collect(),
+            value => value.clone(),
+        }
+    }
+}
+
+// the enum a literal belongs to, when the labels of exactly one list it
+fn find_enum<'a>(
+    value: &str,
+    enums: &'a HashMap<String, EnumMapping>,
+) -> AnyResult<Option<&'a str>> {
+    let mut names = enums
+        .iter()
+        .filter(|(_, mapping)| mapping.labels.contains_key(value))
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    match names.as_slice() {
+        [] => Ok(None),
+        [name] => Ok(Some(name)),
+        _ => Err(anyhow!(
+            "enum value {} is in more than one of {}, pass it as a variable",
+            value,
+            names.join(", ")
+        )),
+    }
+}
+
+#[derive(Default)]
+struct Literals {
+    // the variable and enum of each literal, in order of appearance
+    variables: Vec<(Name, String, String)>,
+}
+
+impl Literals {
+    fn value(
+        &mut self,
+        value: &mut GqlValue,
+        enums: &HashMap<String, EnumMapping>,
+    ) -> AnyResult<()> {
+        match value {
+            GqlValue::Enum(literal) => {
+                if let Some(name) = find_enum(literal, enums)? {
+                    let variable =
+                        Name::new(format!("{ENUM_VARIABLE_PREFIX}{}", self.variables.len()));
+                    self.variables
+                        .push((variable.clone(), name.to_string(), literal.to_string()));
+                    *value = GqlValue::Variable(variable);
+                }
+            }
+            GqlValue::List(list) => {
+                for value in list {
+                    self.value(value, enums)?;
+                }
+            }
+            GqlValue::Object(object) => {
+                for value in object.values_mut() {
+                    self.value(value, enums)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn items(
+        &mut self,
+        items: &mut [Positioned<Selection>],
+        enums: &HashMap<String, EnumMapping>,
+    ) -> AnyResult<()> {
+        for item in items {
+            match &mut item.node {
+                Selection::Field(field) => self.field(&mut field.node, enums)?,
+                Selection::InlineFragment(fragment) => {
+                    self.items(&mut fragment.node.selection_set.node.items, enums)?;
+                }
+                Selection::FragmentSpread(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn field(&mut self, field: &mut Field, enums: &HashMap<String, EnumMapping>) -> AnyResult<()> {
+        for (_, value) in &mut field.arguments {
+            self.value(&mut value.node, enums)?;
+        }
+        self.items(&mut field.selection_set.node.items, enums)
+    }
+}
+
+fn get_type_name(var_type: &Type) -> (&str, bool) {
+    match &var_type.base {
+        BaseType::Named(name) => (name.as_str(), false),
+        BaseType::List(element) => (get_type_name(element).0, true),
+    }
+}
+
+/// Rewrites the literals of registered enums into variables declared with their enum, so
+/// `QueryPlan::apply_enums` can write them as casts, returning the variables to translate with
+/// and the enum of every variable that has one, with whether it is a list.
+pub(crate) fn declare_enums(
+    ast: &mut ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<&str>,
+    enums: &HashMap<String, EnumMapping>,
+) -> AnyResult<(Option<JsonValue>, HashMap<String, (String, bool)>)> {
+    let Some(operation) = get_operation_mut(ast, operation_name) else {
+        return Ok((variables.clone(), HashMap::new()));
+    };
+    let mut literals = Literals::default();
+    literals.items(&mut operation.selection_set.node.items, enums)?;
+    let mut variables = variables.clone();
+    if !literals.variables.is_empty() {
+        let values = match &mut variables {
+            Some(JsonValue::Object(values)) => values,
+            variables => {
+                *variables = Some(JsonValue::Object(serde_json::Map::new()));
+                match variables {
+                    Some(JsonValue::Object(values)) => values,
+                    _ => unreachable!("variables were just set to an object"),
+                }
+            }
+        };
+        for (variable, name, literal) in literals.variables {
+            values.insert(variable.to_string(), JsonValue::String(literal));
+            operation.variable_definitions.push(Positioned::new(
+                VariableDefinition {
+                    name: Positioned::new(variable, Pos::default()),
+                    var_type: Positioned::new(
+                        Type {
+                            base: BaseType::Named(Name::new(name)),
+                            nullable: false,
+                        },
+                        Pos::default(),
+                    ),
+                    directives: vec![],
+                    default_value: None,
+                },
+                Pos::default(),
+            ));
+        }
+    }
+    let declared = operation
+        .variable_definitions
+        .iter()
+        .filter_map(|definition| {
+            let (name, is_list) = get_type_name(&definition.node.var_type.node);
+            enums.contains_key(name).then(|| {
+                (
+                    definition.node.name.node.to_string(),
+                    (name.to_string(), is_list),
+                )
+            })
+        })
+        .collect();
+    Ok((variables, declared))
+}
+
+// placeholders replaced by their new expression
+struct Replace(HashMap<String, Expr>);
+
+impl VisitorMut for Replace {
+    type Break = ();
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<()> {
+        if let Expr::Value(Value::Placeholder(placeholder)) = expr {
+            if let Some(replacement) = self.0.get(placeholder) {
+                *expr = replacement.clone();
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn get_cast(value: Expr, sql_type: &str) -> Expr {
+    Expr::Cast {
+        kind: CastKind::DoubleColon,
+        expr: Box::new(value),
+        data_type: DataType::Custom(ObjectName(vec![Ident::new(sql_type)]), vec![]),
+        format: None,
+    }
+}
+
+impl QueryPlan {
+    /// Binds the variables declared with a registered enum as its Postgres type, with their
+    /// values mapped to its labels, and writes the literals `declare_enums` rewrote as
+    /// `'label'::type`.
+    pub(crate) fn apply_enums(
+        &mut self,
+        declared: &HashMap<String, (String, bool)>,
+        enums: &HashMap<String, EnumMapping>,
+    ) -> AnyResult<()> {
+        let Some(params) = self.params.take() else {
+            return Ok(());
+        };
+        let mut replacements = HashMap::new();
+        let mut kept = vec![];
+        for (i, mut param) in params.into_iter().enumerate() {
+            let old = format!("${}::{}", i + 1, param.sql_type);
+            // the elements of list variables are bound one by one as `name_0`, `name_1`..
+            let enum_name = declared.get(&param.name).map(|(name, _)| name).or_else(|| {
+                let (list, index) = param.name.rsplit_once('_')?;
+                match declared.get(list) {
+                    Some((name, true)) if index.chars().all(|c| c.is_ascii_digit()) => Some(name),
+                    _ => None,
+                }
+            });
+            let Some(mapping) = enum_name.and_then(|name| enums.get(name)) else {
+                if old != format!("${}::{}", kept.len() + 1, param.sql_type) {
+                    replacements.insert(
+                        old,
+                        Expr::Value(Value::Placeholder(format!(
+                            "${}::{}",
+                            kept.len() + 1,
+                            param.sql_type
+                        ))),
+                    );
+                }
+                kept.push(param);
+                continue;
+            };
+            if mapping.sql_type.is_empty()
+                || !mapping
+                    .sql_type
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            {
+                return Err(anyhow!("Invalid enum type: {}", mapping.sql_type));
+            }
+            param.value = mapping.get_param(&param.value);
+            if param.name.starts_with(ENUM_VARIABLE_PREFIX) {
+                let JsonValue::String(label) = param.value else {
+                    return Err(anyhow!("enum literal {} must be a string", param.name));
+                };
+                replacements.insert(
+                    old,
+                    get_cast(
+                        Expr::Value(Value::SingleQuotedString(label)),
+                        &mapping.sql_type,
+                    ),
+                );
+                continue;
+            }
+            param.sql_type = match param.sql_type.as_str() {
+                "text" => mapping.sql_type.clone(),
+                sql_type if sql_type.ends_with("[]") => format!("{}[]", mapping.sql_type),
+                sql_type => sql_type.to_string(),
+            };
+            replacements.insert(
+                old,
+                Expr::Value(Value::Placeholder(format!(
+                    "${}::{}",
+                    kept.len() + 1,
+                    param.sql_type
+                ))),
+            );
+            kept.push(param);
+        }
+        if !replacements.is_empty() {
+            let _ = self.edit(|statement| VisitMut::visit(statement, &mut Replace(replacements)));
+        }
+        self.params = (!kept.is_empty()).then_some(kept);
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+use crate::QueryPlan;
+use serde::Serialize;
+use sqlparser::ast::{
+    Delete, Expr, FromTable, ObjectName, Query, SetExpr, Statement, TableFactor, TableWithJoins,
+    Visit, Visitor,
+};
+use std::fmt::Write;
+use std::ops::ControlFlow;
+
+const AGGREGATES: [&str; 12] = [
+    "count",
+    "sum",
+    "avg",
+    "min",
+    "max",
+    "jsonb_agg",
+    "json_agg",
+    "array_agg",
+    "jsonb_object_agg",
+    "string_agg",
+    "bool_and",
+    "bool_or",
+];
+
+/// A query or write of a `QueryPlan`, with the subqueries and lateral joins it runs as children.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanNode {
+    /// `select`, `insert`, `update` or `delete`
+    pub kind: String,
+    /// The alias of a derived table or lateral join
+    pub alias: Option<String>,
+    pub lateral: bool,
+    /// The tables read or written directly, including plain joins
+    pub tables: Vec<String>,
+    /// The `WHERE` condition as SQL
+    pub filter: Option<String>,
+    /// The aggregate functions computed, e.g. `count` or `jsonb_agg`
+    pub aggregates: Vec<String>,
+    pub limit: Option<String>,
+    pub children: Vec<PlanNode>,
+}
+
+impl PlanNode {
+    fn new(kind: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            ..Self::default()
+        }
+    }
+
+    // wrappers that only reshape their children's rows, like the `root` object subqueries
+    fn is_passthrough(&self) -> bool {
+        self.kind == "select"
+            && !self.lateral
+            && self.tables.is_empty()
+            && self.filter.is_none()
+            && self.aggregates.is_empty()
+            && self.limit.is_none()
+    }
+
+    fn add_tables(&mut self, tables: &[TableWithJoins]) {
+        let relations = tables.iter().flat_map(|table| {
+            std::iter::once(&table.relation).chain(table.joins.iter().map(|join| &join.relation))
+        });
+        for relation in relations {
+            if let TableFactor::Table { name, .. } = relation {
+                self.tables.push(get_name(name));
+            }
+        }
+    }
+}
+
+fn get_name(name: &ObjectName) -> String {
+    name.0
+        .iter()
+        .map(|ident| ident.value.as_str())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[derive(Default)]
+struct Explain {
+    stack: Vec<PlanNode>,
+    // the alias of the derived table whose subquery is visited next
+    derived: Option<(String, bool)>,
+}
+
+impl Explain {
+    fn push(&mut self, mut node: PlanNode) {
+        if let Some((alias, lateral)) = self.derived.take() {
+            node.alias = Some(alias);
+            node.lateral = lateral;
+        }
+        self.stack.push(node);
+    }
+
+    fn pop(&mut self) {
+        let Some(node) = self.stack.pop() else {
+            return;
+        };
+        let Some(parent) = self.stack.last_mut() else {
+            self.stack.push(node);
+            return;
+        };
+        if node.is_passthrough() {
+            parent.children.extend(node.children);
+        } else {
+            parent.children.push(node);
+        }
+    }
+}
+
+impl Visitor for Explain {
+    type Break = ();
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<()> {
+        if let TableFactor::Derived {
+            lateral,
+            alias: Some(alias),
+            ..
+        } = table_factor
+        {
+            self.derived = Some((alias.name.value.clone(), *lateral));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        let mut node = PlanNode::new("select");
+        if let SetExpr::Select(select) = query.body.as_ref() {
+            node.add_tables(&select.from);
+            node.filter = select.selection.as_ref().map(ToString::to_string);
+        }
+        node.limit = query.limit.as_ref().map(ToString::to_string);
+        self.push(node);
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.pop();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<()> {
+        let node = match statement {
+            Statement::Insert(insert) => {
+                let mut node = PlanNode::new("insert");
+                node.tables.push(get_name(&insert.table_name));
+                node
+            }
+            Statement::Update {
+                table, selection, ..
+            } => {
+                let mut node = PlanNode::new("update");
+                node.add_tables(std::slice::from_ref(table));
+                node.filter = selection.as_ref().map(ToString::to_string);
+                node
+            }
+            Statement::Delete(Delete {
+                from: FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from),
+                selection,
+                ..
+            }) => {
+                let mut node = PlanNode::new("delete");
+                node.add_tables(from);
+                node.filter = selection.as_ref().map(ToString::to_string);
+                node
+            }
+            _ => return ControlFlow::Continue(()),
+        };
+        self.push(node);
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_statement(&mut self, statement: &Statement) -> ControlFlow<()> {
+        if matches!(
+            statement,
+            Statement::Insert(_) | Statement::Update { .. } | Statement::Delete(_)
+        ) {
+            self.pop();
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        if let (Expr::Function(function), Some(node)) = (expr, self.stack.last_mut()) {
+            let name = get_name(&function.name).to_lowercase();
+            if AGGREGATES.contains(&name.as_str()) && !node.aggregates.contains(&name) {
+                node.aggregates.push(name);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+// a double quoted dot string, where newlines are `\n`
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_dot(node: &PlanNode, id: &mut usize, dot: &mut String) -> usize {
+    let this = *id;
+    *id += 1;
+    let mut label = node.kind.clone();
+    if let Some(alias) = &node.alias {
+        let _ = write!(label, " {alias}");
+    }
+    if node.lateral {
+        label.push_str(" (lateral)");
+    }
+    if !node.tables.is_empty() {
+        let _ = write!(label, "\ntables: {}", node.tables.join(", "));
+    }
+    if let Some(filter) = &node.filter {
+        let _ = write!(label, "\nwhere: {filter}");
+    }
+    if !node.aggregates.is_empty() {
+        let _ = write!(label, "\naggregates: {}", node.aggregates.join(", "));
+    }
+    if let Some(limit) = &node.limit {
+        let _ = write!(label, "\nlimit: {limit}");
+    }
+    let _ = writeln!(dot, "  n{this} [label=\"{}\"];", escape(&label));
+    for child in &node.children {
+        let child = write_dot(child, id, dot);
+        let _ = writeln!(dot, "  n{this} -> n{child};");
+    }
+    this
+}
+
+impl QueryPlan {
+    /// The queries and writes of the plan as a tree, serializable as JSON.
+    #[must_use]
+    pub fn explain(&self) -> PlanNode {
+        let mut explain = Explain::default();
+        let _ = Visit::visit(self.get_statement().as_ref(), &mut explain);
+        explain
+            .stack
+            .pop()
+            .unwrap_or_else(|| PlanNode::new("select"))
+    }
+
+    /// `explain` as a Graphviz digraph.
+    #[must_use]
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph plan {\n  node [shape=box];\n");
+        write_dot(&self.explain(), &mut 0, &mut dot);
+        dot.push_str("}\n");
+        dot
+    }
+}
@@ -0,0 +1,436 @@
+use crate::consts::{JSONB_BUILD_OBJECT, QUOTE_CHAR, ROOT_LABEL, TO_JSONB};
+use sqlparser::ast::{
+    visit_expressions_mut, BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr,
+    FunctionArgumentList, FunctionArguments, GroupByExpr, Ident, Join, JoinConstraint,
+    JoinOperator, ObjectName, Query, Select, SelectItem, SetExpr, TableAlias, TableFactor,
+    TableWithJoins, Value, Visit, VisitMut, Visitor, VisitorMut,
+};
+use std::collections::HashSet;
+use std::ops::ControlFlow;
+
+// Postgres functions take at most 100 arguments
+const MAX_OBJECT_KEYS: usize = 50;
+
+fn get_select(query: &Query) -> Option<&Select> {
+    match query.body.as_ref() {
+        SetExpr::Select(select) => Some(select),
+        _ => None,
+    }
+}
+
+fn is_plain_query(query: &Query) -> bool {
+    query.with.is_none()
+        && query.order_by.is_empty()
+        && query.offset.is_none()
+        && query.fetch.is_none()
+        && query.locks.is_empty()
+}
+
+fn is_plain_select(select: &Select) -> bool {
+    select.distinct.is_none()
+        && select.having.is_none()
+        && matches!(&select.group_by, GroupByExpr::Expressions(e) if e.is_empty())
+}
+
+fn is_true(expr: &Expr) -> bool {
+    match expr {
+        Expr::Nested(expr) => is_true(expr),
+        Expr::Value(Value::SingleQuotedString(s)) => s == "true",
+        Expr::Value(Value::Boolean(b)) => *b,
+        _ => false,
+    }
+}
+
+// `to_jsonb((SELECT "root" FROM (SELECT ..) AS "root"))`: the selected items
+fn get_object_items(expr: &Expr) -> Option<&[SelectItem]> {
+    let Expr::Function(function) = expr else {
+        return None;
+    };
+    if !matches!(function.name.0.as_slice(), [name] if name.value == TO_JSONB) {
+        return None;
+    }
+    let FunctionArguments::List(list) = &function.args else {
+        return None;
+    };
+    let [FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Subquery(query)))] = list.args.as_slice()
+    else {
+        return None;
+    };
+    let select = get_select(query)?;
+    let [TableWithJoins {
+        relation:
+            TableFactor::Derived {
+                subquery,
+                alias: Some(alias),
+                ..
+            },
+        joins,
+    }] = select.from.as_slice()
+    else {
+        return None;
+    };
+    if !joins.is_empty() || alias.name.value != ROOT_LABEL {
+        return None;
+    }
+    let items = get_select(subquery)?;
+    items.from.is_empty().then_some(items.projection.as_slice())
+}
+
+fn get_object(items: &[SelectItem]) -> Option<Vec<FunctionArg>> {
+    if items.len() > MAX_OBJECT_KEYS {
+        return None;
+    }
+    let mut args = vec![];
+    for item in items {
+        let (key, expr) = match item {
+            SelectItem::ExprWithAlias { expr, alias } => (alias.value.clone(), expr),
+            SelectItem::UnnamedExpr(expr @ Expr::Identifier(ident)) => (ident.value.clone(), expr),
+            SelectItem::UnnamedExpr(expr @ Expr::CompoundIdentifier(idents)) => {
+                (idents.last()?.value.clone(), expr)
+            }
+            _ => return None,
+        };
+        args.push(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+            Value::SingleQuotedString(key),
+        ))));
+        args.push(FunctionArg::Unnamed(FunctionArgExpr::Expr(expr.clone())));
+    }
+    Some(args)
+}
+
+// the condition may only name columns by their table, and may not hold subqueries whose own
+// tables could share that name
+struct Qualified;
+
+impl Visitor for Qualified {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        ControlFlow::Break(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::Identifier(_) | Expr::Value(Value::DoubleQuotedString(_)) => {
+                ControlFlow::Break(())
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+}
+
+// a column of the joined table the condition compares for equality, null when nothing matched
+fn get_match_column(condition: &Expr, alias: &Ident) -> Option<Expr> {
+    match condition {
+        Expr::Nested(expr) => get_match_column(expr, alias),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => get_match_column(left, alias).or_else(|| get_match_column(right, alias)),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } => [left, right].into_iter().find_map(|side| match side.as_ref() {
+            Expr::CompoundIdentifier(idents)
+                if matches!(idents.as_slice(), [table, _] if table.value == alias.value) =>
+            {
+                Some(side.as_ref().clone())
+            }
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// A single relation compiled to a plain join: the joins that replace the lateral one, and the
+/// name and value of the column the lateral join produced.
+struct Flattened {
+    joins: Vec<Join>,
+    column: String,
+    value: Expr,
+}
+
+// `LEFT JOIN LATERAL (SELECT to_jsonb(..) AS "join" FROM (SELECT * FROM t WHERE .. LIMIT 1) AS
+// "base.t" ..) ON true` becomes `LEFT JOIN t AS "base.t" ON ..`
+fn flatten_join(join: &Join) -> Option<Flattened> {
+    let JoinOperator::LeftOuter(JoinConstraint::On(on)) = &join.join_operator else {
+        return None;
+    };
+    let TableFactor::Derived {
+        lateral: true,
+        subquery,
+        ..
+    } = &join.relation
+    else {
+        return None;
+    };
+    if !is_true(on)
+        || !is_plain_query(subquery)
+        || subquery.limit.is_some()
+        || has_bare_columns(subquery)
+    {
+        return None;
+    }
+    let select = get_select(subquery)?;
+    let [SelectItem::ExprWithAlias {
+        expr,
+        alias: column,
+    }] = select.projection.as_slice()
+    else {
+        return None;
+    };
+    let object = get_object(get_object_items(expr)?)?;
+    let [TableWithJoins {
+        relation:
+            TableFactor::Derived {
+                lateral: false,
+                subquery: rows,
+                alias: Some(alias),
+            },
+        joins,
+    }] = select.from.as_slice()
+    else {
+        return None;
+    };
+    if !is_plain_select(select) || select.selection.is_some() || !alias.columns.is_empty() {
+        return None;
+    }
+    let rows_select = get_select(rows)?;
+    let is_single_row = matches!(&rows.limit, Some(Expr::Value(Value::Number(n, _))) if n == "1");
+    if !is_single_row
+        || !is_plain_query(rows)
+        || !is_plain_select(rows_select)
+        || !matches!(rows_select.projection.as_slice(), [SelectItem::Wildcard(_)])
+    {
+        return None;
+    }
+    let [TableWithJoins {
+        relation:
+            TableFactor::Table {
+                name,
+                alias: None,
+                args: None,
+                with_hints,
+                ..
+            },
+        joins: table_joins,
+    }] = rows_select.from.as_slice()
+    else {
+        return None;
+    };
+    if !table_joins.is_empty() {
+        return None;
+    }
+    let mut condition = rows_select.selection.clone()?;
+    if Visit::visit(&condition, &mut Qualified).is_break() {
+        return None;
+    }
+    // columns were qualified by the table, which now goes by the alias of the rows
+    let _ = visit_expressions_mut(&mut condition, |expr| {
+        if let Expr::CompoundIdentifier(idents) = expr {
+            if idents.len() == name.0.len() + 1 && idents.starts_with(&name.0) {
+                *idents = vec![alias.name.clone(), idents[idents.len() - 1].clone()];
+            }
+        }
+        ControlFlow::<()>::Continue(())
+    });
+    let matched = get_match_column(&condition, &alias.name)?;
+    let value = Expr::Case {
+        operand: None,
+        conditions: vec![Expr::IsNotNull(Box::new(matched))],
+        results: vec![Expr::Function(Function {
+            within_group: vec![],
+            name: ObjectName(vec![Ident::new(JSONB_BUILD_OBJECT)]),
+            args: FunctionArguments::List(FunctionArgumentList {
+                duplicate_treatment: None,
+                clauses: vec![],
+                args: object,
+            }),
+            over: None,
+            filter: None,
+            null_treatment: None,
+        })],
+        else_result: Some(Box::new(Expr::Value(Value::Null))),
+    };
+    let mut flat = vec![Join {
+        relation: TableFactor::Table {
+            name: name.clone(),
+            alias: Some(TableAlias {
+                name: alias.name.clone(),
+                columns: vec![],
+            }),
+            args: None,
+            // the sample of the rows, if any, now samples the joined table
+            with_hints: with_hints.clone(),
+            version: None,
+            partitions: vec![],
+        },
+        join_operator: JoinOperator::LeftOuter(JoinConstraint::On(condition)),
+    }];
+    // joins of the child, already flattened where possible, now hang off the parent
+    flat.extend(joins.iter().cloned());
+    Some(Flattened {
+        joins: flat,
+        column: column.value.clone(),
+        value,
+    })
+}
+
+struct Frame {
+    has_from: bool,
+    // a derived table in FROM cannot see the other tables of that FROM
+    is_derived: bool,
+}
+
+// the bare names that resolve to the relations of the outermost query visited
+#[derive(Default)]
+struct Unqualified {
+    frames: Vec<Frame>,
+    next_is_derived: bool,
+    names: HashSet<String>,
+}
+
+impl Unqualified {
+    fn resolves_to_root(&self) -> bool {
+        let Some(mut index) = self.frames.len().checked_sub(1) else {
+            return false;
+        };
+        loop {
+            if self.frames[index].has_from {
+                return index == 0;
+            }
+            while self.frames[index].is_derived {
+                let Some(parent) = index.checked_sub(1) else {
+                    return false;
+                };
+                index = parent;
+            }
+            let Some(parent) = index.checked_sub(1) else {
+                return false;
+            };
+            index = parent;
+        }
+    }
+
+    fn name(&mut self, name: &str) {
+        if self.resolves_to_root() {
+            self.names.insert(name.to_string());
+        }
+    }
+}
+
+impl Visitor for Unqualified {
+    type Break = ();
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<()> {
+        if let TableFactor::Derived { lateral: false, .. } = table_factor {
+            self.next_is_derived = true;
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        self.frames.push(Frame {
+            has_from: !get_select(query).is_some_and(|select| select.from.is_empty()),
+            is_derived: std::mem::take(&mut self.next_is_derived),
+        });
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.frames.pop();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::Identifier(ident) => self.name(&ident.value),
+            Expr::Value(Value::DoubleQuotedString(name)) => self.name(name),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+// a joined table brings its columns into scope, so every bare name the query reads must be the
+// output of one of its lateral joins rather than a column that could become ambiguous
+fn has_bare_columns(query: &Query) -> bool {
+    let Some(select) = get_select(query) else {
+        return true;
+    };
+    let mut unqualified = Unqualified::default();
+    let _ = query.visit(&mut unqualified);
+    let outputs = select
+        .from
+        .iter()
+        .flat_map(|table| table.joins.iter())
+        .filter_map(|join| match &join.relation {
+            TableFactor::Derived { subquery, .. } => get_select(subquery),
+            _ => None,
+        })
+        .flat_map(|select| select.projection.iter())
+        .filter_map(|item| match item {
+            SelectItem::ExprWithAlias { alias, .. } => Some(alias.value.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    unqualified
+        .names
+        .iter()
+        .any(|name| !outputs.contains(name.as_str()))
+}
+
+struct Flatten;
+
+impl VisitorMut for Flatten {
+    type Break = ();
+
+    // children first, so a single relation under another one is already a plain join when the
+    // outer one is flattened
+    fn post_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        if has_bare_columns(query) {
+            return ControlFlow::Continue(());
+        }
+        let SetExpr::Select(select) = query.body.as_mut() else {
+            return ControlFlow::Continue(());
+        };
+        let mut values = vec![];
+        for table in &mut select.from {
+            let mut joins = Vec::with_capacity(table.joins.len());
+            for join in std::mem::take(&mut table.joins) {
+                match flatten_join(&join) {
+                    Some(flattened) => {
+                        joins.extend(flattened.joins);
+                        values.push((flattened.column, flattened.value));
+                    }
+                    None => joins.push(join),
+                }
+            }
+            table.joins = joins;
+        }
+        if !values.is_empty() {
+            let _ = visit_expressions_mut(&mut select.projection, |expr| {
+                if let Expr::Identifier(ident) = expr {
+                    if ident.quote_style == Some(QUOTE_CHAR) {
+                        if let Some((_, value)) =
+                            values.iter().find(|(column, _)| *column == ident.value)
+                        {
+                            *expr = value.clone();
+                        }
+                    }
+                }
+                ControlFlow::<()>::Continue(())
+            });
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Compiles single relations without ordering or paging to plain `LEFT JOIN`s with a
+/// `jsonb_build_object` projection instead of a lateral subquery per row. The related row must
+/// be unique, as the join no longer stops at the first match.
+pub(crate) fn flatten_single_joins<T: VisitMut>(node: &mut T) {
+    let _ = VisitMut::visit(node, &mut Flatten);
+}
@@ -0,0 +1,367 @@
+use crate::consts::QUOTE_CHAR;
+use serde::Deserialize;
+use sqlparser::ast::{
+    ConflictTarget, DataType, Expr, Ident, ObjectName, OnConflict, OnConflictAction, OnInsert,
+    Query, SelectItem, SetExpr, Statement, TableFactor, Visit, VisitMut, Visitor, VisitorMut,
+};
+use sqlparser::keywords::ALL_KEYWORDS;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+
+/// How table and column identifiers are written in the generated SQL.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IdentifierStrategy {
+    /// Always quote, preserving case (the default).
+    #[default]
+    QuoteAlways,
+    /// Leave identifiers unquoted so the database folds them, quoting only when required.
+    QuoteNever,
+    /// Lowercase identifiers and keep them quoted.
+    Lowercase,
+}
+
+impl IdentifierStrategy {
+    fn apply(self, ident: &Ident) -> Ident {
+        match self {
+            Self::QuoteAlways => ident.clone(),
+            Self::Lowercase => Ident::with_quote(QUOTE_CHAR, ident.value.to_lowercase()),
+            Self::QuoteNever => {
+                let mut chars = ident.value.chars();
+                let is_simple = chars
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+                let is_keyword = ALL_KEYWORDS
+                    .binary_search(&ident.value.to_uppercase().as_str())
+                    .is_ok();
+                if is_simple && !is_keyword {
+                    Ident::new(ident.value.clone())
+                } else {
+                    // what the name would have become had it been created unquoted
+                    Ident::with_quote(QUOTE_CHAR, ident.value.to_lowercase())
+                }
+            }
+        }
+    }
+
+    // the name Postgres reports for a column, which becomes the JSON key of the row
+    fn changes_output_name(self, ident: &Ident) -> bool {
+        let normalized = self.apply(ident);
+        normalized.value != ident.value
+            || (normalized.quote_style.is_none() && ident.value != ident.value.to_lowercase())
+    }
+}
+
+// Table, CTE and select aliases are generated by the translator and keep their spelling
+#[derive(Default)]
+struct Aliases {
+    tables: HashSet<String>,
+    columns: HashSet<String>,
+}
+
+impl Aliases {
+    fn collect<T: Visit>(node: &T) -> Self {
+        let mut aliases = Self::default();
+        let _ = Visit::visit(
+            node,
+            &mut EachAlias(|alias: &str, is_table| {
+                if is_table {
+                    aliases.tables.insert(alias.to_string());
+                } else {
+                    aliases.columns.insert(alias.to_string());
+                }
+            }),
+        );
+        aliases
+    }
+}
+
+// calls back with every table, CTE and select alias, and whether it names a table
+struct EachAlias<F>(F);
+
+impl<F: FnMut(&str, bool)> Visitor for EachAlias<F> {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let Some(with) = &query.with {
+            for cte in &with.cte_tables {
+                (self.0)(&cte.alias.name.value, true);
+            }
+        }
+        if let SetExpr::Select(select) = query.body.as_ref() {
+            for item in &select.projection {
+                if let SelectItem::ExprWithAlias { alias, .. } = item {
+                    (self.0)(&alias.value, false);
+                }
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<()> {
+        let alias = match table_factor {
+            TableFactor::Table { alias, .. } | TableFactor::Derived { alias, .. } => alias,
+            _ => &None,
+        };
+        if let Some(alias) = alias {
+            (self.0)(&alias.name.value, true);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+struct Normalizer {
+    strategy: IdentifierStrategy,
+    aliases: Aliases,
+}
+
+impl Normalizer {
+    // unquoted idents are SQL words such as EXCLUDED rather than names from the schema
+    fn ident(&self, ident: &mut Ident) {
+        if ident.quote_style == Some(QUOTE_CHAR) {
+            *ident = self.strategy.apply(ident);
+        }
+    }
+
+    fn idents<'a>(&self, idents: impl IntoIterator<Item = &'a mut Ident>) {
+        for ident in idents {
+            self.ident(ident);
+        }
+    }
+
+    fn object_name(&self, name: &mut ObjectName) {
+        self.idents(name.0.iter_mut());
+    }
+
+    // only the first part of a compound identifier can name a table alias
+    fn compound(&self, idents: &mut [Ident]) {
+        let skip = idents
+            .first()
+            .is_some_and(|ident| self.aliases.tables.contains(&ident.value));
+        self.idents(idents.iter_mut().skip(usize::from(skip)));
+    }
+
+    // a bare identifier can also be a column of a derived table
+    fn is_alias(&self, ident: &Ident) -> bool {
+        self.aliases.tables.contains(&ident.value) || self.aliases.columns.contains(&ident.value)
+    }
+}
+
+impl VisitorMut for Normalizer {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        let SetExpr::Select(select) = query.body.as_mut() else {
+            return ControlFlow::Continue(());
+        };
+        for item in &mut select.projection {
+            let SelectItem::UnnamedExpr(expr) = item else {
+                continue;
+            };
+            let column = match expr {
+                Expr::Identifier(ident) if !self.is_alias(ident) => ident,
+                Expr::CompoundIdentifier(idents) => match idents.last() {
+                    Some(ident) => ident,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            if column.quote_style == Some(QUOTE_CHAR) && self.strategy.changes_output_name(column) {
+                *item = SelectItem::ExprWithAlias {
+                    alias: column.clone(),
+                    expr: expr.clone(),
+                };
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &mut ObjectName) -> ControlFlow<()> {
+        // CTE references
+        if let [name] = relation.0.as_slice() {
+            if self.aliases.tables.contains(&name.value) {
+                return ControlFlow::Continue(());
+            }
+        }
+        self.object_name(relation);
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::Identifier(ident) if !self.is_alias(ident) => self.ident(ident),
+            Expr::CompoundIdentifier(idents) => self.compound(idents),
+            Expr::Cast {
+                data_type: DataType::Custom(name, _),
+                ..
+            } => self.object_name(name),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, statement: &mut Statement) -> ControlFlow<()> {
+        match statement {
+            Statement::Insert(insert) => {
+                self.idents(insert.columns.iter_mut());
+                if let Some(OnInsert::OnConflict(OnConflict {
+                    conflict_target,
+                    action,
+                })) = &mut insert.on
+                {
+                    if let Some(ConflictTarget::Columns(columns)) = conflict_target {
+                        self.idents(columns.iter_mut());
+                    }
+                    if let OnConflictAction::DoUpdate(update) = action {
+                        self.idents(update.assignments.iter_mut().flat_map(|a| a.id.iter_mut()));
+                    }
+                }
+            }
+            Statement::Update { assignments, .. } => {
+                self.idents(assignments.iter_mut().flat_map(|a| a.id.iter_mut()));
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Rewrites the table and column identifiers of a generated statement or expression.
+pub(crate) fn normalize_identifiers<T: Visit + VisitMut>(
+    node: &mut T,
+    strategy: IdentifierStrategy,
+) {
+    if strategy == IdentifierStrategy::QuoteAlways {
+        return;
+    }
+    let aliases = Aliases::collect(&*node);
+    let _ = VisitMut::visit(node, &mut Normalizer { strategy, aliases });
+}
+
+// longer identifiers are truncated by Postgres, so generated aliases sharing a prefix would clash
+const MAX_IDENTIFIER_BYTES: usize = 63;
+
+// FNV-1a, which unlike the std hasher is stable across releases
+pub(crate) fn fnv1a(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn is_long(alias: &str) -> bool {
+    alias.len() > MAX_IDENTIFIER_BYTES && alias.contains('.')
+}
+
+// the start of the alias, cut at a char boundary, followed by a hash of all of it. `taken` holds
+// the hashes of the aliases in use, so a rare false match only numbers the alias
+fn shorten(value: &str, taken: &HashSet<u64>) -> String {
+    let hash = format!("{:016x}", fnv1a(value));
+    let mut attempt = 0;
+    loop {
+        let suffix = if attempt == 0 {
+            hash.clone()
+        } else {
+            format!("{hash}.{attempt}")
+        };
+        let mut end = MAX_IDENTIFIER_BYTES - suffix.len() - 1;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        let short = format!("{}.{suffix}", &value[..end]);
+        if !taken.contains(&fnv1a(&short)) {
+            return short;
+        }
+        attempt += 1;
+    }
+}
+
+struct Shortener {
+    renames: HashMap<String, String>,
+}
+
+impl Shortener {
+    fn idents<'a>(&self, idents: impl IntoIterator<Item = &'a mut Ident>) {
+        for ident in idents {
+            if !is_long(&ident.value) {
+                continue;
+            }
+            if let Some(short) = self.renames.get(&ident.value) {
+                ident.value.clone_from(short);
+            }
+        }
+    }
+}
+impl VisitorMut for Shortener {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        if let Some(with) = &mut query.with {
+            self.idents(with.cte_tables.iter_mut().map(|cte| &mut cte.alias.name));
+        }
+        if let SetExpr::Select(select) = query.body.as_mut() {
+            self.idents(select.projection.iter_mut().filter_map(|item| match item {
+                SelectItem::ExprWithAlias { alias, .. } => Some(alias),
+                _ => None,
+            }));
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(&mut self, table_factor: &mut TableFactor) -> ControlFlow<()> {
+        if let TableFactor::Table {
+            alias: Some(alias), ..
+        }
+        | TableFactor::Derived {
+            alias: Some(alias), ..
+        } = table_factor
+        {
+            self.idents([&mut alias.name]);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &mut ObjectName) -> ControlFlow<()> {
+        self.idents(relation.0.iter_mut());
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::Identifier(ident) => self.idents([ident]),
+            Expr::CompoundIdentifier(idents) => self.idents(idents.iter_mut()),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Shortens the join and path aliases of a generated statement that Postgres would truncate,
+/// keeping them unique. These contain a dot, which GraphQL names, and so response keys, cannot.
+pub(crate) fn shorten_aliases<T: Visit + VisitMut>(node: &mut T) {
+    let mut long = HashSet::new();
+    let mut taken = HashSet::new();
+    let _ = Visit::visit(
+        &*node,
+        &mut EachAlias(|alias: &str, _| {
+            if is_long(alias) {
+                long.insert(alias.to_string());
+            } else {
+                taken.insert(fnv1a(alias));
+            }
+        }),
+    );
+    if long.is_empty() {
+        return;
+    }
+    // sorted so that the rare colliding hash is numbered the same way every time
+    let mut long = long.into_iter().collect::<Vec<_>>();
+    long.sort_unstable();
+    let mut renames = HashMap::with_capacity(long.len());
+    for alias in long {
+        let short = shorten(&alias, &taken);
+        taken.insert(fnv1a(&short));
+        renames.insert(alias, short);
+    }
+    let _ = VisitMut::visit(node, &mut Shortener { renames });
+}
@@ -0,0 +1,93 @@
+use crate::{consts::ID, get_string_or_variable};
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{types::Directive, Positioned};
+use async_graphql_value::Name;
+use indexmap::IndexMap;
+
+type JsonValue = serde_json::Value;
+
+const NANOID_ALPHABET: &[u8; 64] =
+    b"_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const NANOID_LENGTH: usize = 21;
+
+fn fill_random(bytes: &mut [u8]) -> AnyResult<()> {
+    getrandom::getrandom(bytes).map_err(|e| anyhow!("could not generate an id: {e}"))
+}
+
+/// How `@id(generate:)` fills in the ids of inserted rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum IdGenerator {
+    Uuid,
+    Nanoid,
+}
+
+impl IdGenerator {
+    /// A new random id.
+    pub(crate) fn generate(self) -> AnyResult<String> {
+        match self {
+            Self::Uuid => {
+                let mut bytes = [0u8; 16];
+                fill_random(&mut bytes)?;
+                // version 4, RFC 4122 variant
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                let hex = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+                Ok(format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                ))
+            }
+            Self::Nanoid => {
+                let mut bytes = [0u8; NANOID_LENGTH];
+                fill_random(&mut bytes)?;
+                // 64 symbols, so masking a byte keeps the distribution uniform
+                Ok(bytes
+                    .iter()
+                    .map(|b| char::from(NANOID_ALPHABET[usize::from(b & 63)]))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// `@id(generate: "uuid" | "nanoid", column: "id")` on an insert: the column and how to fill it
+/// in for rows that do not set it.
+pub(crate) fn get_id_generator(
+    directives: &[Positioned<Directive>],
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<Option<(String, IdGenerator)>> {
+    let Some(directive) = directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == "id")
+    else {
+        return Ok(None);
+    };
+    let mut column = ID.to_string();
+    let mut generator = None;
+    for (name, value) in &directive.node.arguments {
+        match name.node.as_str() {
+            "generate" => {
+                let value = get_string_or_variable(&value.node, sql_vars)?;
+                generator = Some(match value.as_str() {
+                    "uuid" => IdGenerator::Uuid,
+                    "nanoid" => IdGenerator::Nanoid,
+                    _ => {
+                        return Err(anyhow!(
+                            "@id argument \"generate\" must be \"uuid\" or \"nanoid\" at {}",
+                            directive.pos
+                        ))
+                    }
+                });
+            }
+            "column" => column = get_string_or_variable(&value.node, sql_vars)?,
+            _ => {}
+        }
+    }
+    let generator = generator
+        .ok_or_else(|| anyhow!("@id is missing argument \"generate\" at {}", directive.pos))?;
+    Ok(Some((column, generator)))
+}
@@ -0,0 +1,461 @@
+use crate::consts::QUERY_REFERENCE;
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{
+    Distinct, Expr, GroupByExpr, Join, JoinConstraint, JoinOperator, LockClause, ObjectName,
+    Offset, OrderByExpr, Query, Select, SelectItem, SetExpr, Statement, Table, TableAlias,
+    TableFactor, TableWithJoins, Values, Visit, VisitMut, Visitor, VisitorMut, With,
+};
+use std::ops::ControlFlow;
+
+/// A `SELECT` of a `QueryPlan`, by the clauses tools read and rewrite. The queries it runs
+/// inside it are other entries of `QueryPlan::queries`: `PlanRelation::Query` in `tables`, and
+/// `TABLE #<index>` where an expression or CTE runs one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanQuery {
+    pub with: Option<With>,
+    pub distinct: Option<Distinct>,
+    pub projection: Vec<SelectItem>,
+    /// The `FROM` tables, each with the tables joined to it
+    pub tables: Vec<PlanTable>,
+    /// The `WHERE` condition
+    pub filter: Option<Expr>,
+    pub group_by: GroupByExpr,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderByExpr>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Offset>,
+    pub locks: Vec<LockClause>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanTable {
+    pub relation: PlanRelation,
+    pub joins: Vec<PlanJoin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanJoin {
+    pub relation: PlanRelation,
+    pub kind: JoinKind,
+    /// The join condition, `None` for cross joins
+    pub on: Option<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+    Cross,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PlanRelation {
+    Table {
+        name: ObjectName,
+        alias: Option<TableAlias>,
+        /// The table sample, as `get_sample` builds it
+        hints: Vec<Expr>,
+    },
+    /// A subquery, by its index in `QueryPlan::queries`
+    Query {
+        query: usize,
+        alias: Option<TableAlias>,
+        lateral: bool,
+    },
+    /// Table functions, and subqueries that are not split out
+    Other(Box<TableFactor>),
+}
+
+// the query a subquery was split out as, `TABLE #<index>`
+fn get_reference(index: usize) -> Query {
+    Query {
+        with: None,
+        body: Box::new(SetExpr::Table(Box::new(Table {
+            table_name: Some(format!("{QUERY_REFERENCE}{index}")),
+            schema_name: None,
+        }))),
+        order_by: vec![],
+        limit: None,
+        limit_by: vec![],
+        offset: None,
+        fetch: None,
+        locks: vec![],
+        for_clause: None,
+    }
+}
+
+fn get_index(query: &Query) -> Option<usize> {
+    match query.body.as_ref() {
+        SetExpr::Table(table) if table.schema_name.is_none() => table
+            .table_name
+            .as_deref()?
+            .strip_prefix(QUERY_REFERENCE)?
+            .parse()
+            .ok(),
+        _ => None,
+    }
+}
+
+fn is_modelled(join: &Join) -> bool {
+    matches!(
+        join.join_operator,
+        JoinOperator::Inner(JoinConstraint::On(_))
+            | JoinOperator::LeftOuter(JoinConstraint::On(_))
+            | JoinOperator::RightOuter(JoinConstraint::On(_))
+            | JoinOperator::FullOuter(JoinConstraint::On(_))
+            | JoinOperator::CrossJoin
+    )
+}
+
+// the translator only builds selects like these
+fn is_split(query: &Query) -> bool {
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return false;
+    };
+    query.limit_by.is_empty()
+        && query.fetch.is_none()
+        && query.for_clause.is_none()
+        && select.top.is_none()
+        && select.into.is_none()
+        && select.lateral_views.is_empty()
+        && select.cluster_by.is_empty()
+        && select.distribute_by.is_empty()
+        && select.sort_by.is_empty()
+        && select.named_window.is_empty()
+        && select.qualify.is_none()
+        && !select.window_before_qualify
+        && select.value_table_mode.is_none()
+        && select.connect_by.is_none()
+        && select
+            .from
+            .iter()
+            .flat_map(|table| &table.joins)
+            .all(is_modelled)
+}
+
+fn get_relation(factor: TableFactor) -> PlanRelation {
+    match factor {
+        TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            with_hints,
+            version: None,
+            partitions,
+        } if partitions.is_empty() => PlanRelation::Table {
+            name,
+            alias,
+            hints: with_hints,
+        },
+        TableFactor::Derived {
+            lateral,
+            subquery,
+            alias,
+        } => match get_index(&subquery) {
+            Some(query) => PlanRelation::Query {
+                query,
+                alias,
+                lateral,
+            },
+            None => PlanRelation::Other(Box::new(TableFactor::Derived {
+                lateral,
+                subquery,
+                alias,
+            })),
+        },
+        factor => PlanRelation::Other(Box::new(factor)),
+    }
+}
+
+fn get_factor(relation: PlanRelation) -> TableFactor {
+    match relation {
+        PlanRelation::Table { name, alias, hints } => TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            with_hints: hints,
+            version: None,
+            partitions: vec![],
+        },
+        PlanRelation::Query {
+            query,
+            alias,
+            lateral,
+        } => TableFactor::Derived {
+            lateral,
+            subquery: Box::new(get_reference(query)),
+            alias,
+        },
+        PlanRelation::Other(factor) => *factor,
+    }
+}
+
+/// Splits out a select whose clauses all have a place in `PlanQuery`, giving back others.
+impl TryFrom<Query> for PlanQuery {
+    type Error = Query;
+
+    fn try_from(mut query: Query) -> Result<Self, Query> {
+        if !is_split(&query) {
+            return Err(query);
+        }
+        let body = std::mem::replace(
+            query.body.as_mut(),
+            SetExpr::Values(Values {
+                explicit_row: false,
+                rows: vec![],
+            }),
+        );
+        let SetExpr::Select(select) = body else {
+            *query.body = body;
+            return Err(query);
+        };
+        let tables = select
+            .from
+            .into_iter()
+            .map(|table| PlanTable {
+                relation: get_relation(table.relation),
+                joins: table
+                    .joins
+                    .into_iter()
+                    .map(|join| {
+                        let (kind, on) = match join.join_operator {
+                            JoinOperator::Inner(JoinConstraint::On(on)) => {
+                                (JoinKind::Inner, Some(on))
+                            }
+                            JoinOperator::LeftOuter(JoinConstraint::On(on)) => {
+                                (JoinKind::Left, Some(on))
+                            }
+                            JoinOperator::RightOuter(JoinConstraint::On(on)) => {
+                                (JoinKind::Right, Some(on))
+                            }
+                            JoinOperator::FullOuter(JoinConstraint::On(on)) => {
+                                (JoinKind::Full, Some(on))
+                            }
+                            // `is_split` leaves the other joins in the statement
+                            _ => (JoinKind::Cross, None),
+                        };
+                        PlanJoin {
+                            relation: get_relation(join.relation),
+                            kind,
+                            on,
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(Self {
+            with: query.with,
+            distinct: select.distinct,
+            projection: select.projection,
+            tables,
+            filter: select.selection,
+            group_by: select.group_by,
+            having: select.having,
+            order_by: query.order_by,
+            limit: query.limit,
+            offset: query.offset,
+            locks: query.locks,
+        })
+    }
+}
+
+impl From<PlanQuery> for Query {
+    fn from(query: PlanQuery) -> Self {
+        let from = query
+            .tables
+            .into_iter()
+            .map(|table| TableWithJoins {
+                relation: get_factor(table.relation),
+                joins: table
+                    .joins
+                    .into_iter()
+                    .map(|join| Join {
+                        relation: get_factor(join.relation),
+                        join_operator: match (join.kind, join.on) {
+                            (JoinKind::Inner, Some(on)) => {
+                                JoinOperator::Inner(JoinConstraint::On(on))
+                            }
+                            (JoinKind::Left, Some(on)) => {
+                                JoinOperator::LeftOuter(JoinConstraint::On(on))
+                            }
+                            (JoinKind::Right, Some(on)) => {
+                                JoinOperator::RightOuter(JoinConstraint::On(on))
+                            }
+                            (JoinKind::Full, Some(on)) => {
+                                JoinOperator::FullOuter(JoinConstraint::On(on))
+                            }
+                            (JoinKind::Inner, None) => JoinOperator::Inner(JoinConstraint::None),
+                            (JoinKind::Left, None) => JoinOperator::LeftOuter(JoinConstraint::None),
+                            (JoinKind::Right, None) => {
+                                JoinOperator::RightOuter(JoinConstraint::None)
+                            }
+                            (JoinKind::Full, None) => JoinOperator::FullOuter(JoinConstraint::None),
+                            (JoinKind::Cross, _) => JoinOperator::CrossJoin,
+                        },
+                    })
+                    .collect(),
+            })
+            .collect();
+        Self {
+            with: query.with,
+            body: Box::new(SetExpr::Select(Box::new(Select {
+                distinct: query.distinct,
+                top: None,
+                projection: query.projection,
+                into: None,
+                from,
+                lateral_views: vec![],
+                selection: query.filter,
+                group_by: query.group_by,
+                cluster_by: vec![],
+                distribute_by: vec![],
+                sort_by: vec![],
+                having: query.having,
+                named_window: vec![],
+                qualify: None,
+                window_before_qualify: false,
+                value_table_mode: None,
+                connect_by: None,
+            }))),
+            order_by: query.order_by,
+            limit: query.limit,
+            limit_by: vec![],
+            offset: query.offset,
+            fetch: None,
+            locks: query.locks,
+            for_clause: None,
+        }
+    }
+}
+
+struct Split(Vec<PlanQuery>);
+
+impl VisitorMut for Split {
+    type Break = ();
+
+    // after the subqueries, which are split out first
+    fn post_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        let taken = std::mem::replace(query, get_reference(self.0.len()));
+        match PlanQuery::try_from(taken) {
+            Ok(split) => self.0.push(split),
+            Err(taken) => *query = taken,
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Splits the selects out of a statement, leaving references to them.
+pub(crate) fn split(statement: &mut Statement) -> Vec<PlanQuery> {
+    let mut split = Split(vec![]);
+    let _ = VisitMut::visit(statement, &mut split);
+    split.0
+}
+
+#[derive(Default)]
+struct References(Vec<usize>);
+
+impl Visitor for References {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let Some(count) = get_index(query).and_then(|index| self.0.get_mut(index)) {
+            *count += 1;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+struct Joiner {
+    queries: Vec<Option<Query>>,
+    references: Vec<usize>,
+    // the query each visited query was replaced with, and whether it is a copy, to leave cycles
+    // as references
+    stack: Vec<Option<(usize, bool)>>,
+}
+
+impl VisitorMut for Joiner {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        let index = get_index(query).filter(|index| {
+            !self
+                .stack
+                .iter()
+                .flatten()
+                .any(|(joined, _)| joined == index)
+        });
+        // a query referenced once, from a query that is not copied, is only joined once
+        let copied = self.stack.iter().flatten().any(|(_, copied)| *copied)
+            || index.and_then(|index| self.references.get(index)) != Some(&1);
+        let joined = index.and_then(|index| {
+            let joined = self.queries.get_mut(index)?;
+            if copied {
+                joined.clone()
+            } else {
+                joined.take()
+            }
+        });
+        match (index, joined) {
+            (Some(index), Some(joined)) => {
+                *query = joined;
+                self.stack.push(Some((index, copied)));
+            }
+            _ => self.stack.push(None),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &mut Query) -> ControlFlow<()> {
+        self.stack.pop();
+        ControlFlow::Continue(())
+    }
+}
+
+/// Puts the split out selects back in place of their references. References to queries the
+/// plan does not have, or to a query from within itself, are left as they are.
+pub(crate) fn join(statement: &mut Statement, queries: Vec<PlanQuery>) {
+    if queries.is_empty() {
+        return;
+    }
+    let queries = queries.into_iter().map(Query::from).collect::<Vec<_>>();
+    let mut references = References(vec![0; queries.len()]);
+    let _ = Visit::visit(statement, &mut references);
+    for query in &queries {
+        let _ = Visit::visit(query, &mut references);
+    }
+    let mut joiner = Joiner {
+        queries: queries.into_iter().map(Some).collect(),
+        references: references.0,
+        stack: vec![],
+    };
+    let _ = VisitMut::visit(statement, &mut joiner);
+}
+
+#[derive(Default)]
+struct Unjoined(Option<String>);
+
+impl Visitor for Unjoined {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if get_index(query).is_some() {
+            self.0 = Some(query.body.to_string());
+            return ControlFlow::Break(());
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// A reference `join` left in the statement, as `TABLE #<index>`.
+pub(crate) fn find_reference(statement: &Statement) -> Option<String> {
+    let mut unjoined = Unjoined::default();
+    let _ = Visit::visit(statement, &mut unjoined);
+    unjoined.0
+}
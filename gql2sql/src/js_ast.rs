@@ -0,0 +1,300 @@
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{
+    types::{
+        BaseType, Directive, DocumentOperations, ExecutableDocument, Field, FragmentDefinition,
+        FragmentSpread, InlineFragment, OperationDefinition, OperationType, Selection,
+        SelectionSet, Type, TypeCondition, VariableDefinition,
+    },
+    Pos, Positioned,
+};
+use async_graphql_value::{indexmap::IndexMap, ConstValue, Name, Number, Value as GqlValue};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+
+fn get<'a>(node: &'a JsonValue, key: &str) -> AnyResult<&'a JsonValue> {
+    match node.get(key) {
+        Some(JsonValue::Null) | None => Err(anyhow!(
+            "{} node is missing \"{}\"",
+            kind(node).unwrap_or("Unknown"),
+            key
+        )),
+        Some(value) => Ok(value),
+    }
+}
+
+fn get_list<'a>(node: &'a JsonValue, key: &str) -> &'a [JsonValue] {
+    match node.get(key) {
+        Some(JsonValue::Array(list)) => list,
+        _ => &[],
+    }
+}
+
+fn get_str<'a>(node: &'a JsonValue, key: &str) -> AnyResult<&'a str> {
+    get(node, key)?
+        .as_str()
+        .ok_or_else(|| anyhow!("\"{}\" must be a string", key))
+}
+
+fn kind(node: &JsonValue) -> AnyResult<&str> {
+    node.get("kind")
+        .and_then(JsonValue::as_str)
+        .ok_or_else(|| anyhow!("AST node is missing \"kind\""))
+}
+
+// graphql-js only records line/column on tokens, so nodes parsed with `noLocation` get 0:0
+fn get_pos(node: &JsonValue) -> Pos {
+    let token = node.get("loc").and_then(|loc| loc.get("startToken"));
+    let get_usize = |key| {
+        token
+            .and_then(|t| t.get(key))
+            .and_then(JsonValue::as_u64)
+            .map_or(0, |v| v as usize)
+    };
+    Pos {
+        line: get_usize("line"),
+        column: get_usize("column"),
+    }
+}
+
+fn get_optional<'a>(node: &'a JsonValue, key: &str) -> Option<&'a JsonValue> {
+    node.get(key).filter(|v| !v.is_null())
+}
+
+fn to_name(name: &JsonValue) -> AnyResult<Positioned<Name>> {
+    Ok(Positioned::new(
+        Name::new(get_str(name, "value")?),
+        get_pos(name),
+    ))
+}
+
+fn get_name(node: &JsonValue) -> AnyResult<Positioned<Name>> {
+    to_name(get(node, "name")?)
+}
+
+fn get_number(value: &str) -> AnyResult<Number> {
+    serde_json::from_str(value).map_err(|_| anyhow!("Invalid number: {}", value))
+}
+
+fn get_value(node: &JsonValue) -> AnyResult<Positioned<GqlValue>> {
+    let value = match kind(node)? {
+        "Variable" => GqlValue::Variable(get_name(node)?.node),
+        "IntValue" | "FloatValue" => GqlValue::Number(get_number(get_str(node, "value")?)?),
+        "StringValue" => GqlValue::String(get_str(node, "value")?.to_owned()),
+        "BooleanValue" => GqlValue::Boolean(
+            get(node, "value")?
+                .as_bool()
+                .ok_or_else(|| anyhow!("\"value\" must be a boolean"))?,
+        ),
+        "NullValue" => GqlValue::Null,
+        "EnumValue" => GqlValue::Enum(Name::new(get_str(node, "value")?)),
+        "ListValue" => GqlValue::List(
+            get_list(node, "values")
+                .iter()
+                .map(|v| get_value(v).map(|v| v.node))
+                .collect::<AnyResult<Vec<_>>>()?,
+        ),
+        "ObjectValue" => GqlValue::Object(
+            get_list(node, "fields")
+                .iter()
+                .map(|f| Ok((get_name(f)?.node, get_value(get(f, "value")?)?.node)))
+                .collect::<AnyResult<IndexMap<_, _>>>()?,
+        ),
+        other => return Err(anyhow!("Unsupported value kind: {}", other)),
+    };
+    Ok(Positioned::new(value, get_pos(node)))
+}
+
+fn get_arguments(node: &JsonValue) -> AnyResult<Vec<(Positioned<Name>, Positioned<GqlValue>)>> {
+    get_list(node, "arguments")
+        .iter()
+        .map(|a| Ok((get_name(a)?, get_value(get(a, "value")?)?)))
+        .collect()
+}
+
+fn get_directives(node: &JsonValue) -> AnyResult<Vec<Positioned<Directive>>> {
+    get_list(node, "directives")
+        .iter()
+        .map(|d| {
+            Ok(Positioned::new(
+                Directive {
+                    name: get_name(d)?,
+                    arguments: get_arguments(d)?,
+                },
+                get_pos(d),
+            ))
+        })
+        .collect()
+}
+
+fn get_type(node: &JsonValue) -> AnyResult<Type> {
+    match kind(node)? {
+        "NamedType" => Ok(Type {
+            base: BaseType::Named(get_name(node)?.node),
+            nullable: true,
+        }),
+        "ListType" => Ok(Type {
+            base: BaseType::List(Box::new(get_type(get(node, "type")?)?)),
+            nullable: true,
+        }),
+        "NonNullType" => Ok(Type {
+            nullable: false,
+            ..get_type(get(node, "type")?)?
+        }),
+        other => Err(anyhow!("Unsupported type kind: {}", other)),
+    }
+}
+
+fn get_type_condition(node: &JsonValue) -> AnyResult<Positioned<TypeCondition>> {
+    Ok(Positioned::new(
+        TypeCondition {
+            on: get_name(node)?,
+        },
+        get_pos(node),
+    ))
+}
+
+fn get_selection_set(node: &JsonValue) -> AnyResult<Positioned<SelectionSet>> {
+    let Some(selection_set) = get_optional(node, "selectionSet") else {
+        return Ok(Positioned::new(SelectionSet::default(), get_pos(node)));
+    };
+    let items = get_list(selection_set, "selections")
+        .iter()
+        .map(|s| {
+            let pos = get_pos(s);
+            let selection = match kind(s)? {
+                "Field" => Selection::Field(Positioned::new(
+                    Field {
+                        alias: get_optional(s, "alias").map(to_name).transpose()?,
+                        name: get_name(s)?,
+                        arguments: get_arguments(s)?,
+                        directives: get_directives(s)?,
+                        selection_set: get_selection_set(s)?,
+                    },
+                    pos,
+                )),
+                "FragmentSpread" => Selection::FragmentSpread(Positioned::new(
+                    FragmentSpread {
+                        fragment_name: get_name(s)?,
+                        directives: get_directives(s)?,
+                    },
+                    pos,
+                )),
+                "InlineFragment" => Selection::InlineFragment(Positioned::new(
+                    InlineFragment {
+                        type_condition: get_optional(s, "typeCondition")
+                            .map(get_type_condition)
+                            .transpose()?,
+                        directives: get_directives(s)?,
+                        selection_set: get_selection_set(s)?,
+                    },
+                    pos,
+                )),
+                other => return Err(anyhow!("Unsupported selection kind: {}", other)),
+            };
+            Ok(Positioned::new(selection, pos))
+        })
+        .collect::<AnyResult<Vec<_>>>()?;
+    Ok(Positioned::new(
+        SelectionSet { items },
+        get_pos(selection_set),
+    ))
+}
+
+fn get_default_value(node: &JsonValue) -> AnyResult<Positioned<ConstValue>> {
+    let value = get_value(node)?;
+    let const_value = value
+        .node
+        .into_const()
+        .ok_or_else(|| anyhow!("Default values cannot contain variables"))?;
+    Ok(Positioned::new(const_value, value.pos))
+}
+
+fn get_variable_definitions(node: &JsonValue) -> AnyResult<Vec<Positioned<VariableDefinition>>> {
+    get_list(node, "variableDefinitions")
+        .iter()
+        .map(|v| {
+            let var_type = get(v, "type")?;
+            Ok(Positioned::new(
+                VariableDefinition {
+                    name: get_name(get(v, "variable")?)?,
+                    var_type: Positioned::new(get_type(var_type)?, get_pos(var_type)),
+                    directives: get_directives(v)?,
+                    default_value: get_optional(v, "defaultValue")
+                        .map(get_default_value)
+                        .transpose()?,
+                },
+                get_pos(v),
+            ))
+        })
+        .collect()
+}
+
+/// Converts a graphql-js `DocumentNode` (as produced by `graphql`'s `parse`) into an `ExecutableDocument`.
+pub fn parse_js_ast(document: &JsonValue) -> AnyResult<ExecutableDocument> {
+    if kind(document)? != "Document" {
+        return Err(anyhow!("Expected a Document node"));
+    }
+    let mut operations: Option<DocumentOperations> = None;
+    let mut fragments = HashMap::new();
+    for definition in get_list(document, "definitions") {
+        let pos = get_pos(definition);
+        match kind(definition)? {
+            "OperationDefinition" => {
+                let ty = match get_str(definition, "operation")? {
+                    "query" => OperationType::Query,
+                    "mutation" => OperationType::Mutation,
+                    "subscription" => OperationType::Subscription,
+                    other => return Err(anyhow!("Unsupported operation: {}", other)),
+                };
+                let operation = Positioned::new(
+                    OperationDefinition {
+                        ty,
+                        variable_definitions: get_variable_definitions(definition)?,
+                        directives: get_directives(definition)?,
+                        selection_set: get_selection_set(definition)?,
+                    },
+                    pos,
+                );
+                let name = get_optional(definition, "name")
+                    .map(|n| to_name(n).map(|n| n.node))
+                    .transpose()?;
+                operations = match (operations, name) {
+                    (None, None) => Some(DocumentOperations::Single(operation)),
+                    (None, Some(name)) => Some(DocumentOperations::Multiple(HashMap::from([(
+                        name, operation,
+                    )]))),
+                    (Some(DocumentOperations::Multiple(mut map)), Some(name)) => {
+                        if map.insert(name.clone(), operation).is_some() {
+                            return Err(anyhow!("Duplicate operation: {}", name));
+                        }
+                        Some(DocumentOperations::Multiple(map))
+                    }
+                    _ => {
+                        return Err(anyhow!(
+                            "Anonymous operations must be the only operation in the document"
+                        ));
+                    }
+                };
+            }
+            "FragmentDefinition" => {
+                let name = get_name(definition)?.node;
+                let fragment = Positioned::new(
+                    FragmentDefinition {
+                        type_condition: get_type_condition(get(definition, "typeCondition")?)?,
+                        directives: get_directives(definition)?,
+                        selection_set: get_selection_set(definition)?,
+                    },
+                    pos,
+                );
+                if fragments.insert(name.clone(), fragment).is_some() {
+                    return Err(anyhow!("Duplicate fragment: {}", name));
+                }
+            }
+            other => return Err(anyhow!("Unsupported definition kind: {}", other)),
+        }
+    }
+    Ok(ExecutableDocument {
+        operations: operations.ok_or_else(|| anyhow!("Document does not contain an operation"))?,
+        fragments,
+    })
+}
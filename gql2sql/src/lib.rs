@@ -7,102 +7,158 @@
     clippy::missing_panics_doc
 )]
 
+mod allow_list;
+mod binary;
+mod budget;
+mod cache_policy;
+mod case_style;
+mod claims;
+mod comment;
 mod consts;
+mod correlate;
+mod dates;
+mod dedupe;
+mod dialect;
+mod directives;
+mod dry_run;
+mod enums;
+mod explain;
+mod flatten;
+mod identifiers;
+mod ids;
+mod ir;
+mod js_ast;
+mod plan;
+mod pretty;
+mod prune;
+mod pushdown;
+mod referenced;
+mod relations;
+mod response;
+mod sample;
+mod schemas;
+mod settings;
+mod tenant;
+mod validate;
+mod variables;
+
+pub use allow_list::{constant_time_eq, get_document_hash, AllowList, NotAllowed};
+pub use async_graphql_parser;
+use budget::check_memory;
+#[cfg(feature = "memory-budget")]
+pub use budget::BudgetAllocator;
+pub use cache_policy::{get_cache_policy, CachePolicy};
+pub use case_style::CaseStyle;
+use case_style::{apply_field_case_style, get_meta_case_style};
+pub use claims::{inject_claims, ClaimMapping, ClaimPath};
+pub use comment::{get_operation_name, get_sql_comment, to_commented_sql, SqlComment};
+pub use dates::{detect_date, detect_date_with, DatePolicy, TimestampPolicy};
+use dates::{get_interval, get_now, get_relative_date, is_timestamp};
+use dedupe::dedupe_joins;
+pub use dialect::Dialect;
+pub use directives::{DirectiveContext, DirectiveHandler, DirectiveRegistry};
+pub use dry_run::{dry_run, DryRun};
+pub use enums::EnumMapping;
+pub use explain::PlanNode;
+use flatten::flatten_single_joins;
+pub use identifiers::IdentifierStrategy;
+use identifiers::{normalize_identifiers, shorten_aliases};
+use ids::{get_id_generator, IdGenerator};
+pub use ir::{JoinKind, PlanJoin, PlanQuery, PlanRelation, PlanTable};
+pub use js_ast::parse_js_ast;
+use plan::build_plan;
+pub use plan::{plan, render, QueryPlan};
+pub use pretty::pretty_sql;
+use prune::prune_columns;
+use pushdown::push_down_predicates;
+pub use referenced::{get_referenced_objects, ReferencedObjects, TableColumns};
+use relations::{apply_relations, get_directive as get_relation_directive};
+pub use relations::{ForeignKey, Relation, RelationRegistry, FOREIGN_KEYS_QUERY};
+pub use response::shape_response;
+use sample::{get_sample, set_sample, write_samples};
+use schemas::apply_schemas;
+pub use schemas::{SchemaResolver, TableSchemas};
+pub use settings::{
+    get_local_settings, gql2sql_roots, gql2sql_statements, LocalSettings, RootStatement,
+};
+pub use sqlparser;
+pub use tenant::TenantOptions;
+pub use validate::{parse_check, validate, Diagnostic};
+use variables::check_variables;
+pub use variables::UnknownVariable;
 
 use crate::consts::{
-    BASE, DATA_LABEL, JSONB_AGG, JSONB_BUILD_ARRAY, JSONB_BUILD_OBJECT, ON, QUOTE_CHAR, ROOT_LABEL,
-    TO_JSONB,
+    AFFECTED_ROWS, BASE, CLONE_ID_FUNCTION, CLONE_MAP, CLONE_PARENT, CLONE_SOURCE, DATA_LABEL,
+    DATE_TRUNC, DATE_TRUNC_UNITS, DEFAULT_KEYWORD, DEFAULT_SENTINEL, ISO8601_FORMAT, JSONB_AGG,
+    JSONB_BUILD_ARRAY, JSONB_BUILD_OBJECT, NATURAL_SORT_DIGITS, ON, QUOTE_CHAR, REGEXP_REPLACE,
+    ROOT_LABEL, TO_CHAR, TO_JSONB, VALUE_TYPES, VECTOR, VECTOR_DISTANCE,
 };
 use anyhow::anyhow;
 use async_graphql_parser::{
     types::{
-        Directive, DocumentOperations, ExecutableDocument, Field, OperationType, Selection,
-        VariableDefinition,
+        Directive, DocumentOperations, ExecutableDocument, Field, OperationDefinition,
+        OperationType, Selection, VariableDefinition,
     },
-    Positioned,
+    Pos, Positioned,
 };
 use async_graphql_value::{
     indexmap::{IndexMap, IndexSet},
-    Name, Value as GqlValue,
+    ConstValue, Name, Number, Value as GqlValue,
 };
-use consts::{ID, TYPENAME};
-use lazy_static::lazy_static;
-use regex::Regex;
+use binary::{check_binary, get_binary_literal, get_binary_variables};
+use consts::{
+    AGGREGATE_FIELDS, ALLOW_ALL, CLAIM, COLUMN_ARG_PREFIX, GROUPING, ID, KEY_COLUMNS,
+    RESERVED_ARGS, TYPENAME,
+};
+use correlate::correlate;
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::visit_expressions_mut;
 use sqlparser::ast::{
-    Assignment, BinaryOperator, ConflictTarget, Cte, DataType, Delete, DoUpdate, Expr, FromTable,
-    Function, FunctionArg, FunctionArgExpr, FunctionArgumentList, FunctionArguments, GroupByExpr,
-    Ident, Insert, Join, JoinConstraint, JoinOperator, ObjectName, Offset, OffsetRows, OnConflict,
-    OnConflictAction, OnInsert, OrderByExpr, Query, Select, SelectItem, SetExpr, Statement,
-    TableAlias, TableFactor, TableWithJoins, Value, Values, WildcardAdditionalOptions, With,
+    Array, ArrayElemTypeDef, Assignment, BinaryOperator, CastKind, ConflictTarget, Cte, DataType,
+    DateTimeField, Delete, DoUpdate, DuplicateTreatment, Expr, FromTable, Function, FunctionArg,
+    FunctionArgExpr, FunctionArgumentClause, FunctionArgumentList, FunctionArguments, GroupByExpr,
+    Ident, Insert, Join, JoinConstraint, JoinOperator, LockClause, LockType, NonBlock, ObjectName,
+    Offset, OffsetRows, OnConflict, OnConflictAction, OnInsert, OrderByExpr, Query, Select,
+    SelectItem, SetExpr, Statement, TableAlias, TableFactor, TableWithJoins, UnaryOperator, Value,
+    Values, WildcardAdditionalOptions, With,
 };
-use std::collections::hash_map::DefaultHasher;
-use std::hash::Hasher;
+use std::collections::HashMap;
 use std::{
+    borrow::Cow,
     fmt::{Debug, Formatter},
     iter::zip,
+    ops::ControlFlow,
 };
 
 type JsonValue = serde_json::Value;
 type AnyResult<T> = anyhow::Result<T>;
 
-#[must_use]
-pub fn detect_date(text: &str) -> Option<String> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(
-            r"^((?:(\d{4}-\d{2}-\d{2})T(\d{2}:\d{2}:\d{2}(?:\.\d+)?))(Z|[\+-]\d{2}:\d{2})?)$"
-        )
-        .expect("Failed to compile regex");
-    }
-    if RE.is_match(text) {
-        if text.contains('Z')
-            || text.contains('+')
-            || text.chars().nth_back(5).unwrap_or('T') == '-'
-        {
-            return Some(text.to_owned());
-        } else if text.contains('.') {
-            let date_str = text.to_owned() + "Z";
-            return Some(date_str);
-        }
-        let date_str = text.to_owned() + ".000Z";
-        return Some(date_str);
-    }
-    None
-}
-
-fn value_to_type(value: &JsonValue) -> String {
+fn value_to_type(value: &JsonValue) -> &'static str {
     match value {
-        JsonValue::Null => String::new(),
-        JsonValue::Bool(_) => "::boolean".to_owned(),
-        JsonValue::Number(_) => "::numeric".to_owned(),
-        JsonValue::String(s) => {
-            if detect_date(s).is_some() {
-                "::timestamptz".to_owned()
-            } else {
-                "::text".to_owned()
-            }
-        }
-        JsonValue::Array(_) | JsonValue::Object(_) => "::jsonb".to_owned(),
+        JsonValue::Null => "",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "numeric",
+        JsonValue::String(s) if is_timestamp(s) => "timestamptz",
+        JsonValue::String(_) => "text",
+        JsonValue::Array(_) | JsonValue::Object(_) => "jsonb",
     }
 }
 
 fn get_value<'a>(
     value: &'a GqlValue,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
+    final_vars: &'a mut IndexMap<Name, String>,
 ) -> AnyResult<Expr> {
     match value {
         GqlValue::Variable(v) => {
-            if sql_vars.contains_key(v) {
-                let var_value = sql_vars
-                    .get(v)
-                    .expect("variable not found, gaurded by contains");
+            if let Some(var_value) = sql_vars.get(v) {
                 if let JsonValue::Null = var_value {
                     return Ok(Expr::Value(Value::Null));
                 }
-                let param_cast = value_to_type(var_value);
-                let (i, _) = final_vars.insert_full(v.clone());
+                let param_type = value_to_type(var_value);
+                let (i, _) = final_vars.insert_full(v.clone(), param_type.to_owned());
                 return Ok(Expr::Value(Value::Placeholder(format!(
-                    "${}{param_cast}",
+                    "${}::{param_type}",
                     i + 1,
                 ))));
             }
@@ -113,7 +169,7 @@ fn get_value<'a>(
         GqlValue::Number(f) => Ok(Expr::Value(Value::Number(f.to_string(), false))),
         GqlValue::Boolean(b) => Ok(Expr::Value(Value::Boolean(b.to_owned()))),
         GqlValue::Enum(e) => Ok(Expr::Value(Value::SingleQuotedString(e.as_ref().into()))),
-        GqlValue::Binary(_b) => Err(anyhow!("binary not supported")),
+        GqlValue::Binary(b) => Ok(get_binary_literal(b)),
         GqlValue::List(l) => Ok(Expr::Function(Function {
             within_group: vec![],
             name: ObjectName(vec![Ident::new(JSONB_BUILD_ARRAY)]),
@@ -123,10 +179,10 @@ fn get_value<'a>(
                 args: l
                     .iter()
                     .map(|v| {
-                        let value = get_value(v, sql_vars, final_vars).unwrap();
-                        FunctionArg::Unnamed(FunctionArgExpr::Expr(value))
+                        let value = get_value(v, sql_vars, final_vars)?;
+                        Ok(FunctionArg::Unnamed(FunctionArgExpr::Expr(value)))
                     })
-                    .collect::<Vec<FunctionArg>>(),
+                    .collect::<AnyResult<Vec<FunctionArg>>>()?,
             }),
             over: None,
             filter: None,
@@ -149,16 +205,19 @@ fn get_value<'a>(
                     clauses: vec![],
                     args: o
                         .into_iter()
-                        .flat_map(|(k, v)| {
-                            let value = get_value(v, sql_vars, final_vars).unwrap();
-                            vec![
+                        .map(|(k, v)| {
+                            let value = get_value(v, sql_vars, final_vars)?;
+                            Ok([
                                 FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
                                     Value::SingleQuotedString(k.to_string()),
                                 ))),
                                 FunctionArg::Unnamed(FunctionArgExpr::Expr(value)),
-                            ]
+                            ])
                         })
-                        .collect::<Vec<FunctionArg>>(),
+                        .collect::<AnyResult<Vec<_>>>()?
+                        .into_iter()
+                        .flatten()
+                        .collect(),
                 }),
                 over: None,
                 filter: None,
@@ -168,6 +227,173 @@ fn get_value<'a>(
     }
 }
 
+fn coerce_value(value: &GqlValue, value_type: &str) -> AnyResult<GqlValue> {
+    let text = match value {
+        GqlValue::String(s) => s.trim().to_lowercase(),
+        GqlValue::Enum(e) => e.to_lowercase(),
+        GqlValue::Number(n) => n.to_string(),
+        GqlValue::Boolean(b) => b.to_string(),
+        _ => return Ok(value.clone()),
+    };
+    let coerced = match value_type {
+        "boolean" => match text.as_str() {
+            "true" | "t" | "1" | "yes" | "on" => Some(GqlValue::Boolean(true)),
+            "false" | "f" | "0" | "no" | "off" => Some(GqlValue::Boolean(false)),
+            _ => None,
+        },
+        "integer" => text.parse::<i64>().ok().map(|n| GqlValue::Number(n.into())),
+        _ => text.parse::<Number>().ok().map(GqlValue::Number),
+    };
+    coerced.ok_or_else(|| anyhow!("cannot coerce {} to {}", value, value_type))
+}
+
+// Coerces literals to the hinted type and casts variables whose JSON type does not match it.
+fn get_typed_value(
+    value: &GqlValue,
+    value_type: Option<&str>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Expr> {
+    let Some(value_type) = value_type else {
+        return get_value(value, sql_vars, final_vars);
+    };
+    let GqlValue::Variable(v) = value else {
+        return get_value(&coerce_value(value, value_type)?, sql_vars, final_vars);
+    };
+    let needs_cast = match sql_vars.get(v) {
+        None | Some(JsonValue::Null) => false,
+        Some(JsonValue::Bool(_)) => value_type != "boolean",
+        Some(JsonValue::Number(_)) => value_type == "boolean",
+        Some(_) => true,
+    };
+    let expr = get_value(value, sql_vars, final_vars)?;
+    if !needs_cast {
+        return Ok(expr);
+    }
+    Ok(Expr::Cast {
+        kind: CastKind::DoubleColon,
+        expr: Box::new(expr),
+        data_type: DataType::Custom(ObjectName(vec![Ident::new(value_type)]), vec![]),
+        format: None,
+    })
+}
+
+fn get_vector(
+    value: &GqlValue,
+    sql_vars: &IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Expr> {
+    match value {
+        GqlValue::Variable(v) if matches!(sql_vars.get(v), Some(JsonValue::Array(_))) => {
+            let (i, _) = final_vars.insert_full(v.clone(), VECTOR.to_owned());
+            Ok(Expr::Value(Value::Placeholder(format!(
+                "${}::{VECTOR}",
+                i + 1
+            ))))
+        }
+        GqlValue::List(list) => {
+            let values = list
+                .iter()
+                .map(|v| match v {
+                    GqlValue::Number(n) => Ok(n.to_string()),
+                    _ => Err(anyhow!("vector must be a list of numbers")),
+                })
+                .collect::<AnyResult<Vec<_>>>()?;
+            Ok(Expr::Cast {
+                kind: CastKind::DoubleColon,
+                expr: Box::new(Expr::Value(Value::SingleQuotedString(format!(
+                    "[{}]",
+                    values.join(",")
+                )))),
+                data_type: DataType::Custom(ObjectName(vec![Ident::new(VECTOR)]), vec![]),
+                format: None,
+            })
+        }
+        _ => Err(anyhow!("vector must be a list of numbers")),
+    }
+}
+
+// pgvector euclidean distance, `left <-> to`
+fn get_distance(
+    left: Expr,
+    to: &GqlValue,
+    sql_vars: &IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Expr> {
+    Ok(Expr::BinaryOp {
+        left: Box::new(left),
+        op: BinaryOperator::Custom(VECTOR_DISTANCE.to_owned()),
+        right: Box::new(get_vector(to, sql_vars, final_vars)?),
+    })
+}
+
+fn get_similarity(
+    similarity: &IndexMap<Name, GqlValue>,
+    path: Option<&str>,
+    sql_vars: &IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Expr> {
+    let field = similarity
+        .get("field")
+        .ok_or_else(|| anyhow!("similarity is missing field"))
+        .and_then(|v| get_string_or_variable(v, sql_vars))?;
+    let to = similarity
+        .get("to")
+        .ok_or_else(|| anyhow!("similarity is missing to"))?;
+    let column = Ident::with_quote(QUOTE_CHAR, field);
+    let left = match path {
+        Some(path) => Expr::CompoundIdentifier(vec![Ident::with_quote(QUOTE_CHAR, path), column]),
+        None => Expr::Identifier(column),
+    };
+    get_distance(left, to, sql_vars, final_vars)
+}
+
+// Binds an array variable as a single `$n::type[]` parameter so the SQL text does not
+// depend on the list length.
+fn get_array_expr(
+    left: &Expr,
+    value: &GqlValue,
+    negated: bool,
+    value_type: Option<&str>,
+    sql_vars: &IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> Option<Expr> {
+    let GqlValue::Variable(v) = value else {
+        return None;
+    };
+    let Some(JsonValue::Array(list)) = sql_vars.get(v) else {
+        return None;
+    };
+    if list.is_empty() {
+        return Some(Expr::Value(Value::Boolean(negated)));
+    }
+    let element_type = value_type.unwrap_or_else(|| {
+        list.iter()
+            .map(value_to_type)
+            .find(|t| !t.is_empty())
+            .unwrap_or("text")
+    });
+    let param_type = format!("{element_type}[]");
+    let (i, _) = final_vars.insert_full(v.clone(), param_type.clone());
+    let array = Box::new(Expr::Value(Value::Placeholder(format!(
+        "${}::{param_type}",
+        i + 1,
+    ))));
+    if negated {
+        Some(Expr::AllOp {
+            left: Box::new(left.clone()),
+            compare_op: BinaryOperator::NotEq,
+            right: array,
+        })
+    } else {
+        Some(Expr::AnyOp {
+            left: Box::new(left.clone()),
+            compare_op: BinaryOperator::Eq,
+            right: array,
+        })
+    }
+}
+
 fn get_logical_operator(op: &str) -> AnyResult<BinaryOperator> {
     let value = match op {
         "AND" => BinaryOperator::And,
@@ -179,47 +405,63 @@ fn get_logical_operator(op: &str) -> AnyResult<BinaryOperator> {
     Ok(value)
 }
 
-fn get_op(op: &str) -> BinaryOperator {
-    match op {
+// other operators are written as is, so they may only use the characters of a Postgres operator
+fn get_op(op: &str) -> AnyResult<BinaryOperator> {
+    Ok(match op {
         "eq" | "equals" => BinaryOperator::Eq,
         "neq" | "not_equals" => BinaryOperator::NotEq,
         "lt" | "less_than" => BinaryOperator::Lt,
         "lte" | "less_than_or_equals" => BinaryOperator::LtEq,
         "gt" | "greater_than" => BinaryOperator::Gt,
         "gte" | "greater_than_or_equals" => BinaryOperator::GtEq,
-        _ => BinaryOperator::Custom(op.to_owned()),
-    }
+        _ if !op.is_empty()
+            && op.chars().all(|c| "+-*/<>=~!@#%^&|`?".contains(c))
+            && !op.contains("--")
+            && !op.contains("/*") =>
+        {
+            BinaryOperator::Custom(op.to_owned())
+        }
+        _ => return Err(anyhow!("operator not supported: {}", op)),
+    })
 }
 
 fn get_expr<'a>(
     left: Expr,
     operator: &'a str,
     value: &'a GqlValue,
+    value_type: Option<&'a str>,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
+    final_vars: &'a mut IndexMap<Name, String>,
 ) -> AnyResult<Option<Expr>> {
     match operator {
         "like" => Ok(Some(Expr::Like {
             negated: false,
             expr: Box::new(left),
-            pattern: Box::new(get_value(value, sql_vars, final_vars)?),
+            pattern: Box::new(get_typed_value(value, value_type, sql_vars, final_vars)?),
             escape_char: None,
         })),
         "ilike" => Ok(Some(Expr::ILike {
             negated: false,
             expr: Box::new(left),
-            pattern: Box::new(get_value(value, sql_vars, final_vars)?),
+            pattern: Box::new(get_typed_value(value, value_type, sql_vars, final_vars)?),
             escape_char: None,
         })),
         "null" => Ok(Some(Expr::IsNull(Box::new(left)))),
         "not_null" => Ok(Some(Expr::IsNotNull(Box::new(left)))),
         "in" => {
+            if let Some(expr) =
+                get_array_expr(&left, value, false, value_type, sql_vars, final_vars)
+            {
+                return Ok(Some(expr));
+            }
             let list: Result<Vec<_>, _> = if let GqlValue::List(v) = value {
                 v.into_iter()
-                    .map(|v| get_value(v, sql_vars, final_vars))
+                    .map(|v| get_typed_value(v, value_type, sql_vars, final_vars))
                     .collect()
             } else {
-                Ok(vec![get_value(value, sql_vars, final_vars)?])
+                Ok(vec![get_typed_value(
+                    value, value_type, sql_vars, final_vars,
+                )?])
             };
             let list = list?;
             if list.is_empty() {
@@ -232,12 +474,18 @@ fn get_expr<'a>(
             }))
         }
         "not_in" => {
+            if let Some(expr) = get_array_expr(&left, value, true, value_type, sql_vars, final_vars)
+            {
+                return Ok(Some(expr));
+            }
             let list: Result<Vec<_>, _> = if let GqlValue::List(v) = value {
                 v.into_iter()
-                    .map(|v| get_value(v, sql_vars, final_vars))
+                    .map(|v| get_typed_value(v, value_type, sql_vars, final_vars))
                     .collect()
             } else {
-                Ok(vec![get_value(value, sql_vars, final_vars)?])
+                Ok(vec![get_typed_value(
+                    value, value_type, sql_vars, final_vars,
+                )?])
             };
             let list = list?;
             if list.is_empty() {
@@ -250,8 +498,11 @@ fn get_expr<'a>(
             }))
         }
         _ => {
-            let mut right_value = get_value(value, sql_vars, final_vars)?;
-            let op = get_op(operator);
+            let mut right_value = match get_relative_date(value, sql_vars)? {
+                Some(relative) => relative,
+                None => get_typed_value(value, value_type, sql_vars, final_vars)?,
+            };
+            let op = get_op(operator)?;
             if let Expr::Value(Value::Null) = right_value {
                 if op == BinaryOperator::Eq {
                     return Ok(Some(Expr::IsNull(Box::new(left))));
@@ -279,33 +530,436 @@ fn get_string_or_variable(
     value: &GqlValue,
     variables: &IndexMap<Name, JsonValue>,
 ) -> AnyResult<String> {
+    find_string_or_variable(value, variables).ok_or_else(|| match value {
+        GqlValue::Variable(_) => anyhow!("variable not found"),
+        _ => anyhow!("value not supported"),
+    })
+}
+
+// for callers that only probe for a string, so no error has to be built
+fn find_string_or_variable(
+    value: &GqlValue,
+    variables: &IndexMap<Name, JsonValue>,
+) -> Option<String> {
     match value {
-        GqlValue::Variable(v) => {
-            if let Some(JsonValue::String(s)) = variables.get(v) {
-                Ok(s.clone())
-            } else {
-                Err(anyhow!("variable not found"))
+        GqlValue::Variable(v) => match variables.get(v) {
+            Some(JsonValue::String(s)) => Some(s.clone()),
+            _ => None,
+        },
+        GqlValue::String(s) => Some(s.clone()),
+        GqlValue::Enum(e) => Some(e.to_string()),
+        _ => None,
+    }
+}
+
+// a literal null, or a variable that is null or was not provided
+fn is_null_value(value: &GqlValue, sql_vars: &IndexMap<Name, JsonValue>) -> bool {
+    match value {
+        GqlValue::Null => true,
+        GqlValue::Variable(name) => matches!(sql_vars.get(name), None | Some(JsonValue::Null)),
+        _ => false,
+    }
+}
+
+fn get_null_comparison(left: Expr, operator: &str, policy: NullPolicy) -> AnyResult<Option<Expr>> {
+    match (policy, get_op(operator)?) {
+        (NullPolicy::Ignore, _) => Ok(None),
+        (NullPolicy::MatchNull, BinaryOperator::Eq) => Ok(Some(Expr::IsNull(Box::new(left)))),
+        (NullPolicy::MatchNull, BinaryOperator::NotEq) => Ok(Some(Expr::IsNotNull(Box::new(left)))),
+        (NullPolicy::MatchNull, _) => Err(anyhow!(
+            "Cannot compare {} against null with operator {}",
+            left,
+            operator
+        )),
+        (NullPolicy::Error, _) => Err(anyhow!("Filter on {} received a null value", left)),
+    }
+}
+
+fn nest(expr: Expr) -> Expr {
+    match expr {
+        Expr::Nested(_) => expr,
+        expr => Expr::Nested(Box::new(expr)),
+    }
+}
+
+fn negate(expr: Expr) -> Expr {
+    Expr::UnaryOp {
+        op: UnaryOperator::Not,
+        expr: Box::new(Expr::Nested(Box::new(expr))),
+    }
+}
+
+// `not: { ... }` negates a filter; one that ignoreEmpty leaves out stays out rather than
+// matching nothing, and its tags are dropped as they name the rows it excludes
+fn get_negated_filter(
+    negated: &GqlValue,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+    null_policy: Option<NullPolicy>,
+    scope: Option<&mut FilterScope>,
+) -> AnyResult<(Option<Expr>, Option<IndexSet<Tag>>)> {
+    let GqlValue::Object(filter) = negated else {
+        return Err(anyhow!("not must be a filter object"));
+    };
+    let (selection, _tags) = get_filter(filter, sql_vars, final_vars, null_policy, scope)?;
+    Ok((selection.map(negate), None))
+}
+
+// the keys of a grouped aggregate, by the value field each is selected as, and the ROLLUP, CUBE
+// or GROUPING SETS they are grouped by instead of one group per distinct key
+#[derive(Clone)]
+struct GroupBy {
+    keys: Vec<(String, Expr)>,
+    sets: Option<Expr>,
+}
+
+/// The table a filter applies to, so that `relation:` filters can find its relations and tag
+/// the tables they read.
+struct FilterScope<'a> {
+    table: String,
+    // how the filtered query refers to the table
+    qualifier: Vec<Ident>,
+    items: &'a [Positioned<Selection>],
+    tags: &'a mut IndexMap<String, IndexSet<Tag>>,
+    options: &'a Options,
+}
+
+// the related table, its schema, the columns joining it to the parent and its selections, from
+// the `@relation` of the selected field or else the registry
+fn find_filter_relation<'a>(
+    field: &str,
+    scope: &FilterScope<'a>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &IndexMap<Name, String>,
+) -> AnyResult<(
+    String,
+    Option<String>,
+    Vec<String>,
+    Vec<String>,
+    &'a [Positioned<Selection>],
+)> {
+    let selected = scope
+        .items
+        .iter()
+        .find_map(|selection| match &selection.node {
+            Selection::Field(f)
+                if f.node.name.node.as_str() == field
+                    && f.node
+                        .directives
+                        .iter()
+                        .any(|d| d.node.name.node.as_str() == "relation") =>
+            {
+                Some(&f.node)
+            }
+            _ => None,
+        });
+    if let Some(selected) = selected {
+        let (table, fields, references, _, _, is_many, schema_name, _, _) =
+            get_relation(&selected.directives, sql_vars, final_vars)?;
+        if is_many {
+            return Err(anyhow!(
+                "relation filter on {} does not support many to many relations",
+                field
+            ));
+        }
+        return Ok((
+            table,
+            schema_name,
+            fields,
+            references,
+            &selected.selection_set.node.items,
+        ));
+    }
+    let relation = scope
+        .options
+        .relations
+        .get(&scope.table, field)
+        .ok_or_else(|| anyhow!("relation filter on {} needs a @relation directive", field))?;
+    Ok((
+        relation.table.clone(),
+        relation.schema.clone(),
+        relation.fields.clone(),
+        relation.references.clone(),
+        &[],
+    ))
+}
+
+// a relation of the filtered table, correlated with the parent row
+struct RelatedTable<'a> {
+    table: String,
+    name: ObjectName,
+    alias: Ident,
+    join: Vec<Expr>,
+    items: &'a [Positioned<Selection>],
+    columns: IndexMap<String, Expr>,
+}
+
+fn get_related_table<'a>(
+    field: &str,
+    scope: &mut FilterScope<'a>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &IndexMap<Name, String>,
+) -> AnyResult<RelatedTable<'a>> {
+    let (table, schema_name, fields, references, items) =
+        find_filter_relation(field, scope, sql_vars, final_vars)?;
+    scope.tags.insert(
+        get_tag_key(&table, schema_name.as_deref(), scope.options),
+        IndexSet::new(),
+    );
+    let parent = scope
+        .qualifier
+        .last()
+        .map_or("", |ident| ident.value.as_str());
+    let alias = Ident::with_quote(QUOTE_CHAR, format!("{parent}_{field}"));
+    let join = zip(&fields, &references)
+        .map(|(field, reference)| {
+            let mut parent = scope.qualifier.clone();
+            parent.push(Ident::with_quote(QUOTE_CHAR, reference));
+            Expr::BinaryOp {
+                left: Box::new(Expr::CompoundIdentifier(vec![
+                    alias.clone(),
+                    Ident::with_quote(QUOTE_CHAR, field),
+                ])),
+                op: BinaryOperator::Eq,
+                right: Box::new(Expr::CompoundIdentifier(parent)),
+            }
+        })
+        .collect();
+    let name = ObjectName(
+        schema_name
+            .iter()
+            .chain(std::iter::once(&table))
+            .map(|name| Ident::with_quote(QUOTE_CHAR, name))
+            .collect(),
+    );
+    Ok(RelatedTable {
+        table,
+        name,
+        alias,
+        join,
+        items,
+        columns: get_columns(items, sql_vars)?,
+    })
+}
+
+// a filter on the related rows, with its columns qualified by the related table
+fn get_related_filter(
+    filter: &IndexMap<Name, GqlValue>,
+    related: &RelatedTable,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+    null_policy: Option<NullPolicy>,
+    scope: &mut FilterScope,
+) -> AnyResult<Option<Expr>> {
+    if filter.is_empty() {
+        return Ok(None);
+    }
+    let mut child = FilterScope {
+        table: related.table.clone(),
+        qualifier: vec![related.alias.clone()],
+        items: related.items,
+        tags: scope.tags,
+        options: scope.options,
+    };
+    let (mut selection, _tags) =
+        get_filter(filter, sql_vars, final_vars, null_policy, Some(&mut child))?;
+    if let Some(selection) = selection.as_mut() {
+        correlate(selection, &related.alias, &related.columns);
+    }
+    Ok(selection)
+}
+
+// `SELECT * FROM related WHERE join AND selection`
+fn get_related_query(related: &RelatedTable, selection: Option<Expr>) -> Query {
+    let selection = related
+        .join
+        .iter()
+        .cloned()
+        .chain(selection.map(nest))
+        .reduce(|left, right| Expr::BinaryOp {
+            left: Box::new(left),
+            op: BinaryOperator::And,
+            right: Box::new(right),
+        });
+    let mut query = get_filter_query(
+        selection,
+        vec![],
+        None,
+        None,
+        vec![related.name.clone()],
+        None,
+        None,
+        None,
+    );
+    if let SetExpr::Select(select) = query.body.as_mut() {
+        for from in &mut select.from {
+            if let TableFactor::Table { alias, .. } = &mut from.relation {
+                *alias = Some(TableAlias {
+                    name: related.alias.clone(),
+                    columns: vec![],
+                });
+            }
+        }
+    }
+    query
+}
+
+// `{ field: "posts", relation: { some: {..}, none: {..}, every: {..} } }` checks the related
+// rows with EXISTS subqueries
+fn get_relation_filter(
+    field: &str,
+    relation: &GqlValue,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+    null_policy: Option<NullPolicy>,
+    scope: Option<&mut FilterScope>,
+) -> AnyResult<Option<Expr>> {
+    let Some(scope) = scope else {
+        return Err(anyhow!(
+            "relation filter on {} is only supported in queries",
+            field
+        ));
+    };
+    let GqlValue::Object(relation) = relation else {
+        return Err(anyhow!("relation filter on {} must be an object", field));
+    };
+    let related = get_related_table(field, scope, sql_vars, final_vars)?;
+    let mut condition = None;
+    for (kind, filter) in relation {
+        let GqlValue::Object(filter) = filter else {
+            return Err(anyhow!("relation filter {} must be a filter object", kind));
+        };
+        let selection =
+            get_related_filter(filter, &related, sql_vars, final_vars, null_policy, scope)?;
+        let (selection, negated) = match (kind.as_str(), selection) {
+            ("some", selection) => (selection, false),
+            ("none", selection) => (selection, true),
+            ("every", Some(selection)) => (Some(Expr::IsNotTrue(Box::new(nest(selection)))), true),
+            // every related row passes a filter that ignoreEmpty left out
+            ("every", None) => continue,
+            _ => {
+                return Err(anyhow!(
+                    "relation filter must be some, none or every, got {}",
+                    kind
+                ))
             }
+        };
+        let exists = Expr::Exists {
+            subquery: Box::new(get_related_query(&related, selection)),
+            negated,
+        };
+        condition = Some(match condition {
+            Some(condition) => Expr::BinaryOp {
+                left: Box::new(condition),
+                op: BinaryOperator::And,
+                right: Box::new(exists),
+            },
+            None => exists,
+        });
+    }
+    Ok(condition)
+}
+
+// `{ relation: "comments", aggregate: "count", operator: "gt", value: 10 }` compares an
+// aggregate of the related rows, optionally narrowed by `filter`; aggregates other than count
+// take the related column from `field`
+fn get_relation_aggregate(
+    field: &str,
+    args: &IndexMap<Name, GqlValue>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+    null_policy: Option<NullPolicy>,
+    scope: Option<&mut FilterScope>,
+) -> AnyResult<Expr> {
+    let relation = args
+        .get("relation")
+        .ok_or_else(|| anyhow!("aggregate filter is missing relation"))
+        .and_then(|v| get_string_or_variable(v, sql_vars))?;
+    let Some(scope) = scope else {
+        return Err(anyhow!(
+            "aggregate filter on {} is only supported in queries",
+            relation
+        ));
+    };
+    let aggregate = args
+        .get("aggregate")
+        .ok_or_else(|| anyhow!("aggregate filter is missing aggregate"))
+        .and_then(|v| get_string_or_variable(v, sql_vars))?
+        .to_lowercase();
+    let related = get_related_table(&relation, scope, sql_vars, final_vars)?;
+    let argument = match aggregate.as_str() {
+        "count" => FunctionArgExpr::Wildcard,
+        "sum" | "avg" | "min" | "max" if !field.is_empty() => {
+            let mut column = Expr::Identifier(Ident::with_quote(QUOTE_CHAR, field));
+            correlate(&mut column, &related.alias, &related.columns);
+            FunctionArgExpr::Expr(column)
+        }
+        "sum" | "avg" | "min" | "max" => {
+            return Err(anyhow!("aggregate filter {} is missing field", aggregate))
+        }
+        _ => {
+            return Err(anyhow!(
+                "aggregate filter must be count, sum, avg, min or max, got {}",
+                aggregate
+            ))
         }
-        GqlValue::String(s) => Ok(s.clone()),
-        _ => Err(anyhow!("value not supported")),
+    };
+    let selection = match args.get("filter") {
+        Some(GqlValue::Object(filter)) => {
+            get_related_filter(filter, &related, sql_vars, final_vars, null_policy, scope)?
+        }
+        Some(_) => {
+            return Err(anyhow!(
+                "aggregate filter on {} must be an object",
+                relation
+            ))
+        }
+        None => None,
+    };
+    let mut query = get_related_query(&related, selection);
+    if let SetExpr::Select(select) = query.body.as_mut() {
+        select.projection = vec![SelectItem::UnnamedExpr(Expr::Function(Function {
+            within_group: vec![],
+            name: ObjectName(vec![Ident::new(aggregate.to_uppercase())]),
+            args: FunctionArguments::List(FunctionArgumentList {
+                duplicate_treatment: None,
+                clauses: vec![],
+                args: vec![FunctionArg::Unnamed(argument)],
+            }),
+            over: None,
+            filter: None,
+            null_treatment: None,
+        }))];
     }
+    Ok(Expr::Subquery(Box::new(query)))
 }
 
 fn get_filter(
     args: &IndexMap<Name, GqlValue>,
     sql_vars: &mut IndexMap<Name, JsonValue>,
-    final_vars: &mut IndexSet<Name>,
+    final_vars: &mut IndexMap<Name, String>,
+    null_policy: Option<NullPolicy>,
+    mut scope: Option<&mut FilterScope>,
 ) -> AnyResult<(Option<Expr>, Option<IndexSet<Tag>>)> {
+    if let Some(negated) = args.get("not") {
+        return get_negated_filter(negated, sql_vars, final_vars, null_policy, scope);
+    }
     let mut tags = IndexSet::new();
+    let is_aggregate = args.contains_key("aggregate");
+    // a count of related rows needs no field
     let field = args
         .get("field")
         .map(|v| get_string_or_variable(v, sql_vars))
-        .ok_or(anyhow!("field not found"))??;
+        .transpose()?
+        .or_else(|| is_aggregate.then(String::new))
+        .ok_or_else(|| anyhow!("field not found"))?;
+    // relation filters have no operator of their own
     let operator = args
         .get("operator")
         .map(|v| get_string_or_variable(v, sql_vars))
-        .ok_or(anyhow!("operator not found"))??;
+        .transpose()?
+        .or_else(|| (args.contains_key("relation") && !is_aggregate).then(String::new))
+        .ok_or_else(|| anyhow!("operator not found"))?;
     let ignore_null = args.get("ignoreEmpty").is_some_and(|v| match v {
         GqlValue::Boolean(b) => *b,
         GqlValue::Variable(v) => match sql_vars.get(v) {
@@ -314,61 +968,132 @@ fn get_filter(
         },
         _ => false,
     });
+    let null_policy = match args.get("nullPolicy") {
+        Some(policy) => Some(NullPolicy::parse(&get_string_or_variable(
+            policy, sql_vars,
+        )?)?),
+        None => null_policy,
+    };
 
+    let value_type = args
+        .get("type")
+        .map(|v| get_string_or_variable(v, sql_vars))
+        .transpose()?;
+    if let Some(value_type) = &value_type {
+        if !VALUE_TYPES.contains(&value_type.as_str()) {
+            return Err(anyhow!("filter type not supported: {}", value_type));
+        }
+    }
     let value = args.get("value").unwrap_or_else(|| &GqlValue::Null);
-    if operator == "eq" {
-        if let Ok(value) = get_string_or_variable(value, sql_vars) {
+    if operator == "eq" && !is_aggregate {
+        if let Some(value) = find_string_or_variable(value, sql_vars) {
             tags.insert(Tag {
                 key: field.clone(),
                 value: Some(value),
             });
         }
     }
-    let left = Expr::Identifier(Ident {
-        value: field,
-        quote_style: Some(QUOTE_CHAR),
-    });
-    let primary = if ignore_null && !should_add_filter(value, sql_vars) {
+    let left = if is_aggregate {
+        get_relation_aggregate(
+            &field,
+            args,
+            sql_vars,
+            final_vars,
+            null_policy,
+            scope.as_deref_mut(),
+        )?
+    } else {
+        Expr::Identifier(Ident {
+            value: field.clone(),
+            quote_style: Some(QUOTE_CHAR),
+        })
+    };
+    let primary = if let Some(relation) = args.get("relation").filter(|_| !is_aggregate) {
+        get_relation_filter(
+            &field,
+            relation,
+            sql_vars,
+            final_vars,
+            null_policy,
+            scope.as_deref_mut(),
+        )?
+    } else if ignore_null && !should_add_filter(value, sql_vars) {
         None
+    } else if let Some(policy) = null_policy.filter(|_| {
+        !["null", "not_null"].contains(&operator.as_str()) && is_null_value(value, sql_vars)
+    }) {
+        get_null_comparison(left, &operator, policy)?
+    } else if operator == "nearest" {
+        let distance = args
+            .get("distance")
+            .ok_or_else(|| anyhow!("nearest filter is missing distance"))?;
+        Some(Expr::BinaryOp {
+            left: Box::new(get_distance(left, value, sql_vars, final_vars)?),
+            op: BinaryOperator::Lt,
+            right: Box::new(get_value(distance, sql_vars, final_vars)?),
+        })
+    } else if operator == "within_last" {
+        let interval = args
+            .get("interval")
+            .ok_or_else(|| anyhow!("within_last filter is missing interval"))?;
+        Some(Expr::BinaryOp {
+            left: Box::new(left),
+            op: BinaryOperator::GtEq,
+            right: Box::new(Expr::BinaryOp {
+                left: Box::new(get_now()),
+                op: BinaryOperator::Minus,
+                right: Box::new(get_interval(interval, sql_vars, final_vars)?),
+            }),
+        })
     } else {
-        get_expr(left, operator.as_str(), value, sql_vars, final_vars)?
+        get_expr(
+            left,
+            operator.as_str(),
+            value,
+            value_type.as_deref(),
+            sql_vars,
+            final_vars,
+        )?
     };
     if args.contains_key("children") {
         if let Some(GqlValue::List(children)) = args.get("children") {
-            let op = if let Some(val) = args.get("logicalOperator") {
-                let op_name = get_string_or_variable(val, sql_vars)?;
-                get_logical_operator(op_name.to_uppercase().as_str())?
-            } else {
-                BinaryOperator::And
+            let op_name = args
+                .get("logicalOperator")
+                .map(|v| get_string_or_variable(v, sql_vars))
+                .transpose()?
+                .map(|name| name.to_uppercase());
+            // NOT joins the filter and its children with AND and negates the group
+            let negated = op_name.as_deref() == Some("NOT");
+            let op = match op_name {
+                Some(op_name) if !negated => get_logical_operator(&op_name)?,
+                _ => BinaryOperator::And,
             };
-            if let Some(filters) = children
-                .iter()
-                .map(|v| match v {
-                    GqlValue::Object(o) => {
-                        if let Ok((item, new_tags)) = get_filter(o, sql_vars, final_vars) {
-                            if let Some(new_tags) = new_tags {
-                                tags.extend(new_tags);
-                            }
-                            return item;
-                        }
-                        None
-                    }
-                    _ => None,
-                })
-                .fold(primary, |acc: Option<Expr>, item| {
-                    if let Some(acc) = acc {
-                        let item = item.unwrap_or_else(|| Expr::Value(Value::Boolean(true)));
-                        let expr = Expr::BinaryOp {
-                            left: Box::new(acc),
-                            op: op.clone(),
-                            right: Box::new(item),
-                        };
-                        Some(expr)
-                    } else {
-                        None
-                    }
-                })
-            {
+            let mut items = primary.into_iter().collect::<Vec<_>>();
+            for child in children {
+                let GqlValue::Object(child) = child else {
+                    return Err(anyhow!("filter children must be objects"));
+                };
+                let (item, new_tags) = get_filter(
+                    child,
+                    sql_vars,
+                    final_vars,
+                    null_policy,
+                    scope.as_deref_mut(),
+                )?;
+                if let Some(new_tags) = new_tags {
+                    tags.extend(new_tags);
+                }
+                // a child without a condition, such as an empty value with ignoreEmpty, is left out
+                items.extend(item);
+            }
+            if let Some(filters) = items.into_iter().reduce(|acc, item| Expr::BinaryOp {
+                left: Box::new(acc),
+                op: op.clone(),
+                right: Box::new(item),
+            }) {
+                if negated {
+                    return Ok((Some(negate(filters)), None));
+                }
                 if tags.is_empty() {
                     return Ok((Some(Expr::Nested(Box::new(filters))), None));
                 }
@@ -389,7 +1114,7 @@ fn get_agg_query(
     from: Vec<TableWithJoins>,
     selection: Option<Expr>,
     alias: &str,
-    group_by: Option<Vec<(String, Expr)>>,
+    group_by: Option<GroupBy>,
 ) -> SetExpr {
     SetExpr::Select(Box::new(Select {
         window_before_qualify: false,
@@ -423,13 +1148,13 @@ fn get_agg_query(
         from,
         lateral_views: vec![],
         selection,
-        group_by: GroupByExpr::Expressions(
-            group_by
-                .unwrap_or_else(|| vec![])
-                .into_iter()
-                .map(|(_, expr)| expr)
-                .collect::<Vec<_>>(),
-        ),
+        group_by: GroupByExpr::Expressions(match group_by {
+            Some(GroupBy {
+                sets: Some(sets), ..
+            }) => vec![sets],
+            Some(GroupBy { keys, .. }) => keys.into_iter().map(|(_, expr)| expr).collect(),
+            None => vec![],
+        }),
         cluster_by: vec![],
         distribute_by: vec![],
         sort_by: vec![],
@@ -438,26 +1163,145 @@ fn get_agg_query(
     }))
 }
 
-fn get_root_query(
-    projection: Vec<SelectItem>,
-    from: Vec<TableWithJoins>,
-    selection: Option<Expr>,
-    merges: &[Merge],
-    is_single: bool,
-    alias: &str,
-) -> SetExpr {
-    let mut base = Expr::Function(Function {
-        within_group: vec![],
-        name: ObjectName(vec![Ident {
-            value: TO_JSONB.to_string(),
-            quote_style: None,
-        }]),
-        args: FunctionArguments::List(FunctionArgumentList {
-            duplicate_treatment: None,
-            clauses: vec![],
-            args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Subquery(
-                Box::new(Query {
-                    for_clause: None,
+// the rows of a grouped aggregate as a list, which is empty rather than null without groups
+fn get_groups_list(groups: Query, alias: &str) -> Query {
+    let rows = Ident::with_quote(QUOTE_CHAR, "T");
+    let list = get_function_call(
+        "coalesce",
+        vec![
+            get_function_call(
+                JSONB_AGG,
+                vec![Expr::CompoundIdentifier(vec![
+                    rows.clone(),
+                    Ident::with_quote(QUOTE_CHAR, alias),
+                ])],
+            ),
+            Expr::Value(Value::SingleQuotedString("[]".to_string())),
+        ],
+    );
+    Query {
+        for_clause: None,
+        limit_by: vec![],
+        with: None,
+        body: Box::new(SetExpr::Select(Box::new(Select {
+            window_before_qualify: false,
+            connect_by: None,
+            value_table_mode: None,
+            distinct: None,
+            named_window: vec![],
+            top: None,
+            projection: vec![SelectItem::ExprWithAlias {
+                expr: list,
+                alias: Ident::with_quote(QUOTE_CHAR, alias),
+            }],
+            into: None,
+            from: vec![TableWithJoins {
+                relation: TableFactor::Derived {
+                    lateral: false,
+                    subquery: Box::new(groups),
+                    alias: Some(TableAlias {
+                        name: rows,
+                        columns: vec![],
+                    }),
+                },
+                joins: vec![],
+            }],
+            lateral_views: vec![],
+            selection: None,
+            group_by: GroupByExpr::Expressions(vec![]),
+            cluster_by: vec![],
+            distribute_by: vec![],
+            sort_by: vec![],
+            having: None,
+            qualify: None,
+        }))),
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        fetch: None,
+        locks: vec![],
+    }
+}
+
+fn get_count_query(base_query: Query) -> Expr {
+    Expr::Subquery(Box::new(Query {
+        for_clause: None,
+        limit_by: vec![],
+        with: None,
+        body: Box::new(SetExpr::Select(Box::new(Select {
+            window_before_qualify: false,
+            connect_by: None,
+            value_table_mode: None,
+            distinct: None,
+            named_window: vec![],
+            top: None,
+            projection: vec![SelectItem::UnnamedExpr(Expr::Function(Function {
+                within_group: vec![],
+                name: ObjectName(vec![Ident {
+                    value: "count".to_string(),
+                    quote_style: None,
+                }]),
+                args: FunctionArguments::List(FunctionArgumentList {
+                    duplicate_treatment: None,
+                    clauses: vec![],
+                    args: vec![FunctionArg::Unnamed(FunctionArgExpr::Wildcard)],
+                }),
+                over: None,
+                filter: None,
+                null_treatment: None,
+            }))],
+            into: None,
+            from: vec![TableWithJoins {
+                relation: TableFactor::Derived {
+                    lateral: false,
+                    subquery: Box::new(base_query),
+                    alias: Some(TableAlias {
+                        name: Ident {
+                            value: BASE.to_string(),
+                            quote_style: Some(QUOTE_CHAR),
+                        },
+                        columns: vec![],
+                    }),
+                },
+                joins: vec![],
+            }],
+            lateral_views: vec![],
+            selection: None,
+            group_by: GroupByExpr::Expressions(vec![]),
+            cluster_by: vec![],
+            distribute_by: vec![],
+            sort_by: vec![],
+            having: None,
+            qualify: None,
+        }))),
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        fetch: None,
+        locks: vec![],
+    }))
+}
+
+fn get_root_query(
+    projection: Vec<SelectItem>,
+    from: Vec<TableWithJoins>,
+    selection: Option<Expr>,
+    merges: &[Merge],
+    is_single: bool,
+    alias: &str,
+) -> SetExpr {
+    let mut base = Expr::Function(Function {
+        within_group: vec![],
+        name: ObjectName(vec![Ident {
+            value: TO_JSONB.to_string(),
+            quote_style: None,
+        }]),
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment: None,
+            clauses: vec![],
+            args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Subquery(
+                Box::new(Query {
+                    for_clause: None,
                     limit_by: vec![],
                     with: None,
                     body: Box::new(SetExpr::Select(Box::new(Select {
@@ -635,9 +1479,134 @@ fn get_root_query(
     }))
 }
 
-fn get_agg_agg_projection(field: &Field, table_name: &str) -> Vec<FunctionArg> {
-    let name = field.name.node.as_ref();
+fn get_agg_filter(
+    arguments: &[(Positioned<Name>, Positioned<GqlValue>)],
+    variables: &IndexMap<Name, GqlValue>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+    null_policy: Option<NullPolicy>,
+) -> AnyResult<Option<Box<Expr>>> {
+    let Some((_, value)) = arguments
+        .iter()
+        .find(|(name, _)| matches!(name.node.as_str(), "filter" | "where"))
+    else {
+        return Ok(None);
+    };
+    let value = match &value.node {
+        GqlValue::Variable(name) => variables.get(name).unwrap_or(&GqlValue::Null),
+        value => value,
+    };
+    match value {
+        GqlValue::Object(filter) => {
+            let (selection, _keys) = get_filter(filter, sql_vars, final_vars, null_policy, None)?;
+            Ok(selection.map(Box::new))
+        }
+        GqlValue::Null => Ok(None),
+        _ => Err(anyhow!("Invalid value for aggregate filter")),
+    }
+}
+
+fn get_agg_function(
+    name: &str,
+    column: &str,
+    arguments: &[(Positioned<Name>, Positioned<GqlValue>)],
+    filter: Option<Box<Expr>>,
+    variables: &IndexMap<Name, GqlValue>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Function> {
+    let column = Expr::Identifier(Ident {
+        value: column.to_string(),
+        quote_style: Some(QUOTE_CHAR),
+    });
+    let argument = |key: &str| {
+        arguments
+            .iter()
+            .find(|(name, _)| name.node.as_str() == key)
+            .map(|(_, value)| &value.node)
+    };
+    let mut function_name = name.to_uppercase();
+    let mut duplicate_treatment = None;
+    let mut clauses = vec![];
+    let mut within_group = vec![];
+    let mut args = vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(column.clone()))];
     match name {
+        "count_distinct" => {
+            function_name = "COUNT".to_string();
+            duplicate_treatment = Some(DuplicateTreatment::Distinct);
+        }
+        "string_agg" => {
+            let delimiter = match argument("delimiter") {
+                Some(value @ (GqlValue::String(_) | GqlValue::Variable(_))) => value,
+                _ => return Err(anyhow!("string_agg requires a string delimiter")),
+            };
+            args.push(FunctionArg::Unnamed(FunctionArgExpr::Expr(get_value(
+                delimiter, sql_vars, final_vars,
+            )?)));
+            match argument("order") {
+                Some(GqlValue::Object(order)) => {
+                    clauses.push(FunctionArgumentClause::OrderBy(get_order(
+                        order, variables, sql_vars, final_vars,
+                    )?));
+                }
+                Some(_) => return Err(anyhow!("Invalid value for string_agg order")),
+                None => {}
+            }
+        }
+        "percentile_cont" => {
+            let fraction = match argument("fraction") {
+                Some(GqlValue::Number(n)) if (0.0..=1.0).contains(&n.as_f64().unwrap_or(-1.0)) => {
+                    GqlValue::Number(n.clone())
+                }
+                Some(value @ GqlValue::Variable(_)) => value.clone(),
+                _ => {
+                    return Err(anyhow!(
+                        "percentile_cont requires a fraction between 0 and 1"
+                    ))
+                }
+            };
+            args = vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(get_value(
+                &fraction, sql_vars, final_vars,
+            )?))];
+            within_group.push(OrderByExpr {
+                expr: column,
+                asc: None,
+                nulls_first: None,
+            });
+        }
+        _ => {}
+    }
+    Ok(Function {
+        within_group,
+        name: ObjectName(vec![Ident {
+            value: function_name,
+            quote_style: None,
+        }]),
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment,
+            clauses,
+            args,
+        }),
+        over: None,
+        filter,
+        null_treatment: None,
+    })
+}
+
+fn get_agg_agg_projection(
+    field: &Field,
+    table_name: &str,
+    variables: &IndexMap<Name, GqlValue>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+    null_policy: Option<NullPolicy>,
+) -> AnyResult<Vec<FunctionArg>> {
+    let name = field.name.node.as_ref();
+    let key = field
+        .alias
+        .as_ref()
+        .map_or(name, |alias| alias.node.as_ref());
+    Ok(match name {
         "__typename" => {
             vec![
                 FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
@@ -665,7 +1634,7 @@ fn get_agg_agg_projection(field: &Field, table_name: &str) -> Vec<FunctionArg> {
         "count" => {
             vec![
                 FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                    Value::SingleQuotedString(field.name.node.to_string()),
+                    Value::SingleQuotedString(key.to_string()),
                 ))),
                 FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Function(Function {
                     within_group: vec![],
@@ -679,26 +1648,39 @@ fn get_agg_agg_projection(field: &Field, table_name: &str) -> Vec<FunctionArg> {
                         args: vec![FunctionArg::Unnamed(FunctionArgExpr::Wildcard)],
                     }),
                     over: None,
-                    filter: None,
+                    filter: get_agg_filter(
+                        &field.arguments,
+                        variables,
+                        sql_vars,
+                        final_vars,
+                        null_policy,
+                    )?,
                     null_treatment: None,
                 }))),
             ]
         }
-        "min" | "max" | "avg" | "sum" => {
+        "min" | "max" | "avg" | "sum" | "stddev" | "variance" | "array_agg" | "string_agg"
+        | "percentile_cont" | "count_distinct" => {
+            let arguments = &field.arguments;
+            let filter = get_agg_filter(arguments, variables, sql_vars, final_vars, null_policy)?;
             let projection = field
                 .selection_set
                 .node
                 .items
                 .iter()
-                .flat_map(|arg| {
-                    if let Selection::Field(field) = &arg.node {
+                .map(|arg| {
+                    Ok(if let Selection::Field(field) = &arg.node {
                         let field = &field.node;
                         let field_name = field.name.node.as_ref();
+                        let field_key = field
+                            .alias
+                            .as_ref()
+                            .map_or(field_name, |alias| alias.node.as_ref());
                         match field_name {
                             "__typename" => {
                                 vec![
                                     FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                                        Value::SingleQuotedString(field_name.to_string()),
+                                        Value::SingleQuotedString(field_key.to_string()),
                                     ))),
                                     FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Function(
                                         Function {
@@ -728,43 +1710,33 @@ fn get_agg_agg_projection(field: &Field, table_name: &str) -> Vec<FunctionArg> {
                             _ => {
                                 vec![
                                     FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                                        Value::SingleQuotedString(field_name.to_string()),
+                                        Value::SingleQuotedString(field_key.to_string()),
                                     ))),
                                     FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Function(
-                                        Function {
-                                            within_group: vec![],
-                                            name: ObjectName(vec![Ident {
-                                                value: name.to_uppercase(),
-                                                quote_style: None,
-                                            }]),
-                                            args: FunctionArguments::List(FunctionArgumentList {
-                                                duplicate_treatment: None,
-                                                clauses: vec![],
-                                                args: vec![FunctionArg::Unnamed(
-                                                    FunctionArgExpr::Expr(Expr::Identifier(
-                                                        Ident {
-                                                            value: field_name.to_string(),
-                                                            quote_style: Some(QUOTE_CHAR),
-                                                        },
-                                                    )),
-                                                )],
-                                            }),
-                                            over: None,
-                                            filter: None,
-                                            null_treatment: None,
-                                        },
+                                        get_agg_function(
+                                            name,
+                                            field_name,
+                                            arguments,
+                                            filter.clone(),
+                                            variables,
+                                            sql_vars,
+                                            final_vars,
+                                        )?,
                                     ))),
                                 ]
                             }
                         }
                     } else {
                         vec![]
-                    }
+                    })
                 })
+                .collect::<AnyResult<Vec<Vec<FunctionArg>>>>()?
+                .into_iter()
+                .flatten()
                 .collect();
             vec![
                 FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                    Value::SingleQuotedString(field.name.node.to_string()),
+                    Value::SingleQuotedString(key.to_string()),
                 ))),
                 FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Function(Function {
                     within_group: vec![],
@@ -784,7 +1756,104 @@ fn get_agg_agg_projection(field: &Field, table_name: &str) -> Vec<FunctionArg> {
             ]
         }
         _ => vec![],
+    })
+}
+
+// `grouping(..)` of every key, a bitmask of the keys a ROLLUP, CUBE or GROUPING SETS row is a
+// subtotal over
+fn get_grouping(group_by: &[(String, Expr)]) -> Expr {
+    get_function_call(
+        GROUPING,
+        group_by.iter().map(|(_, expr)| expr.clone()).collect(),
+    )
+}
+
+// the column of a related row that a grouped value field joins on; the relation must be single
+// and reference the grouped column
+fn get_group_relation_key(
+    name: &str,
+    fks: Vec<String>,
+    pks: &[String],
+    is_single: bool,
+    pos: Pos,
+) -> AnyResult<String> {
+    if !is_single {
+        return Err(anyhow!(
+            "groupBy value {} must be a single relation at {}",
+            name,
+            pos
+        ));
     }
+    if !pks.is_empty() && pks != [name] {
+        return Err(anyhow!(
+            "groupBy value {} must reference its grouped column at {}",
+            name,
+            pos
+        ));
+    }
+    match <[String; 1]>::try_from(fks) {
+        Ok([fk]) => Ok(fk),
+        Err(fks) if fks.is_empty() => Ok(ID.to_string()),
+        Err(_) => Err(anyhow!(
+            "groupBy value {} must join on a single column at {}",
+            name,
+            pos
+        )),
+    }
+}
+
+// groupBy keys that are not selected in `value` and value fields that are not grouped, which are
+// left out of the result
+fn check_group_selection(value: &Field, group_by: &[(String, Expr)]) -> Option<Diagnostic> {
+    let selected = value
+        .selection_set
+        .node
+        .items
+        .iter()
+        .filter_map(|s| match &s.node {
+            Selection::Field(f)
+                if f.node.name.node.as_str() != TYPENAME
+                    && (f.node.name.node.as_str() != GROUPING
+                        || group_by.iter().any(|(key, _)| key == GROUPING)) =>
+            {
+                Some(f.node.name.node.as_str())
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let not_selected = group_by
+        .iter()
+        .map(|(key, _)| key.as_str())
+        .filter(|key| !selected.contains(key))
+        .collect::<Vec<_>>();
+    let not_grouped = selected
+        .iter()
+        .filter(|name| !group_by.iter().any(|(key, _)| key == *name))
+        .copied()
+        .collect::<Vec<_>>();
+    if not_selected.is_empty() && not_grouped.is_empty() {
+        return None;
+    }
+    let mut mismatches = vec![];
+    if !not_selected.is_empty() {
+        mismatches.push(format!(
+            "groupBy keys missing from value: {}",
+            not_selected.join(", ")
+        ));
+    }
+    if !not_grouped.is_empty() {
+        mismatches.push(format!(
+            "value fields missing from groupBy: {}",
+            not_grouped.join(", ")
+        ));
+    }
+    Some(Diagnostic::new(
+        format!(
+            "groupBy does not match value selection ({})",
+            mismatches.join("; ")
+        ),
+        Some(value.name.pos),
+    ))
 }
 
 fn get_aggregate_projection<'a>(
@@ -793,8 +1862,10 @@ fn get_aggregate_projection<'a>(
     group_by: Option<Vec<(String, Expr)>>,
     variables: &'a IndexMap<Name, GqlValue>,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
+    final_vars: &'a mut IndexMap<Name, String>,
     tags: &mut IndexMap<String, IndexSet<Tag>>,
+    warnings: &mut Vec<Diagnostic>,
+    options: &Options,
 ) -> AnyResult<Vec<FunctionArg>> {
     let mut aggs = if group_by.is_some() {
         let value = items.iter().find_map(|s| {
@@ -809,9 +1880,19 @@ fn get_aggregate_projection<'a>(
             }
         });
         if let Some(value) = &value {
+            warnings.extend(check_group_selection(
+                value,
+                group_by.as_deref().unwrap_or_default(),
+            ));
             vec![
                 FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                    Value::SingleQuotedString("value".to_string()),
+                    Value::SingleQuotedString(
+                        value
+                            .alias
+                            .as_ref()
+                            .map_or("value", |alias| alias.node.as_ref())
+                            .to_string(),
+                    ),
                 ))),
                 FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Function(Function {
             within_group: vec![],
@@ -827,9 +1908,15 @@ fn get_aggregate_projection<'a>(
                         .node
                         .items
                         .iter()
-                        .flat_map(|ss| {
+                        .map(|ss| {
                             if let Selection::Field(field) = &ss.node {
                                 let name = field.node.name.node.as_ref().to_string();
+                                let key = field
+                                    .node
+                                    .alias
+                                    .as_ref()
+                                    .map_or(name.as_str(), |alias| alias.node.as_ref())
+                                    .to_string();
 
                                 let this_group = group_by
                                     .clone()
@@ -837,31 +1924,45 @@ fn get_aggregate_projection<'a>(
                                     .into_iter()
                                     .find(|(key, _expr)| key == &name);
                                 if this_group.is_none() {
+                                    if name == GROUPING {
+                                        return Ok(vec![
+                                            FunctionArg::Unnamed(FunctionArgExpr::Expr(
+                                                Expr::Value(Value::SingleQuotedString(key)),
+                                            )),
+                                            FunctionArg::Unnamed(FunctionArgExpr::Expr(
+                                                get_grouping(group_by.as_deref().unwrap_or_default()),
+                                            )),
+                                        ]);
+                                    }
                                     return Ok::<Vec<FunctionArg>, anyhow::Error>(vec![]);
                                 }
-                                let (group_key, _group_expr) = this_group.unwrap();
+                                let (group_key, group_expr) = this_group.unwrap();
                                 if field.node.directives.is_empty() {
                                     Ok(vec![
                                         FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                                            Value::SingleQuotedString(name.clone()),
+                                            Value::SingleQuotedString(key),
                                         ))),
-                                        FunctionArg::Unnamed(FunctionArgExpr::Expr(
-                                            Expr::Identifier(Ident {
-                                                value: name,
-                                                quote_style: Some(QUOTE_CHAR),
-                                            }),
-                                        )),
+                                        FunctionArg::Unnamed(FunctionArgExpr::Expr(group_expr)),
                                     ])
                                 } else {
                                     let (
                                         relation,
-                                        _fks,
-                                        _pks,
-                                        _is_single,
+                                        fks,
+                                        pks,
+                                        is_single,
                                         _is_aggregate,
                                         _is_many,
                                         _schema_name,
+                                        _distinct_on,
+                                        _latest_by,
                                     ) = get_relation(&field.node.directives, sql_vars, final_vars)?;
+                                    let fk = get_group_relation_key(
+                                        &name,
+                                        fks,
+                                        &pks,
+                                        is_single,
+                                        field.pos,
+                                    )?;
                                     let (projection, joins, _merges) = get_projection(
                                         &field.node.selection_set.node.items,
                                         &relation,
@@ -870,6 +1971,8 @@ fn get_aggregate_projection<'a>(
                                         sql_vars,
                                         final_vars,
                                         tags,
+                                        warnings,
+                                        options,
                                     )?;
 
                                     let query = SetExpr::Select(Box::new(Select {
@@ -922,7 +2025,7 @@ fn get_aggregate_projection<'a>(
                                                             selection: Some(Expr::BinaryOp {
                                                                 left: Box::new(Expr::Identifier(
                                                                     Ident {
-                                                                        value: "id".to_string(),
+                                                                        value: fk,
                                                                         quote_style: Some(
                                                                             QUOTE_CHAR,
                                                                         ),
@@ -980,7 +2083,7 @@ fn get_aggregate_projection<'a>(
 
                                     Ok(vec![
                                         FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                                            Value::SingleQuotedString(name),
+                                            Value::SingleQuotedString(key),
                                         ))),
                                         FunctionArg::Unnamed(FunctionArgExpr::Expr(
                                             Expr::Function(Function {
@@ -1044,6 +2147,8 @@ fn get_aggregate_projection<'a>(
                                 Ok(vec![])
                             }
                         })
+                        .collect::<AnyResult<Vec<_>>>()?
+                        .into_iter()
                         .flatten()
                         .collect::<Vec<_>>(),
                     }),
@@ -1062,10 +2167,25 @@ fn get_aggregate_projection<'a>(
     for selection in items {
         match &selection.node {
             Selection::Field(field) => {
-                if field.node.name.node.as_ref() == "value" {
+                let name = field.node.name.node.as_ref();
+                if name == "value" {
                     continue;
                 }
-                aggs.extend(get_agg_agg_projection(&field.node, table_name));
+                if !AGGREGATE_FIELDS.contains(&name) {
+                    return Err(anyhow!(
+                        "{} is neither grouped nor aggregated, select it in value and groupBy at {}",
+                        name,
+                        field.pos
+                    ));
+                }
+                aggs.extend(get_agg_agg_projection(
+                    &field.node,
+                    table_name,
+                    variables,
+                    sql_vars,
+                    final_vars,
+                    options.null_policy,
+                )?);
             }
             Selection::FragmentSpread(_) => {
                 return Err(anyhow!(
@@ -1091,21 +2211,70 @@ fn get_join<'a>(
     kind: &'a str,
     variables: &'a IndexMap<Name, GqlValue>,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
+    final_vars: &'a mut IndexMap<Name, String>,
     parent: &'a str,
     tags: &'a mut IndexMap<String, IndexSet<Tag>>,
+    warnings: &mut Vec<Diagnostic>,
+    options: &Options,
 ) -> AnyResult<Join> {
-    let (selection, distinct, distinct_order, order_by, mut first, after, keys, group_by) =
-        parse_args(arguments, variables, sql_vars, final_vars)?;
-    let (relation, fks, pks, is_single, is_aggregate, is_many, schema_name) =
+    check_memory()?;
+    let (relation, fks, pks, is_single, is_aggregate, is_many, schema_name, distinct_on, latest_by) =
         get_relation(directives, sql_vars, final_vars)?;
+    let key_columns = get_key_columns(directives, selection_items, &relation, sql_vars, options)?;
+    let (
+        mut selection,
+        mut distinct,
+        mut distinct_order,
+        mut order_by,
+        mut first,
+        after,
+        keys,
+        group_by,
+        locks,
+        sample,
+    ) = parse_args(
+        arguments,
+        variables,
+        sql_vars,
+        final_vars,
+        &key_columns,
+        options,
+        &mut FilterScope {
+            table: relation.clone(),
+            qualifier: schema_name
+                .iter()
+                .chain(std::iter::once(&relation))
+                .map(|name| Ident::with_quote(QUOTE_CHAR, name))
+                .collect(),
+            items: selection_items,
+            tags,
+            options,
+        },
+    )?;
+    let columns = get_columns(selection_items, sql_vars)?;
+    rename_columns(&mut selection, &mut order_by, &columns);
+    options
+        .directives
+        .filter(name, &relation, directives, sql_vars, &mut selection)?;
+    if let Some(distinct_order) = distinct_order.as_mut() {
+        rename_columns(&mut None, distinct_order, &columns);
+    }
+    // `Child_aggregate` next to `Child` gets its own join name and a scalar aggregate join
+    let (kind, is_aggregate) = kind
+        .strip_suffix("_aggregate")
+        .map_or((kind, is_aggregate), |kind| (kind, true));
+    if distinct.is_none() && distinct_on.is_some() {
+        distinct = distinct_on;
+        distinct_order = distinct_order.or(latest_by);
+    }
     if is_single {
         first = Some(Expr::Value(Value::Number("1".to_string(), false)));
     }
+    let tag_key = get_tag_key(&relation, schema_name.as_deref(), options);
     if let Some(keys) = keys {
-        tags.insert(relation.clone(), keys.into_iter().collect());
+        tags.insert(tag_key.clone(), keys.into_iter().collect());
     } else {
-        tags.insert(relation.clone(), IndexSet::new());
+        tags.insert(tag_key.clone(), IndexSet::new());
     };
 
     let table_name = schema_name.as_ref().map_or_else(
@@ -1129,6 +2298,15 @@ fn get_join<'a>(
         },
     );
 
+    // the junction table of a many-to-many relation lives in the same schema as the relation
+    let qualify = |table: &str| {
+        schema_name
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once(table))
+            .map(|name| Ident::with_quote(QUOTE_CHAR, name))
+            .collect::<Vec<_>>()
+    };
     let sub_path = path.map_or_else(|| relation.to_string(), |v| format!("{v}.{relation}"));
     let mut additional_select_items = vec![];
     let mut join_name = None;
@@ -1182,30 +2360,9 @@ fn get_join<'a>(
                             value: None,
                         });
                     }
-                    if let Some(v) = tags.get_mut(name) {
-                        v.extend(new_tags);
-                    } else {
-                        tags.insert(relation.clone(), new_tags);
-                    };
-                    let mut identifier = vec![
-                        Ident {
-                            value: relation.to_string(),
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                        Ident {
-                            value: fk,
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                    ];
-                    if let Some(schema_name) = schema_name.as_ref() {
-                        identifier.insert(
-                            0,
-                            Ident {
-                                value: schema_name.clone(),
-                                quote_style: Some(QUOTE_CHAR),
-                            },
-                        );
-                    }
+                    tags.entry(tag_key.clone()).or_default().extend(new_tags);
+                    let mut identifier = qualify(&relation);
+                    identifier.push(Ident::with_quote(QUOTE_CHAR, fk));
                     Expr::BinaryOp {
                         left: Box::new(Expr::CompoundIdentifier(identifier)),
                         op: BinaryOperator::Eq,
@@ -1234,42 +2391,20 @@ fn get_join<'a>(
             } else {
                 ("B", "A")
             };
+            let column = |table: &str, column: &str| {
+                let mut identifier = qualify(table);
+                identifier.push(Ident::with_quote(QUOTE_CHAR, column));
+                Expr::CompoundIdentifier(identifier)
+            };
             Some(Expr::BinaryOp {
                 left: Box::new(Expr::BinaryOp {
-                    left: Box::new(Expr::CompoundIdentifier(vec![
-                        Ident {
-                            value: join_name.to_string(),
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                        Ident {
-                            value: join_col.to_string(),
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                    ])),
+                    left: Box::new(column(join_name, join_col)),
                     op: BinaryOperator::Eq,
-                    right: Box::new(Expr::CompoundIdentifier(vec![
-                        Ident {
-                            value: relation.clone(),
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                        Ident {
-                            value: "id".to_string(),
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                    ])),
+                    right: Box::new(column(&relation, ID)),
                 }),
                 op: BinaryOperator::And,
                 right: Box::new(Expr::BinaryOp {
-                    left: Box::new(Expr::CompoundIdentifier(vec![
-                        Ident {
-                            value: join_name.to_string(),
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                        Ident {
-                            value: value_col.to_string(),
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                    ])),
+                    left: Box::new(column(join_name, value_col)),
                     op: BinaryOperator::Eq,
                     right: Box::new(Expr::CompoundIdentifier(vec![
                         Ident {
@@ -1286,7 +2421,7 @@ fn get_join<'a>(
         },
     );
 
-    let sub_query = get_filter_query(
+    let mut sub_query = get_filter_query(
         selection.map_or_else(
             || join_filter.clone(),
             |s| {
@@ -1305,62 +2440,66 @@ fn get_join<'a>(
         after,
         join_name.map_or_else(
             || vec![table_name.clone()],
-            |name| {
-                vec![
-                    table_name.clone(),
-                    ObjectName(vec![Ident {
-                        value: name,
-                        quote_style: Some(QUOTE_CHAR),
-                    }]),
-                ]
-            },
+            |name| vec![table_name.clone(), ObjectName(qualify(&name))],
         ),
+        get_function_args("relation", directives, sql_vars, final_vars)?,
         distinct,
         distinct_order,
     );
+    sub_query.locks = locks;
+    if let Some(sample) = sample {
+        set_sample(&mut sub_query, sample);
+    }
     if is_aggregate {
         let aggs = get_aggregate_projection(
             selection_items,
             kind,
-            group_by.clone(),
+            group_by.as_ref().map(|group_by| group_by.keys.clone()),
             variables,
             sql_vars,
             final_vars,
             tags,
+            warnings,
+            options,
         )?;
+        let is_grouped = group_by.is_some();
+        let mut subquery = Query {
+            for_clause: None,
+            limit_by: vec![],
+            with: None,
+            body: Box::new(get_agg_query(
+                aggs,
+                vec![TableWithJoins {
+                    relation: TableFactor::Derived {
+                        lateral: false,
+                        subquery: Box::new(sub_query),
+                        alias: Some(TableAlias {
+                            name: Ident {
+                                value: sub_path,
+                                quote_style: Some(QUOTE_CHAR),
+                            },
+                            columns: vec![],
+                        }),
+                    },
+                    joins: vec![],
+                }],
+                None,
+                name,
+                group_by,
+            )),
+            order_by: vec![],
+            limit: None,
+            offset: None,
+            fetch: None,
+            locks: vec![],
+        };
+        if is_grouped {
+            subquery = get_groups_list(subquery, name);
+        }
         Ok(Join {
             relation: TableFactor::Derived {
                 lateral: true,
-                subquery: Box::new(Query {
-                    for_clause: None,
-                    limit_by: vec![],
-                    with: None,
-                    body: Box::new(get_agg_query(
-                        aggs,
-                        vec![TableWithJoins {
-                            relation: TableFactor::Derived {
-                                lateral: false,
-                                subquery: Box::new(sub_query),
-                                alias: Some(TableAlias {
-                                    name: Ident {
-                                        value: sub_path,
-                                        quote_style: Some(QUOTE_CHAR),
-                                    },
-                                    columns: vec![],
-                                }),
-                            },
-                            joins: vec![],
-                        }],
-                        None,
-                        name,
-                        group_by,
-                    )),
-                    order_by: vec![],
-                    limit: None,
-                    offset: None,
-                    fetch: None,
-                    locks: vec![],
-                }),
+                subquery: Box::new(subquery),
                 alias: Some(TableAlias {
                     name: Ident {
                         value: format!("{name}.{relation}"),
@@ -1382,6 +2521,8 @@ fn get_join<'a>(
             sql_vars,
             final_vars,
             tags,
+            warnings,
+            options,
         )?;
         additional_select_items.extend(sub_projection);
         Ok(Join {
@@ -1454,7 +2595,10 @@ fn get_static<'a>(
                 .ok_or_else(|| anyhow!("static value not found"))?;
             let value = match &value.node {
                 GqlValue::String(value) => value.to_string(),
-                GqlValue::Number(value) => value.as_i64().expect("value is not an int").to_string(),
+                GqlValue::Number(value) => value
+                    .as_i64()
+                    .ok_or_else(|| anyhow!("static value must be an integer, got {}", value))?
+                    .to_string(),
                 GqlValue::Variable(name) => {
                     if let Some(value) = sql_vars.get(name) {
                         value.to_string()
@@ -1479,57 +2623,274 @@ fn get_static<'a>(
     Ok(None)
 }
 
-fn parse_skip<'a>(directive: &'a Directive, sql_vars: &'a IndexMap<Name, JsonValue>) -> bool {
-    if let Some((_, value_pos)) = directive.arguments.iter().find(|&arg| arg.0.node == "if") {
-        let value = &value_pos.node;
-        match value {
-            GqlValue::Variable(v) => {
-                if sql_vars.contains_key(v) {
-                    let var_value = sql_vars
-                        .get(v)
-                        .expect("variable not found, gaurded by contains");
-                    if let JsonValue::Bool(b) = var_value {
-                        return *b;
-                    }
-                    return false;
-                }
-                return false;
-            }
-            GqlValue::Boolean(b) => {
-                return *b;
+fn get_default<'a>(
+    directives: &'a [Positioned<Directive>],
+    sql_vars: &'a mut IndexMap<Name, JsonValue>,
+    final_vars: &'a mut IndexMap<Name, String>,
+) -> AnyResult<Option<Expr>> {
+    if let Some(p_directive) = directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == "default")
+    {
+        let (_, value) = p_directive
+            .node
+            .arguments
+            .iter()
+            .find(|(name, _)| name.node.as_ref() == "value")
+            .ok_or_else(|| anyhow!("default value not found"))?;
+        return Ok(Some(get_value(&value.node, sql_vars, final_vars)?));
+    }
+    Ok(None)
+}
+
+fn get_column<'a>(
+    directives: &'a [Positioned<Directive>],
+    sql_vars: &'a mut IndexMap<Name, JsonValue>,
+) -> AnyResult<Option<String>> {
+    if let Some(p_directive) = directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == "column")
+    {
+        let (_, value) = p_directive
+            .node
+            .arguments
+            .iter()
+            .find(|(name, _)| name.node.as_ref() == "name")
+            .ok_or_else(|| {
+                anyhow!(
+                    "@column is missing required argument \"name\" at {}",
+                    p_directive.pos
+                )
+            })?;
+        return Ok(Some(value_to_string(&value.node, sql_vars)?));
+    }
+    Ok(None)
+}
+
+fn get_similarity_directive(
+    directives: &[Positioned<Directive>],
+    path: Option<&str>,
+    sql_vars: &IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Option<Expr>> {
+    let Some(p_directive) = directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == "similarity")
+    else {
+        return Ok(None);
+    };
+    let similarity = p_directive
+        .node
+        .arguments
+        .iter()
+        .map(|(name, value)| (name.node.clone(), value.node.clone()))
+        .collect();
+    Ok(Some(get_similarity(
+        &similarity,
+        path,
+        sql_vars,
+        final_vars,
+    )?))
+}
+
+// what the fields renamed by @column or computed by @static select, by field name
+fn get_columns(
+    items: &[Positioned<Selection>],
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+) -> AnyResult<IndexMap<String, Expr>> {
+    let mut columns = IndexMap::new();
+    for selection in items {
+        if let Selection::Field(field) = &selection.node {
+            let field = &field.node;
+            if let Some(column) = get_column(&field.directives, sql_vars)? {
+                columns.insert(
+                    field.name.node.to_string(),
+                    Expr::Identifier(Ident::with_quote(QUOTE_CHAR, column)),
+                );
+            } else if let Some(SelectItem::ExprWithAlias { expr, .. }) =
+                get_static(&field.name.node, &field.directives, sql_vars)?
+            {
+                // a bare string constant can't be ordered by
+                columns.insert(
+                    field.name.node.to_string(),
+                    Expr::Cast {
+                        kind: CastKind::DoubleColon,
+                        expr: Box::new(expr),
+                        data_type: DataType::Text,
+                        format: None,
+                    },
+                );
             }
-            _ => {
-                return false;
+        }
+    }
+    Ok(columns)
+}
+
+// filters and order reference fields by their GraphQL name, so map them onto @column names and
+// @static values
+fn rename_columns(
+    selection: &mut Option<Expr>,
+    order_by: &mut [OrderByExpr],
+    columns: &IndexMap<String, Expr>,
+) {
+    if columns.is_empty() {
+        return;
+    }
+    let mut rename = |expr: &mut Expr| {
+        if let Expr::Identifier(ident) = expr {
+            if let Some(column) = columns.get(&ident.value) {
+                *expr = column.clone();
             }
         }
+        ControlFlow::<()>::Continue(())
+    };
+    if let Some(selection) = selection {
+        let _ = visit_expressions_mut(selection, &mut rename);
+    }
+    for order in order_by {
+        let _ = visit_expressions_mut(&mut order.expr, &mut rename);
+    }
+}
+
+fn with_default(expr: Expr, default: Expr) -> Expr {
+    Expr::Function(Function {
+        within_group: vec![],
+        name: ObjectName(vec![Ident {
+            value: "coalesce".to_string(),
+            quote_style: None,
+        }]),
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment: None,
+            clauses: vec![],
+            args: vec![
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)),
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(default)),
+            ],
+        }),
+        over: None,
+        filter: None,
+        null_treatment: None,
+    })
+}
+
+// the `if` argument of `@skip` or `@include`, when it is a boolean or a boolean variable
+fn parse_if(directive: &Directive, sql_vars: &IndexMap<Name, JsonValue>) -> Option<bool> {
+    let (_, value) = directive.arguments.iter().find(|arg| arg.0.node == "if")?;
+    match &value.node {
+        GqlValue::Variable(v) => match sql_vars.get(v) {
+            Some(JsonValue::Bool(b)) => Some(*b),
+            _ => None,
+        },
+        GqlValue::Boolean(b) => Some(*b),
+        _ => None,
     }
-    false
 }
 
+/// Whether `@skip(if: true)` or `@include(if: false)` leaves the field out of the response.
 fn has_skip<'a>(field: &'a Field, sql_vars: &'a IndexMap<Name, JsonValue>) -> bool {
-    if let Some(directive) = field
+    field
         .directives
         .iter()
-        .find(|&x| x.node.name.node == "skip")
-    {
-        return parse_skip(&directive.node, sql_vars);
+        .any(|directive| match directive.node.name.node.as_str() {
+            "skip" => parse_if(&directive.node, sql_vars) == Some(true),
+            "include" => parse_if(&directive.node, sql_vars) == Some(false),
+            _ => false,
+        })
+}
+
+// the directives that make fields differ: `@skip` and `@include` are resolved before merging
+fn kept_directive(
+    directive: &Positioned<Directive>,
+) -> Option<(&Name, &[(Positioned<Name>, Positioned<GqlValue>)])> {
+    let name = &directive.node.name.node;
+    (!matches!(name.as_str(), "skip" | "include")).then_some((name, &directive.node.arguments))
+}
+
+fn same_directives(a: &[Positioned<Directive>], b: &[Positioned<Directive>]) -> bool {
+    a.iter()
+        .filter_map(kept_directive)
+        .eq(b.iter().filter_map(kept_directive))
+}
+
+/// Merges fields selected more than once under the same response key, as GraphQL does: the
+/// selections of a repeated field are combined into the first one. Fields left out by `@skip`
+/// or `@include` are dropped first. Fields that share a key but differ in name, arguments or
+/// other directives would overwrite each other in the response, so they are an error.
+fn merge_fields<'a>(
+    items: &'a [Positioned<Selection>],
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<Vec<Cow<'a, Positioned<Selection>>>> {
+    // only the fields that selections are merged into are cloned
+    let mut merged: Vec<Cow<'a, Positioned<Selection>>> = Vec::with_capacity(items.len());
+    let mut keys: HashMap<&str, usize> = HashMap::new();
+    for selection in items {
+        let Selection::Field(field) = &selection.node else {
+            merged.push(Cow::Borrowed(selection));
+            continue;
+        };
+        if has_skip(&field.node, sql_vars) {
+            continue;
+        }
+        let key = field.node.response_key().node.as_str();
+        let Some(&index) = keys.get(key) else {
+            keys.insert(key, merged.len());
+            merged.push(Cow::Borrowed(selection));
+            continue;
+        };
+        let Selection::Field(first) = &mut merged[index].to_mut().node else {
+            unreachable!("response keys only index fields");
+        };
+        let (first, field) = (&mut first.node, &field.node);
+        if first.name.node != field.name.node
+            || first.arguments != field.arguments
+            || !same_directives(&first.directives, &field.directives)
+        {
+            return Err(anyhow!(
+                "Fields \"{key}\" conflict because they select different fields, arguments or directives at {}",
+                selection.pos
+            ));
+        }
+        first
+            .selection_set
+            .node
+            .items
+            .extend(field.selection_set.node.items.iter().cloned());
+    }
+    Ok(merged)
+}
+
+/// Names a nested join after where it sits in the query, e.g. `join.base.Component` or
+/// `join.base.Component.Element`, numbering repeats of the same field (`join.base.Component.2`).
+/// These names appear in the generated SQL and are kept stable across releases; names over
+/// the 63-byte Postgres limit are shortened when planning.
+fn get_join_name(path: Option<&str>, kind: &str, used: &mut HashMap<String, usize>) -> String {
+    let name = format!("join.{}.{kind}", path.unwrap_or(BASE));
+    let count = used.entry(name.clone()).or_insert(0);
+    *count += 1;
+    if *count == 1 {
+        name
+    } else {
+        format!("{name}.{count}")
     }
-    false
 }
 
 fn get_projection<'a>(
-    items: &'a Vec<Positioned<Selection>>,
+    items: &'a [Positioned<Selection>],
     relation: &'a str,
     path: Option<&'a str>,
     variables: &'a IndexMap<Name, GqlValue>,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
+    final_vars: &'a mut IndexMap<Name, String>,
     tags: &mut IndexMap<String, IndexSet<Tag>>,
+    warnings: &mut Vec<Diagnostic>,
+    options: &Options,
 ) -> AnyResult<(Vec<SelectItem>, Vec<Join>, Vec<Merge>)> {
     let mut projection = vec![];
     let mut joins = vec![];
     let mut merges = vec![];
-    for selection in items {
+    let mut join_names = HashMap::new();
+    let items = merge_fields(items, sql_vars)?;
+    for selection in items.iter() {
         let selection = &selection.node;
         match selection {
             Selection::Field(field) => {
@@ -1537,12 +2898,86 @@ fn get_projection<'a>(
                 if has_skip(field, sql_vars) {
                     continue;
                 }
+                if let Some(expr) = get_json_field(field, relation, path, sql_vars, options)? {
+                    projection.push(SelectItem::ExprWithAlias {
+                        expr,
+                        alias: Ident::with_quote(
+                            QUOTE_CHAR,
+                            field.alias.as_ref().unwrap_or(&field.name).node.to_string(),
+                        ),
+                    });
+                    continue;
+                }
                 if field.selection_set.node.items.is_empty() {
                     if let Some(value) = get_static(&field.name.node, &field.directives, sql_vars)?
                     {
                         projection.push(value);
                         continue;
                     }
+                    if let Some(similarity) =
+                        get_similarity_directive(&field.directives, path, sql_vars, final_vars)?
+                    {
+                        projection.push(SelectItem::ExprWithAlias {
+                            expr: similarity,
+                            alias: Ident {
+                                value: field.alias.as_ref().map_or_else(
+                                    || field.name.node.to_string(),
+                                    |alias| alias.node.to_string(),
+                                ),
+                                quote_style: Some(QUOTE_CHAR),
+                            },
+                        });
+                        continue;
+                    }
+                    let column = get_column(&field.directives, sql_vars)?;
+                    let default = get_default(&field.directives, sql_vars, final_vars)?;
+                    let format = get_format(&field.directives, sql_vars)?;
+                    let is_handled = options.directives.has_handler(&field.directives);
+                    if column.is_some() || default.is_some() || format.is_some() || is_handled {
+                        let column = Ident {
+                            value: column.unwrap_or_else(|| field.name.node.to_string()),
+                            quote_style: Some(QUOTE_CHAR),
+                        };
+                        let expr = path.map_or_else(
+                            || Expr::Identifier(column.clone()),
+                            |path| {
+                                Expr::CompoundIdentifier(vec![
+                                    Ident {
+                                        value: path.to_string(),
+                                        quote_style: Some(QUOTE_CHAR),
+                                    },
+                                    column.clone(),
+                                ])
+                            },
+                        );
+                        let expr = options
+                            .directives
+                            .project(field, relation, expr, sql_vars)?;
+                        let expr = match default {
+                            Some(default) => with_default(expr, default),
+                            None => expr,
+                        };
+                        projection.push(SelectItem::ExprWithAlias {
+                            expr: match format {
+                                Some((date, timezone)) => {
+                                    let is_naive = options
+                                        .timestamp_columns
+                                        .get(relation)
+                                        .is_some_and(|columns| columns.contains(&column.value));
+                                    with_format(expr, &date, &timezone, is_naive)
+                                }
+                                None => expr,
+                            },
+                            alias: Ident {
+                                value: field.alias.as_ref().map_or_else(
+                                    || field.name.node.to_string(),
+                                    |alias| alias.node.to_string(),
+                                ),
+                                quote_style: Some(QUOTE_CHAR),
+                            },
+                        });
+                        continue;
+                    }
                     match &field.alias {
                         Some(alias) => {
                             projection.push(SelectItem::ExprWithAlias {
@@ -1665,12 +3100,8 @@ fn get_projection<'a>(
                         },
                     });
                 } else {
-                    let mut hasher = DefaultHasher::new();
-                    let arg_bytes = serde_json::to_vec(&field.arguments)?;
-                    hasher.write(&arg_bytes);
-                    let hash_str = format!("{:x}", hasher.finish());
                     let kind = field.name.node.as_ref();
-                    let name = format!("join.{}.{}", kind, &hash_str[..13]);
+                    let name = get_join_name(path, kind, &mut join_names);
                     let join = get_join(
                         &field.arguments,
                         &field.directives,
@@ -1683,15 +3114,21 @@ fn get_projection<'a>(
                         final_vars,
                         relation,
                         tags,
+                        warnings,
+                        options,
                     )?;
                     joins.push(join);
+                    let mut expr = Expr::Identifier(Ident {
+                        value: name,
+                        quote_style: Some(QUOTE_CHAR),
+                    });
+                    if let Some(default) = get_default(&field.directives, sql_vars, final_vars)? {
+                        expr = with_default(expr, default);
+                    }
                     match &field.alias {
                         Some(alias) => {
                             projection.push(SelectItem::ExprWithAlias {
-                                expr: Expr::Identifier(Ident {
-                                    value: name,
-                                    quote_style: Some(QUOTE_CHAR),
-                                }),
+                                expr,
                                 alias: Ident {
                                     value: alias.node.to_string(),
                                     quote_style: Some(QUOTE_CHAR),
@@ -1700,10 +3137,7 @@ fn get_projection<'a>(
                         }
                         None => {
                             projection.push(SelectItem::ExprWithAlias {
-                                expr: Expr::Identifier(Ident {
-                                    value: name,
-                                    quote_style: Some(QUOTE_CHAR),
-                                }),
+                                expr,
                                 alias: Ident {
                                     value: field.name.node.to_string(),
                                     quote_style: Some(QUOTE_CHAR),
@@ -1721,8 +3155,17 @@ fn get_projection<'a>(
                         .directives
                         .iter()
                         .find(|d| d.node.name.node.as_ref() == "args");
-                    let (relation, _fks, _pks, _is_single, _is_aggregate, _is_many, schema_name) =
-                        get_relation(&frag.directives, sql_vars, final_vars)?;
+                    let (
+                        relation,
+                        _fks,
+                        _pks,
+                        _is_single,
+                        _is_aggregate,
+                        _is_many,
+                        schema_name,
+                        _distinct_on,
+                        _latest_by,
+                    ) = get_relation(&frag.directives, sql_vars, final_vars)?;
                     let join = get_join(
                         args.map_or(&vec![], |dir| &dir.node.arguments),
                         &frag.directives,
@@ -1735,6 +3178,8 @@ fn get_projection<'a>(
                         final_vars,
                         &relation,
                         tags,
+                        warnings,
+                        options,
                     )?;
                     joins.push(join);
                     let table_name = schema_name.map_or_else(
@@ -1783,6 +3228,207 @@ fn get_projection<'a>(
     Ok((projection, joins, merges))
 }
 
+// returns the `date` style and the time zone of a `@format` directive
+fn get_format<'a>(
+    directives: &'a [Positioned<Directive>],
+    sql_vars: &'a mut IndexMap<Name, JsonValue>,
+) -> AnyResult<Option<(String, String)>> {
+    let Some(p_directive) = directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == "format")
+    else {
+        return Ok(None);
+    };
+    let mut date = None;
+    let mut timezone = "UTC".to_string();
+    for (name, value) in &p_directive.node.arguments {
+        match name.node.as_str() {
+            "date" => date = Some(value_to_string(&value.node, sql_vars)?),
+            "timezone" => timezone = value_to_string(&value.node, sql_vars)?,
+            _ => {}
+        }
+    }
+    let date = date.ok_or_else(|| {
+        anyhow!(
+            "@format is missing required argument \"date\" at {}",
+            p_directive.pos
+        )
+    })?;
+    if !["iso8601", "epoch_ms"].contains(&date.as_str()) {
+        return Err(anyhow!(
+            "@format date must be one of iso8601 or epoch_ms, got {}",
+            date
+        ));
+    }
+    // the time zone is written into the statement as is
+    if timezone.is_empty()
+        || !timezone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/_+-:".contains(c))
+    {
+        return Err(anyhow!("Invalid time zone: {}", timezone));
+    }
+    Ok(Some((date, timezone)))
+}
+
+// the keys of a `@json(path: "theme.colors")`, which are written into the statement as is
+fn get_json_path_keys(
+    directive: Option<&Positioned<Directive>>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+) -> AnyResult<Vec<String>> {
+    let Some((_, value)) = directive.and_then(|directive| {
+        directive
+            .node
+            .arguments
+            .iter()
+            .find(|(name, _)| name.node.as_str() == "path")
+    }) else {
+        return Ok(vec![]);
+    };
+    let path = value_to_string(&value.node, sql_vars)?;
+    let keys = path.split('.').map(ToString::to_string).collect::<Vec<_>>();
+    if keys.iter().any(|key| {
+        key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }) {
+        return Err(anyhow!(
+            "@json path must be keys of letters, digits, _ or - separated by dots, got {}",
+            path
+        ));
+    }
+    Ok(keys)
+}
+
+// `"settings" #> '{theme,colors}'`, or the column itself for an empty path
+fn get_json_value(column: &Expr, keys: &[String]) -> Expr {
+    if keys.is_empty() {
+        return column.clone();
+    }
+    Expr::BinaryOp {
+        left: Box::new(column.clone()),
+        op: BinaryOperator::HashArrow,
+        right: Box::new(Expr::Value(Value::SingleQuotedString(format!(
+            "{{{}}}",
+            keys.join(",")
+        )))),
+    }
+}
+
+// the fields of a selection set over a json value, null when the value is
+fn get_json_object(
+    column: &Expr,
+    keys: &[String],
+    items: &[Positioned<Selection>],
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<Expr> {
+    let mut args = vec![];
+    for selection in items {
+        let Selection::Field(field) = &selection.node else {
+            return Err(anyhow!("json fields must be selected by name"));
+        };
+        let field = &field.node;
+        if has_skip(field, sql_vars) {
+            continue;
+        }
+        let key = field.alias.as_ref().unwrap_or(&field.name).node.to_string();
+        let mut keys = keys.to_vec();
+        keys.push(field.name.node.to_string());
+        let value = if field.selection_set.node.items.is_empty() {
+            get_json_value(column, &keys)
+        } else {
+            get_json_object(column, &keys, &field.selection_set.node.items, sql_vars)?
+        };
+        args.push(Expr::Value(Value::SingleQuotedString(key)));
+        args.push(value);
+    }
+    Ok(Expr::Case {
+        operand: None,
+        conditions: vec![Expr::IsNotNull(Box::new(get_json_value(column, keys)))],
+        results: vec![get_function_call(JSONB_BUILD_OBJECT, args)],
+        else_result: Some(Box::new(Expr::Value(Value::Null))),
+    })
+}
+
+// a field with `@json`, or with a selection set over a column of `Options::json_columns`
+fn get_json_field(
+    field: &Field,
+    relation: &str,
+    path: Option<&str>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    options: &Options,
+) -> AnyResult<Option<Expr>> {
+    let directive = field
+        .directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == "json");
+    let items = &field.selection_set.node.items;
+    let column =
+        get_column(&field.directives, sql_vars)?.unwrap_or_else(|| field.name.node.to_string());
+    let is_declared = !items.is_empty()
+        && options
+            .json_columns
+            .get(relation)
+            .is_some_and(|columns| columns.contains(&column));
+    if directive.is_none() && !is_declared {
+        return Ok(None);
+    }
+    let keys = get_json_path_keys(directive, sql_vars)?;
+    let column = Ident::with_quote(QUOTE_CHAR, column);
+    let column = match path {
+        Some(path) => Expr::CompoundIdentifier(vec![Ident::with_quote(QUOTE_CHAR, path), column]),
+        None => Expr::Identifier(column),
+    };
+    if items.is_empty() {
+        return Ok(Some(get_json_value(&column, &keys)));
+    }
+    get_json_object(&column, &keys, items, sql_vars).map(Some)
+}
+
+// epoch_ms is time zone independent, iso8601 renders the wall time in `timezone`. A timestamp
+// without time zone holds a UTC wall time, which is only converted for other time zones.
+fn with_format(expr: Expr, date: &str, timezone: &str, is_naive: bool) -> Expr {
+    if date == "epoch_ms" {
+        return Expr::Cast {
+            kind: CastKind::DoubleColon,
+            expr: Box::new(Expr::Nested(Box::new(Expr::BinaryOp {
+                left: Box::new(Expr::Extract {
+                    field: DateTimeField::Epoch,
+                    expr: Box::new(expr),
+                }),
+                op: BinaryOperator::Multiply,
+                right: Box::new(Expr::Value(Value::Number("1000".to_string(), false))),
+            }))),
+            data_type: DataType::BigInt(None),
+            format: None,
+        };
+    }
+    let pattern = if timezone == "UTC" {
+        format!(r#"{ISO8601_FORMAT}"Z""#)
+    } else {
+        ISO8601_FORMAT.to_string()
+    };
+    let timestamp = match (is_naive, timezone) {
+        (true, "UTC") => expr,
+        (true, _) => Expr::AtTimeZone {
+            timestamp: Box::new(Expr::AtTimeZone {
+                timestamp: Box::new(expr),
+                time_zone: "UTC".to_string(),
+            }),
+            time_zone: timezone.to_string(),
+        },
+        (false, _) => Expr::AtTimeZone {
+            timestamp: Box::new(expr),
+            time_zone: timezone.to_string(),
+        },
+    };
+    get_function_call(
+        TO_CHAR,
+        vec![timestamp, Expr::Value(Value::SingleQuotedString(pattern))],
+    )
+}
+
 fn value_to_string<'a>(
     value: &'a GqlValue,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
@@ -1819,7 +3465,7 @@ fn value_to_string<'a>(
 fn get_relation<'a>(
     directives: &'a [Positioned<Directive>],
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    _final_vars: &'a IndexSet<Name>,
+    _final_vars: &'a IndexMap<Name, String>,
 ) -> AnyResult<(
     String,
     Vec<String>,
@@ -1828,6 +3474,8 @@ fn get_relation<'a>(
     bool,
     bool,
     Option<String>,
+    Option<Vec<String>>,
+    Option<Vec<OrderByExpr>>,
 )> {
     let mut relation: String = String::new();
     let mut fk = vec![];
@@ -1836,6 +3484,8 @@ fn get_relation<'a>(
     let mut is_aggregate = false;
     let mut is_many = false;
     let mut schema_name = None;
+    let mut distinct_on = None;
+    let mut latest_by = None;
     if let Some(p_directive) = directives
         .iter()
         .find(|d| d.node.name.node.as_str() == "relation")
@@ -1847,7 +3497,7 @@ fn get_relation<'a>(
                 let name = name.node.as_str();
                 let value = &value.node;
                 match name {
-                    "table" => relation = value_to_string(value, sql_vars)?,
+                    "table" | "function" => relation = value_to_string(value, sql_vars)?,
                     "schema" => schema_name = Some(value_to_string(value, sql_vars)?),
                     "field" | "fields" => {
                         fk = match &value {
@@ -1888,10 +3538,51 @@ fn get_relation<'a>(
                             is_many = *b;
                         }
                     }
+                    "distinctOn" => {
+                        distinct_on = match value {
+                            GqlValue::String(s) => Some(vec![s.clone()]),
+                            GqlValue::List(list) => get_distinct(list, sql_vars),
+                            _ => {
+                                return Err(anyhow!("Invalid value for distinctOn in relation"));
+                            }
+                        }
+                    }
+                    "latestBy" => {
+                        latest_by = Some(vec![OrderByExpr {
+                            expr: Expr::Identifier(Ident {
+                                value: value_to_string(value, sql_vars)?,
+                                quote_style: Some(QUOTE_CHAR),
+                            }),
+                            asc: Some(false),
+                            nulls_first: Some(false),
+                        }]);
+                    }
                     _ => {}
                 }
             }
         }
+        if relation.is_empty() {
+            return Err(anyhow!(
+                "@relation is missing required argument \"table\" at {}",
+                p_directive.pos
+            ));
+        }
+        if fk.len() != pk.len() {
+            return Err(anyhow!(
+                "@relation on \"{}\" has {} fields but {} references at {}",
+                relation,
+                fk.len(),
+                pk.len(),
+                p_directive.pos
+            ));
+        }
+        if latest_by.is_some() && distinct_on.is_none() {
+            return Err(anyhow!(
+                "@relation on \"{}\" has latestBy without distinctOn at {}",
+                relation,
+                p_directive.pos
+            ));
+        }
     }
     Ok((
         relation,
@@ -1901,6 +3592,8 @@ fn get_relation<'a>(
         is_aggregate,
         is_many,
         schema_name,
+        distinct_on,
+        latest_by,
     ))
 }
 
@@ -1910,6 +3603,7 @@ fn get_filter_query(
     first: Option<Expr>,
     after: Option<Offset>,
     table_names: Vec<ObjectName>,
+    mut table_args: Option<Vec<FunctionArg>>,
     distinct: Option<Vec<String>>,
     distinct_order: Option<Vec<OrderByExpr>>,
 ) -> Query {
@@ -1965,7 +3659,7 @@ fn get_filter_query(
                         version: None,
                         name: table_name,
                         alias: None,
-                        args: None,
+                        args: table_args.take(),
                         with_hints: vec![],
                     },
                     joins: vec![],
@@ -2040,11 +3734,28 @@ fn get_filter_query(
     }
 }
 
-fn get_order<'a>(
-    order: &IndexMap<Name, GqlValue>,
-    variables: &'a IndexMap<Name, GqlValue>,
+fn get_order_list(
+    list: &[GqlValue],
+    variables: &IndexMap<Name, GqlValue>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Vec<OrderByExpr>> {
+    let items = list
+        .iter()
+        .filter_map(|v| match v {
+            GqlValue::Object(o) => Some(o),
+            _ => None,
+        })
+        .map(|o| get_order(o, variables, sql_vars, final_vars))
+        .collect::<AnyResult<Vec<Vec<OrderByExpr>>>>()?;
+    Ok(items.into_iter().flatten().collect())
+}
+
+fn get_order<'a>(
+    order: &IndexMap<Name, GqlValue>,
+    variables: &'a IndexMap<Name, GqlValue>,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
+    final_vars: &'a mut IndexMap<Name, String>,
 ) -> AnyResult<Vec<OrderByExpr>> {
     if order.contains_key("field") && order.contains_key("direction") {
         let direction =
@@ -2092,7 +3803,9 @@ fn get_order<'a>(
                     }]);
                 }
                 GqlValue::Object(args) => {
-                    if let (Some(expression), _) = get_filter(args, sql_vars, final_vars)? {
+                    if let (Some(expression), _) =
+                        get_filter(args, sql_vars, final_vars, None, None)?
+                    {
                         return Ok(vec![OrderByExpr {
                             expr: expression,
                             asc,
@@ -2159,19 +3872,95 @@ fn get_order<'a>(
                     });
                 }
             }
+            GqlValue::Object(similarity) if key.as_str() == "similarity" => {
+                order_by.push(OrderByExpr {
+                    expr: get_similarity(similarity, None, sql_vars, final_vars)?,
+                    asc: Some(true),
+                    nulls_first: None,
+                });
+            }
+            GqlValue::Object(column_order) => {
+                order_by.push(get_column_order(key, column_order, sql_vars)?);
+            }
             _ => return Err(anyhow!("Invalid value for order expression")),
         }
     }
     Ok(order_by)
 }
 
+// zero pads every number in the text, so `item2` sorts before `item10`
+fn get_natural_sort(expr: Expr) -> Expr {
+    let padded = get_function_call(
+        REGEXP_REPLACE,
+        vec![
+            expr,
+            Expr::Value(Value::SingleQuotedString("[0-9]+".to_string())),
+            Expr::Value(Value::SingleQuotedString(
+                "0".repeat(NATURAL_SORT_DIGITS) + "\\&",
+            )),
+            Expr::Value(Value::SingleQuotedString("g".to_string())),
+        ],
+    );
+    get_function_call(
+        REGEXP_REPLACE,
+        vec![
+            padded,
+            Expr::Value(Value::SingleQuotedString(format!(
+                "0*([0-9]{{{NATURAL_SORT_DIGITS}}})"
+            ))),
+            Expr::Value(Value::SingleQuotedString("\\1".to_string())),
+            Expr::Value(Value::SingleQuotedString("g".to_string())),
+        ],
+    )
+}
+
+// `order: { name: { dir: ASC, collate: "C", numeric: true } }`
+fn get_column_order(
+    column: &Name,
+    column_order: &IndexMap<Name, GqlValue>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+) -> AnyResult<OrderByExpr> {
+    let dir = match column_order.get("dir") {
+        Some(dir) => value_to_string(dir, sql_vars)?,
+        None => "ASC".to_string(),
+    };
+    if dir != "ASC" && dir != "DESC" {
+        return Err(anyhow!("order dir must be ASC or DESC, got {}", dir));
+    }
+    let mut expr = Expr::Identifier(Ident::with_quote(QUOTE_CHAR, column.as_str()));
+    match column_order.get("numeric") {
+        Some(GqlValue::Boolean(false)) | None => {}
+        Some(GqlValue::Boolean(true)) => expr = get_natural_sort(expr),
+        Some(GqlValue::Variable(name)) => match sql_vars.get(name) {
+            Some(JsonValue::Bool(true)) => expr = get_natural_sort(expr),
+            Some(JsonValue::Bool(false) | JsonValue::Null) | None => {}
+            Some(_) => return Err(anyhow!("order numeric must be a boolean")),
+        },
+        Some(_) => return Err(anyhow!("order numeric must be a boolean")),
+    }
+    if let Some(collate) = column_order.get("collate") {
+        expr = Expr::Collate {
+            expr: Box::new(expr),
+            collation: ObjectName(vec![Ident::with_quote(
+                QUOTE_CHAR,
+                value_to_string(collate, sql_vars)?,
+            )]),
+        };
+    }
+    Ok(OrderByExpr {
+        expr,
+        asc: Some(dir == "ASC"),
+        nulls_first: None,
+    })
+}
+
 fn get_distinct(
     distinct: &[GqlValue],
     variables: &IndexMap<Name, JsonValue>,
 ) -> Option<Vec<String>> {
     let values: Vec<String> = distinct
         .iter()
-        .filter_map(|v| get_string_or_variable(v, variables).ok())
+        .filter_map(|v| find_string_or_variable(v, variables))
         .collect();
 
     if values.is_empty() {
@@ -2181,6 +3970,190 @@ fn get_distinct(
     }
 }
 
+fn get_lock(
+    value: &GqlValue,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<Option<LockClause>> {
+    if let GqlValue::Null = value {
+        return Ok(None);
+    }
+    let lock = get_string_or_variable(value, sql_vars)?;
+    let (lock_type, nonblock) = match lock.as_str() {
+        "FOR_UPDATE" => (LockType::Update, None),
+        "FOR_UPDATE_NOWAIT" => (LockType::Update, Some(NonBlock::Nowait)),
+        "FOR_UPDATE_SKIP_LOCKED" => (LockType::Update, Some(NonBlock::SkipLocked)),
+        "FOR_SHARE" => (LockType::Share, None),
+        "FOR_SHARE_NOWAIT" => (LockType::Share, Some(NonBlock::Nowait)),
+        "FOR_SHARE_SKIP_LOCKED" => (LockType::Share, Some(NonBlock::SkipLocked)),
+        _ => return Err(anyhow!("Invalid value for lock: {}", lock)),
+    };
+    Ok(Some(LockClause {
+        lock_type,
+        of: None,
+        nonblock,
+    }))
+}
+
+fn get_group_order(
+    order_by: Vec<OrderByExpr>,
+    group_by: &[(String, Expr)],
+) -> AnyResult<Vec<OrderByExpr>> {
+    order_by
+        .into_iter()
+        .map(|order| {
+            let Expr::Identifier(ident) = &order.expr else {
+                return Err(anyhow!(
+                    "Grouped aggregates can only be ordered by groupBy keys"
+                ));
+            };
+            let (_, expr) = group_by
+                .iter()
+                .find(|(key, _)| key == &ident.value)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Grouped aggregates can only be ordered by groupBy keys: {}",
+                        ident.value
+                    )
+                })?;
+            Ok(OrderByExpr {
+                expr: expr.clone(),
+                ..order
+            })
+        })
+        .collect()
+}
+
+fn get_group_bucket(
+    bucket: &IndexMap<Name, GqlValue>,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<(String, Expr)> {
+    let field = bucket
+        .get("field")
+        .ok_or_else(|| anyhow!("groupBy bucket is missing field"))
+        .and_then(|v| get_string_or_variable(v, sql_vars))?;
+    let unit = bucket
+        .get("bucket")
+        .ok_or_else(|| anyhow!("groupBy bucket is missing bucket"))
+        .and_then(|v| get_string_or_variable(v, sql_vars))?;
+    if !DATE_TRUNC_UNITS.contains(&unit.as_str()) {
+        return Err(anyhow!("Invalid groupBy bucket: {}", unit));
+    }
+    let expr = Expr::Function(Function {
+        within_group: vec![],
+        name: ObjectName(vec![Ident {
+            value: DATE_TRUNC.to_string(),
+            quote_style: None,
+        }]),
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment: None,
+            clauses: vec![],
+            args: vec![
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                    Value::SingleQuotedString(unit),
+                ))),
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Identifier(Ident {
+                    value: field.clone(),
+                    quote_style: Some(QUOTE_CHAR),
+                }))),
+            ],
+        }),
+        over: None,
+        filter: None,
+        null_treatment: None,
+    });
+    Ok((field, expr))
+}
+
+// a column name or a `{ field, bucket }` object, keyed by the value field it is selected as
+fn get_group_item(
+    value: &GqlValue,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<(String, Expr)> {
+    if let GqlValue::Object(bucket) = value {
+        return get_group_bucket(bucket, sql_vars);
+    }
+    let column = find_string_or_variable(value, sql_vars).ok_or_else(|| {
+        anyhow!(
+            "groupBy must be a list of column names or buckets, got {}",
+            value
+        )
+    })?;
+    if column.is_empty() || column.contains(QUOTE_CHAR) {
+        return Err(anyhow!("Invalid groupBy column: {}", column));
+    }
+    Ok((
+        column.clone(),
+        Expr::Identifier(Ident::with_quote(QUOTE_CHAR, column)),
+    ))
+}
+
+fn get_group_list<'a>(
+    value: &'a GqlValue,
+    modifier: &str,
+    variables: &'a IndexMap<Name, GqlValue>,
+) -> AnyResult<&'a Vec<GqlValue>> {
+    match value {
+        GqlValue::List(list) => Ok(list),
+        GqlValue::Variable(name) if variables.contains_key(name) => {
+            get_group_list(&variables[name], modifier, variables)
+        }
+        _ => Err(anyhow!(
+            "groupBy {} must be a list, got {}",
+            modifier,
+            value
+        )),
+    }
+}
+
+// `{ rollup: [..] }`, `{ cube: [..] }` or `{ groupingSets: [[..], ..] }`, whose items are
+// column names or buckets; every distinct item is a key of the value selection
+fn get_group_sets(
+    modifier: &IndexMap<Name, GqlValue>,
+    variables: &IndexMap<Name, GqlValue>,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<GroupBy> {
+    let mut keys: Vec<(String, Expr)> = vec![];
+    let mut get_set = |list: &Vec<GqlValue>| {
+        list.iter()
+            .map(|v| {
+                let (key, expr) = get_group_item(v, sql_vars)?;
+                if !keys.iter().any(|(k, _)| *k == key) {
+                    keys.push((key, expr.clone()));
+                }
+                Ok(expr)
+            })
+            .collect::<AnyResult<Vec<_>>>()
+    };
+    let sets = match modifier.iter().collect::<Vec<_>>().as_slice() {
+        [(name, value)] if name.as_str() == "rollup" || name.as_str() == "cube" => {
+            let items = get_set(get_group_list(value, name, variables)?)?
+                .into_iter()
+                .map(|expr| vec![expr])
+                .collect();
+            if name.as_str() == "rollup" {
+                Expr::Rollup(items)
+            } else {
+                Expr::Cube(items)
+            }
+        }
+        [(name, value)] if name.as_str() == "groupingSets" => Expr::GroupingSets(
+            get_group_list(value, name, variables)?
+                .iter()
+                .map(|set| get_set(get_group_list(set, name, variables)?))
+                .collect::<AnyResult<_>>()?,
+        ),
+        _ => {
+            return Err(anyhow!(
+                "groupBy must be a list or one of rollup, cube or groupingSets"
+            ))
+        }
+    };
+    Ok(GroupBy {
+        keys,
+        sets: Some(sets),
+    })
+}
+
 fn flatten(name: Name, value: &JsonValue, sql_vars: &mut IndexMap<Name, JsonValue>) -> GqlValue {
     match value {
         JsonValue::Null => GqlValue::Null,
@@ -2200,6 +4173,7 @@ fn flatten(name: Name, value: &JsonValue, sql_vars: &mut IndexMap<Name, JsonValu
             GqlValue::Variable(name)
         }
         JsonValue::Array(list) => {
+            sql_vars.insert(name.clone(), value.clone());
             let new_list = list
                 .iter()
                 .enumerate()
@@ -2227,18 +4201,37 @@ fn flatten(name: Name, value: &JsonValue, sql_vars: &mut IndexMap<Name, JsonValu
 fn flatten_variables(
     variables: &Option<JsonValue>,
     definitions: Vec<Positioned<VariableDefinition>>,
+    binary: &IndexSet<Name>,
 ) -> (IndexMap<Name, GqlValue>, IndexMap<Name, JsonValue>) {
     let mut sql_vars = IndexMap::new();
     let mut parameters = IndexMap::with_capacity(definitions.len());
-    if let Some(JsonValue::Object(map)) = variables {
-        for def in definitions {
-            let def = def.node;
-            let name = def.name.node;
-            if let Some(value) = map.get(name.as_str()) {
-                let new_value = flatten(name.clone(), value, &mut sql_vars);
-                parameters.insert(name, new_value);
-            }
-        }
+    let map = match variables {
+        Some(JsonValue::Object(map)) => Some(map),
+        _ => None,
+    };
+    for def in definitions {
+        let def = def.node;
+        let name = def.name.node;
+        // a provided null overrides the default
+        let default;
+        let value = match map.and_then(|map| map.get(name.as_str())) {
+            Some(value) => value,
+            None => match def.default_value.and_then(|d| d.node.into_json().ok()) {
+                Some(value) => {
+                    default = value;
+                    &default
+                }
+                None => continue,
+            },
+        };
+        // bytes are bound whole, not as an object of fields
+        let new_value = if binary.contains(&name) && value.is_object() {
+            sql_vars.insert(name.clone(), value.clone());
+            GqlValue::Variable(name.clone())
+        } else {
+            flatten(name.clone(), value, &mut sql_vars)
+        };
+        parameters.insert(name, new_value);
     }
     (parameters, sql_vars)
 }
@@ -2260,11 +4253,96 @@ fn should_add_filter<'a>(value: &'a GqlValue, sql_vars: &'a mut IndexMap<Name, J
     }
 }
 
+// `col_` lets columns named like reserved arguments (filter, order, ...) be used as shortcuts.
+// A key column wins over the prefix, so `col_status` can still name a column of that name
+fn shortcut_column<'a>(key: &'a str, key_columns: &[String]) -> Option<&'a str> {
+    if key_columns.iter().any(|column| column == key) {
+        return Some(key);
+    }
+    key.strip_prefix(COLUMN_ARG_PREFIX)
+        .filter(|column| RESERVED_ARGS.contains(column))
+}
+
+// `@meta(keys:)`, then the per table option
+fn get_table_key_columns(
+    directives: &[Positioned<Directive>],
+    table: &str,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    options: &Options,
+) -> AnyResult<Option<Vec<String>>> {
+    if let Some((_, value)) = directives
+        .iter()
+        .filter(|d| d.node.name.node.as_str() == "meta")
+        .flat_map(|d| d.node.arguments.iter())
+        .find(|(name, _)| name.node.as_str() == "keys")
+    {
+        return match &value.node {
+            GqlValue::List(list) => list
+                .iter()
+                .map(|v| value_to_string(v, sql_vars))
+                .collect::<AnyResult<_>>()
+                .map(Some),
+            value => Ok(Some(vec![value_to_string(value, sql_vars)?])),
+        };
+    }
+    Ok(options.table_key_columns.get(table).cloned())
+}
+
+// `@meta(keys:)`, then the per table option, then the default keys plus the columns that
+// nested relations reference
+fn get_key_columns(
+    directives: &[Positioned<Directive>],
+    items: &[Positioned<Selection>],
+    table: &str,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    options: &Options,
+) -> AnyResult<Vec<String>> {
+    if let Some(keys) = get_table_key_columns(directives, table, sql_vars, options)? {
+        return Ok(keys);
+    }
+    let mut keys = options
+        .key_columns
+        .clone()
+        .unwrap_or_else(|| KEY_COLUMNS.map(String::from).to_vec());
+    for selection in items {
+        let Selection::Field(field) = &selection.node else {
+            continue;
+        };
+        for (name, value) in field
+            .node
+            .directives
+            .iter()
+            .filter(|d| d.node.name.node.as_str() == "relation")
+            .flat_map(|d| d.node.arguments.iter())
+        {
+            if !["reference", "references"].contains(&name.node.as_str()) {
+                continue;
+            }
+            let references = match &value.node {
+                GqlValue::List(list) => list
+                    .iter()
+                    .map(|v| value_to_string(v, sql_vars))
+                    .collect::<AnyResult<Vec<_>>>()?,
+                value => vec![value_to_string(value, sql_vars)?],
+            };
+            for reference in references {
+                if !keys.contains(&reference) {
+                    keys.push(reference);
+                }
+            }
+        }
+    }
+    Ok(keys)
+}
+
 fn parse_args<'a>(
     arguments: &'a Vec<(Positioned<Name>, Positioned<GqlValue>)>,
     variables: &'a IndexMap<Name, GqlValue>,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
+    final_vars: &'a mut IndexMap<Name, String>,
+    key_columns: &[String],
+    options: &Options,
+    scope: &mut FilterScope,
 ) -> AnyResult<(
     Option<Expr>,
     Option<Vec<String>>,
@@ -2273,7 +4351,9 @@ fn parse_args<'a>(
     Option<Expr>,
     Option<Offset>,
     Option<IndexSet<Tag>>,
-    Option<Vec<(String, Expr)>>,
+    Option<GroupBy>,
+    Vec<LockClause>,
+    Option<Expr>,
 )> {
     let mut selection = None;
     let mut order_by = vec![];
@@ -2283,6 +4363,8 @@ fn parse_args<'a>(
     let mut after = None;
     let mut keys = None;
     let mut group_by = None;
+    let mut locks = vec![];
+    let mut sample = None;
     for argument in arguments {
         let (p_key, p_value) = argument;
         let key = p_key.node.as_str();
@@ -2291,42 +4373,64 @@ fn parse_args<'a>(
             if let Some(new_value) = variables.get(name) {
                 value = new_value.clone();
                 if let GqlValue::Null = value {
-                    if !["id", "email", "A", "B"].contains(&key) {
+                    if shortcut_column(key, key_columns).is_none() {
                         continue;
                     }
                 }
             }
         }
-        match (key, value) {
-            ("id" | "email" | "A" | "B", value) => {
-                let new_selection;
-                if should_add_filter(&value, sql_vars) {
-                    new_selection = get_expr(
-                        Expr::Identifier(Ident {
-                            value: key.to_string(),
-                            quote_style: Some(QUOTE_CHAR),
-                        }),
-                        "eq",
-                        &value,
-                        sql_vars,
-                        final_vars,
-                    )?;
-                } else {
-                    new_selection = Some(Expr::Value(Value::Boolean(false)));
-                }
-                if selection.is_some() && new_selection.is_some() {
-                    selection = Some(Expr::BinaryOp {
-                        left: Box::new(selection.expect("gaurded by condition")),
-                        op: BinaryOperator::And,
-                        right: Box::new(new_selection.expect("gaurded by condition")),
-                    });
-                } else {
-                    selection = new_selection;
-                }
+        if let Some(column) = shortcut_column(key, key_columns) {
+            let new_selection;
+            if let Some(policy) = options
+                .null_policy
+                .filter(|_| is_null_value(&value, sql_vars))
+            {
+                new_selection = get_null_comparison(
+                    Expr::Identifier(Ident::with_quote(QUOTE_CHAR, column)),
+                    "eq",
+                    policy,
+                )?;
+            } else if options.match_null_shortcuts && matches!(value, GqlValue::Null) {
+                new_selection = Some(Expr::IsNull(Box::new(Expr::Identifier(Ident {
+                    value: column.to_string(),
+                    quote_style: Some(QUOTE_CHAR),
+                }))));
+            } else if should_add_filter(&value, sql_vars) {
+                new_selection = get_expr(
+                    Expr::Identifier(Ident {
+                        value: column.to_string(),
+                        quote_style: Some(QUOTE_CHAR),
+                    }),
+                    "eq",
+                    &value,
+                    None,
+                    sql_vars,
+                    final_vars,
+                )?;
+            } else {
+                new_selection = Some(Expr::Value(Value::Boolean(false)));
+            }
+            if selection.is_some() && new_selection.is_some() {
+                selection = Some(Expr::BinaryOp {
+                    left: Box::new(selection.expect("gaurded by condition")),
+                    op: BinaryOperator::And,
+                    right: Box::new(new_selection.expect("gaurded by condition")),
+                });
+            } else {
+                selection = new_selection;
             }
+            continue;
+        }
+        match (key, value) {
             ("filter" | "where", GqlValue::Object(filter)) => {
                 // keys = get_filter_key(&filter, sql_vars)?;
-                (selection, keys) = get_filter(&filter, sql_vars, final_vars)?;
+                (selection, keys) = get_filter(
+                    &filter,
+                    sql_vars,
+                    final_vars,
+                    options.null_policy,
+                    Some(&mut *scope),
+                )?;
             }
             ("distinct", GqlValue::Object(d)) => {
                 if let Some(GqlValue::List(list)) = d.get("on") {
@@ -2356,28 +4460,17 @@ fn parse_args<'a>(
                 order_by = get_order(&order, variables, sql_vars, final_vars)?;
             }
             ("order", GqlValue::List(list)) => {
-                let items = list
-                    .iter()
-                    .filter_map(|v| match v {
-                        GqlValue::Object(o) => Some(o),
-                        _ => None,
-                    })
-                    .map(|o| get_order(o, variables, sql_vars, final_vars))
-                    .collect::<AnyResult<Vec<Vec<OrderByExpr>>>>()?;
-                order_by.append(
-                    items
-                        .into_iter()
-                        .flatten()
-                        .collect::<Vec<OrderByExpr>>()
-                        .as_mut(),
-                );
+                order_by.append(&mut get_order_list(&list, variables, sql_vars, final_vars)?);
             }
             ("first" | "limit", GqlValue::Variable(name)) => {
                 first = Some(get_value(&GqlValue::Variable(name), sql_vars, final_vars)?);
             }
             ("first" | "limit", GqlValue::Number(count)) => {
                 first = Some(Expr::Value(Value::Number(
-                    count.as_i64().expect("int to be an i64").to_string(),
+                    count
+                        .as_i64()
+                        .ok_or_else(|| anyhow!("{} must be an integer, got {}", key, count))?
+                        .to_string(),
                     false,
                 )));
             }
@@ -2390,22 +4483,34 @@ fn parse_args<'a>(
             ("after" | "offset", GqlValue::Number(count)) => {
                 after = Some(Offset {
                     value: Expr::Value(Value::Number(
-                        count.as_i64().expect("int to be an i64").to_string(),
+                        count
+                            .as_i64()
+                            .ok_or_else(|| anyhow!("{} must be an integer, got {}", key, count))?
+                            .to_string(),
                         false,
                     )),
                     rows: OffsetRows::None,
                 });
             }
             ("group_by" | "groupBy", GqlValue::List(list)) => {
-                let items = list
-                    .into_iter()
-                    .filter_map(|v| {
-                        get_string_or_variable(&v, &sql_vars)
-                            .map(|v| (v.clone(), Expr::Value(Value::DoubleQuotedString(v))))
-                            .ok()
-                    })
-                    .collect::<Vec<_>>();
-                group_by = Some(items);
+                let mut keys = vec![];
+                for v in list {
+                    let item = get_group_item(&v, sql_vars)?;
+                    if keys.iter().any(|(key, _)| *key == item.0) {
+                        return Err(anyhow!("groupBy lists {} more than once", item.0));
+                    }
+                    keys.push(item);
+                }
+                group_by = Some(GroupBy { keys, sets: None });
+            }
+            ("group_by" | "groupBy", GqlValue::Object(sets)) => {
+                group_by = Some(get_group_sets(&sets, variables, sql_vars)?);
+            }
+            ("lock", value) => {
+                locks = get_lock(&value, sql_vars)?.into_iter().collect();
+            }
+            ("sample", value) => {
+                sample = get_sample(&value, sql_vars)?;
             }
             _ => {
                 return Err(anyhow!("Invalid argument for: {}", key));
@@ -2421,17 +4526,41 @@ fn parse_args<'a>(
         after,
         keys,
         group_by,
+        locks,
+        sample,
     ))
 }
 
+// `"__default"` asks for the column default, as DEFAULT in the VALUES row
+fn get_insert_value(
+    value: &GqlValue,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Expr> {
+    let is_default = match value {
+        GqlValue::String(s) => s == DEFAULT_SENTINEL,
+        GqlValue::Variable(v) => {
+            matches!(sql_vars.get(v), Some(JsonValue::String(s)) if s == DEFAULT_SENTINEL)
+        }
+        _ => false,
+    };
+    if is_default {
+        return Ok(Expr::Identifier(Ident::new(DEFAULT_KEYWORD)));
+    }
+    get_value(value, sql_vars, final_vars)
+}
+
 fn get_mutation_columns<'a>(
     arguments: &'a Vec<(Positioned<Name>, Positioned<GqlValue>)>,
     variables: &'a IndexMap<Name, GqlValue>,
     sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
-) -> AnyResult<(Vec<Ident>, Vec<Vec<Expr>>)> {
+    final_vars: &'a mut IndexMap<Name, String>,
+    id_generator: Option<(String, IdGenerator)>,
+    null_policy: Option<NullPolicy>,
+) -> AnyResult<(Vec<Ident>, Vec<Vec<Expr>>, Option<Query>)> {
     let mut columns = vec![];
     let mut rows = vec![];
+    let mut source = None;
     for argument in arguments {
         let (key, value) = argument;
         let (key, mut value) = (&key.node, &value.node);
@@ -2451,7 +4580,7 @@ fn get_mutation_columns<'a>(
                         value: key.to_string(),
                         quote_style: Some(QUOTE_CHAR),
                     });
-                    row.push(get_value(value, sql_vars, final_vars)?);
+                    row.push(get_insert_value(value, sql_vars, final_vars)?);
                 }
                 rows.push(row);
             }
@@ -2462,422 +4591,1132 @@ fn get_mutation_columns<'a>(
                 for (i, item) in list.iter().enumerate() {
                     let mut row = vec![];
                     if let GqlValue::Object(data) = item {
-                        for (key, value) in data {
-                            if i == 0 {
-                                columns.push(Ident {
-                                    value: key.to_string(),
-                                    quote_style: Some(QUOTE_CHAR),
-                                });
+                        if i == 0 {
+                            columns.extend(
+                                data.keys()
+                                    .map(|key| Ident::with_quote(QUOTE_CHAR, key.as_str())),
+                            );
+                        } else {
+                            check_insert_row_keys(&columns, data, i)?;
+                        }
+                        // values follow the column order of the first row
+                        for column in &columns {
+                            if let Some(value) = data.get(column.value.as_str()) {
+                                row.push(get_insert_value(value, sql_vars, final_vars)?);
                             }
-                            row.push(get_value(value, sql_vars, final_vars)?);
                         }
                     }
                     rows.push(row);
                 }
             }
+            ("from", GqlValue::Object(from)) => {
+                let (from_columns, query) =
+                    get_insert_select(from, sql_vars, final_vars, null_policy)?;
+                columns = from_columns;
+                source = Some(query);
+            }
             _ => continue,
         }
     }
-    Ok((columns, rows))
+    if source.is_some() && !rows.is_empty() {
+        return Err(anyhow!("Insert cannot have both data and from"));
+    }
+    if let Some((column, generator)) = id_generator {
+        if source.is_some() {
+            return Err(anyhow!("@id cannot generate ids for an insert from"));
+        }
+        // rows that set the id keep it
+        if !rows.is_empty() && !columns.iter().any(|c| c.value == column) {
+            columns.push(Ident::with_quote(QUOTE_CHAR, &column));
+            // bound like a variable, so every insert prepares the same statement
+            for row in &mut rows {
+                let name = Name::new(format!("__{column}_{}", sql_vars.len()));
+                sql_vars.insert(name.clone(), JsonValue::String(generator.generate()?));
+                row.push(get_value(&GqlValue::Variable(name), sql_vars, final_vars)?);
+            }
+        }
+    }
+    if rows.iter().any(Vec::is_empty) {
+        return Err(anyhow!("Insert rows must set at least one column"));
+    }
+    Ok((columns, rows, source))
 }
 
-fn get_mutation_assignments<'a>(
-    arguments: &'a Vec<(Positioned<Name>, Positioned<GqlValue>)>,
-    variables: &'a IndexMap<Name, GqlValue>,
-    sql_vars: &'a mut IndexMap<Name, JsonValue>,
-    final_vars: &'a mut IndexSet<Name>,
-    has_updated_at_directive: bool,
-) -> AnyResult<(Option<Expr>, Vec<Assignment>)> {
-    let mut selection = None;
-    let mut assignments = vec![];
-    if has_updated_at_directive {
-        assignments.push(Assignment {
-            id: vec![Ident {
-                value: "updated_at".to_string(),
-                quote_style: Some(QUOTE_CHAR),
-            }],
-            value: Expr::Function(Function {
-                within_group: vec![],
-                name: ObjectName(vec![Ident {
-                    value: "now".to_string(),
-                    quote_style: None,
-                }]),
-                args: FunctionArguments::List(FunctionArgumentList {
-                    duplicate_treatment: None,
-                    clauses: vec![],
-                    args: vec![],
-                }),
-                over: None,
-                filter: None,
-                null_treatment: None,
-            }),
-        });
+// every row of a multi-row insert must set the columns of the first row
+fn check_insert_row_keys(
+    columns: &[Ident],
+    data: &IndexMap<Name, GqlValue>,
+    index: usize,
+) -> AnyResult<()> {
+    let missing = columns
+        .iter()
+        .filter(|column| !data.contains_key(column.value.as_str()))
+        .map(|column| column.value.as_str())
+        .collect::<Vec<_>>();
+    let extra = data
+        .keys()
+        .filter(|key| !columns.iter().any(|column| column.value == key.as_str()))
+        .map(Name::as_str)
+        .collect::<Vec<_>>();
+    if missing.is_empty() && extra.is_empty() {
+        return Ok(());
     }
-    for argument in arguments {
-        let (p_key, p_value) = argument;
-        let (key, mut value) = (&p_key.node, &p_value.node);
-        if let GqlValue::Variable(name) = value {
-            if let Some(new_value) = variables.get(name) {
-                value = new_value;
-                if let GqlValue::Null = value {
-                    continue;
-                }
-            }
+    let mut problems = vec![];
+    if !missing.is_empty() {
+        problems.push(format!("missing {}", missing.join(", ")));
+    }
+    if !extra.is_empty() {
+        problems.push(format!("unexpected {}", extra.join(", ")));
+    }
+    Err(anyhow!(
+        "Insert row {} does not have the keys of the first row: {}",
+        index,
+        problems.join("; ")
+    ))
+}
+
+// `from: { table, filter, map: { column: "sourceColumn" | $value } }` compiles to INSERT INTO ... SELECT
+fn get_insert_select(
+    from: &IndexMap<Name, GqlValue>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+    null_policy: Option<NullPolicy>,
+) -> AnyResult<(Vec<Ident>, Query)> {
+    let table = from
+        .get("table")
+        .ok_or_else(|| anyhow!("Insert from is missing table"))
+        .and_then(|v| get_string_or_variable(v, sql_vars))?;
+    let Some(GqlValue::Object(map)) = from.get("map") else {
+        return Err(anyhow!("Insert from is missing map"));
+    };
+    let mut columns = vec![];
+    let mut projection = vec![];
+    for (key, value) in map {
+        columns.push(Ident::with_quote(QUOTE_CHAR, key.as_str()));
+        let expr = match value {
+            GqlValue::String(column) => Expr::Identifier(Ident::with_quote(QUOTE_CHAR, column)),
+            value => get_value(value, sql_vars, final_vars)?,
+        };
+        projection.push(SelectItem::UnnamedExpr(expr));
+    }
+    let selection = match from.get("filter") {
+        Some(GqlValue::Object(filter)) => {
+            get_filter(filter, sql_vars, final_vars, null_policy, None)?.0
         }
-        match (key.as_ref(), value) {
-            ("id" | "email" | "A" | "B", value) => {
-                let new_selection = get_expr(
-                    Expr::Identifier(Ident {
-                        value: key.to_string(),
-                        quote_style: Some(QUOTE_CHAR),
-                    }),
-                    "eq",
-                    value,
-                    sql_vars,
-                    final_vars,
-                )?;
-                if selection.is_some() && new_selection.is_some() {
-                    selection = Some(Expr::BinaryOp {
-                        left: Box::new(selection.expect("gaurded by condition")),
-                        op: BinaryOperator::And,
-                        right: Box::new(new_selection.expect("gaurded by condition")),
-                    });
-                } else {
-                    selection = new_selection;
-                }
-            }
-            ("filter" | "where", GqlValue::Object(filter)) => {
-                (selection, _) = get_filter(filter, sql_vars, final_vars)?;
-            }
-            ("set", GqlValue::Object(data)) => {
-                for (key, value) in data {
-                    assignments.push(Assignment {
-                        id: vec![Ident {
-                            value: key.to_string(),
-                            quote_style: Some(QUOTE_CHAR),
-                        }],
-                        value: get_value(value, sql_vars, final_vars)?,
-                    });
-                }
-            }
-            ("inc" | "increment", GqlValue::Object(data)) => {
-                for (key, value) in data {
-                    let column_ident = Ident {
-                        value: key.to_string(),
-                        quote_style: Some(QUOTE_CHAR),
-                    };
-                    assignments.push(Assignment {
-                        id: vec![column_ident.clone()],
-                        value: Expr::BinaryOp {
-                            left: Box::new(Expr::Identifier(column_ident)),
-                            op: BinaryOperator::Plus,
-                            right: Box::new(get_value(value, sql_vars, final_vars)?),
-                        },
-                    });
-                }
-            }
-            _ => return Err(anyhow!("Invalid argument for update at: {}", key)),
-        }
-    }
+        _ => None,
+    };
     Ok((
-        selection.or_else(|| Some(Expr::Value(Value::Boolean(false)))),
-        assignments,
+        columns,
+        Query {
+            for_clause: None,
+            limit_by: vec![],
+            with: None,
+            body: Box::new(SetExpr::Select(Box::new(Select {
+                window_before_qualify: false,
+                connect_by: None,
+                value_table_mode: None,
+                distinct: None,
+                named_window: vec![],
+                top: None,
+                into: None,
+                projection,
+                from: vec![TableWithJoins {
+                    relation: TableFactor::Table {
+                        partitions: vec![],
+                        version: None,
+                        name: ObjectName(vec![Ident::with_quote(QUOTE_CHAR, table)]),
+                        alias: None,
+                        args: None,
+                        with_hints: vec![],
+                    },
+                    joins: vec![],
+                }],
+                lateral_views: vec![],
+                selection,
+                group_by: GroupByExpr::Expressions(vec![]),
+                cluster_by: vec![],
+                distribute_by: vec![],
+                sort_by: vec![],
+                having: None,
+                qualify: None,
+            }))),
+            order_by: vec![],
+            limit: None,
+            offset: None,
+            fetch: None,
+            locks: vec![],
+        },
     ))
 }
 
-pub fn parse_query_meta(field: &Field) -> AnyResult<(&str, &str, bool, bool, Option<&str>)> {
-    let mut is_aggregate = false;
-    let mut is_single = false;
-    let mut name = field.name.node.as_str();
-    let mut schema_name = None;
-    let key = field
-        .alias
-        .as_ref()
-        .map_or_else(|| field.name.node.as_str(), |alias| alias.node.as_str());
+fn get_function_call(name: &str, args: Vec<Expr>) -> Expr {
+    Expr::Function(Function {
+        within_group: vec![],
+        name: ObjectName(vec![Ident::new(name)]),
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment: None,
+            clauses: vec![],
+            args: args
+                .into_iter()
+                .map(|arg| FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)))
+                .collect(),
+        }),
+        over: None,
+        filter: None,
+        null_treatment: None,
+    })
+}
 
-    if name.ends_with("_aggregate") {
-        name = &name[..name.len() - 10];
-        is_aggregate = true;
-    } else if name.ends_with("_one") {
-        name = &name[..name.len() - 4];
-        is_single = true;
-    }
+fn get_clone_column(table: &str, column: &str) -> Expr {
+    Expr::CompoundIdentifier(vec![
+        Ident::with_quote(QUOTE_CHAR, table),
+        Ident::with_quote(QUOTE_CHAR, column),
+    ])
+}
 
-    if let Some(p_directive) = field
-        .directives
-        .iter()
-        .find(|directive| directive.node.name.node.as_str() == "meta")
-    {
-        let directive = &p_directive.node;
-        directive.arguments.iter().for_each(|(arg_name, argument)| {
-            let arg_name = arg_name.node.as_str();
-            if arg_name == "table" {
-                if let GqlValue::String(table) = &argument.node {
-                    name = table.as_ref();
-                }
-            } else if arg_name == "aggregate" {
-                if let GqlValue::Boolean(aggregate) = &argument.node {
-                    is_aggregate = *aggregate;
-                }
-            } else if arg_name == "single" {
-                if let GqlValue::Boolean(single) = &argument.node {
-                    is_single = *single;
-                }
-            } else if arg_name == "schema" {
-                if let GqlValue::String(schema) = &argument.node {
-                    schema_name = Some(schema.as_ref());
-                }
-            }
-        });
+fn get_clone_select(
+    projection: Vec<SelectItem>,
+    table: ObjectName,
+    join: Option<(&str, &str, Expr)>,
+    selection: Option<Expr>,
+) -> Query {
+    let joins = join
+        .map(|(map, alias, on)| Join {
+            relation: TableFactor::Table {
+                partitions: vec![],
+                version: None,
+                name: ObjectName(vec![Ident::with_quote(QUOTE_CHAR, map)]),
+                alias: Some(TableAlias {
+                    name: Ident::with_quote(QUOTE_CHAR, alias),
+                    columns: vec![],
+                }),
+                args: None,
+                with_hints: vec![],
+            },
+            join_operator: JoinOperator::Inner(JoinConstraint::On(on)),
+        })
+        .into_iter()
+        .collect();
+    Query {
+        for_clause: None,
+        limit_by: vec![],
+        with: None,
+        body: Box::new(SetExpr::Select(Box::new(Select {
+            window_before_qualify: false,
+            connect_by: None,
+            value_table_mode: None,
+            distinct: None,
+            named_window: vec![],
+            top: None,
+            into: None,
+            projection,
+            from: vec![TableWithJoins {
+                relation: TableFactor::Table {
+                    partitions: vec![],
+                    version: None,
+                    name: table,
+                    alias: Some(TableAlias {
+                        name: Ident::with_quote(QUOTE_CHAR, CLONE_SOURCE),
+                        columns: vec![],
+                    }),
+                    args: None,
+                    with_hints: vec![],
+                },
+                joins,
+            }],
+            lateral_views: vec![],
+            selection,
+            group_by: GroupByExpr::Expressions(vec![]),
+            cluster_by: vec![],
+            distribute_by: vec![],
+            sort_by: vec![],
+            having: None,
+            qualify: None,
+        }))),
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        fetch: None,
+        locks: vec![],
     }
+}
 
-    if is_aggregate && is_single {
-        return Err(anyhow!("Query cannot be both aggregate and single"));
-    }
+// Maps every source row key to a fresh key, plus the fresh key of its parent row for children
+fn get_clone_map(
+    table: ObjectName,
+    key: &str,
+    parent: Option<(&str, &str)>,
+    selection: Option<Expr>,
+) -> Query {
+    let mut projection = vec![
+        SelectItem::ExprWithAlias {
+            expr: get_clone_column(CLONE_SOURCE, key),
+            alias: Ident::with_quote(QUOTE_CHAR, "old_id"),
+        },
+        SelectItem::ExprWithAlias {
+            expr: get_function_call(CLONE_ID_FUNCTION, vec![]),
+            alias: Ident::with_quote(QUOTE_CHAR, "new_id"),
+        },
+    ];
+    let join = parent.map(|(parent_map, fk)| {
+        projection.push(SelectItem::ExprWithAlias {
+            expr: get_clone_column(CLONE_PARENT, "new_id"),
+            alias: Ident::with_quote(QUOTE_CHAR, "parent_id"),
+        });
+        (
+            parent_map,
+            CLONE_PARENT,
+            Expr::BinaryOp {
+                left: Box::new(get_clone_column(CLONE_SOURCE, fk)),
+                op: BinaryOperator::Eq,
+                right: Box::new(get_clone_column(CLONE_PARENT, "old_id")),
+            },
+        )
+    });
+    get_clone_select(projection, table, join, selection)
+}
 
-    Ok((name, key, is_aggregate, is_single, schema_name))
+// INSERT INTO t SELECT (jsonb_populate_record(NULL::t, to_jsonb(src) || overrides)).* so every column is copied
+fn get_clone_insert(
+    table: ObjectName,
+    key: &str,
+    map: &str,
+    overrides: Vec<(String, Expr)>,
+    returning: Option<Vec<SelectItem>>,
+) -> Statement {
+    let overrides = get_function_call(
+        JSONB_BUILD_OBJECT,
+        overrides
+            .into_iter()
+            .flat_map(|(column, value)| [Expr::Value(Value::SingleQuotedString(column)), value])
+            .collect(),
+    );
+    let record = get_function_call(
+        "jsonb_populate_record",
+        vec![
+            Expr::Cast {
+                kind: CastKind::DoubleColon,
+                expr: Box::new(Expr::Value(Value::Null)),
+                data_type: DataType::Custom(table.clone(), vec![]),
+                format: None,
+            },
+            Expr::BinaryOp {
+                left: Box::new(get_function_call(
+                    TO_JSONB,
+                    vec![Expr::Identifier(Ident::with_quote(
+                        QUOTE_CHAR,
+                        CLONE_SOURCE,
+                    ))],
+                )),
+                op: BinaryOperator::StringConcat,
+                right: Box::new(overrides),
+            },
+        ],
+    );
+    let source = get_clone_select(
+        vec![SelectItem::UnnamedExpr(Expr::CompositeAccess {
+            expr: Box::new(Expr::Nested(Box::new(record))),
+            key: Ident::new("*"),
+        })],
+        table.clone(),
+        Some((
+            map,
+            CLONE_MAP,
+            Expr::BinaryOp {
+                left: Box::new(get_clone_column(CLONE_SOURCE, key)),
+                op: BinaryOperator::Eq,
+                right: Box::new(get_clone_column(CLONE_MAP, "old_id")),
+            },
+        )),
+        None,
+    );
+    Statement::Insert(Insert {
+        insert_alias: None,
+        ignore: false,
+        priority: None,
+        replace_into: false,
+        table_alias: None,
+        or: None,
+        into: true,
+        table_name: table,
+        columns: vec![],
+        overwrite: false,
+        source: Some(Box::new(source)),
+        partitioned: None,
+        after_columns: vec![],
+        table: false,
+        on: None,
+        returning,
+    })
 }
 
-pub fn parse_mutation_meta(
-    field: &Field,
-) -> AnyResult<(&str, &str, bool, bool, bool, bool, Option<&str>)> {
-    let mut is_insert = false;
-    let mut is_update = false;
-    let mut is_delete = false;
-    let mut is_single = false;
-    let mut schema_name = None;
-    let mut name = field.name.node.as_ref();
-    let key = field
-        .alias
-        .as_ref()
-        .map_or_else(|| field.name.node.as_str(), |alias| alias.node.as_str());
+fn get_clone_cte(name: String, query: Query) -> Cte {
+    Cte {
+        materialized: None,
+        alias: TableAlias {
+            name: Ident::with_quote(QUOTE_CHAR, name),
+            columns: vec![],
+        },
+        query: Box::new(query),
+        from: None,
+    }
+}
 
-    if name.starts_with("insert_") {
-        name = &name[7..];
-        is_insert = true;
-    } else if name.starts_with("update_") {
-        name = &name[7..];
-        is_update = true;
-    } else if name.starts_with("delete_") {
-        name = &name[7..];
-        is_delete = true;
+// The key column of a cloned table, which gets a fresh uuid in every copied row: `@id(column:)`,
+// `@meta(keys:)` or the per table option, else `id`. Keys come from gen_random_uuid(), so keys
+// that `@id` generates as nanoids are rejected.
+fn get_clone_key(
+    directives: &[Positioned<Directive>],
+    table: &str,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    options: &Options,
+    pos: Pos,
+) -> AnyResult<String> {
+    if let Some((column, generator)) = get_id_generator(directives, sql_vars)? {
+        if generator == IdGenerator::Nanoid {
+            return Err(anyhow!(
+                "@clone can only generate uuid keys, but \"{}\" of {} is a nanoid at {}",
+                column,
+                table,
+                pos
+            ));
+        }
+        return Ok(column);
+    }
+    let keys = get_table_key_columns(directives, table, sql_vars, options)?
+        .unwrap_or_else(|| vec![ID.to_string()]);
+    match <[String; 1]>::try_from(keys) {
+        Ok([column]) => Ok(column),
+        Err(keys) => Err(anyhow!(
+            "@clone needs a single key column to copy {}, got {:?} at {}",
+            table,
+            keys,
+            pos
+        )),
     }
+}
 
-    if let Some(p_directive) = field
-        .directives
-        .iter()
-        .find(|directive| directive.node.name.node.as_str() == "meta")
-    {
-        let directive = &p_directive.node;
-        directive.arguments.iter().for_each(|(arg_name, argument)| {
-            let arg_name = arg_name.node.as_str();
-            if arg_name == "table" {
-                if let GqlValue::String(table) = &argument.node {
-                    name = table.as_ref();
-                }
-            } else if arg_name == "insert" {
-                if let GqlValue::Boolean(insert) = &argument.node {
-                    is_insert = *insert;
-                }
-            } else if arg_name == "update" {
-                if let GqlValue::Boolean(update) = &argument.node {
-                    is_update = *update;
-                }
-            } else if arg_name == "delete" {
-                if let GqlValue::Boolean(delete) = &argument.node {
-                    is_delete = *delete;
-                }
-            } else if arg_name == "single" {
-                if let GqlValue::Boolean(delete) = &argument.node {
-                    is_single = *delete;
-                }
-            } else if arg_name == "schema" {
-                if let GqlValue::String(schema) = &argument.node {
-                    schema_name = Some(schema.as_ref());
-                }
+// the selected columns of the cloned rows, leaving out the cloned relations and the row count
+fn get_clone_returning(
+    items: &[Positioned<Selection>],
+    table: &str,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+) -> AnyResult<Vec<SelectItem>> {
+    let mut returning = vec![];
+    for selection in items {
+        let Selection::Field(field) = &selection.node else {
+            continue;
+        };
+        let field = &field.node;
+        if has_skip(field, sql_vars)
+            || !field.selection_set.node.items.is_empty()
+            || field.name.node == AFFECTED_ROWS
+        {
+            continue;
+        }
+        let alias = Ident::with_quote(QUOTE_CHAR, field.response_key().node.as_str());
+        if field.name.node == TYPENAME {
+            returning.push(SelectItem::ExprWithAlias {
+                expr: Expr::Value(Value::SingleQuotedString(table.to_owned())),
+                alias,
+            });
+            continue;
+        }
+        let column = Ident::with_quote(
+            QUOTE_CHAR,
+            get_column(&field.directives, sql_vars)?.unwrap_or_else(|| field.name.node.to_string()),
+        );
+        returning.push(if column == alias {
+            SelectItem::UnnamedExpr(Expr::Identifier(column))
+        } else {
+            SelectItem::ExprWithAlias {
+                expr: Expr::Identifier(column),
+                alias,
             }
         });
     }
-
-    if is_insert && is_update {
-        return Err(anyhow!("Mutation cannot be both insert and update"));
-    } else if is_insert && is_delete {
-        return Err(anyhow!("Mutation cannot be both insert and delete"));
-    } else if is_update && is_delete {
-        return Err(anyhow!("Mutation cannot be both update and delete"));
+    // RETURNING needs a column, and the type is one the response has anyway
+    if returning.is_empty() {
+        returning.push(SelectItem::ExprWithAlias {
+            expr: Expr::Value(Value::SingleQuotedString(table.to_owned())),
+            alias: Ident::with_quote(QUOTE_CHAR, TYPENAME),
+        });
     }
-
-    Ok((
-        name,
-        key,
-        is_insert,
-        is_update,
-        is_delete,
-        is_single,
-        schema_name,
-    ))
+    Ok(returning)
 }
 
-#[must_use]
-pub fn wrap_mutation(key: &str, value: Statement, is_single: bool) -> Statement {
-    let mut base = Expr::Function(Function {
-        within_group: vec![],
-        over: None,
-        name: ObjectName(vec![Ident {
-            value: "coalesce".to_string(),
-            quote_style: None,
-        }]),
-        args: FunctionArguments::List(FunctionArgumentList {
-            duplicate_treatment: None,
-            clauses: vec![],
-            args: vec![
-                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Function(Function {
-                    within_group: vec![],
-                    name: ObjectName(vec![Ident {
-                        value: JSONB_AGG.to_string(),
-                        quote_style: None,
-                    }]),
-                    args: FunctionArguments::List(FunctionArgumentList {
-                        duplicate_treatment: None,
-                        clauses: vec![],
-                        args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(
-                            Expr::Identifier(Ident {
-                                value: "result".to_string(),
-                                quote_style: Some(QUOTE_CHAR),
-                            }),
-                        ))],
-                    }),
-                    over: None,
-                    filter: None,
-                    null_treatment: None,
-                }))),
-                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                    Value::SingleQuotedString("[]".to_string()),
+// Walks @relation children of a @clone mutation, adding a map and an insert CTE per table
+fn get_clone_children(
+    items: &[Positioned<Selection>],
+    parent_map: &str,
+    parent_key: &str,
+    ctes: &mut Vec<Cte>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &IndexMap<Name, String>,
+    options: &Options,
+) -> AnyResult<()> {
+    for selection in items {
+        let Selection::Field(p_field) = &selection.node else {
+            continue;
+        };
+        let field = &p_field.node;
+        if !field
+            .directives
+            .iter()
+            .any(|d| d.node.name.node.as_str() == "relation")
+        {
+            continue;
+        }
+        let (relation, fk, pk, _, _, _, schema_name, _, _) =
+            get_relation(&field.directives, sql_vars, final_vars)?;
+        if fk.len() != 1 || pk.first().map(String::as_str) != Some(parent_key) {
+            return Err(anyhow!(
+                "@clone children must reference the parent key \"{}\" with a single field at {}",
+                parent_key,
+                p_field.pos
+            ));
+        }
+        let key = get_clone_key(&field.directives, &relation, sql_vars, options, p_field.pos)?;
+        let table = ObjectName(
+            schema_name
+                .into_iter()
+                .chain(std::iter::once(relation))
+                .map(|name| Ident::with_quote(QUOTE_CHAR, name))
+                .collect(),
+        );
+        let index = ctes.len() / 2 + 1;
+        let map = format!("clone_map_{index}");
+        let insert = format!("clone_{index}");
+        ctes.push(get_clone_cte(
+            map.clone(),
+            get_clone_map(table.clone(), &key, Some((parent_map, &fk[0])), None),
+        ));
+        ctes.push(get_clone_cte(
+            insert,
+            Query {
+                for_clause: None,
+                limit_by: vec![],
+                with: None,
+                body: Box::new(SetExpr::Insert(get_clone_insert(
+                    table,
+                    &key,
+                    &map,
+                    vec![
+                        (key.clone(), get_clone_column(CLONE_MAP, "new_id")),
+                        (fk[0].clone(), get_clone_column(CLONE_MAP, "parent_id")),
+                    ],
+                    None,
                 ))),
-            ],
-        }),
-        filter: None,
-        null_treatment: None,
+                order_by: vec![],
+                limit: None,
+                offset: None,
+                fetch: None,
+                locks: vec![],
+            },
+        ));
+        get_clone_children(
+            &field.selection_set.node.items,
+            &map,
+            &key,
+            ctes,
+            sql_vars,
+            final_vars,
+            options,
+        )?;
+    }
+    Ok(())
+}
+
+// a write to a relation nested in the `set` of an update
+enum NestedWrite {
+    /// `{ upsert: {...} }` for a single relation
+    Upsert(IndexMap<Name, GqlValue>),
+    /// `{ connect, disconnect, set }` for a many relation
+    Links(IndexMap<Name, GqlValue>),
+}
+
+const LINK_OPERATIONS: [&str; 3] = ["connect", "disconnect", "set"];
+
+// the @relation directive of `column`: from its selection, or else from the registry
+fn find_relation(
+    table: &str,
+    column: &Name,
+    items: &[Positioned<Selection>],
+    registry: &RelationRegistry,
+) -> Option<Vec<Positioned<Directive>>> {
+    let selected = items.iter().find_map(|selection| match &selection.node {
+        Selection::Field(field)
+            if field.node.name.node == *column
+                && field
+                    .node
+                    .directives
+                    .iter()
+                    .any(|d| d.node.name.node.as_str() == "relation") =>
+        {
+            Some(field.node.directives.clone())
+        }
+        _ => None,
     });
-    if is_single {
-        base = Expr::BinaryOp {
-            left: Box::new(base),
-            op: BinaryOperator::Custom("->".to_string()),
-            right: Box::new(Expr::Value(Value::Number("0".to_string(), false))),
+    selected.or_else(|| {
+        registry
+            .get(table, column)
+            .map(|relation| vec![get_relation_directive(relation, Pos::default())])
+    })
+}
+
+// takes the writes to relations out of `set`, leaving the column sets for the parent row
+fn split_nested_writes(
+    arguments: &[(Positioned<Name>, Positioned<GqlValue>)],
+    variables: &IndexMap<Name, GqlValue>,
+    is_relation: impl Fn(&Name) -> bool,
+) -> (
+    Vec<(Positioned<Name>, Positioned<GqlValue>)>,
+    Vec<(Name, NestedWrite)>,
+) {
+    let resolve = |value: &GqlValue| match value {
+        GqlValue::Variable(name) => variables.get(name).cloned().unwrap_or(GqlValue::Null),
+        value => value.clone(),
+    };
+    let mut writes = vec![];
+    let arguments = arguments
+        .iter()
+        .map(|(key, value)| {
+            let data = match &value.node {
+                GqlValue::Variable(name) => variables.get(name),
+                value => Some(value),
+            };
+            let (Some(GqlValue::Object(data)), "set") = (data, key.node.as_str()) else {
+                return (key.clone(), value.clone());
+            };
+            let mut columns = IndexMap::with_capacity(data.len());
+            for (column, value) in data {
+                if let GqlValue::Object(nested) = value {
+                    if is_relation(column) {
+                        if let (1, Some(GqlValue::Object(upsert))) =
+                            (nested.len(), nested.get("upsert"))
+                        {
+                            writes.push((column.clone(), NestedWrite::Upsert(upsert.clone())));
+                            continue;
+                        }
+                        if !nested.is_empty()
+                            && nested
+                                .keys()
+                                .all(|key| LINK_OPERATIONS.contains(&key.as_str()))
+                        {
+                            let links = nested
+                                .iter()
+                                .map(|(key, value)| (key.clone(), resolve(value)))
+                                .collect();
+                            writes.push((column.clone(), NestedWrite::Links(links)));
+                            continue;
+                        }
+                    }
+                }
+                columns.insert(column.clone(), value.clone());
+            }
+            (
+                key.clone(),
+                Positioned::new(GqlValue::Object(columns), value.pos),
+            )
+        })
+        .collect();
+    (arguments, writes)
+}
+
+// `set: { profile: { upsert: {...} } }` for a single @relation `profile` of the updated table:
+// a CTE inserting the child row for every updated row, or updating it when the relation
+// fields already match. Returns the parent columns the child references.
+fn get_nested_upsert(
+    column: &Name,
+    data: &IndexMap<Name, GqlValue>,
+    directives: &[Positioned<Directive>],
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<(Cte, Vec<String>)> {
+    let (relation, fk, pk, is_single, _, _, schema_name, _, _) =
+        get_relation(directives, sql_vars, final_vars)?;
+    if !is_single || fk.is_empty() || fk.len() != pk.len() {
+        return Err(anyhow!(
+            "Upsert of {} needs a single @relation with matching field and references",
+            column
+        ));
+    }
+    let child = ObjectName(
+        schema_name
+            .into_iter()
+            .chain(std::iter::once(relation))
+            .map(|name| Ident::with_quote(QUOTE_CHAR, name))
+            .collect(),
+    );
+    let mut columns = vec![];
+    let mut projection = vec![];
+    let mut defaults = vec![];
+    for (key, value) in data {
+        // the relation fields come from the parent row
+        if fk.iter().any(|field| field == key.as_str()) {
+            continue;
+        }
+        let column = Ident::with_quote(QUOTE_CHAR, key.as_str());
+        let value = get_insert_value(value, sql_vars, final_vars)?;
+        // a SELECT has no DEFAULT: the insert leaves the column out, the update sets it
+        if matches!(&value, Expr::Identifier(ident) if ident.quote_style.is_none() && ident.value == DEFAULT_KEYWORD)
+        {
+            defaults.push(column);
+            continue;
         }
+        columns.push(column);
+        projection.push(SelectItem::UnnamedExpr(value));
     }
-    Statement::Query(Box::new(Query {
+    let assignments = columns
+        .iter()
+        .map(|c| Assignment {
+            id: vec![c.clone()],
+            value: Expr::CompoundIdentifier(vec![Ident::new("EXCLUDED"), c.clone()]),
+        })
+        .chain(defaults.into_iter().map(|c| Assignment {
+            id: vec![c],
+            value: Expr::Identifier(Ident::new(DEFAULT_KEYWORD)),
+        }))
+        .collect::<Vec<_>>();
+    for (field, reference) in zip(&fk, &pk) {
+        columns.push(Ident::with_quote(QUOTE_CHAR, field));
+        projection.push(SelectItem::UnnamedExpr(get_clone_column(
+            CLONE_SOURCE,
+            reference,
+        )));
+    }
+    let result = ObjectName(vec![Ident::with_quote(QUOTE_CHAR, "result")]);
+    let insert = Statement::Insert(Insert {
+        insert_alias: None,
+        ignore: false,
+        priority: None,
+        replace_into: false,
+        table_alias: None,
+        or: None,
+        into: true,
+        table_name: child,
+        columns,
+        overwrite: false,
+        source: Some(Box::new(get_clone_select(projection, result, None, None))),
+        partitioned: None,
+        after_columns: vec![],
+        table: false,
+        on: Some(OnInsert::OnConflict(OnConflict {
+            conflict_target: Some(ConflictTarget::Columns(
+                fk.iter()
+                    .map(|field| Ident::with_quote(QUOTE_CHAR, field))
+                    .collect(),
+            )),
+            action: if assignments.is_empty() {
+                OnConflictAction::DoNothing
+            } else {
+                OnConflictAction::DoUpdate(DoUpdate {
+                    assignments,
+                    selection: None,
+                })
+            },
+        })),
+        returning: None,
+    });
+    Ok((
+        get_clone_cte(
+            format!("upsert_{column}"),
+            Query {
+                for_clause: None,
+                limit_by: vec![],
+                with: None,
+                body: Box::new(SetExpr::Insert(insert)),
+                order_by: vec![],
+                limit: None,
+                offset: None,
+                fetch: None,
+                locks: vec![],
+            },
+        ),
+        pk,
+    ))
+}
+
+// the ids of `connect`, `disconnect` or `set`
+fn get_link_ids(
+    value: &GqlValue,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Vec<Expr>> {
+    match value {
+        GqlValue::Null => Ok(vec![]),
+        GqlValue::List(ids) => ids
+            .iter()
+            .map(|id| get_value(id, sql_vars, final_vars))
+            .collect(),
+        id => Ok(vec![get_value(id, sql_vars, final_vars)?]),
+    }
+}
+
+fn get_link_query(statement: Statement) -> Query {
+    Query {
         for_clause: None,
         limit_by: vec![],
-        with: Some(With {
-            cte_tables: vec![Cte {
-                materialized: None,
-                alias: TableAlias {
-                    name: Ident {
-                        value: "result".to_string(),
-                        quote_style: Some(QUOTE_CHAR),
-                    },
-                    columns: vec![],
-                },
-                query: Box::new(Query {
+        with: None,
+        body: Box::new(SetExpr::Insert(statement)),
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        fetch: None,
+        locks: vec![],
+    }
+}
+
+// links every updated row to `ids`, keeping existing links
+fn get_link_insert(junction: ObjectName, parent: &str, child: &str, ids: Vec<Expr>) -> Statement {
+    let result = TableWithJoins {
+        relation: TableFactor::Table {
+            partitions: vec![],
+            version: None,
+            name: ObjectName(vec![Ident::with_quote(QUOTE_CHAR, "result")]),
+            alias: Some(TableAlias {
+                name: Ident::with_quote(QUOTE_CHAR, CLONE_SOURCE),
+                columns: vec![],
+            }),
+            args: None,
+            with_hints: vec![],
+        },
+        joins: vec![Join {
+            relation: TableFactor::Derived {
+                lateral: false,
+                subquery: Box::new(Query {
                     for_clause: None,
                     limit_by: vec![],
                     with: None,
-                    body: Box::new(SetExpr::Insert(value)),
+                    body: Box::new(SetExpr::Values(Values {
+                        explicit_row: false,
+                        rows: ids.into_iter().map(|id| vec![id]).collect(),
+                    })),
                     order_by: vec![],
                     limit: None,
                     offset: None,
                     fetch: None,
                     locks: vec![],
                 }),
-                from: None,
-            }],
-            recursive: false,
-        }),
-        body: Box::new(SetExpr::Select(Box::new(Select {
-            window_before_qualify: false,
-            connect_by: None,
-            value_table_mode: None,
-            distinct: None,
-            named_window: vec![],
-            top: None,
-            into: None,
-            projection: vec![SelectItem::ExprWithAlias {
-                expr: Expr::Function(Function {
-                    within_group: vec![],
-                    name: ObjectName(vec![Ident {
-                        value: JSONB_BUILD_OBJECT.to_string(),
-                        quote_style: None,
-                    }]),
-                    args: FunctionArguments::List(FunctionArgumentList {
-                        duplicate_treatment: None,
-                        clauses: vec![],
-                        args: vec![
-                            FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                                Value::SingleQuotedString(key.to_string()),
-                            ))),
-                            FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Subquery(Box::new(
-                                Query {
-                                    for_clause: None,
-                                    limit_by: vec![],
-                                    with: None,
-                                    body: Box::new(SetExpr::Select(Box::new(Select {
-                                        window_before_qualify: false,
-                                        connect_by: None,
-                                        value_table_mode: None,
-                                        distinct: None,
-                                        named_window: vec![],
-                                        top: None,
-                                        projection: vec![SelectItem::UnnamedExpr(base)],
-                                        into: None,
-                                        from: vec![TableWithJoins {
-                                            relation: TableFactor::Table {
-                                                partitions: vec![],
-                                                version: None,
-                                                name: ObjectName(vec![Ident {
-                                                    value: "result".to_string(),
-                                                    quote_style: Some(QUOTE_CHAR),
-                                                }]),
-                                                alias: None,
-                                                args: None,
-                                                with_hints: vec![],
-                                            },
-                                            joins: vec![],
-                                        }],
-                                        lateral_views: vec![],
-                                        selection: None,
-                                        group_by: GroupByExpr::Expressions(vec![]),
-                                        cluster_by: vec![],
-                                        distribute_by: vec![],
-                                        sort_by: vec![],
-                                        having: None,
-                                        qualify: None,
-                                    }))),
-                                    order_by: vec![],
-                                    limit: None,
-                                    offset: None,
-                                    fetch: None,
-                                    locks: vec![],
-                                },
-                            )))),
-                        ],
-                    }),
-                    over: None,
-                    filter: None,
-                    null_treatment: None,
+                alias: Some(TableAlias {
+                    name: Ident::with_quote(QUOTE_CHAR, "ids"),
+                    columns: vec![Ident::with_quote(QUOTE_CHAR, ID)],
                 }),
-                alias: Ident {
-                    value: DATA_LABEL.to_string(),
-                    quote_style: Some(QUOTE_CHAR),
-                },
-            }],
-            from: vec![],
-            lateral_views: vec![],
-            selection: None,
-            group_by: GroupByExpr::Expressions(vec![]),
-            cluster_by: vec![],
-            distribute_by: vec![],
-            sort_by: vec![],
+            },
+            join_operator: JoinOperator::CrossJoin,
+        }],
+    };
+    Statement::Insert(Insert {
+        insert_alias: None,
+        ignore: false,
+        priority: None,
+        replace_into: false,
+        table_alias: None,
+        or: None,
+        into: true,
+        table_name: junction,
+        columns: vec![
+            Ident::with_quote(QUOTE_CHAR, parent),
+            Ident::with_quote(QUOTE_CHAR, child),
+        ],
+        overwrite: false,
+        source: Some(Box::new(Query {
+            for_clause: None,
+            limit_by: vec![],
+            with: None,
+            body: Box::new(SetExpr::Select(Box::new(Select {
+                window_before_qualify: false,
+                connect_by: None,
+                value_table_mode: None,
+                distinct: None,
+                named_window: vec![],
+                top: None,
+                into: None,
+                projection: vec![
+                    SelectItem::UnnamedExpr(get_clone_column(CLONE_SOURCE, ID)),
+                    SelectItem::UnnamedExpr(get_clone_column("ids", ID)),
+                ],
+                from: vec![result],
+                lateral_views: vec![],
+                selection: None,
+                group_by: GroupByExpr::Expressions(vec![]),
+                cluster_by: vec![],
+                distribute_by: vec![],
+                sort_by: vec![],
+                having: None,
+                qualify: None,
+            }))),
+            order_by: vec![],
+            limit: None,
+            offset: None,
+            fetch: None,
+            locks: vec![],
+        })),
+        partitioned: None,
+        after_columns: vec![],
+        table: false,
+        on: Some(OnInsert::OnConflict(OnConflict {
+            conflict_target: None,
+            action: OnConflictAction::DoNothing,
+        })),
+        returning: None,
+    })
+}
+
+// unlinks every updated row from `ids`, or from all but `ids` when `negated`
+fn get_link_delete(
+    junction: ObjectName,
+    parent: &str,
+    child: &str,
+    ids: Vec<Expr>,
+    negated: bool,
+) -> Statement {
+    let mut selection = Expr::InSubquery {
+        expr: Box::new(Expr::Identifier(Ident::with_quote(QUOTE_CHAR, parent))),
+        subquery: Box::new(result_query(Expr::Identifier(Ident::with_quote(
+            QUOTE_CHAR, ID,
+        )))),
+        negated: false,
+    };
+    if !ids.is_empty() {
+        selection = Expr::BinaryOp {
+            left: Box::new(selection),
+            op: BinaryOperator::And,
+            right: Box::new(Expr::InList {
+                expr: Box::new(Expr::Identifier(Ident::with_quote(QUOTE_CHAR, child))),
+                list: ids,
+                negated,
+            }),
+        };
+    }
+    Statement::Delete(Delete {
+        limit: None,
+        order_by: vec![],
+        tables: vec![],
+        from: FromTable::WithFromKeyword(vec![TableWithJoins {
+            relation: TableFactor::Table {
+                partitions: vec![],
+                version: None,
+                name: junction,
+                alias: None,
+                args: None,
+                with_hints: vec![],
+            },
+            joins: vec![],
+        }]),
+        using: None,
+        selection: Some(selection),
+        returning: None,
+    })
+}
+
+// `set: { tags: { connect: [...], disconnect: [...] } }` or `{ set: [...] }` for a many @relation
+// `tags` of the updated table: inserts into and deletes from its `_AToB` junction table
+fn get_nested_links(
+    table: &str,
+    column: &Name,
+    links: &IndexMap<Name, GqlValue>,
+    directives: &[Positioned<Directive>],
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Vec<Cte>> {
+    let (relation, _, _, _, _, is_many, schema_name, _, _) =
+        get_relation(directives, sql_vars, final_vars)?;
+    if !is_many {
+        return Err(anyhow!(
+            "Linking {} needs a @relation with many: true",
+            column
+        ));
+    }
+    if links.contains_key("set") && links.len() > 1 {
+        return Err(anyhow!(
+            "set of {} cannot be combined with connect or disconnect",
+            column
+        ));
+    }
+    // as in queries, "A" is the id of the table that sorts first
+    let (a, b, parent, child) = if relation.as_str() < table {
+        (relation.as_str(), table, "B", "A")
+    } else {
+        (table, relation.as_str(), "A", "B")
+    };
+    let junction = ObjectName(
+        schema_name
+            .into_iter()
+            .chain(std::iter::once(format!("_{a}To{b}")))
+            .map(|name| Ident::with_quote(QUOTE_CHAR, name))
+            .collect(),
+    );
+    let mut ctes = vec![];
+    for (operation, value) in links {
+        let ids = get_link_ids(value, sql_vars, final_vars)?;
+        let name = format!("{operation}_{column}");
+        match operation.as_str() {
+            "set" => {
+                ctes.push(get_clone_cte(
+                    format!("unset_{column}"),
+                    get_link_query(get_link_delete(
+                        junction.clone(),
+                        parent,
+                        child,
+                        ids.clone(),
+                        true,
+                    )),
+                ));
+                if !ids.is_empty() {
+                    ctes.push(get_clone_cte(
+                        name,
+                        get_link_query(get_link_insert(junction.clone(), parent, child, ids)),
+                    ));
+                }
+            }
+            _ if ids.is_empty() => {}
+            "connect" => ctes.push(get_clone_cte(
+                name,
+                get_link_query(get_link_insert(junction.clone(), parent, child, ids)),
+            )),
+            _ => ctes.push(get_clone_cte(
+                name,
+                get_link_query(get_link_delete(junction.clone(), parent, child, ids, false)),
+            )),
+        }
+    }
+    Ok(ctes)
+}
+
+/// A delete without a filter, which would otherwise remove every row of the table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnfilteredDelete {
+    pub table: String,
+}
+
+impl std::fmt::Display for UnfilteredDelete {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "refusing to delete from {} without filter, pass allowAll: true to delete every row",
+            self.table
+        )
+    }
+}
+
+impl std::error::Error for UnfilteredDelete {}
+
+// `allowAll: true` on a delete, which may then have no filter
+fn get_allow_all(
+    arguments: &[(Positioned<Name>, Positioned<GqlValue>)],
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<bool> {
+    let Some((_, value)) = arguments
+        .iter()
+        .find(|(name, _)| name.node.as_str() == ALLOW_ALL)
+    else {
+        return Ok(false);
+    };
+    match &value.node {
+        GqlValue::Boolean(allow_all) => Ok(*allow_all),
+        GqlValue::Variable(name) => match sql_vars.get(name) {
+            Some(JsonValue::Bool(allow_all)) => Ok(*allow_all),
+            None | Some(JsonValue::Null) => Ok(false),
+            Some(value) => Err(anyhow!("{} must be a boolean, got {}", ALLOW_ALL, value)),
+        },
+        value => Err(anyhow!("{} must be a boolean, got {}", ALLOW_ALL, value)),
+    }
+}
+
+// `@meta(claim: true, limit:)` on an update, the number of rows it claims, 1 unless set
+fn get_claim_limit(directives: &[Positioned<Directive>]) -> AnyResult<Option<u64>> {
+    let Some(meta) = directives
+        .iter()
+        .find(|directive| directive.node.name.node.as_str() == "meta")
+    else {
+        return Ok(None);
+    };
+    let get = |name: &str| {
+        meta.node
+            .arguments
+            .iter()
+            .find(|(arg_name, _)| arg_name.node.as_str() == name)
+            .map(|(_, value)| &value.node)
+    };
+    if !matches!(get(CLAIM), Some(GqlValue::Boolean(true))) {
+        return Ok(None);
+    }
+    match get("limit") {
+        None => Ok(Some(1)),
+        Some(GqlValue::Number(n)) if n.as_u64().is_some_and(|n| n > 0) => Ok(n.as_u64()),
+        Some(value) => Err(anyhow!(
+            "claim limit must be a positive integer, got {}",
+            value
+        )),
+    }
+}
+
+// the rows a claim updates, by their key columns: the first `limit` that match and no other
+// transaction has locked, locked by this one until it ends
+fn get_claim_selection(
+    table_name: ObjectName,
+    keys: &[String],
+    selection: Option<Expr>,
+    order_by: Vec<OrderByExpr>,
+    limit: u64,
+) -> AnyResult<Expr> {
+    let columns = keys
+        .iter()
+        .map(|key| Expr::Identifier(Ident::with_quote(QUOTE_CHAR, key)))
+        .collect::<Vec<_>>();
+    let key = match columns.as_slice() {
+        [] => return Err(anyhow!("claim on {} needs key columns", table_name)),
+        [column] => column.clone(),
+        columns => Expr::Tuple(columns.to_vec()),
+    };
+    let mut query = get_filter_query(
+        selection,
+        order_by,
+        Some(Expr::Value(Value::Number(limit.to_string(), false))),
+        None,
+        vec![table_name],
+        None,
+        None,
+        None,
+    );
+    if let SetExpr::Select(select) = query.body.as_mut() {
+        select.projection = columns.into_iter().map(SelectItem::UnnamedExpr).collect();
+    }
+    query.locks = vec![LockClause {
+        lock_type: LockType::Update,
+        of: None,
+        nonblock: Some(NonBlock::SkipLocked),
+    }];
+    Ok(Expr::InSubquery {
+        expr: Box::new(key),
+        subquery: Box::new(query),
+        negated: false,
+    })
+}
+
+// `SELECT WHERE false`, the source of an insert without rows
+fn get_empty_source() -> Query {
+    Query {
+        for_clause: None,
+        limit_by: vec![],
+        with: None,
+        body: Box::new(SetExpr::Select(Box::new(Select {
+            window_before_qualify: false,
+            connect_by: None,
+            value_table_mode: None,
+            distinct: None,
+            named_window: vec![],
+            top: None,
+            projection: vec![],
+            into: None,
+            from: vec![],
+            lateral_views: vec![],
+            selection: Some(Expr::Value(Value::Boolean(false))),
+            group_by: GroupByExpr::Expressions(vec![]),
+            cluster_by: vec![],
+            distribute_by: vec![],
+            sort_by: vec![],
             having: None,
             qualify: None,
         }))),
@@ -2886,2170 +5725,7188 @@ pub fn wrap_mutation(key: &str, value: Statement, is_single: bool) -> Statement
         offset: None,
         fetch: None,
         locks: vec![],
-    }))
+    }
 }
 
-#[derive(PartialEq, Eq, Hash)]
-struct Tag {
-    key: String,
-    value: Option<String>,
+// updates without a filter match nothing
+fn or_match_nothing(selection: Option<Expr>) -> Option<Expr> {
+    selection.or_else(|| Some(Expr::Value(Value::Boolean(false))))
 }
 
-impl Debug for Tag {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        if self.value.is_some() {
-            return write!(f, "{}:{}", self.key, self.value.as_ref().expect("is_some"));
+fn get_mutation_assignments<'a>(
+    arguments: &'a Vec<(Positioned<Name>, Positioned<GqlValue>)>,
+    variables: &'a IndexMap<Name, GqlValue>,
+    sql_vars: &'a mut IndexMap<Name, JsonValue>,
+    final_vars: &'a mut IndexMap<Name, String>,
+    has_updated_at_directive: bool,
+    key_columns: &[String],
+    null_policy: Option<NullPolicy>,
+) -> AnyResult<(Option<Expr>, Vec<Assignment>)> {
+    let mut selection = None;
+    let mut assignments = vec![];
+    if has_updated_at_directive {
+        assignments.push(Assignment {
+            id: vec![Ident {
+                value: "updated_at".to_string(),
+                quote_style: Some(QUOTE_CHAR),
+            }],
+            value: Expr::Function(Function {
+                within_group: vec![],
+                name: ObjectName(vec![Ident {
+                    value: "now".to_string(),
+                    quote_style: None,
+                }]),
+                args: FunctionArguments::List(FunctionArgumentList {
+                    duplicate_treatment: None,
+                    clauses: vec![],
+                    args: vec![],
+                }),
+                over: None,
+                filter: None,
+                null_treatment: None,
+            }),
+        });
+    }
+    for argument in arguments {
+        let (p_key, p_value) = argument;
+        let (key, mut value) = (&p_key.node, &p_value.node);
+        if let GqlValue::Variable(name) = value {
+            if let Some(new_value) = variables.get(name) {
+                value = new_value;
+                if let GqlValue::Null = value {
+                    if null_policy.is_none() || shortcut_column(key.as_ref(), key_columns).is_none()
+                    {
+                        continue;
+                    }
+                }
+            }
+        }
+        if let Some(column) = shortcut_column(key.as_ref(), key_columns) {
+            let left = Expr::Identifier(Ident::with_quote(QUOTE_CHAR, column));
+            let new_selection = match null_policy.filter(|_| is_null_value(value, sql_vars)) {
+                Some(policy) => get_null_comparison(left, "eq", policy)?,
+                None => get_expr(left, "eq", value, None, sql_vars, final_vars)?,
+            };
+            if selection.is_some() && new_selection.is_some() {
+                selection = Some(Expr::BinaryOp {
+                    left: Box::new(selection.expect("gaurded by condition")),
+                    op: BinaryOperator::And,
+                    right: Box::new(new_selection.expect("gaurded by condition")),
+                });
+            } else {
+                selection = new_selection;
+            }
+            continue;
+        }
+        match (key.as_ref(), value) {
+            ("filter" | "where", GqlValue::Object(filter)) => {
+                (selection, _) = get_filter(filter, sql_vars, final_vars, null_policy, None)?;
+            }
+            ("set", GqlValue::Object(data)) => {
+                for (key, value) in data {
+                    assignments.push(Assignment {
+                        id: vec![Ident {
+                            value: key.to_string(),
+                            quote_style: Some(QUOTE_CHAR),
+                        }],
+                        value: get_value(value, sql_vars, final_vars)?,
+                    });
+                }
+            }
+            ("inc" | "increment", GqlValue::Object(data)) => {
+                for (key, value) in data {
+                    let column_ident = Ident {
+                        value: key.to_string(),
+                        quote_style: Some(QUOTE_CHAR),
+                    };
+                    assignments.push(Assignment {
+                        id: vec![column_ident.clone()],
+                        value: Expr::BinaryOp {
+                            left: Box::new(Expr::Identifier(column_ident)),
+                            op: BinaryOperator::Plus,
+                            right: Box::new(get_value(value, sql_vars, final_vars)?),
+                        },
+                    });
+                }
+            }
+            ("append" | "prepend" | "delete_key" | "delete_at_path", GqlValue::Object(data)) => {
+                for (column, value) in data {
+                    // a JSON variable is flattened into a list or object of its own
+                    let value = match value {
+                        GqlValue::Variable(name) => variables.get(name).unwrap_or(value),
+                        value => value,
+                    };
+                    let column_ident = Ident::with_quote(QUOTE_CHAR, column.as_str());
+                    let current = Expr::Identifier(column_ident.clone());
+                    assignments.push(Assignment {
+                        id: vec![column_ident],
+                        value: get_update_operation(
+                            key.as_ref(),
+                            column,
+                            current,
+                            value,
+                            sql_vars,
+                            final_vars,
+                        )?,
+                    });
+                }
+            }
+            _ => return Err(anyhow!("Invalid argument for update at: {}", key)),
         }
-        write!(f, "{}", self.key)
     }
+    Ok((selection, assignments))
 }
 
-impl ToString for Tag {
-    fn to_string(&self) -> String {
-        if self.value.is_some() {
-            return format!("{}:{}", self.key, self.value.as_ref().expect("is_some"));
-        }
-        self.key.clone()
+// lists and objects are jsonb, concatenated with `||`; anything else is an array element
+fn is_json_value(value: &GqlValue, sql_vars: &IndexMap<Name, JsonValue>) -> bool {
+    match value {
+        GqlValue::List(_) | GqlValue::Object(_) => true,
+        GqlValue::Variable(v) => matches!(
+            sql_vars.get(v),
+            Some(JsonValue::Array(_) | JsonValue::Object(_))
+        ),
+        _ => false,
     }
 }
 
-pub fn gql2sql(
-    ast: ExecutableDocument,
-    variables: &Option<JsonValue>,
-    operation_name: Option<String>,
-) -> AnyResult<(Statement, Option<Vec<JsonValue>>, Option<Vec<String>>, bool)> {
-    let mut statements = vec![];
-    let operation = match ast.operations {
-        DocumentOperations::Single(operation) => operation.node,
-        DocumentOperations::Multiple(map) => {
-            if let Some(name) = operation_name {
-                map.get(name.as_str())
-                    .ok_or_else(|| anyhow::anyhow!("Operation {} not found in the document", name))?
-                    .node
-                    .clone()
+// an element of a `#-` path, which is a text[] of keys and array indexes
+fn get_path_element(
+    value: &GqlValue,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Expr> {
+    match value {
+        GqlValue::String(_) => get_value(value, sql_vars, final_vars),
+        GqlValue::Number(n) => Ok(Expr::Value(Value::SingleQuotedString(n.to_string()))),
+        GqlValue::Variable(v) => {
+            let is_text = sql_vars.get(v).is_some_and(|v| value_to_type(v) == "text");
+            let expr = get_value(value, sql_vars, final_vars)?;
+            Ok(if is_text {
+                expr
             } else {
-                map.values()
-                    .next()
-                    .ok_or_else(|| {
-                        anyhow::anyhow!("No operation found in the document, please specify one")
-                    })?
-                    .node
-                    .clone()
-            }
+                Expr::Cast {
+                    kind: CastKind::DoubleColon,
+                    expr: Box::new(expr),
+                    data_type: DataType::Text,
+                    format: None,
+                }
+            })
         }
-    };
-
-    let (variables, mut sql_vars) = flatten_variables(variables, operation.variable_definitions);
-    let mut tags: IndexMap<String, IndexSet<Tag>> = IndexMap::new();
-    let mut final_vars: IndexSet<Name> = IndexSet::new();
-
-    match operation.ty {
-        OperationType::Query => {
-            for selection in &operation.selection_set.node.items {
-                match &selection.node {
-                    Selection::Field(p_field) => {
-                        let field = &p_field.node;
-                        if has_skip(field, &sql_vars) {
-                            continue;
-                        }
-                        let (name, key, is_aggregate, is_single, schema_name) =
-                            parse_query_meta(field)?;
+        _ => Err(anyhow!(
+            "delete_at_path paths must be lists of keys and indexes"
+        )),
+    }
+}
 
-                        let (
-                            selection,
+// `append`, `prepend`, `delete_key` and `delete_at_path` change a jsonb or array column in place
+fn get_update_operation(
+    operation: &str,
+    column: &Name,
+    current: Expr,
+    value: &GqlValue,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Expr> {
+    let binary = |left: Expr, op: BinaryOperator, right: Expr| Expr::BinaryOp {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    };
+    let is_json = is_json_value(value, sql_vars);
+    Ok(match operation {
+        "append" if is_json => binary(
+            current,
+            BinaryOperator::StringConcat,
+            get_value(value, sql_vars, final_vars)?,
+        ),
+        "append" => get_function_call(
+            "array_append",
+            vec![current, get_value(value, sql_vars, final_vars)?],
+        ),
+        "prepend" if is_json => binary(
+            get_value(value, sql_vars, final_vars)?,
+            BinaryOperator::StringConcat,
+            current,
+        ),
+        "prepend" => get_function_call(
+            "array_prepend",
+            vec![get_value(value, sql_vars, final_vars)?, current],
+        ),
+        "delete_key" => match value {
+            GqlValue::List(keys) => keys.iter().try_fold(current, |expr, key| {
+                Ok::<_, anyhow::Error>(binary(
+                    expr,
+                    BinaryOperator::Minus,
+                    get_value(key, sql_vars, final_vars)?,
+                ))
+            })?,
+            key => binary(
+                current,
+                BinaryOperator::Minus,
+                get_value(key, sql_vars, final_vars)?,
+            ),
+        },
+        _ => {
+            let path = match value {
+                GqlValue::List(path) if !path.is_empty() => path
+                    .iter()
+                    .map(|element| get_path_element(element, sql_vars, final_vars))
+                    .collect::<AnyResult<Vec<_>>>()?,
+                GqlValue::String(_) | GqlValue::Variable(_) => {
+                    vec![get_path_element(value, sql_vars, final_vars)?]
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "delete_at_path of {} must be a non-empty list of keys",
+                        column
+                    ))
+                }
+            };
+            binary(
+                current,
+                BinaryOperator::HashMinus,
+                Expr::Cast {
+                    kind: CastKind::DoubleColon,
+                    expr: Box::new(Expr::Array(Array {
+                        elem: path,
+                        named: true,
+                    })),
+                    data_type: DataType::Array(ArrayElemTypeDef::SquareBracket(
+                        Box::new(DataType::Text),
+                        None,
+                    )),
+                    format: None,
+                },
+            )
+        }
+    })
+}
+
+fn get_function_args(
+    directive_name: &str,
+    directives: &[Positioned<Directive>],
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+    final_vars: &mut IndexMap<Name, String>,
+) -> AnyResult<Option<Vec<FunctionArg>>> {
+    let Some(p_directive) = directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == directive_name)
+    else {
+        return Ok(None);
+    };
+    let arguments = &p_directive.node.arguments;
+    if !arguments
+        .iter()
+        .any(|(name, _)| name.node.as_str() == "function")
+    {
+        return Ok(None);
+    }
+    let args = match arguments
+        .iter()
+        .find(|(name, _)| name.node.as_str() == "args")
+    {
+        Some((_, value)) => match &value.node {
+            GqlValue::List(list) => list.clone(),
+            GqlValue::Null => vec![],
+            value => vec![value.clone()],
+        },
+        None => vec![],
+    };
+    args.into_iter()
+        .map(|arg| {
+            // `"$term"` is accepted as well as `$term` so args can be listed as plain strings
+            let arg = match arg {
+                GqlValue::String(s) if s.starts_with('$') => GqlValue::Variable(Name::new(&s[1..])),
+                arg => arg,
+            };
+            if let GqlValue::Variable(name) = &arg {
+                if !sql_vars.contains_key(name) {
+                    return Err(anyhow!(
+                        "@{} function argument ${} is not a provided variable at {}",
+                        directive_name,
+                        name,
+                        p_directive.pos
+                    ));
+                }
+            }
+            Ok(FunctionArg::Unnamed(FunctionArgExpr::Expr(get_value(
+                &arg, sql_vars, final_vars,
+            )?)))
+        })
+        .collect::<AnyResult<Vec<_>>>()
+        .map(Some)
+}
+
+fn get_meta_table(argument: &Positioned<GqlValue>, pos: Pos) -> AnyResult<&str> {
+    match &argument.node {
+        GqlValue::String(table) if !table.is_empty() => Ok(table.as_str()),
+        _ => Err(anyhow!(
+            "@meta argument \"table\" must be a non-empty string at {}",
+            pos
+        )),
+    }
+}
+
+pub fn parse_query_meta(
+    field: &Field,
+) -> AnyResult<(&str, &str, bool, bool, bool, bool, Option<&str>)> {
+    let mut is_aggregate = false;
+    let mut is_single = false;
+    let mut is_count = false;
+    let mut is_exists = false;
+    let mut name = field.name.node.as_str();
+    let mut schema_name = None;
+    let key = field
+        .alias
+        .as_ref()
+        .map_or_else(|| field.name.node.as_str(), |alias| alias.node.as_str());
+
+    if name.ends_with("_aggregate") {
+        name = &name[..name.len() - 10];
+        is_aggregate = true;
+    } else if name.ends_with("_one") {
+        name = &name[..name.len() - 4];
+        is_single = true;
+    } else if name.ends_with("_count") {
+        name = &name[..name.len() - 6];
+        is_count = true;
+    } else if name.ends_with("_exists") {
+        name = &name[..name.len() - 7];
+        is_exists = true;
+    }
+
+    if let Some(p_directive) = field
+        .directives
+        .iter()
+        .find(|directive| directive.node.name.node.as_str() == "meta")
+    {
+        let directive = &p_directive.node;
+        for (arg_name, argument) in &directive.arguments {
+            let arg_name = arg_name.node.as_str();
+            if arg_name == "table" {
+                name = get_meta_table(argument, p_directive.pos)?;
+            } else if arg_name == "function" {
+                if let GqlValue::String(function) = &argument.node {
+                    name = function.as_str();
+                }
+            } else if arg_name == "aggregate" {
+                if let GqlValue::Boolean(aggregate) = &argument.node {
+                    is_aggregate = *aggregate;
+                }
+            } else if arg_name == "single" {
+                if let GqlValue::Boolean(single) = &argument.node {
+                    is_single = *single;
+                }
+            } else if arg_name == "count" {
+                if let GqlValue::Boolean(count) = &argument.node {
+                    is_count = *count;
+                }
+            } else if arg_name == "exists" {
+                if let GqlValue::Boolean(exists) = &argument.node {
+                    is_exists = *exists;
+                }
+            } else if arg_name == "schema" {
+                if let GqlValue::String(schema) = &argument.node {
+                    schema_name = Some(schema.as_ref());
+                }
+            }
+        }
+    }
+
+    if [is_aggregate, is_single, is_count, is_exists]
+        .iter()
+        .filter(|b| **b)
+        .count()
+        > 1
+    {
+        return Err(anyhow!(
+            "Query can only be one of aggregate, single, count or exists"
+        ));
+    }
+
+    Ok((
+        name,
+        key,
+        is_aggregate,
+        is_single,
+        is_count,
+        is_exists,
+        schema_name,
+    ))
+}
+
+pub fn parse_mutation_meta(
+    field: &Field,
+) -> AnyResult<(&str, &str, bool, bool, bool, bool, Option<&str>)> {
+    let mut is_insert = false;
+    let mut is_update = false;
+    let mut is_delete = false;
+    let mut is_single = false;
+    let mut schema_name = None;
+    let mut name = field.name.node.as_ref();
+    let key = field
+        .alias
+        .as_ref()
+        .map_or_else(|| field.name.node.as_str(), |alias| alias.node.as_str());
+
+    if name.starts_with("insert_") {
+        name = &name[7..];
+        is_insert = true;
+    } else if name.starts_with("update_") {
+        name = &name[7..];
+        is_update = true;
+    } else if name.starts_with("delete_") {
+        name = &name[7..];
+        is_delete = true;
+    }
+
+    if let Some(p_directive) = field
+        .directives
+        .iter()
+        .find(|directive| directive.node.name.node.as_str() == "meta")
+    {
+        let directive = &p_directive.node;
+        for (arg_name, argument) in &directive.arguments {
+            let arg_name = arg_name.node.as_str();
+            if arg_name == "table" {
+                name = get_meta_table(argument, p_directive.pos)?;
+            } else if arg_name == "insert" {
+                if let GqlValue::Boolean(insert) = &argument.node {
+                    is_insert = *insert;
+                }
+            } else if arg_name == "update" {
+                if let GqlValue::Boolean(update) = &argument.node {
+                    is_update = *update;
+                }
+            } else if arg_name == CLAIM {
+                // a claim is an update of the rows it locks
+                if let GqlValue::Boolean(true) = &argument.node {
+                    is_update = true;
+                }
+            } else if arg_name == "delete" {
+                if let GqlValue::Boolean(delete) = &argument.node {
+                    is_delete = *delete;
+                }
+            } else if arg_name == "single" {
+                if let GqlValue::Boolean(delete) = &argument.node {
+                    is_single = *delete;
+                }
+            } else if arg_name == "schema" {
+                if let GqlValue::String(schema) = &argument.node {
+                    schema_name = Some(schema.as_ref());
+                }
+            }
+        }
+    }
+
+    if is_insert && is_update {
+        return Err(anyhow!("Mutation cannot be both insert and update"));
+    } else if is_insert && is_delete {
+        return Err(anyhow!("Mutation cannot be both insert and delete"));
+    } else if is_update && is_delete {
+        return Err(anyhow!("Mutation cannot be both update and delete"));
+    }
+
+    Ok((
+        name,
+        key,
+        is_insert,
+        is_update,
+        is_delete,
+        is_single,
+        schema_name,
+    ))
+}
+
+// `affected_rows` in the selection of a mutation: its response key, and whether it is the only
+// field so the returned rows can be left out
+fn get_affected_rows(items: &[Positioned<Selection>]) -> Option<(&str, bool)> {
+    let mut key = None;
+    let mut only = true;
+    for selection in items {
+        match &selection.node {
+            Selection::Field(field) if field.node.name.node == AFFECTED_ROWS => {
+                key = Some(
+                    field
+                        .node
+                        .alias
+                        .as_ref()
+                        .unwrap_or(&field.node.name)
+                        .node
+                        .as_str(),
+                );
+            }
+            _ => only = false,
+        }
+    }
+    key.map(|key| (key, only))
+}
+
+// `(SELECT <projection> FROM "result")` over the mutation CTE
+fn select_from_result(projection: Expr) -> Expr {
+    Expr::Subquery(Box::new(result_query(projection)))
+}
+
+fn result_query(projection: Expr) -> Query {
+    Query {
+        for_clause: None,
+        limit_by: vec![],
+        with: None,
+        body: Box::new(SetExpr::Select(Box::new(Select {
+            window_before_qualify: false,
+            connect_by: None,
+            value_table_mode: None,
+            distinct: None,
+            named_window: vec![],
+            top: None,
+            projection: vec![SelectItem::UnnamedExpr(projection)],
+            into: None,
+            from: vec![TableWithJoins {
+                relation: TableFactor::Table {
+                    partitions: vec![],
+                    version: None,
+                    name: ObjectName(vec![Ident {
+                        value: "result".to_string(),
+                        quote_style: Some(QUOTE_CHAR),
+                    }]),
+                    alias: None,
+                    args: None,
+                    with_hints: vec![],
+                },
+                joins: vec![],
+            }],
+            lateral_views: vec![],
+            selection: None,
+            group_by: GroupByExpr::Expressions(vec![]),
+            cluster_by: vec![],
+            distribute_by: vec![],
+            sort_by: vec![],
+            having: None,
+            qualify: None,
+        }))),
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        fetch: None,
+        locks: vec![],
+    }
+}
+
+#[must_use]
+pub fn wrap_mutation(key: &str, value: Statement, is_single: bool) -> Statement {
+    wrap_mutation_with(key, value, is_single, None)
+}
+
+// `affected_rows` is the response key of the count and whether the rows are left out
+fn wrap_mutation_with(
+    key: &str,
+    value: Statement,
+    is_single: bool,
+    affected_rows: Option<(&str, bool)>,
+) -> Statement {
+    let mut base = Expr::Function(Function {
+        within_group: vec![],
+        over: None,
+        name: ObjectName(vec![Ident {
+            value: "coalesce".to_string(),
+            quote_style: None,
+        }]),
+        args: FunctionArguments::List(FunctionArgumentList {
+            duplicate_treatment: None,
+            clauses: vec![],
+            args: vec![
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Function(Function {
+                    within_group: vec![],
+                    name: ObjectName(vec![Ident {
+                        value: JSONB_AGG.to_string(),
+                        quote_style: None,
+                    }]),
+                    args: FunctionArguments::List(FunctionArgumentList {
+                        duplicate_treatment: None,
+                        clauses: vec![],
+                        args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(
+                            Expr::Identifier(Ident {
+                                value: "result".to_string(),
+                                quote_style: Some(QUOTE_CHAR),
+                            }),
+                        ))],
+                    }),
+                    over: None,
+                    filter: None,
+                    null_treatment: None,
+                }))),
+                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                    Value::SingleQuotedString("[]".to_string()),
+                ))),
+            ],
+        }),
+        filter: None,
+        null_treatment: None,
+    });
+    if is_single {
+        base = Expr::BinaryOp {
+            left: Box::new(base),
+            op: BinaryOperator::Custom("->".to_string()),
+            right: Box::new(Expr::Value(Value::Number("0".to_string(), false))),
+        }
+    }
+    let mut args = vec![];
+    if !affected_rows.is_some_and(|(_, only)| only) {
+        args.push(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+            Value::SingleQuotedString(key.to_string()),
+        ))));
+        args.push(FunctionArg::Unnamed(FunctionArgExpr::Expr(
+            select_from_result(base),
+        )));
+    }
+    if let Some((affected_key, _)) = affected_rows {
+        args.push(FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+            Value::SingleQuotedString(affected_key.to_string()),
+        ))));
+        args.push(FunctionArg::Unnamed(FunctionArgExpr::Expr(
+            select_from_result(Expr::Function(Function {
+                within_group: vec![],
+                name: ObjectName(vec![Ident::new("count")]),
+                args: FunctionArguments::List(FunctionArgumentList {
+                    duplicate_treatment: None,
+                    clauses: vec![],
+                    args: vec![FunctionArg::Unnamed(FunctionArgExpr::Wildcard)],
+                }),
+                over: None,
+                filter: None,
+                null_treatment: None,
+            })),
+        )));
+    }
+    Statement::Query(Box::new(Query {
+        for_clause: None,
+        limit_by: vec![],
+        with: Some(With {
+            cte_tables: vec![Cte {
+                materialized: None,
+                alias: TableAlias {
+                    name: Ident {
+                        value: "result".to_string(),
+                        quote_style: Some(QUOTE_CHAR),
+                    },
+                    columns: vec![],
+                },
+                query: Box::new(Query {
+                    for_clause: None,
+                    limit_by: vec![],
+                    with: None,
+                    body: Box::new(SetExpr::Insert(value)),
+                    order_by: vec![],
+                    limit: None,
+                    offset: None,
+                    fetch: None,
+                    locks: vec![],
+                }),
+                from: None,
+            }],
+            recursive: false,
+        }),
+        body: Box::new(SetExpr::Select(Box::new(Select {
+            window_before_qualify: false,
+            connect_by: None,
+            value_table_mode: None,
+            distinct: None,
+            named_window: vec![],
+            top: None,
+            into: None,
+            projection: vec![SelectItem::ExprWithAlias {
+                expr: Expr::Function(Function {
+                    within_group: vec![],
+                    name: ObjectName(vec![Ident {
+                        value: JSONB_BUILD_OBJECT.to_string(),
+                        quote_style: None,
+                    }]),
+                    args: FunctionArguments::List(FunctionArgumentList {
+                        duplicate_treatment: None,
+                        clauses: vec![],
+                        args,
+                    }),
+                    over: None,
+                    filter: None,
+                    null_treatment: None,
+                }),
+                alias: Ident {
+                    value: DATA_LABEL.to_string(),
+                    quote_style: Some(QUOTE_CHAR),
+                },
+            }],
+            from: vec![],
+            lateral_views: vec![],
+            selection: None,
+            group_by: GroupByExpr::Expressions(vec![]),
+            cluster_by: vec![],
+            distribute_by: vec![],
+            sort_by: vec![],
+            having: None,
+            qualify: None,
+        }))),
+        order_by: vec![],
+        limit: None,
+        offset: None,
+        fetch: None,
+        locks: vec![],
+    }))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Param {
+    pub name: String,
+    pub value: JsonValue,
+    #[serde(rename = "type")]
+    pub sql_type: String,
+}
+
+fn get_params(
+    final_vars: IndexMap<Name, String>,
+    sql_vars: &mut IndexMap<Name, JsonValue>,
+) -> Option<Vec<Param>> {
+    if final_vars.is_empty() {
+        return None;
+    }
+    Some(
+        final_vars
+            .into_iter()
+            .filter_map(|(name, sql_type)| {
+                sql_vars.swap_remove(&name).map(|value| Param {
+                    name: name.to_string(),
+                    value,
+                    sql_type,
+                })
+            })
+            .collect(),
+    )
+}
+
+fn get_tag_key(table: &str, schema_name: Option<&str>, options: &Options) -> String {
+    match schema_name {
+        Some(schema_name) if !options.unqualified_tags => format!("{schema_name}.{table}"),
+        _ => table.to_string(),
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct Tag {
+    key: String,
+    value: Option<String>,
+}
+
+impl Debug for Tag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.value.is_some() {
+            return write!(f, "{}:{}", self.key, self.value.as_ref().expect("is_some"));
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+impl ToString for Tag {
+    fn to_string(&self) -> String {
+        if self.value.is_some() {
+            return format!("{}:{}", self.key, self.value.as_ref().expect("is_some"));
+        }
+        self.key.clone()
+    }
+}
+
+/// A cache tag of a query result: `type:{table}`, `type:{table}:{key}` or
+/// `type:{table}:{key}:{value}` once formatted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CacheTag {
+    /// The table, with its schema unless `Options::unqualified_tags` is set
+    pub table: String,
+    /// A column the result was filtered or joined on
+    pub key: Option<String>,
+    /// The value the column was filtered to
+    pub value: Option<String>,
+}
+
+impl std::fmt::Display for CacheTag {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "type:{}", self.table)?;
+        if let Some(key) = &self.key {
+            write!(f, ":{key}")?;
+        }
+        if let Some(value) = &self.value {
+            write!(f, ":{value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// What a comparison against a null value compiles to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NullPolicy {
+    /// Fail the translation
+    Error,
+    /// Drop the condition
+    Ignore,
+    /// Compare with `IS NULL` / `IS NOT NULL`
+    MatchNull,
+}
+
+impl NullPolicy {
+    fn parse(value: &str) -> AnyResult<Self> {
+        match value {
+            "error" => Ok(Self::Error),
+            "ignore" => Ok(Self::Ignore),
+            "matchNull" | "match_null" => Ok(Self::MatchNull),
+            _ => Err(anyhow!(
+                "nullPolicy must be one of error, ignore or matchNull, got {}",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Options {
+    /// `id: null` compiles to `"id" IS NULL` instead of matching nothing
+    pub match_null_shortcuts: bool,
+    /// Reject operations whose selections, arguments and variables add up to more nodes than this
+    pub node_budget: Option<usize>,
+    /// Abort translations that hold more bytes at once than this, with the `memory-budget`
+    /// feature and `BudgetAllocator` as the global allocator
+    pub memory_budget: Option<usize>,
+    /// Leave the schema out of cache tags (`type:users` instead of `type:auth.users`)
+    pub unqualified_tags: bool,
+    /// Quoting and case of table and column identifiers
+    pub identifiers: IdentifierStrategy,
+    /// Case of the result keys, overridden per root field by `@meta(caseStyle:)`
+    pub case_style: Option<CaseStyle>,
+    /// How filters and key arguments treat null values, overridden per filter by `nullPolicy`
+    pub null_policy: Option<NullPolicy>,
+    /// Columns usable as top-level equality arguments, `id`, `email`, `A` and `B` by default
+    pub key_columns: Option<Vec<String>>,
+    /// Key columns per table, overridden per field by `@meta(keys:)`
+    pub table_key_columns: HashMap<String, Vec<String>>,
+    /// Relations for nested fields without a `@relation` directive
+    pub relations: RelationRegistry,
+    /// Tenant column per table, added as a condition wherever the table is read or written
+    pub tenant: Option<TenantOptions>,
+    /// `to_sql` indents the statement, one clause per line
+    pub pretty: bool,
+    /// Variables that are undeclared, or required and missing, compile to NULL instead of
+    /// failing with `UnknownVariable`
+    pub allow_unknown_variables: bool,
+    /// Single relations without ordering or paging compile to plain joins instead of lateral
+    /// subqueries; the related row must be unique
+    pub flatten_single_joins: bool,
+    /// How date and time variables are typed and normalized
+    pub date_policy: DatePolicy,
+    /// The database `gql2sql_with_options` renders the statement for
+    pub dialect: Dialect,
+    /// JSON columns per table, whose fields can be selected with a selection set as with
+    /// `@json`
+    pub json_columns: HashMap<String, Vec<String>>,
+    /// `timestamp without time zone` columns per table, whose values `@format` reads as UTC;
+    /// other formatted columns are taken to be `timestamptz`
+    pub timestamp_columns: HashMap<String, Vec<String>>,
+    /// `to_commented_sql` prepends a comment with the operation name and a fingerprint of the
+    /// statement
+    pub comment: Option<SqlComment>,
+    /// `SET LOCAL` settings by operation size, see `get_local_settings`
+    pub local_settings: Vec<LocalSettings>,
+    /// Schema per table, for the tables whose `@meta` or `@relation` names none: a map, or a
+    /// resolver set in code
+    pub table_schemas: TableSchemas,
+    /// Parameters are numbered by variable name rather than by first use
+    pub sort_params: bool,
+    /// The bindings also compile each root field of a query on its own, see `gql2sql_roots`
+    pub split_roots: bool,
+    /// Postgres enums by GraphQL enum name; their variables are bound as the enum and their
+    /// literals written as `'label'::type`
+    pub enums: HashMap<String, EnumMapping>,
+    /// Handlers for custom directives; they are registered in code, so bindings have none
+    #[serde(skip)]
+    pub directives: DirectiveRegistry,
+}
+
+fn count_value_nodes(value: &GqlValue) -> usize {
+    match value {
+        GqlValue::List(list) => 1 + list.iter().map(count_value_nodes).sum::<usize>(),
+        GqlValue::Object(object) => 1 + object.values().map(count_value_nodes).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+fn count_json_nodes(value: &JsonValue) -> usize {
+    match value {
+        JsonValue::Array(list) => 1 + list.iter().map(count_json_nodes).sum::<usize>(),
+        JsonValue::Object(object) => 1 + object.values().map(count_json_nodes).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+fn count_directive_nodes(directives: &[Positioned<Directive>]) -> usize {
+    directives
+        .iter()
+        .map(|d| {
+            1 + d
+                .node
+                .arguments
+                .iter()
+                .map(|(_, v)| count_value_nodes(&v.node))
+                .sum::<usize>()
+        })
+        .sum()
+}
+
+fn count_selection_nodes(items: &[Positioned<Selection>]) -> usize {
+    items
+        .iter()
+        .map(|selection| match &selection.node {
+            Selection::Field(field) => {
+                1 + field
+                    .node
+                    .arguments
+                    .iter()
+                    .map(|(_, v)| count_value_nodes(&v.node))
+                    .sum::<usize>()
+                    + count_directive_nodes(&field.node.directives)
+                    + count_selection_nodes(&field.node.selection_set.node.items)
+            }
+            Selection::FragmentSpread(spread) => 1 + count_directive_nodes(&spread.node.directives),
+            Selection::InlineFragment(frag) => {
+                1 + count_directive_nodes(&frag.node.directives)
+                    + count_selection_nodes(&frag.node.selection_set.node.items)
+            }
+        })
+        .sum()
+}
+
+// the size of an operation and its variables, which `node_budget` limits
+fn count_operation_nodes(operation: &OperationDefinition, variables: &Option<JsonValue>) -> usize {
+    count_selection_nodes(&operation.selection_set.node.items)
+        + variables.as_ref().map_or(0, count_json_nodes)
+}
+
+pub fn compile_filter(
+    filter: &JsonValue,
+    options: &Options,
+) -> AnyResult<(String, Option<Vec<Param>>)> {
+    let mut sql_vars = IndexMap::new();
+    let mut final_vars = IndexMap::new();
+    let GqlValue::Object(filter) = flatten(Name::new("filter"), filter, &mut sql_vars) else {
+        return Err(anyhow!("filter must be an object"));
+    };
+    let (mut selection, _keys) = get_filter(
+        &filter,
+        &mut sql_vars,
+        &mut final_vars,
+        options.null_policy,
+        None,
+    )?;
+    normalize_identifiers(&mut selection, options.identifiers);
+    let sql = selection.map_or_else(|| "true".to_string(), |s| s.to_string());
+    Ok((sql, get_params(final_vars, &mut sql_vars)))
+}
+
+/// The operation named `operation_name`, or the first operation of the document.
+fn get_operation<'a>(
+    ast: &'a ExecutableDocument,
+    operation_name: Option<&str>,
+) -> AnyResult<&'a OperationDefinition> {
+    Ok(match &ast.operations {
+        DocumentOperations::Single(operation) => &operation.node,
+        DocumentOperations::Multiple(map) => match operation_name {
+            Some(name) => {
+                &map.get(name)
+                    .ok_or_else(|| anyhow!("Operation {} not found in the document", name))?
+                    .node
+            }
+            None => {
+                &map.values()
+                    .next()
+                    .ok_or_else(|| {
+                        anyhow!("No operation found in the document, please specify one")
+                    })?
+                    .node
+            }
+        },
+    })
+}
+
+pub(crate) fn get_operation_mut<'a>(
+    ast: &'a mut ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Option<&'a mut OperationDefinition> {
+    match &mut ast.operations {
+        DocumentOperations::Single(operation) => Some(&mut operation.node),
+        DocumentOperations::Multiple(map) => match operation_name {
+            Some(name) => map.get_mut(name).map(|operation| &mut operation.node),
+            None => map.values_mut().next().map(|operation| &mut operation.node),
+        },
+    }
+}
+
+/// The common table expressions custom directives add for the root fields of an operation.
+pub(crate) fn get_directive_ctes(
+    ast: &ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<&str>,
+    options: &Options,
+) -> AnyResult<Vec<Cte>> {
+    if options.directives.is_empty() {
+        return Ok(vec![]);
+    }
+    let operation = get_operation(ast, operation_name)?;
+    let sql_vars = match variables {
+        Some(JsonValue::Object(values)) => values
+            .iter()
+            .map(|(name, value)| (Name::new(name), value.clone()))
+            .collect(),
+        _ => IndexMap::new(),
+    };
+    let mut ctes = vec![];
+    for selection in &operation.selection_set.node.items {
+        let Selection::Field(field) = &selection.node else {
+            continue;
+        };
+        let field = &field.node;
+        if has_skip(field, &sql_vars) {
+            continue;
+        }
+        let table = match operation.ty {
+            OperationType::Mutation => parse_mutation_meta(field)?.0,
+            _ => parse_query_meta(field)?.0,
+        };
+        ctes.extend(options.directives.ctes(field, table, &sql_vars)?);
+    }
+    Ok(ctes)
+}
+
+/// Collects `@meta(maxMs:)` timeout hints keyed by root field response key.
+pub fn get_timeouts(
+    ast: &ExecutableDocument,
+    operation_name: Option<&str>,
+) -> AnyResult<Option<IndexMap<String, u64>>> {
+    let operation = get_operation(ast, operation_name)?;
+    let mut timeouts = IndexMap::new();
+    for selection in &operation.selection_set.node.items {
+        let Selection::Field(field) = &selection.node else {
+            continue;
+        };
+        let Some(p_directive) = field
+            .node
+            .directives
+            .iter()
+            .find(|directive| directive.node.name.node.as_str() == "meta")
+        else {
+            continue;
+        };
+        let Some((_, value)) = p_directive
+            .node
+            .arguments
+            .iter()
+            .find(|(name, _)| name.node.as_str() == "maxMs")
+        else {
+            continue;
+        };
+        let max_ms = match &value.node {
+            GqlValue::Number(n) => n.as_u64().filter(|n| *n > 0),
+            _ => None,
+        }
+        .ok_or_else(|| {
+            anyhow!(
+                "@meta argument \"maxMs\" must be a positive integer at {}",
+                p_directive.pos
+            )
+        })?;
+        let key = field
+            .node
+            .alias
+            .as_ref()
+            .unwrap_or(&field.node.name)
+            .node
+            .to_string();
+        timeouts.insert(key, max_ms);
+    }
+    if timeouts.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(timeouts))
+}
+
+/// Collects the directives of the operation itself, e.g. `query Q @cached(ttl: 60)`, as
+/// `{"cached": {"ttl": 60}}` with variables and their defaults resolved.
+pub fn get_operation_directives(
+    ast: &ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<&str>,
+) -> AnyResult<Option<IndexMap<String, JsonValue>>> {
+    let operation = get_operation(ast, operation_name)?;
+    if operation.directives.is_empty() {
+        return Ok(None);
+    }
+    let mut directives = IndexMap::new();
+    for directive in &operation.directives {
+        directives.insert(
+            directive.node.name.node.to_string(),
+            JsonValue::Object(get_directive_arguments(
+                operation,
+                variables,
+                &directive.node,
+            )?),
+        );
+    }
+    Ok(Some(directives))
+}
+
+// the arguments of a directive as JSON, taking variables from `variables` or their defaults
+fn get_directive_arguments(
+    operation: &OperationDefinition,
+    variables: &Option<JsonValue>,
+    directive: &Directive,
+) -> AnyResult<serde_json::Map<String, JsonValue>> {
+    let lookup = |name: Name| -> AnyResult<ConstValue> {
+        let provided = variables.as_ref().and_then(|v| v.get(name.as_str()));
+        if let Some(value) = provided {
+            return Ok(ConstValue::from_json(value.clone())?);
+        }
+        Ok(operation
+            .variable_definitions
+            .iter()
+            .find(|d| d.node.name.node == name)
+            .and_then(|d| d.node.default_value.as_ref())
+            .map_or(ConstValue::Null, |d| d.node.clone()))
+    };
+    let mut arguments = serde_json::Map::new();
+    for (name, value) in &directive.arguments {
+        let value = value.node.clone().into_const_with(lookup)?.into_json()?;
+        arguments.insert(name.node.to_string(), value);
+    }
+    Ok(arguments)
+}
+
+/// Renders a translated statement, indented when `options.pretty` is set. Table samples are
+/// written as `TABLESAMPLE` clauses, which the statement's own `to_string` can't spell.
+pub fn to_sql(statement: &Statement, options: &Options) -> String {
+    let sql = write_samples(statement);
+    if options.pretty {
+        pretty_sql(&sql)
+    } else {
+        sql
+    }
+}
+
+pub fn parse(query: &str) -> AnyResult<ExecutableDocument> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("gql2sql::parse", bytes = query.len()).entered();
+    Ok(async_graphql_parser::parse_query(query)?)
+}
+
+pub fn gql2sql(
+    ast: ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+) -> AnyResult<(Statement, Option<Vec<Param>>, Option<Vec<CacheTag>>, bool)> {
+    gql2sql_with_options(ast, variables, operation_name, &Options::default())
+}
+
+pub fn gql2sql_with_options(
+    ast: ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+    options: &Options,
+) -> AnyResult<(Statement, Option<Vec<Param>>, Option<Vec<CacheTag>>, bool)> {
+    render(
+        build_plan(ast, variables, operation_name, options)?,
+        options.dialect,
+        options,
+    )
+}
+
+/// `gql2sql_with_options` for a document that must be on the allow list, failing with
+/// `NotAllowed` before it is parsed otherwise.
+pub fn gql2sql_checked(
+    query: &str,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+    options: &Options,
+    allow_list: &AllowList,
+) -> AnyResult<(Statement, Option<Vec<Param>>, Option<Vec<CacheTag>>, bool)> {
+    allow_list.check(query)?;
+    gql2sql_with_options(parse(query)?, variables, operation_name, options)
+}
+
+fn translate(
+    ast: ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+    options: &Options,
+    warnings: &mut Vec<Diagnostic>,
+) -> AnyResult<(Statement, Option<Vec<Param>>, Option<Vec<CacheTag>>, bool)> {
+    let mut statements = vec![];
+    let mut operation = match ast.operations {
+        DocumentOperations::Single(operation) => operation.node,
+        DocumentOperations::Multiple(mut map) => {
+            if let Some(name) = operation_name {
+                map.remove(name.as_str())
+                    .ok_or_else(|| anyhow::anyhow!("Operation {} not found in the document", name))?
+                    .node
+            } else {
+                map.into_values()
+                    .next()
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("No operation found in the document, please specify one")
+                    })?
+                    .node
+            }
+        }
+    };
+
+    if !options.allow_unknown_variables {
+        check_variables(&operation, variables)?;
+    }
+
+    if let Some(budget) = options.node_budget {
+        let nodes = count_operation_nodes(&operation, variables);
+        if nodes > budget {
+            return Err(anyhow!(
+                "Operation has {} nodes which exceeds the budget of {}",
+                nodes,
+                budget
+            ));
+        }
+    }
+
+    for selection in &mut operation.selection_set.node.items {
+        if let Selection::Field(field) = &mut selection.node {
+            let field = &mut field.node;
+            if let Some(style) = get_meta_case_style(field)?.or(options.case_style) {
+                apply_field_case_style(field, style);
+            }
+            if !options.relations.is_empty() {
+                let table = match operation.ty {
+                    OperationType::Mutation => parse_mutation_meta(field)?.0,
+                    _ => parse_query_meta(field)?.0,
+                }
+                .to_string();
+                apply_relations(
+                    &mut field.selection_set.node.items,
+                    &table,
+                    &options.relations,
+                );
+            }
+        }
+    }
+
+    let binary = get_binary_variables(&operation.variable_definitions);
+    let (variables, mut sql_vars) =
+        flatten_variables(variables, operation.variable_definitions, &binary);
+    check_binary(&sql_vars, &binary)?;
+    for selection in &mut operation.selection_set.node.items {
+        if let Selection::Field(field) = &mut selection.node {
+            let field = &mut field.node;
+            let table = match operation.ty {
+                OperationType::Mutation => parse_mutation_meta(field)?.0,
+                _ => parse_query_meta(field)?.0,
+            }
+            .to_string();
+            apply_schemas(field, &table, &options.table_schemas, &sql_vars)?;
+        }
+    }
+    let mut tags: IndexMap<String, IndexSet<Tag>> = IndexMap::new();
+    let mut final_vars: IndexMap<Name, String> = IndexMap::new();
+
+    match operation.ty {
+        OperationType::Query => {
+            let items = merge_fields(&operation.selection_set.node.items, &sql_vars)?;
+            for selection in items.iter() {
+                check_memory()?;
+                match &selection.node {
+                    Selection::Field(p_field) => {
+                        let field = &p_field.node;
+                        if has_skip(field, &sql_vars) {
+                            continue;
+                        }
+                        let (name, key, is_aggregate, is_single, is_count, is_exists, schema_name) =
+                            parse_query_meta(field)?;
+                        let key_columns = get_key_columns(
+                            &field.directives,
+                            &field.selection_set.node.items,
+                            name,
+                            &mut sql_vars,
+                            options,
+                        )?;
+
+                        let (
+                            mut selection,
                             distinct,
-                            distinct_order,
-                            order_by,
+                            mut distinct_order,
+                            mut order_by,
                             mut first,
                             after,
                             keys,
                             group_by,
+                            locks,
+                            sample,
                         ) = parse_args(
                             &field.arguments,
                             &variables,
                             &mut sql_vars,
-                            &mut final_vars,
+                            &mut final_vars,
+                            &key_columns,
+                            options,
+                            &mut FilterScope {
+                                table: name.to_string(),
+                                qualifier: schema_name
+                                    .into_iter()
+                                    .chain(std::iter::once(name))
+                                    .map(|name| Ident::with_quote(QUOTE_CHAR, name))
+                                    .collect(),
+                                items: &field.selection_set.node.items,
+                                tags: &mut tags,
+                                options,
+                            },
+                        )?;
+                        let columns = get_columns(&field.selection_set.node.items, &mut sql_vars)?;
+                        rename_columns(&mut selection, &mut order_by, &columns);
+                        options.directives.filter(
+                            &field.name.node,
+                            name,
+                            &field.directives,
+                            &sql_vars,
+                            &mut selection,
+                        )?;
+                        if let Some(distinct_order) = distinct_order.as_mut() {
+                            rename_columns(&mut None, distinct_order, &columns);
+                        }
+                        if is_single {
+                            first = Some(Expr::Value(Value::Number("1".to_string(), false)));
+                        }
+                        let tag_key = get_tag_key(name, schema_name, options);
+                        if let Some(keys) = keys {
+                            tags.insert(tag_key, keys.into_iter().collect());
+                        } else {
+                            tags.insert(tag_key, IndexSet::new());
+                        };
+                        let table_name = schema_name.map_or_else(
+                            || {
+                                ObjectName(vec![Ident {
+                                    value: name.to_string(),
+                                    quote_style: Some(QUOTE_CHAR),
+                                }])
+                            },
+                            |schema_name| {
+                                ObjectName(vec![
+                                    Ident {
+                                        value: schema_name.to_string(),
+                                        quote_style: Some(QUOTE_CHAR),
+                                    },
+                                    Ident {
+                                        value: name.to_string(),
+                                        quote_style: Some(QUOTE_CHAR),
+                                    },
+                                ])
+                            },
+                        );
+                        let (order_by, group_order) = match &group_by {
+                            Some(group_by) if is_aggregate => {
+                                (vec![], get_group_order(order_by, &group_by.keys)?)
+                            }
+                            _ => (order_by, vec![]),
+                        };
+                        let table_args = get_function_args(
+                            "meta",
+                            &field.directives,
+                            &mut sql_vars,
+                            &mut final_vars,
+                        )?;
+                        let mut base_query = get_filter_query(
+                            selection,
+                            order_by,
+                            first,
+                            after,
+                            vec![table_name],
+                            table_args,
+                            distinct,
+                            distinct_order,
+                        );
+                        base_query.locks = locks;
+                        if let Some(sample) = sample {
+                            set_sample(&mut base_query, sample);
+                        }
+                        if is_count {
+                            statements.push((key, get_count_query(base_query)));
+                        } else if is_exists {
+                            statements.push((
+                                key,
+                                Expr::Exists {
+                                    subquery: Box::new(base_query),
+                                    negated: false,
+                                },
+                            ));
+                        } else if is_aggregate {
+                            let aggs = get_aggregate_projection(
+                                &field.selection_set.node.items,
+                                name,
+                                group_by.as_ref().map(|group_by| group_by.keys.clone()),
+                                &variables,
+                                &mut sql_vars,
+                                &mut final_vars,
+                                &mut tags,
+                                warnings,
+                                options,
+                            )?;
+                            let subquery = Query {
+                                for_clause: None,
+                                limit_by: vec![],
+                                with: None,
+                                body: Box::new(get_agg_query(
+                                    aggs,
+                                    vec![TableWithJoins {
+                                        relation: TableFactor::Derived {
+                                            lateral: false,
+                                            subquery: Box::new(base_query),
+                                            alias: Some(TableAlias {
+                                                name: Ident {
+                                                    value: BASE.to_string(),
+                                                    quote_style: Some(QUOTE_CHAR),
+                                                },
+                                                columns: vec![],
+                                            }),
+                                        },
+                                        joins: vec![],
+                                    }],
+                                    None,
+                                    ROOT_LABEL,
+                                    group_by.clone(),
+                                )),
+                                order_by: group_order,
+                                limit: None,
+                                offset: None,
+                                fetch: None,
+                                locks: vec![],
+                            };
+                            // TODO: Do I need to be deleted?
+                            if group_by.is_some() {
+                                // find-me
+                                statements.push((
+                                    key,
+                                    Expr::Subquery(Box::new(get_groups_list(subquery, ROOT_LABEL))),
+                                ));
+                                // statements.push((
+                                //     key,
+                                //     Expr::Function(Function {
+                                //         order_by: vec![],
+                                //         name: ObjectName(vec![Ident {
+                                //             value: JSONB_AGG.to_string(),
+                                //             quote_style: None,
+                                //         }]),
+                                //         args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(
+
+                                //             Expr::Function(Function {
+                                //                 name: ObjectName(vec![Ident {
+                                //                     value: TO_JSONB.to_string(),
+                                //                     quote_style: None,
+                                //                 }]),
+                                //                 args: vec![FunctionArg::Unnamed(
+                                //                     FunctionArgExpr::Expr(Expr::Subquery(
+                                //                         Box::new(Query {
+                                //                             body: Box::new(SetExpr::Select(
+                                //                                 Box::new(Select {
+                                //                                     distinct: None,
+                                //                                     top: None,
+                                //                                     projection: vec![SelectItem::UnnamedExpr(Expr::Identifier(Ident {
+                                //                                         value: ROOT_LABEL.to_string(),
+                                //                                         quote_style: Some(QUOTE_CHAR),
+                                //                                     }))],
+                                //                                     // find me
+                                //                                     into: None,
+                                //                                     from: vec![TableWithJoins {
+                                //                                         relation: TableFactor::Derived { lateral: false, subquery: Box::new(subquery) , alias: Some(TableAlias { name: Ident { value: ROOT_LABEL.to_string(), quote_style: Some(QUOTE_CHAR) }, columns: vec![] }) },
+                                //                                         joins: vec![],
+                                //                                     }],
+                                //                                     lateral_views: vec![],
+                                //                                     selection: None,
+                                //                                     group_by: GroupByExpr::Expressions(vec![]),
+                                //                                     cluster_by: vec![],
+                                //                                     distribute_by: vec![],
+                                //                                     sort_by: vec![],
+                                //                                     having: None,
+                                //                                     named_window: vec![],
+                                //                                     qualify: None,
+                                //                                     value_table_mode: None,
+                                //                                 }),
+                                //                             )),
+                                //                             for_clause: None,
+                                //                             limit_by: vec![],
+                                //                             with: None,
+                                //                             order_by: vec![],
+                                //                             limit: None,
+                                //                             offset: None,
+                                //                             fetch: None,
+                                //                             locks: vec![],
+                                //                         }),
+                                //                     )),
+                                //                 )],
+                                //                 filter: None,
+                                //                 null_treatment: None,
+                                //                 over: None,
+                                //                 distinct: false,
+                                //                 special: false,
+                                //                 order_by: vec![],
+                                //             }),
+                                //         ))],
+                                //         over: None,
+                                //         distinct: false,
+                                //         special: false,
+                                //         filter: None,
+                                //         null_treatment: None,
+                                //     }),
+                                // ));
+                            } else {
+                                statements.push((key, Expr::Subquery(Box::new(subquery))));
+                            }
+                        } else {
+                            let (projection, joins, merges) = get_projection(
+                                &field.selection_set.node.items,
+                                name,
+                                Some(BASE),
+                                &variables,
+                                &mut sql_vars,
+                                &mut final_vars,
+                                &mut tags,
+                                warnings,
+                                options,
+                            )?;
+                            let root_query = get_root_query(
+                                projection,
+                                vec![TableWithJoins {
+                                    relation: TableFactor::Derived {
+                                        lateral: false,
+                                        subquery: Box::new(base_query),
+                                        alias: Some(TableAlias {
+                                            name: Ident {
+                                                value: BASE.to_string(),
+                                                quote_style: Some(QUOTE_CHAR),
+                                            },
+                                            columns: vec![],
+                                        }),
+                                    },
+                                    joins,
+                                }],
+                                None,
+                                &merges,
+                                is_single,
+                                ROOT_LABEL,
+                            );
+                            statements.push((
+                                key,
+                                Expr::Subquery(Box::new(Query {
+                                    for_clause: None,
+                                    limit_by: vec![],
+                                    with: None,
+                                    body: Box::new(root_query),
+                                    order_by: vec![],
+                                    limit: None,
+                                    offset: None,
+                                    fetch: None,
+                                    locks: vec![],
+                                })),
+                            ));
+                        };
+                    }
+                    Selection::FragmentSpread(_) | Selection::InlineFragment(_) => {
+                        return Err(anyhow::anyhow!("Fragment not supported"))
+                    }
+                }
+            }
+            let statement = Statement::Query(Box::new(Query {
+                for_clause: None,
+                limit_by: vec![],
+                with: None,
+                body: Box::new(SetExpr::Select(Box::new(Select {
+                    window_before_qualify: false,
+                    connect_by: None,
+                    value_table_mode: None,
+                    distinct: None,
+                    named_window: vec![],
+                    top: None,
+                    into: None,
+                    projection: vec![SelectItem::ExprWithAlias {
+                        alias: Ident {
+                            value: DATA_LABEL.into(),
+                            quote_style: Some(QUOTE_CHAR),
+                        },
+                        expr: Expr::Function(Function {
+                            within_group: vec![],
+                            name: ObjectName(vec![Ident {
+                                value: JSONB_BUILD_OBJECT.to_string(),
+                                quote_style: None,
+                            }]),
+                            args: FunctionArguments::List(FunctionArgumentList {
+                                duplicate_treatment: None,
+                                clauses: vec![],
+                                args: statements
+                                    .into_iter()
+                                    .flat_map(|(key, query)| {
+                                        vec![
+                                            FunctionArg::Unnamed(FunctionArgExpr::Expr(
+                                                Expr::Value(Value::SingleQuotedString(
+                                                    key.to_string(),
+                                                )),
+                                            )),
+                                            FunctionArg::Unnamed(FunctionArgExpr::Expr(query)),
+                                        ]
+                                    })
+                                    .collect(),
+                            }),
+                            over: None,
+                            filter: None,
+                            null_treatment: None,
+                        }),
+                    }],
+                    from: vec![],
+                    lateral_views: vec![],
+                    selection: None,
+                    group_by: GroupByExpr::Expressions(vec![]),
+                    cluster_by: vec![],
+                    distribute_by: vec![],
+                    sort_by: vec![],
+                    having: None,
+                    qualify: None,
+                }))),
+                order_by: vec![],
+                limit: None,
+                offset: None,
+                fetch: None,
+                locks: vec![],
+            }));
+            let params = get_params(final_vars, &mut sql_vars);
+            if tags.is_empty() {
+                return Ok((statement, params, None, false));
+            }
+            let mut sub_tags = tags
+                .into_iter()
+                .flat_map(|(table, values)| {
+                    if values.is_empty() {
+                        return vec![CacheTag {
+                            table,
+                            key: None,
+                            value: None,
+                        }];
+                    }
+                    values
+                        .into_iter()
+                        .map(|tag| CacheTag {
+                            table: table.clone(),
+                            key: Some(tag.key),
+                            value: tag.value,
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<CacheTag>>();
+            sub_tags.sort_by_cached_key(ToString::to_string);
+            return Ok((statement, params, Some(sub_tags), false));
+        }
+        OperationType::Mutation => {
+            for selection in operation.selection_set.node.items {
+                check_memory()?;
+                match &selection.node {
+                    Selection::Field(p_field) => {
+                        let field = &p_field.node;
+                        let (name, key, is_insert, is_update, is_delete, is_single, schema_name) =
+                            parse_mutation_meta(field)?;
+                        let affected_rows = get_affected_rows(&field.selection_set.node.items);
+                        let key_columns = get_key_columns(
+                            &field.directives,
+                            &field.selection_set.node.items,
+                            name,
+                            &mut sql_vars,
+                            options,
                         )?;
-                        if is_single {
-                            first = Some(Expr::Value(Value::Number("1".to_string(), false)));
+
+                        let table_name = schema_name.map_or_else(
+                            || {
+                                ObjectName(vec![Ident {
+                                    value: name.to_string(),
+                                    quote_style: Some(QUOTE_CHAR),
+                                }])
+                            },
+                            |schema_name| {
+                                ObjectName(vec![
+                                    Ident {
+                                        value: schema_name.to_string(),
+                                        quote_style: Some(QUOTE_CHAR),
+                                    },
+                                    Ident {
+                                        value: name.to_string(),
+                                        quote_style: Some(QUOTE_CHAR),
+                                    },
+                                ])
+                            },
+                        );
+                        if field
+                            .directives
+                            .iter()
+                            .any(|d| d.node.name.node.as_str() == "clone")
+                        {
+                            let (selection, assignments) = get_mutation_assignments(
+                                &field.arguments,
+                                &variables,
+                                &mut sql_vars,
+                                &mut final_vars,
+                                false,
+                                &key_columns,
+                                options.null_policy,
+                            )?;
+                            let clone_key = get_clone_key(
+                                &field.directives,
+                                name,
+                                &mut sql_vars,
+                                options,
+                                p_field.pos,
+                            )?;
+                            let returning = get_clone_returning(
+                                &field.selection_set.node.items,
+                                name,
+                                &mut sql_vars,
+                            )?;
+                            let mut overrides =
+                                vec![(clone_key.clone(), get_clone_column(CLONE_MAP, "new_id"))];
+                            overrides.extend(assignments.into_iter().filter_map(|a| {
+                                a.id.last().map(|column| (column.value.clone(), a.value))
+                            }));
+                            let mut selection = or_match_nothing(selection);
+                            options.directives.filter(
+                                &field.name.node,
+                                name,
+                                &field.directives,
+                                &sql_vars,
+                                &mut selection,
+                            )?;
+                            let map = "clone_map".to_string();
+                            let mut ctes = vec![get_clone_cte(
+                                map.clone(),
+                                get_clone_map(table_name.clone(), &clone_key, None, selection),
+                            )];
+                            let statement = wrap_mutation_with(
+                                key,
+                                get_clone_insert(
+                                    table_name,
+                                    &clone_key,
+                                    &map,
+                                    overrides,
+                                    Some(returning),
+                                ),
+                                is_single,
+                                affected_rows,
+                            );
+                            let Statement::Query(mut query) = statement else {
+                                unreachable!("wrap_mutation always returns a query");
+                            };
+                            if let Some(with) = query.with.as_mut() {
+                                get_clone_children(
+                                    &field.selection_set.node.items,
+                                    &map,
+                                    &clone_key,
+                                    &mut ctes,
+                                    &mut sql_vars,
+                                    &final_vars,
+                                    options,
+                                )?;
+                                let result = with.cte_tables.drain(..);
+                                let children = ctes.split_off(1);
+                                with.cte_tables =
+                                    ctes.into_iter().chain(result).chain(children).collect();
+                            }
+                            let params = get_params(final_vars, &mut sql_vars);
+                            return Ok((Statement::Query(query), params, None, true));
+                        }
+                        if is_insert {
+                            // an insert reads no rows a filter could narrow
+                            let mut filtered = None;
+                            options.directives.filter(
+                                &field.name.node,
+                                name,
+                                &field.directives,
+                                &sql_vars,
+                                &mut filtered,
+                            )?;
+                            if filtered.is_some() {
+                                return Err(anyhow!(
+                                    "Directive filters cannot apply to the insert into {}",
+                                    name
+                                ));
+                            }
+                            let id_generator = get_id_generator(&field.directives, &sql_vars)?;
+                            let (columns, rows, source) = get_mutation_columns(
+                                &field.arguments,
+                                &variables,
+                                &mut sql_vars,
+                                &mut final_vars,
+                                id_generator,
+                                options.null_policy,
+                            )?;
+                            // let (projection, _, _) = get_projection(
+                            //     &field.selection_set.node.items,
+                            //     name,
+                            //     None,
+                            //     &variables,
+                            //     &mut sql_vars,
+                            //     &mut final_vars,
+                            //     &mut tags,
+                            // )?;
+                            // no rows still go through the insert, so the result is shaped like one
+                            // that inserted some
+                            let source = match source {
+                                None if rows.is_empty() => Some(get_empty_source()),
+                                source => source,
+                            };
+                            let params = get_params(final_vars, &mut sql_vars);
+                            let is_potential_upsert = columns.contains(&Ident {
+                                value: "id".to_owned(),
+                                quote_style: Some(QUOTE_CHAR),
+                            });
+                            return Ok((
+                                wrap_mutation_with(
+                                    key,
+                                    Statement::Insert(Insert {
+                                        insert_alias: None,
+                                        ignore: false,
+                                        priority: None,
+                                        replace_into: false,
+                                        table_alias: None,
+                                        or: None,
+                                        into: true,
+                                        table_name,
+                                        columns: columns.clone(),
+                                        overwrite: false,
+                                        source: Some(Box::new(source.unwrap_or_else(|| Query {
+                                            for_clause: None,
+                                            limit_by: vec![],
+                                            with: None,
+                                            body: Box::new(SetExpr::Values(Values {
+                                                explicit_row: false,
+                                                rows,
+                                            })),
+                                            order_by: vec![],
+                                            limit: None,
+                                            offset: None,
+                                            fetch: None,
+                                            locks: vec![],
+                                        }))),
+                                        partitioned: None,
+                                        after_columns: vec![],
+                                        table: false,
+                                        on: if is_potential_upsert {
+                                            Some(OnInsert::OnConflict(OnConflict {
+                                                conflict_target: Some(ConflictTarget::Columns(
+                                                    vec![Ident {
+                                                        value: "id".to_owned(),
+                                                        quote_style: Some(QUOTE_CHAR),
+                                                    }],
+                                                )),
+                                                action: OnConflictAction::DoUpdate(DoUpdate {
+                                                    assignments: columns
+                                                        .iter()
+                                                        .filter_map(|c| {
+                                                            // with only the id, a no-op update still
+                                                            // returns the existing row
+                                                            if c.value == "id" && columns.len() > 1
+                                                            {
+                                                                return None;
+                                                            }
+                                                            Some(Assignment {
+                                                                id: vec![c.clone()],
+                                                                value: Expr::CompoundIdentifier(
+                                                                    vec![
+                                                                        Ident::new("EXCLUDED"),
+                                                                        c.clone(),
+                                                                    ],
+                                                                ),
+                                                            })
+                                                        })
+                                                        .collect(),
+                                                    selection: None,
+                                                }),
+                                            }))
+                                        } else {
+                                            None
+                                        },
+                                        returning: Some(vec![
+                                            SelectItem::ExprWithAlias {
+                                                alias: Ident {
+                                                    value: TYPENAME.to_string(),
+                                                    quote_style: Some(QUOTE_CHAR),
+                                                },
+                                                expr: Expr::Value(Value::SingleQuotedString(
+                                                    name.to_owned(),
+                                                )),
+                                            },
+                                            SelectItem::Wildcard(
+                                                WildcardAdditionalOptions::default(),
+                                            ),
+                                        ]),
+                                    }),
+                                    is_single,
+                                    affected_rows,
+                                ),
+                                params,
+                                None,
+                                true,
+                            ));
+                        } else if is_update {
+                            let has_updated_at_directive = field
+                                .directives
+                                .iter()
+                                .any(|d| d.node.name.node == "updatedAt");
+                            let items = &field.selection_set.node.items;
+                            let claim_limit = get_claim_limit(&field.directives)?;
+                            // a claim takes the order of the rows it picks
+                            let (order, arguments): (Vec<_>, Vec<_>) =
+                                field.arguments.iter().cloned().partition(|(name, _)| {
+                                    claim_limit.is_some() && name.node.as_str() == "order"
+                                });
+                            let (arguments, writes) =
+                                split_nested_writes(&arguments, &variables, |column| {
+                                    find_relation(name, column, items, &options.relations).is_some()
+                                });
+                            let (mut selection, mut assignments) = get_mutation_assignments(
+                                &arguments,
+                                &variables,
+                                &mut sql_vars,
+                                &mut final_vars,
+                                has_updated_at_directive,
+                                &key_columns,
+                                options.null_policy,
+                            )?;
+                            let mut nested_ctes = vec![];
+                            for (column, write) in &writes {
+                                let directives =
+                                    find_relation(name, column, items, &options.relations)
+                                        .unwrap_or_default();
+                                let reference = match write {
+                                    NestedWrite::Upsert(data) => {
+                                        let (cte, references) = get_nested_upsert(
+                                            column,
+                                            data,
+                                            &directives,
+                                            &mut sql_vars,
+                                            &mut final_vars,
+                                        )?;
+                                        nested_ctes.push(cte);
+                                        references[0].clone()
+                                    }
+                                    NestedWrite::Links(links) => {
+                                        nested_ctes.extend(get_nested_links(
+                                            name,
+                                            column,
+                                            links,
+                                            &directives,
+                                            &mut sql_vars,
+                                            &mut final_vars,
+                                        )?);
+                                        ID.to_string()
+                                    }
+                                };
+                                // only writing relations still needs the parent rows returned
+                                if assignments.is_empty() {
+                                    let reference = Ident::with_quote(QUOTE_CHAR, reference);
+                                    assignments.push(Assignment {
+                                        id: vec![reference.clone()],
+                                        value: Expr::Identifier(reference),
+                                    });
+                                }
+                            }
+                            if assignments.is_empty() {
+                                return Err(anyhow!("Update must set at least one column"));
+                            }
+                            let selection = match claim_limit {
+                                Some(limit) => {
+                                    let mut order_by = vec![];
+                                    for (_, value) in &order {
+                                        let value = match &value.node {
+                                            GqlValue::Variable(v) => {
+                                                variables.get(v).unwrap_or(&GqlValue::Null)
+                                            }
+                                            value => value,
+                                        };
+                                        order_by.append(&mut match value {
+                                            GqlValue::Object(order) => get_order(
+                                                order,
+                                                &variables,
+                                                &mut sql_vars,
+                                                &mut final_vars,
+                                            )?,
+                                            GqlValue::List(list) => get_order_list(
+                                                list,
+                                                &variables,
+                                                &mut sql_vars,
+                                                &mut final_vars,
+                                            )?,
+                                            GqlValue::Null => vec![],
+                                            _ => {
+                                                return Err(anyhow!(
+                                                    "Invalid value for claim order"
+                                                ))
+                                            }
+                                        });
+                                    }
+                                    // inside the claim, so the rows it picks are ones that pass
+                                    options.directives.filter(
+                                        &field.name.node,
+                                        name,
+                                        &field.directives,
+                                        &sql_vars,
+                                        &mut selection,
+                                    )?;
+                                    // the keys that identify a row, rather than every
+                                    // column usable as a key argument
+                                    let keys = get_table_key_columns(
+                                        &field.directives,
+                                        name,
+                                        &mut sql_vars,
+                                        options,
+                                    )?
+                                    .or_else(|| options.key_columns.clone())
+                                    .unwrap_or_else(|| vec![ID.to_string()]);
+                                    Some(get_claim_selection(
+                                        table_name.clone(),
+                                        &keys,
+                                        selection,
+                                        order_by,
+                                        limit,
+                                    )?)
+                                }
+                                None => {
+                                    let mut selection = or_match_nothing(selection);
+                                    options.directives.filter(
+                                        &field.name.node,
+                                        name,
+                                        &field.directives,
+                                        &sql_vars,
+                                        &mut selection,
+                                    )?;
+                                    selection
+                                }
+                            };
+                            let params = get_params(final_vars, &mut sql_vars);
+                            let mut statement = wrap_mutation_with(
+                                key,
+                                Statement::Update {
+                                    table: TableWithJoins {
+                                        relation: TableFactor::Table {
+                                            partitions: vec![],
+                                            version: None,
+                                            name: table_name,
+                                            alias: None,
+                                            args: None,
+                                            with_hints: vec![],
+                                        },
+                                        joins: vec![],
+                                    },
+                                    assignments,
+                                    from: None,
+                                    selection,
+                                    returning: Some(vec![
+                                        SelectItem::ExprWithAlias {
+                                            alias: Ident {
+                                                value: TYPENAME.to_string(),
+                                                quote_style: Some(QUOTE_CHAR),
+                                            },
+                                            expr: Expr::Value(Value::SingleQuotedString(
+                                                name.to_owned(),
+                                            )),
+                                        },
+                                        SelectItem::Wildcard(WildcardAdditionalOptions::default()),
+                                    ]),
+                                },
+                                is_single,
+                                affected_rows,
+                            );
+                            if let Statement::Query(query) = &mut statement {
+                                if let Some(with) = query.with.as_mut() {
+                                    with.cte_tables.extend(nested_ctes);
+                                }
+                            }
+                            return Ok((statement, params, None, true));
+                        } else if is_delete {
+                            let allow_all = get_allow_all(&field.arguments, &sql_vars)?;
+                            let arguments = field
+                                .arguments
+                                .iter()
+                                .filter(|(name, _)| name.node.as_str() != ALLOW_ALL)
+                                .cloned()
+                                .collect();
+                            let (mut selection, _) = get_mutation_assignments(
+                                &arguments,
+                                &variables,
+                                &mut sql_vars,
+                                &mut final_vars,
+                                false,
+                                &key_columns,
+                                options.null_policy,
+                            )?;
+                            if selection.is_none() && !allow_all {
+                                return Err(UnfilteredDelete {
+                                    table: name.to_string(),
+                                }
+                                .into());
+                            }
+                            options.directives.filter(
+                                &field.name.node,
+                                name,
+                                &field.directives,
+                                &sql_vars,
+                                &mut selection,
+                            )?;
+                            let params = get_params(final_vars, &mut sql_vars);
+                            return Ok((
+                                wrap_mutation_with(
+                                    key,
+                                    Statement::Delete(Delete {
+                                        limit: None,
+                                        order_by: vec![],
+                                        tables: vec![],
+                                        from: FromTable::WithFromKeyword(vec![TableWithJoins {
+                                            relation: TableFactor::Table {
+                                                partitions: vec![],
+                                                version: None,
+                                                name: table_name,
+                                                alias: None,
+                                                args: None,
+                                                with_hints: vec![],
+                                            },
+                                            joins: vec![],
+                                        }]),
+                                        using: None,
+                                        selection,
+                                        returning: Some(vec![
+                                            SelectItem::ExprWithAlias {
+                                                alias: Ident {
+                                                    value: TYPENAME.to_string(),
+                                                    quote_style: Some(QUOTE_CHAR),
+                                                },
+                                                expr: Expr::Value(Value::SingleQuotedString(
+                                                    name.to_owned(),
+                                                )),
+                                            },
+                                            SelectItem::Wildcard(
+                                                WildcardAdditionalOptions::default(),
+                                            ),
+                                        ]),
+                                    }),
+                                    is_single,
+                                    affected_rows,
+                                ),
+                                params,
+                                None,
+                                true,
+                            ));
+                        }
+                    }
+                    Selection::FragmentSpread(_) | Selection::InlineFragment(_) => {
+                        return Err(anyhow::anyhow!("Fragment not supported"))
+                    }
+                }
+            }
+        }
+        OperationType::Subscription => return Err(anyhow::anyhow!("Subscription not supported")),
+    }
+    Err(anyhow!("No operation found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_graphql_parser::parse_query;
+
+    use insta::assert_snapshot;
+    use serde_json::json;
+    use sqlparser::ast::visit_relations;
+
+    #[test]
+    fn simple() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query App {
+                app(filter: { field: "id", operator: "eq", value: "345810043118026832" }, order: { name: ASC }) @meta(table: "App") {
+                    id
+                    components @relation(table: "Component", field: ["appId"], references: ["id"]) {
+                        id
+                        pageMeta @relation(table: "PageMeta", field: ["componentId"], references: ["id"], single: true) {
+                          id
+                          path
+                        }
+                        elements(order: { order: ASC }) @relation(table: "Element", field: ["componentParentId"], references: ["id"]) {
+                            id
+                            name
+                        }
+                    }
+                }
+                Component_aggregate(filter: { field: "appId", operator: "eq", value: "345810043118026832" }) {
+                  count
+                  min {
+                    createdAt
+                  }
+                }
+            }
+            query Another {
+                Component_aggregate(filter: { field: "appId", operator: "eq", value: "345810043118026832" }) {
+                  count
+                  min {
+                    createdAt
+                  }
+                }
+            }
+        "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql(gqlast, &None, Some("App".to_owned()))?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn id_ignore() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query App($id: String) {
+                app(id: $id) @meta(table: "App") {
+                    id
+                }
+            }
+        "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "id": null
+            })),
+            Some("App".to_owned()),
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn simple_ignore() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query App($filter: Filter) {
+                app(filter: $filter, order: { name: ASC }) @meta(table: "App") {
+                    id
+                }
+            }
+        "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "filter": {
+                    "field": "id",
+                    "operator": "eq",
+                    "value": null,
+                    "ignoreEmpty": true,
+                    "children": [{
+                        "field": "other",
+                        "operator": "gte",
+                        "value": null,
+                        "ignoreEmpty": true,
+                    }]
+                }
+            })),
+            Some("App".to_owned()),
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_insert() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"mutation insertVillains($data: [Villain_insert_input!]!) {
+                insert(data: $data) @meta(table: "Villain", insert: true, schema: "auth") { id name }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "data": [
+                    { "name": "Ronan the Accuser", "id": "1" },
+                    { "name": "Red Skull", "id": "2" },
+                    { "name": "The Vulture", "id": "3" }
+                ]
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_empty_insert() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"mutation insertVillains($data: [Villain_insert_input!]!) {
+                insert(data: $data) @meta(table: "Villain", insert: true, schema: "auth") { id name }
+            }"#,
+        )?;
+        let (statement, params, _tags, is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "data": [
+                ]
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_eq!(params, None);
+        assert!(is_mutation);
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            parse_query(
+                r#"mutation insertVillain($data: Villain_insert_input!) {
+                    insert(data: $data) @meta(table: "Villain", insert: true, single: true) { id }
+                }"#,
+            )?,
+            &Some(json!({ "data": [] })),
+            None,
+        )?;
+        assert!(statement
+            .to_string()
+            .contains(r#"SELECT coalesce(jsonb_agg("result"), '[]') -> 0 FROM "result""#));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_empty_update_delete() -> Result<(), anyhow::Error> {
+        let error = gql2sql(
+            parse_query(
+                r#"mutation updateHero($set: Hero_set_input!) {
+                    update_Hero(id: "h1", set: $set) { id }
+                }"#,
+            )?,
+            &Some(json!({ "set": {} })),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Update must set at least one column");
+        let (statement, params, _tags, is_mutation) = gql2sql(
+            parse_query(
+                r#"mutation deleteHeroes($ids: [String!]!) {
+                    delete_Hero(filter: { field: "id", operator: "in", value: $ids }) {
+                        affected_rows
+                    }
+                }"#,
+            )?,
+            &Some(json!({ "ids": [] })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert!(is_mutation);
+        assert_eq!(params, None);
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_update() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"mutation updateHero {
+                update(
+                    filter: { field: "secret_identity", operator: "eq", value: "Sam Wilson" },
+                    set: {
+                        name: "Captain America",
+                    }
+                    increment: {
+                        number_of_movies: 1
+                    }
+                ) @meta(table: "Hero", update: true, schema: "auth") @updatedAt {
+                    id
+                    name
+                    secret_identity
+                    number_of_movies
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_mega() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetApp($orgId: String!, $appId: String!, $branch: String!) {
+      app: App_one(
+        filter: {
+          field: "orgId",
+          operator: "eq",
+          value: $orgId,
+          logicalOperator: "AND",
+          children: [
+            { field: "id", operator: "eq", value: $appId },
+            { field: "branch", operator: "eq", value: $branch }
+          ]
+        }
+      ) {
+        orgId
+        id
+        branch
+        name
+        description
+        theme
+        favicon
+        customCSS
+        analytics
+        customDomain
+        components
+          @relation(
+            table: "Component"
+            field: ["appId", "branch"]
+            references: ["id", "branch"]
+          ) {
+          id
+          branch
+          ... on PageMeta
+            @relation(
+              table: "PageMeta"
+              field: ["componentId", "branch"]
+              references: ["id", "branch"]
+              single: true
+            ) {
+            title
+            description
+            path
+            socialImage
+            urlParams
+            loader
+            protection
+            maxAge
+            sMaxAge
+            staleWhileRevalidate
+          }
+          ... on ComponentMeta
+            @relation(
+              table: "ComponentMeta"
+              field: ["componentId", "branch"]
+              references: ["id", "branch"]
+              single: true
+            ) {
+            title
+            sources
+              @relation(
+                table: "Source"
+                field: ["componentId", "branch"]
+                references: ["id", "branch"]
+              ) {
+              id
+              branch
+              name
+              provider
+              description
+              template
+              instanceTemplate
+              outputType
+              source
+              sourceProp
+              componentId
+              utilityId
+              component(order: { order: ASC })
+                @relation(
+                  table: "Element"
+                  field: ["id", "branch"]
+                  references: ["componentId", "branch"]
+                  single: true
+                ) {
+                id
+                branch
+                name
+                kind
+                source
+                styles
+                props
+                order
+                conditions
+              }
+              utility
+                @relation(
+                  table: "Utility"
+                  field: ["id", "branch"]
+                  references: ["componentId", "branch"]
+                  single: true
+                ) {
+                id
+                branch
+                name
+                kind
+                kindId
+                data
+              }
+            }
+            events @relation(table: "Event", field: ["componentMetaId", "branch"], references: ["id", "branch"]) {
+                id
+                branch
+                name
+                label
+                help
+                type
+            }
+          }
+        }
+        connections @relation(table: "Connection", field: ["appId", "branch"], references: ["id", "branch"]) {
+          id
+          branch
+          name
+          kind
+          prodUrl
+          mutationSchema @relation(table: "Schema", field: ["mutationConnectionId", "branch"], references: ["id", "branch"], single: true) {
+            id
+            branch
+            schema
+          }
+          endpoints @relation(table: "Endpoint", field: ["connectionId", "branch"], references: ["id", "branch"]) {
+            id
+            branch
+            name
+            method
+            path
+            responseSchemaId
+            headers @relation(table: "Header", field: ["parentEndpointId", "branch"], references: ["id", "branch"]) {
+              id
+              branch
+              key
+              value
+              dynamic
+            }
+            search @relation(table: "Search", field: ["endpointId", "branch"], references: ["id", "branch"]) {
+              id
+              branch
+              key
+              value
+              dynamic
+            }
+          }
+          headers @relation(table: "Header", field: ["parentConnectionId", "branch"], references: ["id", "branch"]) {
+            id
+            branch
+            key
+            value
+            dynamic
+          }
+        }
+        layouts @relation(table: "Layout", field: ["appId", "branch"], references: ["id", "branch"]) {
+          id
+          branch
+          name
+          source
+          kind
+          styles
+          props
+        }
+        plugins @relation(table: "Plugin", field: ["appId", "branch"], references: ["id", "branch"]) {
+          instanceId
+          kind
+        }
+        schemas @relation(table: "Schema", field: ["appId", "branch"], references: ["id", "branch"]) {
+          id
+          branch
+          schema
+        }
+        styles @relation(table: "Style", field: ["appId", "branch"], references: ["id", "branch"]) {
+          id
+          branch
+          name
+          kind
+          styles
+          isDefault
+        }
+        workflows @relation(table: "Workflow", field: ["appId", "branch"], references: ["id", "branch"]) {
+          id
+          branch
+          name
+          args
+          steps(order: { order: ASC }) @relation(table: "Step", field: ["workflowId", "branch"], references: ["id", "branch"]) {
+            id
+            branch
+            parentId
+            kind
+            kindId
+            data
+            order
+          }
+        }
+      }
+    }
+"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "orgId": "org",
+                "appId": "app",
+                "branch": "branch"
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_frag() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetApp($componentId: String!, $branch: String!) {
+                component: Component_one(filter: { field: "id", operator: "eq", value: $componentId }) {
+                   id
+                   branch
+                   ... on ComponentMeta @relation(
+                        table: "ComponentMeta"
+                        field: ["componentId"]
+                        references: ["id"]
+                        single: true
+                    ) @args(
+                        filter: {
+                          field: "branch"
+                          operator: "eq",
+                          value: $branch,
+                          logicalOperator: "OR",
+                          children: [
+                            { field: "branch", operator: "eq", value: "main" }
+                          ]
+                        }
+                    ) {
+                     title
+                   }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "componentId": "comp",
+                "branch": "branch"
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_static() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetApp($componentId: String!) {
+                component: Component_one(filter: { field: "id", operator: "eq", value: $componentId }) {
+                   id
+                   branch
+                   kind @static(value: "page")
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "componentId": "fake"
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_distinct() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetApp($componentId: String!, $branch: String!) {
+                component: Component_one(
+                    filter: {
+                        field: "id",
+                        operator: "eq",
+                        value: $componentId
+                        logicalOperator: "AND",
+                        children: [
+                            { field: "branch", operator: "eq", value: $branch, logicalOperator: "OR", children: [
+                                { field: "branch", operator: "eq", value: "main" }
+                            ]}
+                        ]
+                    },
+                    order: [
+                        { orderKey: ASC }
+                    ],
+                    distinct: { on: ["id"], order: [{ expr: { field: "branch", operator: "eq", value: $branch }, dir: DESC }] }
+                ) {
+                   id
+                   branch
+                   kind @static(value: "page")
+                   stuff(filter: { field: "componentId", operator: "eq", value: { _parentRef: "id" } }) @relation(table: "Stuff") {
+                     id
+                   }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "componentId": "fake",
+                "branch": "branch",
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_sub_agg() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetData {
+                testing @meta(table: "UcwtYEtmmpXagcpcRiYKC") {
+                    id
+                    created_at
+                    updated_at
+                    anothers @relation(table: "N8Ag4Vgad4rYwcRmMJhGR", fields: ["id"], reference:["xb8nemrkchVQgxkXkCPhE"], aggregate: true) {
+                        __typename
+                        count
+                        avg {
+                          __typename
+                          value
+                        }
+                    }
+                    stuff @relation(table: "iYrk3kyTqaDQrLgjDaE9n", fields: ["eT86hgrpFB49r7N6AXz63"], references: ["id"], single: true) {
+                        id
+                    }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_schema_arg() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+              query GetSession($sessionToken: String!) {
+    session(
+        filter: {
+            field: "sessionToken"
+            operator: "eq"
+            value: $sessionToken
+        }
+    ) @meta(table: "sessions", single: true, schema: "auth") {
+        sessionToken
+        userId
+        expires
+        user2: user
+            @relation(
+                table: "users"
+                field: ["id"]
+                references: ["userId"]
+                single: true
+                schema: "auth"
+            ) {
+            id
+            name
+            email
+            emailVerified
+            image
+        }
+    }
+}
+            "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+              "sessionToken": "fake"
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_wrap_arg() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+                mutation CreateVerificationToken($data: [VerificationToken!]!) {
+                    insert(data: $data)
+                        @meta(table: "verification_tokens", insert: true, schema: "auth", single: true) {
+                        identifier
+                        token
+                        expires
+                    }
+                }
+            "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+            "data": [{
+                "identifier": "nick@brevity.io",
+                "token": "da978cc2c1e0e7b61e1be31b2e3979af576e494d68bd6f5dc156084d9924ee12",
+                "expires": "2023-04-26T21:38:26"
+                }]
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_json_arg() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+                query BrevityQuery($order_getTodoList: tXY7bJTNXP7RAhLFGybN4d_Order, $filter: tXY7bJTNXP7RAhLFGybN4d_Filter) {
+                getTodoList(order: $order_getTodoList, filter: $filter) @meta(table: "tXY7bJTNXP7RAhLFGybN4d") {
+                    id
+                    cJ9jmpnjfYhRbCQBpWAzB8
+                    cPQdcYiWcPWWVeKVniUMjy
+                }
+                }
+            "#,
+        )?;
+        // let sql = r#""#;
+        let (_statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "order_getTodoList": {
+                    "cPQdcYiWcPWWVeKVniUMjy": "ASC"
+                },
+                "filter": null
+            })),
+            None,
+        )?;
+        // assert_eq!(statement.to_string(), sql);
+        Ok(())
+    }
+
+    #[test]
+    fn query_simple_filter() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+                query Test($id: String!) {
+                    record(id: $id) @meta(table: "Record") {
+                        id
+                        name
+                        age
+                    }
+                }
+            "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "id": "fake"
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_many_to_many() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+                query ManyToMany($id: String!) {
+                    currentUser(id: $id) @meta(table: "User") {
+                        id
+                        lists @relation(table: "wrHJEgwMUmdJ3eWtPLPk8", many: true) {
+                            id
+                        }
+                    }
+                }
+            "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "id": "fake"
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_andre() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+            query BrevityQuery($id_getH33iDwNVqqMxAnVEgPaThById: ID) {
+            getH33iDwNVqqMxAnVEgPaThById(id: $id_getH33iDwNVqqMxAnVEgPaThById)
+                @meta(table: "H33iDwNVqqMxAnVEgPaTh", single: true) {
+                d8GJJg9DjNehPAeJcpTjM
+                Fjjm3XAhyDmbhzymrrkRT_Aggregate
+                @relation(
+                    table: "Fjjm3XAhyDmbhzymrrkRT"
+                    fields: ["id"]
+                    aggregate: true
+                    references: ["TbFeY8XVMaYnkQjDPWMkb_id"]
+                ) {
+                avg {
+                    XF4f6Qrhk86AX6dFWjYDt
+                }
+                }
+                q6pJYTjmbprTNRdqG9Jrw
+                egeyQ33H3z4EqzcRVFchV
+                HYWfawTyxPNUf9a4DAH79
+                H33iDwNVqqMxAnVEgPaTh_by_MdYg7jdht8ByhnKdfXBAb
+                @relation(
+                    table: "MdYg7jdht8ByhnKdfXBAb"
+                    fields: ["id"]
+                    single: true
+                    references: ["MiyNcUJzKGJgQ9BERD8fr_id"]
+                ) {
+                H6hp6JGhzgPTYmLYwLk8P
+                id
+                }
+                zFjEBPkLYmEAxLHrt3N4B
+                LJDX6neXAYeXt9aVWxTRk
+                FwpKpCegQH4EkzbjbNqVn
+                ayipLT8iKHNTdhmiVqmxq
+                Mr3R877DKbWTNWRzmEjxE_Aggregate
+                @relation(many: true, table: "Mr3R877DKbWTNWRzmEjxE", aggregate: true) {
+                count
+                }
+                r7xwAFrckDaVLwPzUAADB
+                H33iDwNVqqMxAnVEgPaTh_by_User
+                @relation(
+                    table: "User"
+                    fields: ["id"]
+                    single: true
+                    references: ["Gb8jAGqGDbYqfeqDDxKUF_id"]
+                ) {
+                gnHezR9MdBFH9kCthN3aB
+                created_at
+                id
+                }
+                id
+            }
+            }
+            "#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+              "id_getH33iDwNVqqMxAnVEgPaThById": "HAzqFfhQGbaB6WKBr6LA7"
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_delete() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+            mutation DeleteVerificationToken(
+                $identifier: String!
+                $token: String!
+                ) {
+                delete(
+                    filter: {
+                    field: "identifier"
+                    operator: "eq"
+                    value: $identifier
+                    logicalOperator: "AND"
+                    children: [{ field: "token", operator: "eq", value: $token }]
+                    }
+                ) @meta(table: "verification_tokens", delete: true, schema: "auth") {
+                    identifier
+                    token
+                    expires
+                }
+            }
+            "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({ "token": "12345", "identifier": "fake@email.com" })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_image() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+            mutation Update($id: String!, $set: dogUpdateInput!) {
+                update(
+                  filter: {
+                    field: "id"
+                    operator: "eq"
+                    value: $id
+                  }
+                  set: $set
+                ) @meta(table: "WFqGH6dk8MpxfpHXh7awi", update: true) {
+                  id
+                }
+              }
+            "#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(
+                json!({"id":"ffj9ACLQqpzjyh8yNFeQ6","set":{"updated_at":"2023-06-06T19:41:47+00:00","ynWfqMzGjjVQYzbKx4rMX":"DOGGY","QYtpTcmJCe6zfCHWwpNjR":"MYDOG","a8heQgUMyFync44JACwKA":{"src":"https://assets.brevity.io/uploads/jwy1g8rs7bxr9ptkaf6sy/lp_image-1685987665741.png","width":588,"height":1280}}}),
+            ),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
+    #[test]
+    fn nested_query() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+                query BrevityQuery($id_getU7BBKiUwTgwiWMcgUYA4CById: ID) {
+                getU7BBKiUwTgwiWMcgUYA4CById(id: $id_getU7BBKiUwTgwiWMcgUYA4CById) @meta(table: "U7BBKiUwTgwiWMcgUYA4C", single: true) {
+                    BtaHL8fRtKFw8gDJULFYp
+                    WFqGH6dk8MpxfpHXh7awi_by_U7BBKiUwTgwiWMcgUYA4C @relation(table: "WFqGH6dk8MpxfpHXh7awi", fields: ["MHPB9NP84gr3eXBmBfbxh_id"], references: ["id"]) {
+                    ynWfqMzGjjVQYzbKx4rMX
+                    QYtpTcmJCe6zfCHWwpNjR
+                    MHPB9NP84gr3eXBmBfbxh_id @relation(table: "U7BBKiUwTgwiWMcgUYA4C", fields: ["id"], single: true, references: ["MHPB9NP84gr3eXBmBfbxh_id"]) {
+                        id
+                        __typename
+                    }
+                    id
+                    }
+                    id
+                }
+                }
+            "#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({ "id_getU7BBKiUwTgwiWMcgUYA4CById": "piWkMrFFXgdQBBkzf84MD" })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
+    #[test]
+    fn group_by_query() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+                query BrevityQuery($groupBy: [String]) {
+                    Event(filter: { field: "xVAFwi3LkLnRYqtkV3e9A_id", operator: "eq", value: "ge3xraXEcwPTF6hJxLXC7" }, groupBy: $groupBy) @meta(table: "LC4PdkWrXEq6PnJNF98RE", aggregate: true) {
+                        value {
+                          W3htYNGnCaJp4MAp6p6c9_id @relation(table: "AQfNfkgxq4iLcAhkdNAWf", fields: ["id"], references: ["W3htYNGnCaJp4MAp6p6c9_id"], single: true) {
+                            id
+                            name: QJ3MwMUiXqrkPwb88eW8g
+                          }
+                          t473xCb8nhWCxX7Ag7k6q_id @relation(table: "fTgjFRxYgaj3qHriEdQi3", fields: ["id"], references: ["t473xCb8nhWCxX7Ag7k6q_id"], single: true) {
+                            id
+                            title: tcGyWe4CLwhpTJp4krApd
+                          }
                         }
-                        if let Some(keys) = keys {
-                            tags.insert(name.to_string(), keys.into_iter().collect());
-                        } else {
-                            tags.insert(name.to_string(), IndexSet::new());
-                        };
-                        let table_name = schema_name.map_or_else(
-                            || {
-                                ObjectName(vec![Ident {
-                                    value: name.to_string(),
-                                    quote_style: Some(QUOTE_CHAR),
-                                }])
-                            },
-                            |schema_name| {
-                                ObjectName(vec![
-                                    Ident {
-                                        value: schema_name.to_string(),
-                                        quote_style: Some(QUOTE_CHAR),
-                                    },
-                                    Ident {
-                                        value: name.to_string(),
-                                        quote_style: Some(QUOTE_CHAR),
-                                    },
-                                ])
-                            },
-                        );
-                        let base_query = get_filter_query(
-                            selection,
-                            order_by,
-                            first,
-                            after,
-                            vec![table_name],
-                            distinct,
-                            distinct_order,
-                        );
-                        if is_aggregate {
-                            let aggs = get_aggregate_projection(
-                                &field.selection_set.node.items,
-                                name,
-                                group_by.clone(),
-                                &variables,
-                                &mut sql_vars,
-                                &mut final_vars,
-                                &mut tags,
-                            )?;
-                            let subquery = Query {
-                                for_clause: None,
-                                limit_by: vec![],
-                                with: None,
-                                body: Box::new(get_agg_query(
-                                    aggs,
-                                    vec![TableWithJoins {
-                                        relation: TableFactor::Derived {
-                                            lateral: false,
-                                            subquery: Box::new(base_query),
-                                            alias: Some(TableAlias {
-                                                name: Ident {
-                                                    value: BASE.to_string(),
-                                                    quote_style: Some(QUOTE_CHAR),
-                                                },
-                                                columns: vec![],
-                                            }),
-                                        },
-                                        joins: vec![],
-                                    }],
-                                    None,
-                                    ROOT_LABEL,
-                                    group_by.clone(),
-                                )),
-                                order_by: vec![],
-                                limit: None,
-                                offset: None,
-                                fetch: None,
-                                locks: vec![],
-                            };
-                            // TODO: Do I need to be deleted?
-                            if group_by.is_some() {
-                                // find-me
-                                statements.push((
-                                    key,
-                                    Expr::Subquery(Box::new(Query {
-                                        with: None,
-                                        body: Box::new(SetExpr::Select(Box::new(Select {
-                                            window_before_qualify: false,
-                                            connect_by: None,
-                                            distinct: None,
-                                            top: None,
-                                            projection: vec![SelectItem::UnnamedExpr(
-                                                Expr::Function(Function {
-                                                    within_group: vec![],
-                                                    name: ObjectName(vec![Ident {
-                                                        value: JSONB_AGG.to_owned(),
-                                                        quote_style: None,
-                                                    }]),
-                                                    args: FunctionArguments::List(
-                                                        FunctionArgumentList {
-                                                            duplicate_treatment: None,
-                                                            clauses: vec![],
-                                                            args: vec![FunctionArg::Unnamed(
-                                                                FunctionArgExpr::Expr(
-                                                                    Expr::CompoundIdentifier(vec![
-                                                                        Ident {
-                                                                            value: "T".to_owned(),
-                                                                            quote_style: Some(
-                                                                                QUOTE_CHAR,
-                                                                            ),
-                                                                        },
-                                                                        Ident {
-                                                                            value: ROOT_LABEL
-                                                                                .to_owned(),
-                                                                            quote_style: Some(
-                                                                                QUOTE_CHAR,
-                                                                            ),
-                                                                        },
-                                                                    ]),
-                                                                ),
-                                                            )],
-                                                        },
-                                                    ),
-                                                    filter: None,
-                                                    null_treatment: None,
-                                                    over: None,
-                                                }),
-                                            )],
-                                            into: None,
-                                            from: vec![TableWithJoins {
-                                                relation: TableFactor::Derived {
-                                                    lateral: false,
-                                                    subquery: Box::new(subquery),
-                                                    alias: Some(TableAlias {
-                                                        name: Ident {
-                                                            value: "T".to_owned(),
-                                                            quote_style: Some(QUOTE_CHAR),
-                                                        },
-                                                        columns: vec![],
-                                                    }),
-                                                },
-                                                joins: vec![],
-                                            }],
-                                            lateral_views: vec![],
-                                            selection: None,
-                                            group_by: GroupByExpr::Expressions(vec![]),
-                                            cluster_by: vec![],
-                                            distribute_by: vec![],
-                                            sort_by: vec![],
-                                            having: None,
-                                            named_window: vec![],
-                                            qualify: None,
-                                            value_table_mode: None,
-                                        }))),
-                                        order_by: vec![],
-                                        limit: None,
-                                        limit_by: vec![],
-                                        offset: None,
-                                        fetch: None,
-                                        locks: vec![],
-                                        for_clause: None,
-                                    })),
-                                ));
-                                // statements.push((
-                                //     key,
-                                //     Expr::Function(Function {
-                                //         order_by: vec![],
-                                //         name: ObjectName(vec![Ident {
-                                //             value: JSONB_AGG.to_string(),
-                                //             quote_style: None,
-                                //         }]),
-                                //         args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(
+                        count
+                    }
+                }
+            "#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({ "groupBy": ["W3htYNGnCaJp4MAp6p6c9_id", "t473xCb8nhWCxX7Ag7k6q_id"] })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
+    #[test]
+    fn nested_playground() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"
+            query BrevityDBQuery(
+  $playbook_id: String!
+  $template_BahPd_id_order: [boardcolumn_Order]
+  $playbook_LFc9r_id_order: [boardcolumn_Order]
+  $playbook_playbook_id_order: [playbookstandard_Order]
+  $boardrow_row_id_filter: boardcell_Filter
+  $boardrow_row_id_distinct: boardcell_Distinct
+  $workflows_Kdda9_id_order: [approvalworkflow_cqaw9_Order]
+) {
+  playbook: getplaybookById(id: $playbook_id)
+    @meta(table: "playbook", single: true) {
+    __typename
+    id
+    name
+    created_at
+    updated_at
+    folder_UEiw4_id {
+      id
+    }
+    workspace_VTGmA_id {
+      id
+    }
+    ownercolumn_3hJaT_id
+      @relation(
+        table: "boardcolumn"
+        fields: ["id"]
+        single: true
+        references: ["ownercolumn_3hJaT_id"]
+      ) {
+      __typename
+      id
+      created_at
+      name_CwYar
+      type_Hbagk
+      updated_at
+      order_WUkJE
+      width_xJ846
+      board_3jDDw_id {
+        id
+      }
+      required_4jaR4
+      isdefault_KCmRr
+      temporary_4NyhY
+      playbook_LFc9r_id {
+        id
+      }
+      template_BahPd_id {
+        id
+      }
+    }
+    playbook_template_id
+      @relation(
+        table: "template"
+        fields: ["id"]
+        single: true
+        references: ["playbook_template_id"]
+      ) {
+      __typename
+      id
+      title
+      created_at
+      updated_at
+      organization_organization_id {
+        id
+      }
+      defaultdescriptioncolumn_kMT8n_id {
+        id
+      }
+      template_BahPd_id(order: $template_BahPd_id_order)
+        @relation(
+          table: "boardcolumn"
+          fields: ["template_BahPd_id"]
+          references: ["id"]
+        ) {
+        __typename
+        id
+        created_at
+        name_CwYar
+        type_Hbagk
+        updated_at
+        order_WUkJE
+        width_xJ846
+        board_3jDDw_id {
+          id
+        }
+        required_4jaR4
+        isdefault_KCmRr
+        temporary_4NyhY
+        playbook_LFc9r_id {
+          id
+        }
+        template_BahPd_id {
+          id
+        }
+        column_Xdjyz_id
+          @relation(
+            table: "boardcolumnoptions_mrX6T"
+            fields: ["column_Xdjyz_id"]
+            references: ["id"]
+          ) {
+          __typename
+          id
+          created_at
+          name_bFeAf
+          updated_at
+          column_Xdjyz_id {
+            id
+          }
+        }
+      }
+    }
+    statuscolumn_fFigH_id
+      @relation(
+        table: "boardcolumn"
+        fields: ["id"]
+        single: true
+        references: ["statuscolumn_fFigH_id"]
+      ) {
+      __typename
+      id
+      created_at
+      name_CwYar
+      type_Hbagk
+      updated_at
+      order_WUkJE
+      width_xJ846
+      board_3jDDw_id {
+        id
+      }
+      required_4jaR4
+      isdefault_KCmRr
+      temporary_4NyhY
+      playbook_LFc9r_id {
+        id
+      }
+      template_BahPd_id {
+        id
+      }
+    }
+    duedatecolumn_Qajep_id
+      @relation(
+        table: "boardcolumn"
+        fields: ["id"]
+        single: true
+        references: ["duedatecolumn_Qajep_id"]
+      ) {
+      __typename
+      id
+      created_at
+      name_CwYar
+      type_Hbagk
+      updated_at
+      order_WUkJE
+      width_xJ846
+      board_3jDDw_id {
+        id
+      }
+      required_4jaR4
+      isdefault_KCmRr
+      temporary_4NyhY
+      playbook_LFc9r_id {
+        id
+      }
+      template_BahPd_id {
+        id
+      }
+    }
+    descriptioncolumn_nNkVP_id
+      @relation(
+        table: "boardcolumn"
+        fields: ["id"]
+        single: true
+        references: ["descriptioncolumn_nNkVP_id"]
+      ) {
+      __typename
+      id
+      created_at
+      name_CwYar
+      type_Hbagk
+      updated_at
+      order_WUkJE
+      width_xJ846
+      board_3jDDw_id {
+        id
+      }
+      required_4jaR4
+      isdefault_KCmRr
+      temporary_4NyhY
+      playbook_LFc9r_id {
+        id
+      }
+      template_BahPd_id
+        @relation(
+          table: "template"
+          fields: ["id"]
+          single: true
+          references: ["template_BahPd_id"]
+        ) {
+        __typename
+        id
+        title
+        created_at
+        updated_at
+        organization_organization_id {
+          id
+        }
+        defaultdescriptioncolumn_kMT8n_id {
+          id
+        }
+      }
+    }
+    playbook_id
+      @relation(
+        table: "folderitem"
+        fields: ["playbook_id"]
+        single: true
+        references: ["id"]
+      ) {
+      __typename
+      id
+      board_id {
+        id
+      }
+      folder_id {
+        id
+      }
+      created_at
+      updated_at
+      playbook_id {
+        id
+      }
+      okr_ByTYz_id {
+        id
+      }
+      dashboard_zzwnp_id {
+        id
+      }
+    }
+    playbook_LFc9r_id(order: $playbook_LFc9r_id_order)
+      @relation(
+        table: "boardcolumn"
+        fields: ["playbook_LFc9r_id"]
+        references: ["id"]
+      ) {
+      __typename
+      id
+      created_at
+      name_CwYar
+      type_Hbagk
+      updated_at
+      order_WUkJE
+      width_xJ846
+      board_3jDDw_id {
+        id
+      }
+      required_4jaR4
+      isdefault_KCmRr
+      temporary_4NyhY
+      playbook_LFc9r_id {
+        id
+      }
+      template_BahPd_id
+        @relation(
+          table: "template"
+          fields: ["id"]
+          single: true
+          references: ["template_BahPd_id"]
+        ) {
+        __typename
+        id
+        title
+        created_at
+        updated_at
+        organization_organization_id {
+          id
+        }
+        defaultdescriptioncolumn_kMT8n_id {
+          id
+        }
+      }
+      column_Xdjyz_id
+        @relation(
+          table: "boardcolumnoptions_mrX6T"
+          fields: ["column_Xdjyz_id"]
+          references: ["id"]
+        ) {
+        __typename
+        id
+        created_at
+        name_bFeAf
+        updated_at
+        column_Xdjyz_id {
+          id
+        }
+      }
+    }
+    playbook_playbook_id(order: $playbook_playbook_id_order)
+      @relation(
+        table: "playbookstandard"
+        fields: ["playbook_playbook_id"]
+        references: ["id"]
+      ) {
+      __typename
+      id
+      created_at
+      updated_at
+      row_DBUfb_id
+        @relation(
+          table: "boardrow"
+          fields: ["id"]
+          single: true
+          references: ["row_DBUfb_id"]
+        ) {
+        __typename
+        id
+        created_at
+        updated_at
+        board_mkGmp_id {
+          id
+        }
+        playbook_t7raV_id {
+          id
+        }
+        template_xdeiM_id {
+          id
+        }
+        defaultdescriptionvalue_tHbN3_id {
+          id
+        }
+        boardrow_row_id(
+          filter: $boardrow_row_id_filter
+          distinct: $boardrow_row_id_distinct
+        )
+          @relation(
+            table: "boardcell"
+            fields: ["boardrow_row_id"]
+            references: ["id"]
+          ) {
+          __typename
+          id
+          created_at
+          updated_at
+          boardrow_row_id {
+            id
+          }
+          datevalue_hd3CD
+          status_HVqrA_id {
+            id
+          }
+          textvalue_ahacc
+          numberfield_YfkE7
+          playbook_EqwVY_id {
+            id
+          }
+          selectvalue_q66xK
+          uservalue_faXth_id {
+            id
+          }
+          parentboard_BWMCH_id {
+            id
+          }
+          boardcolumn_column_id {
+            id
+          }
+          multiselectvalue_bHY6V
+        }
+      }
+      status_d9CPd_id {
+        id
+      }
+      duedate_cRCB8_id {
+        id
+      }
+      ownercell_TyVaR_id {
+        id
+      }
+      playbook_playbook_id {
+        id
+      }
+      playbookrow_VJ6Vw_id
+        @relation(
+          table: "approvalworkflow_cqaw9"
+          fields: ["id"]
+          single: true
+          references: ["playbookrow_VJ6Vw_id"]
+        ) {
+        __typename
+        id
+        created_at
+        name_HKxd6
+        updated_at
+        workflows_Kdda9_id {
+          id
+        }
+      }
+    }
+    workflows_Kdda9_id(order: $workflows_Kdda9_id_order)
+      @relation(
+        table: "approvalworkflow_cqaw9"
+        fields: ["workflows_Kdda9_id"]
+        references: ["id"]
+      ) {
+      __typename
+      id
+      created_at
+      name_HKxd6
+      updated_at
+      workflows_Kdda9_id {
+        id
+      }
+    }
+  }
+}
+            "#,
+        )?;
+        let (statement, params, tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!(
+            {
+              "playbook_id": "PMxiGmJ4eyndrdp3J3Li6",
+              "template_BahPd_id_order": [
+                {
+                  "id": "ASC",
+                  "field": "created_at",
+                  "direction": "ASC"
+                }
+              ],
+              "playbook_LFc9r_id_order": [
+                {
+                  "id": "ASC",
+                  "field": "created_at",
+                  "direction": "ASC"
+                }
+              ],
+              "playbook_playbook_id_order": [
+                {
+                  "id": "ASC",
+                  "field": "created_at",
+                  "direction": "ASC"
+                }
+              ],
+              "boardrow_row_id_filter": {
+                "id": "filter_YipDb8gGjkbHRpLfbGBNt",
+                "field": "playbook_EqwVY_id",
+                "value": null,
+                "children": [
+                  {
+                    "id": "filter_L6NRaeg8JXzdDFdtFePdc",
+                    "field": "playbook_EqwVY_id",
+                    "value": "PMxiGmJ4eyndrdp3J3Li6",
+                    "children": [],
+                    "operator": "eq",
+                    "logicalOperator": "AND"
+                  }
+                ],
+                "operator": "null",
+                "logicalOperator": "OR"
+              },
+              "boardrow_row_id_distinct": {
+                "on": [
+                  "boardrow_row_id",
+                  "boardcolumn_column_id"
+                ],
+                "order": [
+                  {
+                    "id": "ASC",
+                    "field": "created_at",
+                    "direction": "DESC"
+                  }
+                ]
+              },
+              "workflows_Kdda9_id_order": [
+                {
+                  "id": "ASC",
+                  "field": "created_at",
+                  "direction": "ASC"
+                }
+              ]
+            }
+                        )),
+            None,
+        )?;
 
-                                //             Expr::Function(Function {
-                                //                 name: ObjectName(vec![Ident {
-                                //                     value: TO_JSONB.to_string(),
-                                //                     quote_style: None,
-                                //                 }]),
-                                //                 args: vec![FunctionArg::Unnamed(
-                                //                     FunctionArgExpr::Expr(Expr::Subquery(
-                                //                         Box::new(Query {
-                                //                             body: Box::new(SetExpr::Select(
-                                //                                 Box::new(Select {
-                                //                                     distinct: None,
-                                //                                     top: None,
-                                //                                     projection: vec![SelectItem::UnnamedExpr(Expr::Identifier(Ident {
-                                //                                         value: ROOT_LABEL.to_string(),
-                                //                                         quote_style: Some(QUOTE_CHAR),
-                                //                                     }))],
-                                //                                     // find me
-                                //                                     into: None,
-                                //                                     from: vec![TableWithJoins {
-                                //                                         relation: TableFactor::Derived { lateral: false, subquery: Box::new(subquery) , alias: Some(TableAlias { name: Ident { value: ROOT_LABEL.to_string(), quote_style: Some(QUOTE_CHAR) }, columns: vec![] }) },
-                                //                                         joins: vec![],
-                                //                                     }],
-                                //                                     lateral_views: vec![],
-                                //                                     selection: None,
-                                //                                     group_by: GroupByExpr::Expressions(vec![]),
-                                //                                     cluster_by: vec![],
-                                //                                     distribute_by: vec![],
-                                //                                     sort_by: vec![],
-                                //                                     having: None,
-                                //                                     named_window: vec![],
-                                //                                     qualify: None,
-                                //                                     value_table_mode: None,
-                                //                                 }),
-                                //                             )),
-                                //                             for_clause: None,
-                                //                             limit_by: vec![],
-                                //                             with: None,
-                                //                             order_by: vec![],
-                                //                             limit: None,
-                                //                             offset: None,
-                                //                             fetch: None,
-                                //                             locks: vec![],
-                                //                         }),
-                                //                     )),
-                                //                 )],
-                                //                 filter: None,
-                                //                 null_treatment: None,
-                                //                 over: None,
-                                //                 distinct: false,
-                                //                 special: false,
-                                //                 order_by: vec![],
-                                //             }),
-                                //         ))],
-                                //         over: None,
-                                //         distinct: false,
-                                //         special: false,
-                                //         filter: None,
-                                //         null_treatment: None,
-                                //     }),
-                                // ));
-                            } else {
-                                statements.push((key, Expr::Subquery(Box::new(subquery))));
-                            }
-                        } else {
-                            let (projection, joins, merges) = get_projection(
-                                &field.selection_set.node.items,
-                                name,
-                                Some(BASE),
-                                &variables,
-                                &mut sql_vars,
-                                &mut final_vars,
-                                &mut tags,
-                            )?;
-                            let root_query = get_root_query(
-                                projection,
-                                vec![TableWithJoins {
-                                    relation: TableFactor::Derived {
-                                        lateral: false,
-                                        subquery: Box::new(base_query),
-                                        alias: Some(TableAlias {
-                                            name: Ident {
-                                                value: BASE.to_string(),
-                                                quote_style: Some(QUOTE_CHAR),
-                                            },
-                                            columns: vec![],
-                                        }),
-                                    },
-                                    joins,
-                                }],
-                                None,
-                                &merges,
-                                is_single,
-                                ROOT_LABEL,
-                            );
-                            statements.push((
-                                key,
-                                Expr::Subquery(Box::new(Query {
-                                    for_clause: None,
-                                    limit_by: vec![],
-                                    with: None,
-                                    body: Box::new(root_query),
-                                    order_by: vec![],
-                                    limit: None,
-                                    offset: None,
-                                    fetch: None,
-                                    locks: vec![],
-                                })),
-                            ));
-                        };
+        println!("query: {statement}");
+        println!("vars: {}", serde_json::to_string_pretty(&params)?);
+        println!("tags: {}", serde_json::to_string_pretty(&tags)?);
+        // assert_snapshot!(pretty_sql(&statement.to_string()));
+        // assert_snapshot!();
+        Ok(())
+    }
+
+    #[test]
+    fn query_default() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetApp($componentId: String!) {
+                component: Component_one(filter: { field: "id", operator: "eq", value: $componentId }) {
+                   id
+                   title @default(value: "")
+                   label: name @default(value: "Untitled")
+                   tags @default(value: [])
+                   meta @relation(table: "ComponentMeta", field: ["componentId"], references: ["id"], single: true) @default(value: {}) {
+                     id
+                   }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "componentId": "fake"
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_reserved_column_arg() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetSteps($order: Int!) {
+                steps(col_order: $order, col_filter: "active", order: { order: ASC }) @meta(table: "Step") {
+                    id
+                    order
+                    filter
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "order": 3
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_prefixed_key_column() -> Result<(), anyhow::Error> {
+        let options = Options {
+            key_columns: Some(vec!["id".to_string(), "col_status".to_string()]),
+            ..Options::default()
+        };
+        let gqlast = parse_query(
+            r#"query GetSteps {
+                steps(col_status: "active", col_order: 3) @meta(table: "Step") {
+                    id
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(gqlast, &None, None, &options)?;
+        let sql = statement.to_string();
+        assert!(sql.contains(r#""col_status" = 'active'"#), "{sql}");
+        assert!(sql.contains(r#""order" = 3"#), "{sql}");
+        let gqlast = parse_query(
+            r#"query GetSteps {
+                steps(col_parentId: "1") @meta(table: "Step") {
+                    id
+                }
+            }"#,
+        )?;
+        let error = gql2sql_with_options(gqlast, &None, None, &options).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid argument for: col_parentId");
+        Ok(())
+    }
+
+    #[test]
+    fn relation_missing_table() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetApp {
+                app @meta(table: "App") {
+                    id
+                    components @relation(field: ["appId"], references: ["id"]) {
+                        id
                     }
-                    Selection::FragmentSpread(_) | Selection::InlineFragment(_) => {
-                        return Err(anyhow::anyhow!("Fragment not supported"))
+                }
+            }"#,
+        )?;
+        let err = gql2sql(gqlast, &None, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "@relation is missing required argument \"table\" at 4:32"
+        );
+        let gqlast = parse_query(
+            r#"query GetApp {
+                app @meta(table: "App") {
+                    id
+                    components @relation(table: "Component", field: ["appId", "branch"], references: ["id"]) {
+                        id
                     }
                 }
-            }
-            let statement = Statement::Query(Box::new(Query {
-                for_clause: None,
-                limit_by: vec![],
-                with: None,
-                body: Box::new(SetExpr::Select(Box::new(Select {
-                    window_before_qualify: false,
-                    connect_by: None,
-                    value_table_mode: None,
-                    distinct: None,
-                    named_window: vec![],
-                    top: None,
-                    into: None,
-                    projection: vec![SelectItem::ExprWithAlias {
-                        alias: Ident {
-                            value: DATA_LABEL.into(),
-                            quote_style: Some(QUOTE_CHAR),
-                        },
-                        expr: Expr::Function(Function {
-                            within_group: vec![],
-                            name: ObjectName(vec![Ident {
-                                value: JSONB_BUILD_OBJECT.to_string(),
-                                quote_style: None,
-                            }]),
-                            args: FunctionArguments::List(FunctionArgumentList {
-                                duplicate_treatment: None,
-                                clauses: vec![],
-                                args: statements
-                                    .into_iter()
-                                    .flat_map(|(key, query)| {
-                                        vec![
-                                            FunctionArg::Unnamed(FunctionArgExpr::Expr(
-                                                Expr::Value(Value::SingleQuotedString(
-                                                    key.to_string(),
-                                                )),
-                                            )),
-                                            FunctionArg::Unnamed(FunctionArgExpr::Expr(query)),
-                                        ]
-                                    })
-                                    .collect(),
-                            }),
-                            over: None,
-                            filter: None,
-                            null_treatment: None,
-                        }),
-                    }],
-                    from: vec![],
-                    lateral_views: vec![],
-                    selection: None,
-                    group_by: GroupByExpr::Expressions(vec![]),
-                    cluster_by: vec![],
-                    distribute_by: vec![],
-                    sort_by: vec![],
-                    having: None,
-                    qualify: None,
-                }))),
-                order_by: vec![],
-                limit: None,
-                offset: None,
-                fetch: None,
-                locks: vec![],
-            }));
-            let params = if final_vars.is_empty() {
-                None
-            } else {
-                Some(
-                    final_vars
-                        .into_iter()
-                        .filter_map(|n| sql_vars.swap_remove(&n))
-                        .collect(),
-                )
-            };
-            if tags.is_empty() {
-                return Ok((statement, params, None, false));
-            }
-            let mut sub_tags = tags
-                .into_iter()
-                .flat_map(|(key, values)| {
-                    if values.is_empty() {
-                        return vec![format!("type:{key}")];
+            }"#,
+        )?;
+        let err = gql2sql(gqlast, &None, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "@relation on \"Component\" has 2 fields but 1 references at 4:32"
+        );
+        let gqlast = parse_query(
+            r#"query GetApp {
+                app @meta(table: "") {
+                    id
+                }
+            }"#,
+        )?;
+        let err = gql2sql(gqlast, &None, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "@meta argument \"table\" must be a non-empty string at 2:21"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_in_array_variable() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Components($ids: [String!]!, $skip: [Int!]!, $none: [String!]!) {
+                components(
+                    filter: {
+                        field: "id",
+                        operator: "in",
+                        value: $ids,
+                        children: [
+                            { field: "order", operator: "not_in", value: $skip },
+                            { field: "kind", operator: "in", value: $none }
+                        ]
                     }
-                    values
-                        .into_iter()
-                        .map(|v| format!("type:{key}:{}", v.to_string()))
-                        .collect::<Vec<_>>()
-                })
-                .collect::<Vec<String>>();
-            sub_tags.sort_unstable();
-            return Ok((statement, params, Some(sub_tags), false));
-        }
-        OperationType::Mutation => {
-            for selection in operation.selection_set.node.items {
-                match &selection.node {
-                    Selection::Field(p_field) => {
-                        let field = &p_field.node;
-                        let (name, key, is_insert, is_update, is_delete, is_single, schema_name) =
-                            parse_mutation_meta(field)?;
+                ) @meta(table: "Component") {
+                    id
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({
+                "ids": ["a", "b", "c"],
+                "skip": [1, 2],
+                "none": []
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
 
-                        let table_name = schema_name.map_or_else(
-                            || {
-                                ObjectName(vec![Ident {
-                                    value: name.to_string(),
-                                    quote_style: Some(QUOTE_CHAR),
-                                }])
-                            },
-                            |schema_name| {
-                                ObjectName(vec![
-                                    Ident {
-                                        value: schema_name.to_string(),
-                                        quote_style: Some(QUOTE_CHAR),
-                                    },
-                                    Ident {
-                                        value: name.to_string(),
-                                        quote_style: Some(QUOTE_CHAR),
-                                    },
-                                ])
-                            },
-                        );
-                        if is_insert {
-                            let (columns, rows) = get_mutation_columns(
-                                &field.arguments,
-                                &variables,
-                                &mut sql_vars,
-                                &mut final_vars,
-                            )?;
-                            // let (projection, _, _) = get_projection(
-                            //     &field.selection_set.node.items,
-                            //     name,
-                            //     None,
-                            //     &variables,
-                            //     &mut sql_vars,
-                            //     &mut final_vars,
-                            //     &mut tags,
-                            // )?;
-                            if rows.is_empty() {
-                                return Ok((
-                                    Statement::Query(Box::new(Query {
-                                        for_clause: None,
-                                        limit_by: vec![],
-                                        with: None,
-                                        body: Box::new(SetExpr::Select(Box::new(Select {
-                                            window_before_qualify: false,
-                                            connect_by: None,
-                                            value_table_mode: None,
-                                            distinct: None,
-                                            named_window: vec![],
-                                            top: None,
-                                            into: None,
-                                            projection: vec![SelectItem::ExprWithAlias {
-                                                expr: Expr::Function(Function {
-                                                    within_group: vec![],
-                                                    name: ObjectName(vec![Ident {
-                                                        value: JSONB_BUILD_OBJECT.to_string(),
-                                                        quote_style: None,
-                                                    }]),
-                                                    args: FunctionArguments::List(
-                                                        FunctionArgumentList {
-                                                            duplicate_treatment: None,
-                                                            clauses: vec![],
-                                                            args: vec![
-                                                                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
-                                                                    Value::SingleQuotedString(key.to_string()),
-                                                                ))),
-                                                                FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Function(Function {
-                                                                    within_group: vec![],
-                                                                    name: ObjectName(vec![Ident {
-                                                                        value: JSONB_BUILD_ARRAY.to_string(),
-                                                                        quote_style: None,
-                                                                    }]),
-                                                                    args: FunctionArguments::List(
-                                                                        FunctionArgumentList {
-                                                                            duplicate_treatment: None,
-                                                                            clauses: vec![],
-                                                                            args: vec![],
-                                                                        },
-                                                                    ),
-                                                                    over: None,
-                                                                    filter: None,
-                                                                    null_treatment: None,
-                                                                }))),
-                        ],
-                                                        },
-                                                    ),
-                                                    over: None,
-                                                    filter: None,
-                                                    null_treatment: None,
-                                                }),
-                                                alias: Ident {
-                                                    value: DATA_LABEL.to_string(),
-                                                    quote_style: Some(QUOTE_CHAR),
-                                                },
-                                            }],
-                                            from: vec![],
-                                            lateral_views: vec![],
-                                            selection: None,
-                                            group_by: GroupByExpr::Expressions(vec![]),
-                                            cluster_by: vec![],
-                                            distribute_by: vec![],
-                                            sort_by: vec![],
-                                            having: None,
-                                            qualify: None,
-                                        }))),
-                                        order_by: vec![],
-                                        limit: None,
-                                        offset: None,
-                                        fetch: None,
-                                        locks: vec![],
-                                    })),
-                                    None,
-                                    None,
-                                    false,
-                                ));
+    #[test]
+    fn query_match_null_shortcuts() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Rows($parent: String) {
+                rows(parentId: $parent, email: null) @meta(table: "Row") {
+                    id
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql_with_options(
+            gqlast,
+            &Some(json!({ "parent": null })),
+            None,
+            &Options {
+                match_null_shortcuts: true,
+                key_columns: Some(vec!["email".to_string(), "parentId".to_string()]),
+                ..Options::default()
+            },
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_count_exists() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Badges($appId: String!) {
+                pages: Component_count(filter: { field: "appId", operator: "eq", value: $appId })
+                hasDraft: Component_exists(filter: { field: "status", operator: "eq", value: "draft" })
+                total: components @meta(table: "Component", count: true)
+            }"#,
+        )?;
+        let (statement, _params, tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "appId": "fake" })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&tags)?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_relation_distinct_on() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Team($teamId: String!) {
+                members: Member(filter: { field: "teamId", operator: "eq", value: $teamId }) {
+                    id
+                    status: StatusChange @relation(table: "StatusChange", field: ["memberId"], references: ["id"], distinctOn: ["memberId"], latestBy: "createdAt", single: true) {
+                        state
+                        createdAt
+                    }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "teamId": "fake" })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn group_by_bucket_query() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Signups($unit: String!) {
+                Signup_aggregate(groupBy: [{ field: "createdAt", bucket: $unit }, "plan"]) {
+                    value {
+                        createdAt
+                        plan
+                    }
+                    count
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "unit": "day" })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_js_ast() -> Result<(), anyhow::Error> {
+        let document: JsonValue = serde_json::from_str(
+            r#"
+            {
+              "kind": "Document",
+              "definitions": [
+                {
+                  "kind": "OperationDefinition",
+                  "operation": "query",
+                  "name": {
+                    "kind": "Name",
+                    "value": "GetApp"
+                  },
+                  "variableDefinitions": [
+                    {
+                      "kind": "VariableDefinition",
+                      "variable": {
+                        "kind": "Variable",
+                        "name": {
+                          "kind": "Name",
+                          "value": "appId"
+                        }
+                      },
+                      "type": {
+                        "kind": "NonNullType",
+                        "type": {
+                          "kind": "NamedType",
+                          "name": {
+                            "kind": "Name",
+                            "value": "String"
+                          }
+                        }
+                      },
+                      "directives": []
+                    },
+                    {
+                      "kind": "VariableDefinition",
+                      "variable": {
+                        "kind": "Variable",
+                        "name": {
+                          "kind": "Name",
+                          "value": "limit"
+                        }
+                      },
+                      "type": {
+                        "kind": "NamedType",
+                        "name": {
+                          "kind": "Name",
+                          "value": "Int"
+                        }
+                      },
+                      "defaultValue": {
+                        "kind": "IntValue",
+                        "value": "10"
+                      },
+                      "directives": []
+                    }
+                  ],
+                  "directives": [],
+                  "selectionSet": {
+                    "kind": "SelectionSet",
+                    "selections": [
+                      {
+                        "kind": "Field",
+                        "alias": {
+                          "kind": "Name",
+                          "value": "app"
+                        },
+                        "name": {
+                          "kind": "Name",
+                          "value": "App_one"
+                        },
+                        "arguments": [
+                          {
+                            "kind": "Argument",
+                            "name": {
+                              "kind": "Name",
+                              "value": "filter"
+                            },
+                            "value": {
+                              "kind": "ObjectValue",
+                              "fields": [
+                                {
+                                  "kind": "ObjectField",
+                                  "name": {
+                                    "kind": "Name",
+                                    "value": "field"
+                                  },
+                                  "value": {
+                                    "kind": "StringValue",
+                                    "value": "id"
+                                  }
+                                },
+                                {
+                                  "kind": "ObjectField",
+                                  "name": {
+                                    "kind": "Name",
+                                    "value": "operator"
+                                  },
+                                  "value": {
+                                    "kind": "StringValue",
+                                    "value": "eq"
+                                  }
+                                },
+                                {
+                                  "kind": "ObjectField",
+                                  "name": {
+                                    "kind": "Name",
+                                    "value": "value"
+                                  },
+                                  "value": {
+                                    "kind": "Variable",
+                                    "name": {
+                                      "kind": "Name",
+                                      "value": "appId"
+                                    }
+                                  }
+                                }
+                              ]
                             }
-                            let params = if final_vars.is_empty() {
-                                None
-                            } else {
-                                Some(
-                                    final_vars
-                                        .into_iter()
-                                        .filter_map(|n| sql_vars.swap_remove(&n))
-                                        .collect(),
-                                )
-                            };
-                            let is_potential_upsert = columns.contains(&Ident {
-                                value: "id".to_owned(),
-                                quote_style: Some(QUOTE_CHAR),
-                            });
-                            return Ok((
-                                wrap_mutation(
-                                    key,
-                                    Statement::Insert(Insert {
-                                        insert_alias: None,
-                                        ignore: false,
-                                        priority: None,
-                                        replace_into: false,
-                                        table_alias: None,
-                                        or: None,
-                                        into: true,
-                                        table_name,
-                                        columns: columns.clone(),
-                                        overwrite: false,
-                                        source: Some(Box::new(Query {
-                                            for_clause: None,
-                                            limit_by: vec![],
-                                            with: None,
-                                            body: Box::new(SetExpr::Values(Values {
-                                                explicit_row: false,
-                                                rows,
-                                            })),
-                                            order_by: vec![],
-                                            limit: None,
-                                            offset: None,
-                                            fetch: None,
-                                            locks: vec![],
-                                        })),
-                                        partitioned: None,
-                                        after_columns: vec![],
-                                        table: false,
-                                        on: if is_potential_upsert {
-                                            Some(OnInsert::OnConflict(OnConflict {
-                                                conflict_target: Some(ConflictTarget::Columns(
-                                                    vec![Ident {
-                                                        value: "id".to_owned(),
-                                                        quote_style: Some(QUOTE_CHAR),
-                                                    }],
-                                                )),
-                                                action: OnConflictAction::DoUpdate(DoUpdate {
-                                                    assignments: columns
-                                                        .iter()
-                                                        .filter_map(|c| {
-                                                            if c.value == "id" {
-                                                                return None;
-                                                            }
-                                                            Some(Assignment {
-                                                                id: vec![c.clone()],
-                                                                value: Expr::CompoundIdentifier(
-                                                                    vec![
-                                                                        Ident::new("EXCLUDED"),
-                                                                        c.clone(),
-                                                                    ],
-                                                                ),
-                                                            })
-                                                        })
-                                                        .collect(),
-                                                    selection: None,
-                                                }),
-                                            }))
-                                        } else {
-                                            None
-                                        },
-                                        returning: Some(vec![
-                                            SelectItem::ExprWithAlias {
-                                                alias: Ident {
-                                                    value: TYPENAME.to_string(),
-                                                    quote_style: Some(QUOTE_CHAR),
-                                                },
-                                                expr: Expr::Value(Value::SingleQuotedString(
-                                                    name.to_owned(),
-                                                )),
-                                            },
-                                            SelectItem::Wildcard(
-                                                WildcardAdditionalOptions::default(),
-                                            ),
-                                        ]),
-                                    }),
-                                    is_single,
-                                ),
-                                params,
-                                None,
-                                true,
-                            ));
-                        } else if is_update {
-                            let has_updated_at_directive = field
-                                .directives
-                                .iter()
-                                .any(|d| d.node.name.node == "updatedAt");
-                            let (selection, assignments) = get_mutation_assignments(
-                                &field.arguments,
-                                &variables,
-                                &mut sql_vars,
-                                &mut final_vars,
-                                has_updated_at_directive,
-                            )?;
-                            let params = if final_vars.is_empty() {
-                                None
-                            } else {
-                                Some(
-                                    final_vars
-                                        .into_iter()
-                                        .filter_map(|n| sql_vars.swap_remove(&n))
-                                        .collect(),
-                                )
-                            };
-                            return Ok((
-                                wrap_mutation(
-                                    key,
-                                    Statement::Update {
-                                        table: TableWithJoins {
-                                            relation: TableFactor::Table {
-                                                partitions: vec![],
-                                                version: None,
-                                                name: table_name,
-                                                alias: None,
-                                                args: None,
-                                                with_hints: vec![],
-                                            },
-                                            joins: vec![],
+                          }
+                        ],
+                        "directives": [],
+                        "selectionSet": {
+                          "kind": "SelectionSet",
+                          "selections": [
+                            {
+                              "kind": "Field",
+                              "name": {
+                                "kind": "Name",
+                                "value": "id"
+                              },
+                              "arguments": [],
+                              "directives": []
+                            },
+                            {
+                              "kind": "Field",
+                              "name": {
+                                "kind": "Name",
+                                "value": "name"
+                              },
+                              "arguments": [],
+                              "directives": []
+                            },
+                            {
+                              "kind": "Field",
+                              "alias": {
+                                "kind": "Name",
+                                "value": "components"
+                              },
+                              "name": {
+                                "kind": "Name",
+                                "value": "Component"
+                              },
+                              "arguments": [
+                                {
+                                  "kind": "Argument",
+                                  "name": {
+                                    "kind": "Name",
+                                    "value": "order"
+                                  },
+                                  "value": {
+                                    "kind": "ObjectValue",
+                                    "fields": [
+                                      {
+                                        "kind": "ObjectField",
+                                        "name": {
+                                          "kind": "Name",
+                                          "value": "name"
                                         },
-                                        assignments,
-                                        from: None,
-                                        selection,
-                                        returning: Some(vec![
-                                            SelectItem::ExprWithAlias {
-                                                alias: Ident {
-                                                    value: TYPENAME.to_string(),
-                                                    quote_style: Some(QUOTE_CHAR),
-                                                },
-                                                expr: Expr::Value(Value::SingleQuotedString(
-                                                    name.to_owned(),
-                                                )),
-                                            },
-                                            SelectItem::Wildcard(
-                                                WildcardAdditionalOptions::default(),
-                                            ),
-                                        ]),
+                                        "value": {
+                                          "kind": "EnumValue",
+                                          "value": "ASC"
+                                        }
+                                      }
+                                    ]
+                                  }
+                                },
+                                {
+                                  "kind": "Argument",
+                                  "name": {
+                                    "kind": "Name",
+                                    "value": "first"
+                                  },
+                                  "value": {
+                                    "kind": "Variable",
+                                    "name": {
+                                      "kind": "Name",
+                                      "value": "limit"
+                                    }
+                                  }
+                                }
+                              ],
+                              "directives": [
+                                {
+                                  "kind": "Directive",
+                                  "name": {
+                                    "kind": "Name",
+                                    "value": "relation"
+                                  },
+                                  "arguments": [
+                                    {
+                                      "kind": "Argument",
+                                      "name": {
+                                        "kind": "Name",
+                                        "value": "table"
+                                      },
+                                      "value": {
+                                        "kind": "StringValue",
+                                        "value": "Component"
+                                      }
                                     },
-                                    is_single,
-                                ),
-                                params,
-                                None,
-                                true,
-                            ));
-                        } else if is_delete {
-                            let (selection, _) = get_mutation_assignments(
-                                &field.arguments,
-                                &variables,
-                                &mut sql_vars,
-                                &mut final_vars,
-                                false,
-                            )?;
-                            let params = if final_vars.is_empty() {
-                                None
-                            } else {
-                                Some(
-                                    final_vars
-                                        .into_iter()
-                                        .filter_map(|n| sql_vars.swap_remove(&n))
-                                        .collect(),
-                                )
-                            };
-                            return Ok((
-                                wrap_mutation(
-                                    key,
-                                    Statement::Delete(Delete {
-                                        limit: None,
-                                        order_by: vec![],
-                                        tables: vec![],
-                                        from: FromTable::WithFromKeyword(vec![TableWithJoins {
-                                            relation: TableFactor::Table {
-                                                partitions: vec![],
-                                                version: None,
-                                                name: table_name,
-                                                alias: None,
-                                                args: None,
-                                                with_hints: vec![],
-                                            },
-                                            joins: vec![],
-                                        }]),
-                                        using: None,
-                                        selection,
-                                        returning: Some(vec![
-                                            SelectItem::ExprWithAlias {
-                                                alias: Ident {
-                                                    value: TYPENAME.to_string(),
-                                                    quote_style: Some(QUOTE_CHAR),
-                                                },
-                                                expr: Expr::Value(Value::SingleQuotedString(
-                                                    name.to_owned(),
-                                                )),
-                                            },
-                                            SelectItem::Wildcard(
-                                                WildcardAdditionalOptions::default(),
-                                            ),
-                                        ]),
-                                    }),
-                                    is_single,
-                                ),
-                                params,
-                                None,
-                                true,
-                            ));
+                                    {
+                                      "kind": "Argument",
+                                      "name": {
+                                        "kind": "Name",
+                                        "value": "field"
+                                      },
+                                      "value": {
+                                        "kind": "ListValue",
+                                        "values": [
+                                          {
+                                            "kind": "StringValue",
+                                            "value": "appId"
+                                          }
+                                        ]
+                                      }
+                                    },
+                                    {
+                                      "kind": "Argument",
+                                      "name": {
+                                        "kind": "Name",
+                                        "value": "references"
+                                      },
+                                      "value": {
+                                        "kind": "ListValue",
+                                        "values": [
+                                          {
+                                            "kind": "StringValue",
+                                            "value": "id"
+                                          }
+                                        ]
+                                      }
+                                    }
+                                  ]
+                                }
+                              ],
+                              "selectionSet": {
+                                "kind": "SelectionSet",
+                                "selections": [
+                                  {
+                                    "kind": "Field",
+                                    "name": {
+                                      "kind": "Name",
+                                      "value": "id"
+                                    },
+                                    "arguments": [],
+                                    "directives": []
+                                  }
+                                ]
+                              }
+                            }
+                          ]
                         }
+                      }
+                    ]
+                  }
+                }
+              ]
+            }
+            "#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            parse_js_ast(&document)?,
+            &Some(json!({ "appId": "fake", "limit": 10 })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_aggregate_functions() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Stats($fraction: Float!) {
+                Order_aggregate {
+                    stddev { total }
+                    variance { total }
+                    array_agg { id }
+                    string_agg(delimiter: ", ", order: { customer: ASC }) { customer }
+                    percentile_cont(fraction: $fraction) { total }
+                    count_distinct { customerId }
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "fraction": 0.9 })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_aggregate_invalid_fraction() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query {
+                Order_aggregate {
+                    percentile_cont(fraction: 2) { total }
+                }
+            }"#,
+        )?;
+        let error = gql2sql(gqlast, &None, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "percentile_cont requires a fraction between 0 and 1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_aggregate_filter() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Progress($projectId: String!) {
+                Task_aggregate(filter: { field: "projectId", operator: "eq", value: $projectId }) {
+                    count
+                    done: count(filter: { field: "status", operator: "eq", value: "done" })
+                    open: count(filter: { field: "status", operator: "neq", value: "done" })
+                    doneEstimate: sum(filter: { field: "status", operator: "eq", value: "done" }) {
+                        estimate
+                    }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "projectId": "fake" })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn group_by_order_query() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Signups {
+                Signup_aggregate(groupBy: [{ field: "createdAt", bucket: "day" }], order: { createdAt: ASC }) {
+                    value {
+                        createdAt
                     }
-                    Selection::FragmentSpread(_) | Selection::InlineFragment(_) => {
-                        return Err(anyhow::anyhow!("Fragment not supported"))
+                    count
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn group_by_enum_keys() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Signups {
+                Signup_aggregate(groupBy: [plan, region]) {
+                    value {
+                        plan
+                        region
+                    }
+                    count
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn group_by_selection_mismatch() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Signups($groupBy: [String]) {
+                Signup_aggregate(groupBy: $groupBy) {
+                    value {
+                        plan
+                        source
+                    }
+                    count
+                }
+            }"#,
+        )?;
+        let plan = plan(
+            gqlast,
+            &Some(json!({ "groupBy": ["plan", "region"] })),
+            None,
+            &Options::default(),
+        )?;
+        assert_eq!(
+            plan.warnings,
+            [Diagnostic {
+                message: "groupBy does not match value selection (groupBy keys missing from value: region; value fields missing from groupBy: source)".to_string(),
+                line: Some(3),
+                column: Some(21),
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_relation_aggregate_suffix() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Posts {
+                Post(first: 10) {
+                    id
+                    comments: Comment(first: 3) @relation(table: "Comment", field: ["postId"], references: ["id"]) {
+                        id
+                        body
+                    }
+                    commentStats: Comment_aggregate @relation(table: "Comment", field: ["postId"], references: ["id"]) {
+                        count
+                    }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_column_directive() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Tasks {
+                Task(filter: { field: "status", operator: "eq", value: "open" }, order: { dueDate: ASC }) {
+                    id
+                    status @column(name: "status_x7Rt2")
+                    due: dueDate @column(name: "due_date_9KpQ1")
+                    owner: User @relation(table: "User", field: ["id"], references: ["owner_id_Lm3"]) {
+                        displayName @column(name: "display_name_Vb8")
                     }
                 }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_node_budget() -> Result<(), anyhow::Error> {
+        let query = r#"query Apps($ids: [String!]!) {
+            App(filter: { field: "id", operator: "in", value: $ids }) {
+                id
+                name
             }
-        }
-        OperationType::Subscription => return Err(anyhow::anyhow!("Subscription not supported")),
+        }"#;
+        let variables = Some(json!({ "ids": ["a", "b", "c"] }));
+        let options = Options {
+            node_budget: Some(12),
+            ..Options::default()
+        };
+        gql2sql_with_options(parse_query(query)?, &variables, None, &options)?;
+        let error = gql2sql_with_options(
+            parse_query(query)?,
+            &Some(json!({ "ids": ["a", "b", "c", "d"] })),
+            None,
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Operation has 13 nodes which exceeds the budget of 12"
+        );
+        Ok(())
     }
-    Err(anyhow!("No operation found"))
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use async_graphql_parser::parse_query;
+    #[cfg(not(feature = "memory-budget"))]
+    #[test]
+    fn query_memory_budget() -> Result<(), anyhow::Error> {
+        let options = Options {
+            memory_budget: Some(1 << 20),
+            ..Options::default()
+        };
+        let error = gql2sql_with_options(parse_query("{ App { id } }")?, &None, None, &options)
+            .expect_err("the budget needs the feature");
+        assert_eq!(
+            error.to_string(),
+            "memoryBudget needs gql2sql to be built with the memory-budget feature"
+        );
+        Ok(())
+    }
 
-    use insta::assert_snapshot;
-    use serde_json::json;
+    #[test]
+    fn compile_filter_fragment() -> Result<(), anyhow::Error> {
+        let (sql, params) = compile_filter(
+            &json!({
+                "field": "appId",
+                "operator": "eq",
+                "value": "fake",
+                "children": [
+                    { "field": "status", "operator": "in", "value": ["draft", "live"] },
+                    { "field": "deletedAt", "operator": "null" }
+                ]
+            }),
+            &Options::default(),
+        )?;
+        assert_snapshot!(sql);
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
 
     #[test]
-    fn simple() -> Result<(), anyhow::Error> {
+    fn query_table_function() -> Result<(), anyhow::Error> {
         let gqlast = parse_query(
-            r#"query App {
-                app(filter: { field: "id", operator: "eq", value: "345810043118026832" }, order: { name: ASC }) @meta(table: "App") {
+            r#"query Search($term: String!, $limit: Int!) {
+                results: apps(first: 10) @meta(function: "search_apps", args: ["$term", $limit]) {
                     id
-                    components @relation(table: "Component", field: ["appId"], references: ["id"]) {
+                    name
+                    owners: User @relation(function: "app_owners", args: [$term], field: ["appId"], references: ["id"]) {
                         id
-                        pageMeta @relation(table: "PageMeta", field: ["componentId"], references: ["id"], single: true) {
-                          id
-                          path
-                        }
-                        elements(order: { order: ASC }) @relation(table: "Element", field: ["componentParentId"], references: ["id"]) {
-                            id
-                            name
-                        }
                     }
                 }
-                Component_aggregate(filter: { field: "appId", operator: "eq", value: "345810043118026832" }) {
-                  count
-                  min {
-                    createdAt
-                  }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "term": "crm", "limit": 5 })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_schema_tags() -> Result<(), anyhow::Error> {
+        let query = r#"query Users {
+            authUsers: users(filter: { field: "id", operator: "eq", value: "1" }) @meta(table: "users", schema: "auth") {
+                id
+                profile: users @relation(table: "users", schema: "public", field: ["id"], references: ["id"], single: true) {
+                    id
                 }
             }
-            query Another {
-                Component_aggregate(filter: { field: "appId", operator: "eq", value: "345810043118026832" }) {
-                  count
-                  min {
-                    createdAt
-                  }
+        }"#;
+        let (_statement, _params, tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(serde_json::to_string_pretty(&tags)?);
+        assert_eq!(
+            tags.unwrap_or_default()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["type:auth.users:id:1", "type:public.users:id"]
+        );
+        let (_statement, _params, tags, _is_mutation) = gql2sql_with_options(
+            parse_query(query)?,
+            &None,
+            None,
+            &Options {
+                unqualified_tags: true,
+                ..Options::default()
+            },
+        )?;
+        assert_snapshot!(serde_json::to_string_pretty(&tags)?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_lock() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query NextJob($queue: String!) {
+                Job_one(filter: { field: "queue", operator: "eq", value: $queue }, order: { createdAt: ASC }, lock: FOR_UPDATE_SKIP_LOCKED) {
+                    id
+                    payload
                 }
-            }
-        "#,
+            }"#,
         )?;
         let (statement, _params, _tags, _is_mutation) =
-            gql2sql(gqlast, &None, Some("App".to_owned()))?;
-        assert_snapshot!(statement.to_string());
+            gql2sql(gqlast, &Some(json!({ "queue": "emails" })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_filter_type_hint() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Active($published: String!, $ids: [String!]) {
+                Post(filter: { field: "archived", operator: "eq", value: "0", type: "boolean", children: [
+                    { field: "published", operator: "eq", value: $published, type: "boolean" },
+                    { field: "views", operator: "gte", value: "10", type: "integer" },
+                    { field: "id", operator: "in", value: $ids, type: "integer" }
+                ] }) {
+                    id
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({ "published": "true", "ids": ["1", "2"] })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let types: Vec<_> = params.unwrap().into_iter().map(|p| p.sql_type).collect();
+        assert_eq!(types, vec!["text", "integer[]"]);
+        let gqlast = parse_query(
+            r#"query {
+                Post(filter: { field: "archived", operator: "eq", value: "maybe", type: "boolean" }) {
+                    id
+                }
+            }"#,
+        )?;
+        let err = gql2sql(gqlast, &None, None).unwrap_err();
+        assert_eq!(err.to_string(), "cannot coerce \"maybe\" to boolean");
+        Ok(())
+    }
+
+    #[test]
+    fn query_vector_similarity() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Search($vec: [Float!]!) {
+                Document(
+                    filter: { field: "embedding", operator: "nearest", value: $vec, distance: 0.5 },
+                    order: { similarity: { field: "embedding", to: $vec } },
+                    limit: 5
+                ) {
+                    id
+                    distance @similarity(field: "embedding", to: $vec)
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "vec": [0.1, 0.2, 0.3] })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let params = params.unwrap();
+        assert_eq!(params.len(), 1);
+        assert_eq!(params[0].sql_type, "vector");
+        Ok(())
+    }
+
+    #[test]
+    fn query_timeouts() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Dashboard {
+                recent: Order(limit: 10) @meta(table: "Order", maxMs: 500) {
+                    id
+                }
+                Report @meta(maxMs: 2000) {
+                    id
+                }
+                User {
+                    id
+                }
+            }"#,
+        )?;
+        let timeouts = get_timeouts(&gqlast, None)?.unwrap();
+        assert_eq!(
+            timeouts.into_iter().collect::<Vec<_>>(),
+            vec![("recent".to_string(), 500), ("Report".to_string(), 2000)]
+        );
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert!(statement.to_string().contains(r#"FROM "Order" LIMIT 10"#));
+        let gqlast = parse_query(r#"query { User @meta(maxMs: -1) { id } }"#)?;
+        assert!(get_timeouts(&gqlast, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn query_split_roots() -> Result<(), anyhow::Error> {
+        let query = r#"query Dashboard($status: String) {
+            recent: Order(limit: 10, filter: { field: "status", operator: "eq", value: $status }) @meta(table: "Order", maxMs: 500) {
+                id
+            }
+            Report @meta(maxMs: 2000) {
+                id
+            }
+            User {
+                id
+            }
+        }"#;
+        let options = Options {
+            local_settings: vec![LocalSettings {
+                statement_timeout_ms: Some(10000),
+                work_mem: Some("64MB".to_string()),
+                ..LocalSettings::default()
+            }],
+            ..Options::default()
+        };
+        let roots = gql2sql_roots(
+            parse_query(query)?,
+            &Some(json!({ "status": "open" })),
+            None,
+            &options,
+        )?;
+        assert_eq!(
+            roots
+                .iter()
+                .map(|root| (
+                    root.key.as_str(),
+                    root.settings
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>(),
+                    root.timeout_ms,
+                    root.params.as_ref().map(Vec::len),
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "recent",
+                    vec![
+                        "SET LOCAL statement_timeout = 500".to_string(),
+                        "SET LOCAL work_mem = '64MB'".to_string()
+                    ],
+                    Some(500),
+                    Some(1)
+                ),
+                (
+                    "Report",
+                    vec![
+                        "SET LOCAL statement_timeout = 2000".to_string(),
+                        "SET LOCAL work_mem = '64MB'".to_string()
+                    ],
+                    Some(2000),
+                    None
+                ),
+                (
+                    "User",
+                    vec![
+                        "SET LOCAL statement_timeout = 10000".to_string(),
+                        "SET LOCAL work_mem = '64MB'".to_string()
+                    ],
+                    None,
+                    None
+                ),
+            ]
+        );
+        let sql = roots[1].statement.to_string();
+        assert!(
+            sql.contains(r#"jsonb_build_object('Report', "#) && !sql.contains("Order"),
+            "{sql}"
+        );
+        let mutation = r#"mutation { delete_User(id: "u1") { id } }"#;
+        assert_eq!(
+            gql2sql_roots(parse_query(mutation)?, &None, None, &options)
+                .expect_err("mutations are not split")
+                .to_string(),
+            "the root fields of a mutation run in one statement, they cannot be split"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_insert_select() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"mutation CloneTemplate($templateId: String!, $appId: String!) {
+                insert_Page(from: {
+                    table: "Template",
+                    filter: { field: "id", operator: "eq", value: $templateId },
+                    map: { name: "name", layout: "layout", appId: $appId }
+                }) {
+                    id
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({ "templateId": "t1", "appId": "a1" })),
+            None,
+        )?;
+        assert!(is_mutation);
+        assert_eq!(params.map(|p| p.len()), Some(2));
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_clone() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"mutation DuplicateApp($id: String!, $name: String!) {
+                duplicate: App(id: $id, set: { name: $name }) @clone @meta(single: true) {
+                    __typename
+                    id
+                    title: name
+                    pages @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                        components @relation(table: "Component", field: ["pageId"], references: ["id"]) {
+                            id
+                        }
+                    }
+                    settings @relation(table: "Setting", field: ["appId"], references: ["id"]) {
+                        id
+                    }
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({ "id": "a1", "name": "Copy of app" })),
+            None,
+        )?;
+        assert!(is_mutation);
+        assert_eq!(params.map(|p| p.len()), Some(2));
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_clone_keys() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"mutation DuplicateApp($key: String!) {
+                App(key: $key) @clone @meta(single: true) {
+                    key
+                    pages @relation(table: "Page", field: ["appKey"], references: ["key"]) @id(column: "slug", generate: "uuid") {
+                        slug
+                    }
+                }
+            }"#,
+        )?;
+        let options = Options {
+            table_key_columns: HashMap::from([("App".to_string(), vec!["key".to_string()])]),
+            ..Options::default()
+        };
+        let (statement, ..) =
+            gql2sql_with_options(gqlast, &Some(json!({ "key": "a1" })), None, &options)?;
+        let sql = statement.to_string();
+        assert!(
+            sql.contains(
+                r#"SELECT "src"."key" AS "old_id", gen_random_uuid() AS "new_id" FROM "App""#
+            ),
+            "{sql}"
+        );
+        assert!(
+            sql.contains(r#"jsonb_build_object('key', "map"."new_id")"#),
+            "{sql}"
+        );
+        assert!(sql.contains(r#"SELECT "src"."slug" AS "old_id""#), "{sql}");
+        assert!(
+            sql.contains(
+                r#"jsonb_build_object('slug', "map"."new_id", 'appKey', "map"."parent_id")"#
+            ),
+            "{sql}"
+        );
+        assert!(sql.contains(r#"RETURNING "key")"#), "{sql}");
+
+        let composite = Options {
+            table_key_columns: HashMap::from([(
+                "App".to_string(),
+                vec!["orgId".to_string(), "key".to_string()],
+            )]),
+            ..Options::default()
+        };
+        let error = gql2sql_with_options(
+            parse_query(r#"mutation { App(key: "a1") @clone { key } }"#)?,
+            &None,
+            None,
+            &composite,
+        )
+        .expect_err("a composite key cannot be remapped");
+        assert_eq!(
+            error.to_string(),
+            r#"@clone needs a single key column to copy App, got ["orgId", "key"] at 1:12"#
+        );
+        let error = gql2sql(
+            parse_query(r#"mutation { App(id: "a1") @clone @id(generate: "nanoid") { id } }"#)?,
+            &None,
+            None,
+        )
+        .expect_err("nanoid keys cannot be generated in SQL");
+        assert_eq!(
+            error.to_string(),
+            r#"@clone can only generate uuid keys, but "id" of App is a nanoid at 1:12"#
+        );
+        let error = gql2sql(
+            parse_query(
+                r#"mutation {
+                    App(id: "a1") @clone {
+                        id
+                        pages @relation(table: "Page", field: ["appId"], references: ["slug"]) { id }
+                    }
+                }"#,
+            )?,
+            &None,
+            None,
+        )
+        .expect_err("children hang off the parent key");
+        assert!(
+            error
+                .to_string()
+                .starts_with(r#"@clone children must reference the parent key "id""#),
+            "{error}"
+        );
         Ok(())
     }
 
     #[test]
-    fn id_ignore() -> Result<(), anyhow::Error> {
+    fn query_schema_many_to_many() -> Result<(), anyhow::Error> {
         let gqlast = parse_query(
-            r#"query App($id: String) {
-                app(id: $id) @meta(table: "App") {
+            r#"query {
+                Post @meta(schema: "Blog") {
                     id
+                    Tag @relation(table: "Tag", schema: "Blog", many: true) {
+                        id
+                    }
+                    Comment @relation(table: "Comment", schema: "Blog", field: ["postId"], references: ["id"]) {
+                        authorId
+                    }
                 }
-            }
-        "#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "id": null
-            })),
-            Some("App".to_owned()),
+            }"#,
         )?;
-        assert_snapshot!(statement.to_string());
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let Statement::Query(mut query) = statement else {
+            panic!("expected a query");
+        };
+        let mut unquoted = vec![];
+        let _ = visit_relations(&query, |name| {
+            unquoted.extend(
+                name.0
+                    .iter()
+                    .filter(|i| i.quote_style != Some(QUOTE_CHAR))
+                    .cloned(),
+            );
+            ControlFlow::<()>::Continue(())
+        });
+        let _ = visit_expressions_mut(&mut query, |expr| {
+            match expr {
+                Expr::Identifier(ident) => unquoted
+                    .extend(Some(ident.clone()).filter(|i| i.quote_style != Some(QUOTE_CHAR))),
+                Expr::CompoundIdentifier(idents) => unquoted.extend(
+                    idents
+                        .iter()
+                        .filter(|i| i.quote_style != Some(QUOTE_CHAR))
+                        .cloned(),
+                ),
+                _ => {}
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        assert!(unquoted.is_empty(), "unquoted identifiers: {unquoted:?}");
         Ok(())
     }
 
     #[test]
-    fn simple_ignore() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"query App($filter: Filter) {
-                app(filter: $filter, order: { name: ASC }) @meta(table: "App") {
+    fn query_identifier_strategy() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            Post(filter: { field: "authorId", operator: "eq", value: "u1" }, order: { createdAt: DESC }) @meta(schema: "Blog") {
+                id
+                createdAt
+                Comment @relation(table: "Comment", schema: "Blog", field: ["postId"], references: ["id"]) {
+                    body
+                }
+            }
+        }"#;
+        for identifiers in [
+            IdentifierStrategy::QuoteNever,
+            IdentifierStrategy::Lowercase,
+        ] {
+            let options = Options {
+                identifiers,
+                ..Options::default()
+            };
+            let (statement, _params, _tags, _is_mutation) =
+                gql2sql_with_options(parse_query(query)?, &None, None, &options)?;
+            assert_snapshot!(
+                format!("query_identifier_strategy_{identifiers:?}"),
+                statement.to_string()
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shape_response_spec() -> Result<(), anyhow::Error> {
+        let query = r#"query($hide: Boolean) {
+            App_one(filter: { field: "id", operator: "eq", value: "a1" }) {
+                __typename
+                id
+                name @skip(if: $hide)
+                components @relation(table: "Component", field: ["appId"], references: ["id"]) {
+                    __typename
                     id
+                    label
                 }
             }
-        "#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "filter": {
-                    "field": "id",
-                    "operator": "eq",
-                    "value": null,
-                    "ignoreEmpty": true,
-                    "children": [{
-                        "field": "other",
-                        "operator": "gte",
-                        "value": null,
-                        "ignoreEmpty": true,
-                    }]
+        }"#;
+        let variables = Some(json!({ "hide": true }));
+        let data = json!({
+            "App_one": {
+                "id": "a1",
+                "name": null,
+                "components": [{ "id": "c1" }]
+            }
+        });
+        let response = shape_response(&parse_query(query)?, &variables, None, Some(data), vec![])?;
+        assert_eq!(
+            response,
+            json!({
+                "data": {
+                    "App_one": {
+                        "__typename": "App",
+                        "id": "a1",
+                        "components": [{ "__typename": "Component", "id": "c1", "label": null }]
+                    }
                 }
-            })),
-            Some("App".to_owned()),
+            })
+        );
+        let response = shape_response(
+            &parse_query(query)?,
+            &None,
+            None,
+            None,
+            vec!["permission denied".to_string()],
         )?;
-        assert_snapshot!(statement.to_string());
+        assert_eq!(
+            response,
+            json!({ "data": null, "errors": [{ "message": "permission denied" }] })
+        );
         Ok(())
     }
 
     #[test]
-    fn mutation_insert() -> Result<(), anyhow::Error> {
+    fn query_format_date() -> Result<(), anyhow::Error> {
         let gqlast = parse_query(
-            r#"mutation insertVillains($data: [Villain_insert_input!]!) {
-                insert(data: $data) @meta(table: "Villain", insert: true, schema: "auth") { id name }
+            r#"query {
+                Launch {
+                    id
+                    createdAt @format(date: "iso8601")
+                    localAt: createdAt @format(date: "iso8601", timezone: "America/New_York")
+                    updatedAt @format(date: "epoch_ms") @default(value: "2020-01-01")
+                }
             }"#,
         )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "data": [
-                    { "name": "Ronan the Accuser", "id": "1" },
-                    { "name": "Red Skull", "id": "2" },
-                    { "name": "The Vulture", "id": "3" }
-                ]
-            })),
-            None,
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let gqlast = parse_query(
+            r#"query { Launch { createdAt @format(date: "iso8601", timezone: "UTC'; --") } }"#,
         )?;
-        assert_snapshot!(statement.to_string());
-        Ok(())
-    }
-
-    #[test]
-    fn mutation_empty_insert() -> Result<(), anyhow::Error> {
+        assert!(gql2sql(gqlast, &None, None).is_err());
         let gqlast = parse_query(
-            r#"mutation insertVillains($data: [Villain_insert_input!]!) {
-                insert(data: $data) @meta(table: "Villain", insert: true, schema: "auth") { id name }
+            r#"query {
+                Launch {
+                    createdAt @format(date: "iso8601")
+                    localAt: createdAt @format(date: "iso8601", timezone: "America/New_York")
+                    updatedAt @format(date: "iso8601")
+                }
             }"#,
         )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
+        let (statement, _params, _tags, _is_mutation) = gql2sql_with_options(
             gqlast,
-            &Some(json!({
-                "data": [
-                ]
-            })),
+            &None,
             None,
+            &Options {
+                timestamp_columns: HashMap::from([(
+                    "Launch".to_string(),
+                    vec!["createdAt".to_string()],
+                )]),
+                ..Options::default()
+            },
         )?;
-        assert_snapshot!(statement.to_string());
+        assert_snapshot!(pretty_sql(&statement.to_string()));
         Ok(())
     }
 
     #[test]
-    fn mutation_update() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"mutation updateHero {
-                update(
-                    filter: { field: "secret_identity", operator: "eq", value: "Sam Wilson" },
-                    set: {
-                        name: "Captain America",
-                    }
-                    increment: {
-                        number_of_movies: 1
-                    }
-                ) @meta(table: "Hero", update: true, schema: "auth") @updatedAt {
-                    id
-                    name
-                    secret_identity
-                    number_of_movies
+    fn query_case_style() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            launch_site(order: { created_at: DESC }) {
+                __typename
+                id
+                created_at
+                site_name: name
+                launch_pad @relation(table: "launch_pad", field: ["id"], references: ["launch_site_id"]) {
+                    pad_number
                 }
-            }"#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
-        assert_snapshot!(statement.to_string());
+            }
+            mission @meta(caseStyle: "snakeCase") {
+                missionName
+            }
+        }"#;
+        let options = Options {
+            case_style: Some(CaseStyle::CamelCase),
+            ..Options::default()
+        };
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &None, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
         Ok(())
     }
 
     #[test]
-    fn query_mega() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"query GetApp($orgId: String!, $appId: String!, $branch: String!) {
-      app: App_one(
-        filter: {
-          field: "orgId",
-          operator: "eq",
-          value: $orgId,
-          logicalOperator: "AND",
-          children: [
-            { field: "id", operator: "eq", value: $appId },
-            { field: "branch", operator: "eq", value: $branch }
-          ]
-        }
-      ) {
-        orgId
-        id
-        branch
-        name
-        description
-        theme
-        favicon
-        customCSS
-        analytics
-        customDomain
-        components
-          @relation(
-            table: "Component"
-            field: ["appId", "branch"]
-            references: ["id", "branch"]
-          ) {
-          id
-          branch
-          ... on PageMeta
-            @relation(
-              table: "PageMeta"
-              field: ["componentId", "branch"]
-              references: ["id", "branch"]
-              single: true
-            ) {
-            title
-            description
-            path
-            socialImage
-            urlParams
-            loader
-            protection
-            maxAge
-            sMaxAge
-            staleWhileRevalidate
-          }
-          ... on ComponentMeta
-            @relation(
-              table: "ComponentMeta"
-              field: ["componentId", "branch"]
-              references: ["id", "branch"]
-              single: true
-            ) {
-            title
-            sources
-              @relation(
-                table: "Source"
-                field: ["componentId", "branch"]
-                references: ["id", "branch"]
-              ) {
-              id
-              branch
-              name
-              provider
-              description
-              template
-              instanceTemplate
-              outputType
-              source
-              sourceProp
-              componentId
-              utilityId
-              component(order: { order: ASC })
-                @relation(
-                  table: "Element"
-                  field: ["id", "branch"]
-                  references: ["componentId", "branch"]
-                  single: true
-                ) {
+    fn query_null_policy() -> Result<(), anyhow::Error> {
+        let query = r#"query($id: String, $status: String, $owner: String) {
+            Task(filter: {
+                field: "status", operator: "eq", value: $status,
+                children: [{ field: "ownerId", operator: "neq", value: $owner, nullPolicy: "ignore" }]
+            }, id: $id) {
                 id
-                branch
+            }
+        }"#;
+        let variables = Some(json!({ "id": null, "status": null }));
+        let options = Options {
+            null_policy: Some(NullPolicy::MatchNull),
+            ..Options::default()
+        };
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &variables, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let options = Options {
+            null_policy: Some(NullPolicy::Error),
+            ..Options::default()
+        };
+        let error = gql2sql_with_options(parse_query(query)?, &variables, None, &options)
+            .expect_err("null status should be rejected");
+        assert_eq!(
+            error.to_string(),
+            r#"Filter on "status" received a null value"#
+        );
+        let aggregate = r#"query($status: String) {
+            Task_aggregate { count(filter: { field: "status", operator: "eq", value: $status }) }
+        }"#;
+        let error = gql2sql_with_options(parse_query(aggregate)?, &variables, None, &options)
+            .expect_err("null aggregate filter should be rejected");
+        assert_eq!(
+            error.to_string(),
+            r#"Filter on "status" received a null value"#
+        );
+        let insert = r#"mutation($status: String) {
+            insert_Task(from: {
+                table: "Template",
+                filter: { field: "status", operator: "eq", value: $status },
+                map: { name: "name" }
+            }) { id }
+        }"#;
+        let error = gql2sql_with_options(parse_query(insert)?, &variables, None, &options)
+            .expect_err("null insert filter should be rejected");
+        assert_eq!(
+            error.to_string(),
+            r#"Filter on "status" received a null value"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_key_columns() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            Device(uuid: "d1") @meta(keys: ["uuid"]) {
+                uuid
+            }
+            Membership(orgId: "o1", userId: "u1") {
+                role
+            }
+            Org(slug: "acme") {
                 name
-                kind
-                source
-                styles
-                props
-                order
-                conditions
-              }
-              utility
-                @relation(
-                  table: "Utility"
-                  field: ["id", "branch"]
-                  references: ["componentId", "branch"]
-                  single: true
-                ) {
+                Team @relation(table: "Team", field: ["orgSlug"], references: ["slug"]) {
+                    name
+                }
+            }
+        }"#;
+        let options = Options {
+            table_key_columns: HashMap::from([(
+                "Membership".to_string(),
+                vec!["orgId".to_string(), "userId".to_string()],
+            )]),
+            ..Options::default()
+        };
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &None, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_relation_registry() -> Result<(), anyhow::Error> {
+        let foreign_keys: Vec<ForeignKey> = serde_json::from_value(json!([
+            {
+                "schema": "public", "table": "Component", "columns": ["appId"],
+                "foreignSchema": "public", "foreignTable": "App", "foreignColumns": ["id"]
+            },
+            {
+                "schema": "public", "table": "Component", "columns": ["parentId"],
+                "foreignSchema": "public", "foreignTable": "Component", "foreignColumns": ["id"]
+            }
+        ]))?;
+        let options = Options {
+            relations: RelationRegistry::from_foreign_keys(foreign_keys),
+            ..Options::default()
+        };
+        let query = r#"query {
+            App_one(id: "a1") {
                 id
-                branch
-                name
-                kind
-                kindId
-                data
-              }
+                Component {
+                    id
+                    App { id }
+                    Component_parentId @relation(table: "Component", field: ["id"], references: ["parentId"], single: true) {
+                        id
+                    }
+                }
             }
-            events @relation(table: "Event", field: ["componentMetaId", "branch"], references: ["id", "branch"]) {
+        }"#;
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &None, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_plan() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            App(filter: { field: "id", operator: "eq", value: "a1" }) @meta(schema: "builder") {
                 id
-                branch
-                name
-                label
-                help
-                type
+                components @relation(table: "Component", schema: "builder", field: ["appId"], references: ["id"]) {
+                    id
+                }
+            }
+        }"#;
+        let options = Options::default();
+        let query_plan = plan(parse_query(query)?, &None, None, &options)?;
+        assert_eq!(
+            query_plan
+                .tables()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![r#""builder"."App""#, r#""builder"."Component""#]
+        );
+        let json = serde_json::to_value(&query_plan)?;
+        let mut query_plan: QueryPlan = serde_json::from_value(json)?;
+        let (statement, ..) = render(query_plan.clone(), Dialect::Postgres, &options)?;
+        let (expected, ..) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_eq!(statement, expected);
+        // rewrite the tables and filter of the query reading components
+        for query in &mut query_plan.queries {
+            for table in &mut query.tables {
+                let PlanRelation::Table { name, .. } = &mut table.relation else {
+                    continue;
+                };
+                if name.0[1].value != "Component" {
+                    continue;
+                }
+                name.0[1].value = "Part".to_string();
+                let deleted = Expr::IsNull(Box::new(Expr::Identifier(Ident::with_quote(
+                    '"',
+                    "deletedAt",
+                ))));
+                query.filter = Some(match query.filter.take() {
+                    Some(filter) => Expr::BinaryOp {
+                        left: Box::new(filter),
+                        op: BinaryOperator::And,
+                        right: Box::new(deleted),
+                    },
+                    None => deleted,
+                });
             }
-          }
         }
-        connections @relation(table: "Connection", field: ["appId", "branch"], references: ["id", "branch"]) {
-          id
-          branch
-          name
-          kind
-          prodUrl
-          mutationSchema @relation(table: "Schema", field: ["mutationConnectionId", "branch"], references: ["id", "branch"], single: true) {
-            id
-            branch
-            schema
-          }
-          endpoints @relation(table: "Endpoint", field: ["connectionId", "branch"], references: ["id", "branch"]) {
-            id
-            branch
-            name
-            method
-            path
-            responseSchemaId
-            headers @relation(table: "Header", field: ["parentEndpointId", "branch"], references: ["id", "branch"]) {
-              id
-              branch
-              key
-              value
-              dynamic
+        let (statement, ..) = render(query_plan.clone(), Dialect::Postgres, &options)?;
+        let sql = statement.to_string();
+        assert!(
+            sql.contains(r#"FROM "builder"."Part" WHERE "builder"."Component"."appId" = "base"."id" AND "builder"."Component"."appId" = 'a1' AND "deletedAt" IS NULL"#),
+            "{sql}"
+        );
+        assert_eq!(
+            query_plan
+                .tables()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![r#""builder"."App""#, r#""builder"."Part""#]
+        );
+        query_plan.queries.pop();
+        assert_eq!(
+            render(query_plan, Dialect::Postgres, &options)
+                .expect_err("the statement refers to the last query")
+                .to_string(),
+            "the plan has no query for TABLE #8"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_dialect() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            Event(sample: { percent: 10 }) { id }
+        }"#;
+        let cockroach = Options {
+            dialect: Dialect::CockroachDb,
+            ..Options::default()
+        };
+        gql2sql_with_options(parse_query(query)?, &None, None, &Options::default())?;
+        assert_eq!(
+            gql2sql_with_options(parse_query(query)?, &None, None, &cockroach)
+                .expect_err("CockroachDB has no table samples")
+                .to_string(),
+            "CockroachDB does not support TABLESAMPLE"
+        );
+        let query = r#"query {
+            Sale_aggregate(groupBy: { rollup: ["region", "product"] }) {
+                count
             }
-            search @relation(table: "Search", field: ["endpointId", "branch"], references: ["id", "branch"]) {
-              id
-              branch
-              key
-              value
-              dynamic
+        }"#;
+        let query_plan = plan(parse_query(query)?, &None, None, &cockroach)?;
+        render(query_plan.clone(), Dialect::Postgres, &cockroach)?;
+        assert_eq!(
+            render(query_plan, Dialect::CockroachDb, &cockroach)
+                .expect_err("CockroachDB has no grouping sets")
+                .to_string(),
+            "CockroachDB does not support ROLLUP"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_tenant_isolation() -> Result<(), anyhow::Error> {
+        let options = Options {
+            tenant: Some(TenantOptions {
+                columns: HashMap::from([
+                    ("Project".to_string(), "org_id".to_string()),
+                    ("Task".to_string(), "org_id".to_string()),
+                ]),
+                value: json!("org-1"),
+            }),
+            ..Options::default()
+        };
+        let query = r#"query($status: String) {
+            Project(filter: { field: "status", operator: "eq", value: $status, logicalOperator: "OR", children: [{ field: "public", operator: "eq", value: true }] }) {
+                id
+                Task @relation(table: "Task", field: ["projectId"], references: ["id"]) {
+                    id
+                }
             }
-          }
-          headers @relation(table: "Header", field: ["parentConnectionId", "branch"], references: ["id", "branch"]) {
-            id
-            branch
-            key
-            value
-            dynamic
-          }
-        }
-        layouts @relation(table: "Layout", field: ["appId", "branch"], references: ["id", "branch"]) {
-          id
-          branch
-          name
-          source
-          kind
-          styles
-          props
-        }
-        plugins @relation(table: "Plugin", field: ["appId", "branch"], references: ["id", "branch"]) {
-          instanceId
-          kind
-        }
-        schemas @relation(table: "Schema", field: ["appId", "branch"], references: ["id", "branch"]) {
-          id
-          branch
-          schema
-        }
-        styles @relation(table: "Style", field: ["appId", "branch"], references: ["id", "branch"]) {
-          id
-          branch
-          name
-          kind
-          styles
-          isDefault
-        }
-        workflows @relation(table: "Workflow", field: ["appId", "branch"], references: ["id", "branch"]) {
-          id
-          branch
-          name
-          args
-          steps(order: { order: ASC }) @relation(table: "Step", field: ["workflowId", "branch"], references: ["id", "branch"]) {
-            id
-            branch
-            parentId
-            kind
-            kindId
-            data
-            order
-          }
-        }
-      }
+        }"#;
+        let (statement, params, _tags, _is_mutation) = gql2sql_with_options(
+            parse_query(query)?,
+            &Some(json!({ "status": "open" })),
+            None,
+            &options,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_eq!(
+            params.map(|p| p.into_iter().map(|p| p.value).collect::<Vec<_>>()),
+            Some(vec![json!("open"), json!("org-1")])
+        );
+        let mutation = r#"mutation {
+            update_Task(id: "t1", set: { done: true }) { id }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(mutation)?, &None, None, &options)?;
+        assert_snapshot!(
+            "query_tenant_isolation_mutation",
+            pretty_sql(&statement.to_string())
+        );
+        Ok(())
     }
-"#,
+
+    #[test]
+    fn mutation_tenant_isolation() -> Result<(), anyhow::Error> {
+        let options = Options {
+            tenant: Some(TenantOptions {
+                columns: HashMap::from([
+                    ("Task".to_string(), "org_id".to_string()),
+                    ("Template".to_string(), "org_id".to_string()),
+                    ("auth.User".to_string(), "org_id".to_string()),
+                ]),
+                value: json!("org-1"),
+            }),
+            ..Options::default()
+        };
+        let translate = |query: &str| {
+            let (statement, _params, _tags, _is_mutation) =
+                gql2sql_with_options(parse_query(query)?, &None, None, &options)?;
+            Ok::<_, anyhow::Error>(pretty_sql(&statement.to_string()))
+        };
+        assert_snapshot!(
+            "mutation_tenant_isolation_insert",
+            translate(
+                r#"mutation {
+                    insert_Task(data: [{ id: "t1", name: "Mine", org_id: "org-2" }, { id: "t2", name: "Also mine", org_id: "org-3" }]) { id }
+                }"#,
+            )?
+        );
+        assert_snapshot!(
+            "mutation_tenant_isolation_insert_from",
+            translate(
+                r#"mutation {
+                    insert_Task(from: { table: "Template", map: { name: "name" } }) { id }
+                }"#,
+            )?
+        );
+        assert_snapshot!(
+            "mutation_tenant_isolation_update",
+            translate(r#"mutation { update_Task(id: "t1", set: { org_id: "org-2" }) { id } }"#)?
+        );
+        assert_snapshot!(
+            "mutation_tenant_isolation_schema",
+            translate(
+                r#"query {
+                    users: User @meta(table: "User", schema: "auth") { id }
+                    people: User @meta(table: "User", schema: "public") { id }
+                }"#,
+            )?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_join_names() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            App(id: "a1") {
+                id
+                pages: Page(filter: { field: "draft", operator: "eq", value: false }) @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                    id
+                    Component @relation(table: "Component", field: ["pageId"], references: ["id"]) {
+                        id
+                    }
+                }
+                drafts: Page(filter: { field: "draft", operator: "eq", value: true }) @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                    id
+                }
+            }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn validate_diagnostics() -> Result<(), anyhow::Error> {
+        assert!(parse_check("query { App { id } }").is_empty());
+        let query = r#"query App($id: String!, $name: String, $limit: Int! = 10) {
+            App(id: $id) @meta(table: "") {
+                id
+                name @include(if: $show)
+                ...AppFields
+            }
+        }"#;
+        let diagnostics = validate(&parse_query(query)?, &Some(json!({})), None);
+        assert_snapshot!(serde_json::to_string_pretty(&json!({
+            "parse": parse_check("query { App { id }"),
+            "validate": diagnostics,
+        }))?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_inject_claims() -> Result<(), anyhow::Error> {
+        let mapping: ClaimMapping = serde_json::from_value(json!({
+            "currentUserId": "sub",
+            "orgId": ["https://example.com/claims", "org.id"],
+        }))?;
+        let claims = json!({
+            "sub": "u1",
+            "https://example.com/claims": { "org.id": "o1" },
+        });
+        let query = r#"query Projects($currentUserId: String!, $orgId: String!) {
+            Project(filter: {
+                field: "ownerId", operator: "eq", value: $currentUserId,
+                children: [{ field: "orgId", operator: "eq", value: $orgId }]
+            }) {
+                id
+            }
+        }"#;
+        let mut variables = Some(json!({ "currentUserId": "spoofed" }));
+        inject_claims(&mut variables, &claims, &mapping)?;
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql(parse_query(query)?, &variables, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+
+        let error = inject_claims(&mut None, &json!({ "sub": "u1" }), &mapping)
+            .expect_err("missing org claim should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "claim https://example.com/claims > org.id for variable $orgId is missing"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_unsafe_input() -> Result<(), anyhow::Error> {
+        let translate = |query: &str| gql2sql(parse_query(query)?, &None, None);
+        let (statement, _params, _tags, _is_mutation) = translate(
+            r#"query { User(filter: { field: "name", operator: "eq", value: "\\' OR true --" }) { id } }"#,
+        )?;
+        assert!(statement
+            .to_string()
+            .contains(r#""name" = E'\\\' OR true --'"#));
+        let error = translate(
+            r#"query { User(filter: { field: "name", operator: "= '' OR true --", value: "x" }) { id } }"#,
+        )
+        .expect_err("operator should be rejected");
+        assert_eq!(error.to_string(), "operator not supported: = '' OR true --");
+        let error = translate(
+            r#"query { User(filter: { field: "a\\\"b", operator: "eq", value: "x" }) { id } }"#,
+        )
+        .expect_err("identifier should be rejected");
+        assert_eq!(
+            error.to_string(),
+            r#"identifier a\"b must not contain a double quote"#
+        );
+        let error =
+            translate("query { User(first: 1.5) { id } }").expect_err("limit should be rejected");
+        assert_eq!(error.to_string(), "first must be an integer, got 1.5");
+        Ok(())
+    }
+
+    #[test]
+    fn query_unknown_variable() -> Result<(), anyhow::Error> {
+        let query = r#"query Posts($id: String!, $title: String) {
+            User(id: $id) {
+                id
+                posts(filter: { field: "name", operator: "eq", value: $name }) @relation(table: "Post", field: ["authorId"], references: ["id"]) {
+                    id
+                }
+                drafts: posts(filter: { field: "title", operator: "eq", value: $title }) @relation(table: "Post", field: ["authorId"], references: ["id"]) {
+                    id
+                }
+            }
+        }"#;
+        let error = gql2sql(parse_query(query)?, &Some(json!({ "id": "u1" })), None)
+            .expect_err("undeclared variable should be rejected");
+        assert_eq!(
+            error.downcast_ref::<UnknownVariable>(),
+            Some(&UnknownVariable {
+                variable: "name".to_string(),
+                field: "User.posts".to_string(),
+            })
+        );
+        let error = gql2sql(parse_query(query)?, &None, None)
+            .expect_err("missing required variable should be rejected");
+        assert_eq!(error.to_string(), "Unknown variable $id used in User");
+
+        let options = Options {
+            allow_unknown_variables: true,
+            ..Options::default()
+        };
+        let (statement, ..) = gql2sql_with_options(
+            parse_query(query)?,
+            &Some(json!({ "id": "u1" })),
+            None,
+            &options,
         )?;
+        assert!(statement.to_string().contains(r#""name" IS NULL"#));
+        Ok(())
+    }
+
+    #[test]
+    fn query_variable_defaults() -> Result<(), anyhow::Error> {
+        let query = r#"query Users($limit: Int = 20, $name: String = "Ada", $age: Int = 30, $scope: String = "user")
+            @cached(ttl: 60, scope: $scope) {
+            User(limit: $limit, filter: {
+                field: "name", operator: "eq", value: $name,
+                children: [{ field: "age", operator: "eq", value: $age }]
+            }) {
+                id
+            }
+        }"#;
+        let variables = Some(json!({ "name": "Grace", "age": null }));
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql(parse_query(query)?, &variables, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        assert_eq!(
+            get_operation_directives(&parse_query(query)?, &variables, None)?,
+            Some(IndexMap::from([(
+                "cached".to_string(),
+                json!({ "ttl": 60, "scope": "user" })
+            )]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_cache_policy() -> Result<(), anyhow::Error> {
+        let query = r#"query Home($ttl: Int = 300) @cached(ttl: $ttl, staleWhileRevalidate: 60) {
+            posts: Post(limit: 10) { id }
+            me: User(id: "u1") @cached(ttl: 30, staleWhileRevalidate: 120) { id }
+        }"#;
+        let policy = get_cache_policy(&parse_query(query)?, &None, None)?;
+        assert_eq!(
+            policy,
+            Some(CachePolicy {
+                ttl: 30,
+                stale_while_revalidate: Some(60),
+            })
+        );
+        assert_eq!(
+            policy.map(|p| p.cache_control()).as_deref(),
+            Some("max-age=30, stale-while-revalidate=60")
+        );
+        gql2sql(parse_query(query)?, &None, None)?;
+
+        let uncovered = r#"query { Post @cached(ttl: 60) { id } User { id } }"#;
+        assert_eq!(
+            get_cache_policy(&parse_query(uncovered)?, &None, None)?,
+            None
+        );
+        let invalid = r#"query { Post @cached(ttl: -1) { id } }"#;
+        let error = get_cache_policy(&parse_query(invalid)?, &None, None)
+            .expect_err("negative ttl should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "@cached argument \"ttl\" must be a non-negative integer at 1:14"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_insert_row_keys() -> Result<(), anyhow::Error> {
         let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
+            parse_query(
+                r#"mutation {
+                    insert(data: [
+                        { id: "u1", name: "Ada", age: 36 },
+                        { age: 45, name: "Grace", id: "u2" }
+                    ]) @meta(table: "User", insert: true) { id name age }
+                }"#,
+            )?,
+            &None,
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+
+        let query = r#"mutation AddUsers($data: [User_insert_input!]!) {
+            insert(data: $data) @meta(table: "User", insert: true) { id name age }
+        }"#;
+        let error = gql2sql(
+            parse_query(query)?,
             &Some(json!({
-                "orgId": "org",
-                "appId": "app",
-                "branch": "branch"
+                "data": [
+                    { "id": "u1", "name": "Ada", "age": 36 },
+                    { "id": "u2", "email": "grace@example.com" }
+                ]
             })),
             None,
-        )?;
-        assert_snapshot!(statement.to_string());
+        )
+        .expect_err("mismatched rows should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "Insert row 1 does not have the keys of the first row: missing age, name; unexpected email"
+        );
         Ok(())
     }
 
     #[test]
-    fn query_frag() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"query GetApp($componentId: String!, $branch: String!) {
-                component: Component_one(filter: { field: "id", operator: "eq", value: $componentId }) {
-                   id
-                   branch
-                   ... on ComponentMeta @relation(
-                        table: "ComponentMeta"
-                        field: ["componentId"]
-                        references: ["id"]
-                        single: true
-                    ) @args(
-                        filter: {
-                          field: "branch"
-                          operator: "eq",
-                          value: $branch,
-                          logicalOperator: "OR",
-                          children: [
-                            { field: "branch", operator: "eq", value: "main" }
-                          ]
-                        }
-                    ) {
-                     title
-                   }
-                }
-            }"#,
+    fn mutation_insert_defaults() -> Result<(), anyhow::Error> {
+        let query = r#"mutation AddUsers($data: [User_insert_input!]!) {
+            insert(data: $data) @meta(table: "User", insert: true) { id name createdAt }
+        }"#;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            parse_query(query)?,
+            &Some(json!({
+                "data": [
+                    { "id": "u1", "name": "Ada", "createdAt": "__default" },
+                    { "id": "u2", "name": "__default", "createdAt": "2024-01-02" }
+                ]
+            })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_eq!(params.map_or(0, |params| params.len()), 4);
+
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            parse_query(
+                r#"mutation {
+                    insert(data: [{ name: "Ada" }, { name: "Grace" }])
+                        @meta(table: "User", insert: true) @id(generate: "uuid") { id name }
+                }"#,
+            )?,
+            &None,
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let uuid = regex::Regex::new(
+            "^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$",
+        )?;
+        let ids = params
+            .unwrap_or_default()
+            .into_iter()
+            .map(|param| (param.sql_type, param.value.as_str().map(ToOwned::to_owned)))
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.iter().all(|(sql_type, id)| sql_type == "text"
+            && id.as_deref().is_some_and(|id| uuid.is_match(id))));
+        assert_ne!(ids[0], ids[1]);
+
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            parse_query(
+                r#"mutation {
+                    insert(data: { name: "Ada" })
+                        @meta(table: "User", insert: true) @id(generate: "nanoid", column: "key") { key }
+                }"#,
+            )?,
+            &None,
+            None,
         )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "componentId": "comp",
-                "branch": "branch"
-            })),
+        assert!(statement
+            .to_string()
+            .contains(r#"("name", "key") VALUES ('Ada', $1::text)"#));
+        let nanoid = regex::Regex::new("^[A-Za-z0-9_-]{21}$")?;
+        let params = params.unwrap_or_default();
+        assert!(params[0]
+            .value
+            .as_str()
+            .is_some_and(|id| nanoid.is_match(id)));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_update_operators() -> Result<(), anyhow::Error> {
+        let query = r#"mutation UpdatePost($tag: String, $meta: JSON) {
+            update(
+                id: "p1",
+                append: { tags: $tag, history: [{ event: "edited" }] },
+                prepend: { labels: "new", meta: $meta },
+                delete_key: { settings: "draft", flags: ["a", "b"] },
+                delete_at_path: { data: ["items", 0, "price"] }
+            ) @meta(table: "Post", update: true) { id }
+        }"#;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "tag": "rust", "meta": { "pinned": true } })),
             None,
         )?;
-        assert_snapshot!(statement.to_string());
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_eq!(
+            params.map(|params| params.into_iter().map(|p| p.value).collect::<Vec<_>>()),
+            Some(vec![json!("rust"), json!(true)])
+        );
         Ok(())
     }
 
     #[test]
-    fn query_static() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"query GetApp($componentId: String!) {
-                component: Component_one(filter: { field: "id", operator: "eq", value: $componentId }) {
-                   id
-                   branch
-                   kind @static(value: "page")
+    fn mutation_affected_rows() -> Result<(), anyhow::Error> {
+        let query = r#"mutation {
+            update(filter: { field: "published", operator: "eq", value: false }, set: { archived: true })
+                @meta(table: "Post", update: true) { id count: affected_rows }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let response = shape_response(
+            &parse_query(query)?,
+            &None,
+            None,
+            Some(json!({ "update": [{ "id": "p1", "archived": true }], "count": 1 })),
+            vec![],
+        )?;
+        assert_eq!(
+            response,
+            json!({ "data": { "update": [{ "id": "p1", "archived": true }], "count": 1 } })
+        );
+
+        let query = r#"mutation {
+            delete(filter: { field: "archived", operator: "eq", value: true })
+                @meta(table: "Post", delete: true) { affected_rows }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_nested_upsert() -> Result<(), anyhow::Error> {
+        let query = r#"mutation UpdateUser($bio: String) {
+            update(id: "u1", set: { name: "Ada", profile: { upsert: { bio: $bio, userId: "u2" } } })
+                @meta(table: "User", update: true) {
+                id
+                profile @relation(table: "Profile", field: ["userId"], references: ["id"], single: true) {
+                    bio
                 }
-            }"#,
+            }
+        }"#;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "bio": "Analyst" })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_eq!(params.map_or(0, |params| params.len()), 1);
+
+        let mut relations = RelationRegistry::default();
+        relations.insert(
+            "User",
+            "settings",
+            Relation {
+                table: "Settings".to_string(),
+                schema: None,
+                fields: vec!["ownerId".to_string()],
+                references: vec!["id".to_string()],
+                single: true,
+            },
+        );
+        let (statement, _params, _tags, _is_mutation) = translate(
+            parse_query(
+                r#"mutation {
+                    update(id: "u1", set: { settings: { upsert: { theme: "dark" } } })
+                        @meta(table: "User", update: true) { id }
+                }"#,
+            )?,
+            &None,
+            None,
+            &Options {
+                relations,
+                ..Options::default()
+            },
+            &mut vec![],
         )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+
         let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "componentId": "fake"
-            })),
+            parse_query(
+                r#"mutation {
+                    update(id: "u1", set: { profile: { upsert: { bio: "__default", title: "Analyst" } } })
+                        @meta(table: "User", update: true) {
+                        id
+                        profile @relation(table: "Profile", field: ["userId"], references: ["id"], single: true) {
+                            bio
+                        }
+                    }
+                }"#,
+            )?,
+            &None,
             None,
         )?;
-        assert_snapshot!(statement.to_string());
+        assert_snapshot!(pretty_sql(&statement.to_string()));
         Ok(())
     }
 
     #[test]
-    fn query_distinct() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"query GetApp($componentId: String!, $branch: String!) {
-                component: Component_one(
-                    filter: {
-                        field: "id",
-                        operator: "eq",
-                        value: $componentId
-                        logicalOperator: "AND",
-                        children: [
-                            { field: "branch", operator: "eq", value: $branch, logicalOperator: "OR", children: [
-                                { field: "branch", operator: "eq", value: "main" }
-                            ]}
-                        ]
-                    },
-                    order: [
-                        { orderKey: ASC }
-                    ],
-                    distinct: { on: ["id"], order: [{ expr: { field: "branch", operator: "eq", value: $branch }, dir: DESC }] }
-                ) {
-                   id
-                   branch
-                   kind @static(value: "page")
-                   stuff(filter: { field: "componentId", operator: "eq", value: { _parentRef: "id" } }) @relation(table: "Stuff") {
-                     id
-                   }
-                }
-            }"#,
-        )?;
+    fn mutation_nested_links() -> Result<(), anyhow::Error> {
+        let query = r#"mutation Retag($add: [String!]) {
+            update(id: "p1", set: { title: "Notes", tags: { connect: $add, disconnect: ["t2"] } })
+                @meta(table: "Post", update: true) {
+                id
+                tags @relation(table: "Tag", many: true) { name }
+            }
+        }"#;
         let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "componentId": "fake",
-                "branch": "branch",
-            })),
+            parse_query(query)?,
+            &Some(json!({ "add": ["t1", "t3"] })),
             None,
         )?;
-        assert_snapshot!(statement.to_string());
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+
+        let query = r#"mutation {
+            update(id: "p1", set: { tags: { set: ["t1"] } }) @meta(table: "Post", update: true) {
+                id
+                tags @relation(table: "Tag", many: true) { name }
+            }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
         Ok(())
     }
 
     #[test]
-    fn query_sub_agg() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"query GetData {
-                testing @meta(table: "UcwtYEtmmpXagcpcRiYKC") {
+    fn query_long_aliases() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            Workspace_2f9c1d7e4b8a6035e1d2c3b4(first: 1) {
+                id
+                Project_7a6b5c4d3e2f1a0b9c8d7e6f @relation(table: "Project_7a6b5c4d3e2f1a0b9c8d7e6f", field: ["workspaceId"], references: ["id"]) {
                     id
-                    created_at
-                    updated_at
-                    anothers @relation(table: "N8Ag4Vgad4rYwcRmMJhGR", fields: ["id"], reference:["xb8nemrkchVQgxkXkCPhE"], aggregate: true) {
-                        __typename
-                        count
-                        avg {
-                          __typename
-                          value
-                        }
-                    }
-                    stuff @relation(table: "iYrk3kyTqaDQrLgjDaE9n", fields: ["eT86hgrpFB49r7N6AXz63"], references: ["id"], single: true) {
+                    Task_0f1e2d3c4b5a69788796a5b4 @relation(table: "Task_0f1e2d3c4b5a69788796a5b4", field: ["projectId"], references: ["id"], single: true) {
                         id
                     }
                 }
-            }"#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
-        assert_snapshot!(statement.to_string());
+            }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        let sql = statement.to_string();
+        let identifiers = regex::Regex::new(r#""([^"]*)""#)?;
+        assert!(identifiers.captures_iter(&sql).all(|c| c[1].len() <= 63));
+        assert_snapshot!(pretty_sql(&sql));
         Ok(())
     }
 
     #[test]
-    fn query_schema_arg() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-              query GetSession($sessionToken: String!) {
-    session(
-        filter: {
-            field: "sessionToken"
-            operator: "eq"
-            value: $sessionToken
-        }
-    ) @meta(table: "sessions", single: true, schema: "auth") {
-        sessionToken
-        userId
-        expires
-        user2: user
-            @relation(
-                table: "users"
-                field: ["id"]
-                references: ["userId"]
-                single: true
-                schema: "auth"
-            ) {
-            id
-            name
-            email
-            emailVerified
-            image
+    fn query_merged_fields() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            App(id: "a1") {
+                id
+                pages: Page(filter: { field: "draft", operator: "eq", value: false }) @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                    id
+                }
+                name
+                pages: Page(filter: { field: "draft", operator: "eq", value: false }) @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                    id
+                    title
+                }
+            }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let conflict = r#"query {
+            App(id: "a1") {
+                pages: Page(filter: { field: "draft", operator: "eq", value: false }) @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                    id
+                }
+                pages: Page(filter: { field: "draft", operator: "eq", value: true }) @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                    id
+                }
+            }
+        }"#;
+        let error = gql2sql(parse_query(conflict)?, &None, None).unwrap_err();
+        assert!(error.to_string().starts_with("Fields \"pages\" conflict"));
+
+        let conditional = r#"query App($withTitle: Boolean!, $drafts: Boolean!) {
+            App(id: "a1") {
+                pages: Page(filter: { field: "draft", operator: "eq", value: false }) @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                    id
+                }
+                pages: Page(filter: { field: "draft", operator: "eq", value: false }) @relation(table: "Page", field: ["appId"], references: ["id"]) @include(if: $withTitle) {
+                    title
+                }
+                pages: Page(filter: { field: "draft", operator: "eq", value: true }) @relation(table: "Page", field: ["appId"], references: ["id"]) @include(if: $drafts) {
+                    id
+                }
+            }
+        }"#;
+        for (with_title, selects_title) in [(true, true), (false, false)] {
+            let (statement, _params, _tags, _is_mutation) = gql2sql(
+                parse_query(conditional)?,
+                &Some(json!({ "withTitle": with_title, "drafts": false })),
+                None,
+            )?;
+            let sql = statement.to_string();
+            assert_eq!(sql.contains(r#""title""#), selects_title);
+            assert!(sql.contains(r#""draft" = false"#));
         }
-    }
-}
-            "#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-              "sessionToken": "fake"
-            })),
+        let error = gql2sql(
+            parse_query(conditional)?,
+            &Some(json!({ "withTitle": true, "drafts": true })),
             None,
-        )?;
-        assert_snapshot!(statement.to_string());
+        )
+        .unwrap_err();
+        assert!(error.to_string().starts_with("Fields \"pages\" conflict"));
         Ok(())
     }
 
     #[test]
-    fn query_wrap_arg() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-                mutation CreateVerificationToken($data: [VerificationToken!]!) {
-                    insert(data: $data)
-                        @meta(table: "verification_tokens", insert: true, schema: "auth", single: true) {
-                        identifier
-                        token
-                        expires
+    fn query_column_pruning() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            Post(order: { createdAt: DESC }) {
+                title
+                author @relation(table: "User", field: ["id"], references: ["authorId"], single: true) {
+                    name
+                }
+            }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_flatten_single_joins() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            Post(order: { id: ASC }) {
+                title
+                author @relation(table: "User", field: ["id"], references: ["authorId"], single: true) {
+                    name
+                    profile @relation(table: "Profile", field: ["userId"], references: ["id"], single: true) {
+                        bio
+                    }
+                    posts: Post @relation(table: "Post", field: ["authorId"], references: ["id"]) {
+                        title
                     }
                 }
-            "#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-            "data": [{
-                "identifier": "nick@brevity.io",
-                "token": "da978cc2c1e0e7b61e1be31b2e3979af576e494d68bd6f5dc156084d9924ee12",
-                "expires": "2023-04-26T21:38:26"
-                }]
-            })),
-            None,
-        )?;
-        assert_snapshot!(statement.to_string());
+                latest: Post(order: { id: DESC }) @relation(table: "Post", field: ["authorId"], references: ["authorId"], single: true) {
+                    title
+                }
+            }
+        }"#;
+        let options = Options {
+            flatten_single_joins: true,
+            ..Options::default()
+        };
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &None, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
         Ok(())
     }
 
     #[test]
-    fn query_json_arg() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-                query BrevityQuery($order_getTodoList: tXY7bJTNXP7RAhLFGybN4d_Order, $filter: tXY7bJTNXP7RAhLFGybN4d_Filter) {
-                getTodoList(order: $order_getTodoList, filter: $filter) @meta(table: "tXY7bJTNXP7RAhLFGybN4d") {
+    fn query_predicate_pushdown() -> Result<(), anyhow::Error> {
+        let query = r#"query GetApp($appId: String!, $branch: String!) {
+            app: App_one(id: $appId, branch: $branch) {
+                id
+                components: Component @relation(table: "Component", field: ["appId", "branch"], references: ["id", "branch"]) {
                     id
-                    cJ9jmpnjfYhRbCQBpWAzB8
-                    cPQdcYiWcPWWVeKVniUMjy
-                }
+                    elements: Element @relation(table: "Element", field: ["componentId", "branch"], references: ["id", "branch"]) {
+                        id
+                    }
                 }
-            "#,
-        )?;
-        // let sql = r#""#;
-        let (_statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "order_getTodoList": {
-                    "cPQdcYiWcPWWVeKVniUMjy": "ASC"
-                },
-                "filter": null
-            })),
+            }
+        }"#;
+        let variables = Some(json!({ "appId": "a1", "branch": "main" }));
+        let options = Options {
+            key_columns: Some(vec!["id".to_string(), "branch".to_string()]),
+            ..Options::default()
+        };
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &variables, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_date_policy() -> Result<(), anyhow::Error> {
+        let query = r#"query($after: String, $day: Date, $at: Time, $tz: Time, $help: String) {
+            Event(filter: {
+                field: "startsAt", operator: "gte", value: $after,
+                children: [
+                    { field: "day", operator: "eq", value: $day },
+                    { field: "opensAt", operator: "eq", value: $at },
+                    { field: "closesAt", operator: "eq", value: $tz },
+                    { field: "help", operator: "eq", value: $help }
+                ]
+            }) {
+                id
+            }
+        }"#;
+        let variables = Some(json!({
+            "after": "2024-01-02T03:04:05",
+            "day": "2024-01-02",
+            "at": "09:30:00",
+            "tz": "17:00:00+02:00",
+            "help": "2024-01-02"
+        }));
+        let bindings = |params: Option<Vec<Param>>| {
+            params
+                .unwrap_or_default()
+                .into_iter()
+                .map(|p| (p.value, p.sql_type))
+                .collect::<Vec<_>>()
+        };
+        let text = |value: &str| (json!(value), "text".to_string());
+        // the values are bound as they are, dates and times as text
+        let (_statement, params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &variables, None, &Options::default())?;
+        assert_eq!(
+            bindings(params),
+            [
+                (json!("2024-01-02T03:04:05"), "timestamptz".to_string()),
+                text("2024-01-02"),
+                text("09:30:00"),
+                text("17:00:00+02:00"),
+                text("2024-01-02")
+            ]
+        );
+        let options = Options {
+            date_policy: DatePolicy {
+                timestamps: TimestampPolicy::AssumeUtc,
+                dates_and_times: true,
+            },
+            ..Options::default()
+        };
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &variables, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let day = (json!("2024-01-02"), "date".to_string());
+        let at = (json!("09:30:00"), "time".to_string());
+        let tz = (json!("17:00:00+02:00"), "timetz".to_string());
+        // a variable declared String stays text
+        assert_eq!(
+            bindings(params),
+            [
+                (json!("2024-01-02T03:04:05.000Z"), "timestamptz".to_string()),
+                day.clone(),
+                at.clone(),
+                tz.clone(),
+                text("2024-01-02")
+            ]
+        );
+        let options = Options {
+            date_policy: DatePolicy {
+                timestamps: TimestampPolicy::Passthrough,
+                dates_and_times: true,
+            },
+            ..Options::default()
+        };
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &variables, None, &options)?;
+        assert!(statement.to_string().contains("$1::timestamp "));
+        assert_eq!(
+            bindings(params),
+            [
+                (json!("2024-01-02T03:04:05"), "timestamp".to_string()),
+                day.clone(),
+                at.clone(),
+                tz.clone(),
+                text("2024-01-02")
+            ]
+        );
+        let options = Options {
+            date_policy: DatePolicy {
+                timestamps: TimestampPolicy::AssumeOffset("+02:00".to_string()),
+                dates_and_times: true,
+            },
+            ..Options::default()
+        };
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &variables, None, &options)?;
+        assert!(statement.to_string().contains("$1::timestamptz "));
+        assert_eq!(
+            bindings(params),
+            [
+                (
+                    json!("2024-01-02T03:04:05+02:00"),
+                    "timestamptz".to_string()
+                ),
+                day,
+                at,
+                tz,
+                text("2024-01-02")
+            ]
+        );
+        // a date in a list of timestamps is bound as it is
+        let list = r#"query($days: [String!]) {
+            Event(filter: { field: "startsAt", operator: "in", value: $days }) {
+                id
+            }
+        }"#;
+        let days = Some(json!({ "days": ["2024-01-02T03:04:05", "2024-01-02"] }));
+        let (_statement, params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(list)?, &days, None, &options)?;
+        assert_eq!(
+            bindings(params),
+            [(
+                json!(["2024-01-02T03:04:05+02:00", "2024-01-02"]),
+                "timestamptz[]".to_string()
+            )]
+        );
+        assert_eq!(
+            detect_date_with("2024-01-02T03:04:05", &options.date_policy),
+            Some(("2024-01-02T03:04:05+02:00".to_string(), "timestamptz"))
+        );
+        assert_eq!(detect_date_with("2024-01-02", &DatePolicy::default()), None);
+        assert_eq!(
+            detect_date("2024-01-02T03:04:05.5").as_deref(),
+            Some("2024-01-02T03:04:05.5Z")
+        );
+        let options = Options {
+            date_policy: DatePolicy {
+                timestamps: TimestampPolicy::AssumeOffset("CET".to_string()),
+                dates_and_times: false,
+            },
+            ..Options::default()
+        };
+        let error = gql2sql_with_options(parse_query(query)?, &variables, None, &options)
+            .expect_err("offset should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "datePolicy offset must be Z or +HH:MM, got CET"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_relative_dates() -> Result<(), anyhow::Error> {
+        let query = r#"query($window: String, $since: String) {
+            Event(filter: {
+                field: "createdAt", operator: "within_last", interval: $window,
+                children: [
+                    { field: "startsAt", operator: "lt", value: { relative: "now+1h" } },
+                    { field: "updatedAt", operator: "gte", value: { relative: $since } },
+                    { field: "endsAt", operator: "gt", value: { relative: "now" } },
+                    { field: "seenAt", operator: "within_last", interval: "30 minutes" }
+                ]
+            }) {
+                id
+            }
+        }"#;
+        let variables = Some(json!({ "window": "7 days", "since": "now-2w" }));
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql(parse_query(query)?, &variables, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let params = params.unwrap_or_default();
+        assert_eq!(params.len(), 1);
+        assert_eq!(params[0].sql_type, "interval");
+        let error = gql2sql(
+            parse_query(r#"query { Event(filter: { field: "a", operator: "eq", value: { relative: "yesterday" } }) { id } }"#)?,
+            &None,
             None,
-        )?;
-        // assert_eq!(statement.to_string(), sql);
+        )
+        .expect_err("relative date should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "relative date must look like now-7d, got yesterday"
+        );
         Ok(())
     }
 
     #[test]
-    fn query_simple_filter() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-                query Test($id: String!) {
-                    record(id: $id) @meta(table: "Record") {
-                        id
-                        name
-                        age
-                    }
+    fn query_negated_filters() -> Result<(), anyhow::Error> {
+        let query = r#"query($owner: String, $tag: String) {
+            Task(filter: {
+                field: "status", operator: "eq", value: "done",
+                logicalOperator: "NOT",
+                children: [
+                    { field: "ownerId", operator: "eq", value: $owner },
+                    { not: { field: "archived", operator: "eq", value: true } },
+                    { not: { field: "tag", operator: "eq", value: $tag, ignoreEmpty: true } }
+                ]
+            }) {
+                id
+            }
+            Project(filter: { not: { field: "name", operator: "like", value: "tmp%" } }) {
+                id
+            }
+        }"#;
+        let variables = Some(json!({ "owner": "u1" }));
+        let (statement, _params, tags, _is_mutation) =
+            gql2sql(parse_query(query)?, &variables, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert!(!tags
+            .unwrap_or_default()
+            .iter()
+            .any(|tag| tag.to_string().contains("done")));
+        Ok(())
+    }
+
+    #[test]
+    fn query_relation_filters() -> Result<(), anyhow::Error> {
+        let query = r#"query($kind: String) {
+            Page(filter: {
+                field: "components", relation: {
+                    some: {
+                        field: "type", operator: "eq", value: $kind,
+                        children: [{ field: "props", relation: { none: { field: "hidden", operator: "eq", value: true } } }]
+                    },
+                    every: { field: "version", operator: "gte", value: 2, ignoreEmpty: true }
+                },
+                children: [{ field: "Owner", relation: { some: {} } }]
+            }) {
+                id
+                components(filter: { field: "props", relation: { some: {} } })
+                    @relation(table: "Component", field: ["pageId"], references: ["id"]) {
+                    type @column(name: "kind")
                 }
-            "#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "id": "fake"
-            })),
+            }
+        }"#;
+        let mut relations = RelationRegistry::default();
+        relations.insert(
+            "Page",
+            "Owner",
+            Relation {
+                table: "User".to_string(),
+                schema: Some("auth".to_string()),
+                fields: vec!["id".to_string()],
+                references: vec!["ownerId".to_string()],
+                single: true,
+            },
+        );
+        relations.insert(
+            "Component",
+            "props",
+            Relation {
+                table: "Prop".to_string(),
+                schema: None,
+                fields: vec!["componentId".to_string()],
+                references: vec!["id".to_string()],
+                single: false,
+            },
+        );
+        let options = Options {
+            relations,
+            ..Options::default()
+        };
+        let variables = Some(json!({ "kind": "page" }));
+        let (statement, _params, tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &variables, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let tags = tags
+            .unwrap_or_default()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert!(tags.contains(&"type:Prop".to_string()));
+        assert!(tags.contains(&"type:auth.User".to_string()));
+        let error = gql2sql(
+            parse_query(
+                r#"query { Page(filter: { field: "tags", relation: { some: {} } }) { id } }"#,
+            )?,
+            &None,
             None,
-        )?;
-        assert_snapshot!(statement.to_string());
+        )
+        .expect_err("unknown relation should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "relation filter on tags needs a @relation directive"
+        );
+        // a failing child fails the filter instead of matching every row
+        let error = gql2sql(
+            parse_query(
+                r#"query { Page(filter: { field: "id", operator: "eq", value: 1, children: [
+                    { field: "tags", relation: { some: {} } }
+                ] }) { id } }"#,
+            )?,
+            &None,
+            None,
+        )
+        .expect_err("unknown relation in a child should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "relation filter on tags needs a @relation directive"
+        );
         Ok(())
     }
 
     #[test]
-    fn query_many_to_many() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-                query ManyToMany($id: String!) {
-                    currentUser(id: $id) @meta(table: "User") {
-                        id
-                        lists @relation(table: "wrHJEgwMUmdJ3eWtPLPk8", many: true) {
-                            id
-                        }
+    fn query_relation_aggregate_filters() -> Result<(), anyhow::Error> {
+        let query = r#"query($min: Int) {
+            Post(filter: {
+                relation: "comments", aggregate: "count", operator: "gt", value: 10,
+                children: [
+                    {
+                        relation: "comments", aggregate: "max", field: "score", operator: "gte", value: $min,
+                        filter: { field: "approved", operator: "eq", value: true }
                     }
+                ]
+            }) {
+                id
+                comments @relation(table: "Comment", field: ["postId"], references: ["id"]) {
+                    score @column(name: "points")
                 }
-            "#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-                "id": "fake"
-            })),
+            }
+        }"#;
+        let variables = Some(json!({ "min": 5 }));
+        let (statement, _params, tags, _is_mutation) =
+            gql2sql(parse_query(query)?, &variables, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert!(tags
+            .unwrap_or_default()
+            .iter()
+            .any(|tag| tag.table == "Comment" && tag.key.is_none()));
+        let error = gql2sql(
+            parse_query(
+                r#"query { Post(filter: { relation: "comments", aggregate: "sum", operator: "gt", value: 1 }) {
+                    id
+                    comments @relation(table: "Comment", field: ["postId"], references: ["id"]) { id }
+                } }"#,
+            )?,
+            &None,
             None,
-        )?;
-        assert_snapshot!(statement.to_string());
+        )
+        .expect_err("sum without a field should be rejected");
+        assert_eq!(error.to_string(), "aggregate filter sum is missing field");
         Ok(())
     }
 
     #[test]
-    fn query_andre() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-            query BrevityQuery($id_getH33iDwNVqqMxAnVEgPaThById: ID) {
-            getH33iDwNVqqMxAnVEgPaThById(id: $id_getH33iDwNVqqMxAnVEgPaThById)
-                @meta(table: "H33iDwNVqqMxAnVEgPaTh", single: true) {
-                d8GJJg9DjNehPAeJcpTjM
-                Fjjm3XAhyDmbhzymrrkRT_Aggregate
-                @relation(
-                    table: "Fjjm3XAhyDmbhzymrrkRT"
-                    fields: ["id"]
-                    aggregate: true
-                    references: ["TbFeY8XVMaYnkQjDPWMkb_id"]
-                ) {
-                avg {
-                    XF4f6Qrhk86AX6dFWjYDt
-                }
-                }
-                q6pJYTjmbprTNRdqG9Jrw
-                egeyQ33H3z4EqzcRVFchV
-                HYWfawTyxPNUf9a4DAH79
-                H33iDwNVqqMxAnVEgPaTh_by_MdYg7jdht8ByhnKdfXBAb
-                @relation(
-                    table: "MdYg7jdht8ByhnKdfXBAb"
-                    fields: ["id"]
-                    single: true
-                    references: ["MiyNcUJzKGJgQ9BERD8fr_id"]
-                ) {
-                H6hp6JGhzgPTYmLYwLk8P
+    fn query_dedupe_joins() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            Step {
                 id
+                template: Template @relation(table: "Template", field: ["id"], references: ["template_BahPd_id"], single: true) {
+                    name
                 }
-                zFjEBPkLYmEAxLHrt3N4B
-                LJDX6neXAYeXt9aVWxTRk
-                FwpKpCegQH4EkzbjbNqVn
-                ayipLT8iKHNTdhmiVqmxq
-                Mr3R877DKbWTNWRzmEjxE_Aggregate
-                @relation(many: true, table: "Mr3R877DKbWTNWRzmEjxE", aggregate: true) {
-                count
+                templateName: Template @relation(table: "Template", field: ["id"], references: ["template_BahPd_id"], single: true) {
+                    name
                 }
-                r7xwAFrckDaVLwPzUAADB
-                H33iDwNVqqMxAnVEgPaTh_by_User
-                @relation(
-                    table: "User"
-                    fields: ["id"]
-                    single: true
-                    references: ["Gb8jAGqGDbYqfeqDDxKUF_id"]
-                ) {
-                gnHezR9MdBFH9kCthN3aB
-                created_at
-                id
+                templateId: Template @relation(table: "Template", field: ["id"], references: ["template_BahPd_id"], single: true) {
+                    id
                 }
+            }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_explain() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            Post(filter: { field: "published", operator: "eq", value: true }) {
                 id
+                author @relation(table: "User", field: ["id"], references: ["authorId"], single: true) {
+                    name
+                }
+                comments @relation(table: "Comment", field: ["postId"], references: ["id"]) {
+                    body
+                }
             }
+            Post_aggregate {
+                count
             }
-            "#,
-        )?;
-        let (statement, params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({
-              "id_getH33iDwNVqqMxAnVEgPaThById": "HAzqFfhQGbaB6WKBr6LA7"
-            })),
-            None,
-        )?;
-        assert_snapshot!(statement.to_string());
-        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        }"#;
+        let query_plan = plan(parse_query(query)?, &None, None, &Options::default())?;
+        assert_snapshot!(serde_json::to_string_pretty(&query_plan.explain())?);
+        assert_snapshot!(query_plan.to_dot());
         Ok(())
     }
 
     #[test]
-    fn mutation_delete() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-            mutation DeleteVerificationToken(
-                $identifier: String!
-                $token: String!
-                ) {
-                delete(
-                    filter: {
-                    field: "identifier"
-                    operator: "eq"
-                    value: $identifier
-                    logicalOperator: "AND"
-                    children: [{ field: "token", operator: "eq", value: $token }]
+    fn query_json_fields() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            App {
+                id
+                colors: settings @json(path: "theme.colors")
+                font: settings @json(path: "theme") {
+                    family
+                    size: px
+                }
+                metadata {
+                    owner {
+                        name
                     }
-                ) @meta(table: "verification_tokens", delete: true, schema: "auth") {
-                    identifier
-                    token
-                    expires
+                    version
                 }
             }
-            "#,
-        )?;
-        let (statement, _params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({ "token": "12345", "identifier": "fake@email.com" })),
+        }"#;
+        let options = Options {
+            json_columns: HashMap::from([("App".to_string(), vec!["metadata".to_string()])]),
+            ..Options::default()
+        };
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &None, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let error = gql2sql(
+            parse_query(r#"query { App { colors: settings @json(path: "theme.{colors}") } }"#)?,
+            &None,
             None,
-        )?;
-        assert_snapshot!(statement.to_string());
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("@json path must be"));
         Ok(())
     }
 
     #[test]
-    fn mutation_image() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-            mutation Update($id: String!, $set: dogUpdateInput!) {
-                update(
-                  filter: {
-                    field: "id"
-                    operator: "eq"
-                    value: $id
-                  }
-                  set: $set
-                ) @meta(table: "WFqGH6dk8MpxfpHXh7awi", update: true) {
-                  id
-                }
-              }
-            "#,
-        )?;
-        let (statement, params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(
-                json!({"id":"ffj9ACLQqpzjyh8yNFeQ6","set":{"updated_at":"2023-06-06T19:41:47+00:00","ynWfqMzGjjVQYzbKx4rMX":"DOGGY","QYtpTcmJCe6zfCHWwpNjR":"MYDOG","a8heQgUMyFync44JACwKA":{"src":"https://assets.brevity.io/uploads/jwy1g8rs7bxr9ptkaf6sy/lp_image-1685987665741.png","width":588,"height":1280}}}),
-            ),
+    fn query_order_collate_numeric() -> Result<(), anyhow::Error> {
+        let query = r#"query Files($natural: Boolean) {
+            File(order: { name: { dir: ASC, collate: "C", numeric: $natural }, size: { dir: DESC } }) {
+                name
+                size
+            }
+        }"#;
+        let variables = Some(json!({ "natural": true }));
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql(parse_query(query)?, &variables, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert!(params.is_none());
+        let error = gql2sql(
+            parse_query(r#"query { File(order: { name: { dir: UP } }) { name } }"#)?,
+            &None,
             None,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("order dir must be ASC or DESC"));
+        Ok(())
+    }
+
+    #[test]
+    fn query_sql_comment() -> Result<(), anyhow::Error> {
+        let query = r#"query GetApp($org: String) {
+            App(filter: { field: "id", operator: "eq", value: "a1" }) {
+                id
+            }
+        }"#;
+        let options = Options {
+            comment: Some(SqlComment {
+                tags: true,
+                values: [
+                    ("tenant".to_string(), "$org".to_string()),
+                    ("region".to_string(), "$region".to_string()),
+                    ("app".to_string(), "web*/1".to_string()),
+                ]
+                .into(),
+            }),
+            ..Options::default()
+        };
+        let variables = Some(json!({ "org": "org*/1" }));
+        let ast = parse_query(query)?;
+        let operation_name = get_operation_name(&ast, None);
+        assert_eq!(operation_name.as_deref(), Some("GetApp"));
+        let (statement, _params, tags, _is_mutation) =
+            gql2sql_with_options(ast, &variables, None, &options)?;
+        let sql = to_commented_sql(
+            &statement,
+            operation_name.as_deref(),
+            &variables,
+            tags.as_deref(),
+            &options,
+        );
+        assert_snapshot!(sql);
+        assert!(sql.ends_with(&statement.to_string()));
+        let sql = to_commented_sql(&statement, None, &variables, None, &Options::default());
+        assert_eq!(sql, statement.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn query_local_settings() -> Result<(), anyhow::Error> {
+        let options = Options {
+            local_settings: vec![
+                LocalSettings {
+                    statement_timeout_ms: Some(2000),
+                    ..LocalSettings::default()
+                },
+                LocalSettings {
+                    min_nodes: 5,
+                    statement_timeout_ms: Some(10000),
+                    work_mem: Some("64MB".to_string()),
+                    values: [("enable_nestloop".to_string(), "off".to_string())].into(),
+                },
+            ],
+            ..Options::default()
+        };
+        let (statements, ..) =
+            gql2sql_statements(parse_query("query { App { id } }")?, &None, None, &options)?;
+        assert_eq!(statements.len(), 2);
+        assert_eq!(
+            statements[0].to_string(),
+            "SET LOCAL statement_timeout = 2000"
+        );
+        let (statements, ..) = gql2sql_statements(
+            parse_query("query { App { id name slug ownerId createdAt } }")?,
+            &None,
+            None,
+            &options,
         )?;
-        assert_snapshot!(statement.to_string());
-        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        assert_snapshot!(statements
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(";\n"));
         Ok(())
     }
+
     #[test]
-    fn nested_query() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-                query BrevityQuery($id_getU7BBKiUwTgwiWMcgUYA4CById: ID) {
-                getU7BBKiUwTgwiWMcgUYA4CById(id: $id_getU7BBKiUwTgwiWMcgUYA4CById) @meta(table: "U7BBKiUwTgwiWMcgUYA4C", single: true) {
-                    BtaHL8fRtKFw8gDJULFYp
-                    WFqGH6dk8MpxfpHXh7awi_by_U7BBKiUwTgwiWMcgUYA4C @relation(table: "WFqGH6dk8MpxfpHXh7awi", fields: ["MHPB9NP84gr3eXBmBfbxh_id"], references: ["id"]) {
-                    ynWfqMzGjjVQYzbKx4rMX
-                    QYtpTcmJCe6zfCHWwpNjR
-                    MHPB9NP84gr3eXBmBfbxh_id @relation(table: "U7BBKiUwTgwiWMcgUYA4C", fields: ["id"], single: true, references: ["MHPB9NP84gr3eXBmBfbxh_id"]) {
-                        id
-                        __typename
-                    }
+    fn query_schema_routing() -> Result<(), anyhow::Error> {
+        let query = r#"query GetApp($schema: String!) {
+            App @meta(schema: $schema) {
+                id
+                components @relation(table: "Component", schema: $schema, field: ["appId"], references: ["id"]) {
                     id
+                    owner @relation(table: "User", field: ["id"], references: ["ownerId"], single: true) {
+                        name
                     }
-                    id
                 }
+            }
+            Plan {
+                id
+            }
+        }"#;
+        let options = Options {
+            table_schemas: HashMap::from([
+                ("User".to_string(), "auth".to_string()),
+                ("Plan".to_string(), "billing".to_string()),
+            ])
+            .into(),
+            ..Options::default()
+        };
+        let variables = Some(json!({ "schema": "tenant_42" }));
+        let (statement, _params, tags, _is_mutation) =
+            gql2sql_with_options(parse_query(query)?, &variables, None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert!(tags
+            .unwrap_or_default()
+            .iter()
+            .any(|tag| tag.table == "tenant_42.App"));
+        let error = gql2sql(
+            parse_query(r#"query Q($schema: Int) { App @meta(schema: $schema) { id } }"#)?,
+            &Some(json!({ "schema": 1 })),
+            None,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("must be a string or string variable"));
+        // a resolver computes the schema of each table, here that of the tenant
+        let tenant = "tenant_42".to_string();
+        let options = Options {
+            table_schemas: TableSchemas::new(move |table: &str| {
+                (table != "User").then(|| tenant.clone())
+            }),
+            ..Options::default()
+        };
+        let (_statement, _params, tags, _is_mutation) =
+            gql2sql_with_options(parse_query("query { Plan { id } }")?, &None, None, &options)?;
+        assert!(tags
+            .unwrap_or_default()
+            .iter()
+            .any(|tag| tag.table == "tenant_42.Plan"));
+        Ok(())
+    }
+
+    #[test]
+    fn query_sort_params() -> Result<(), anyhow::Error> {
+        let options = Options {
+            sort_params: true,
+            ..Options::default()
+        };
+        let variables = Some(json!({ "name": "crm", "limit": 5, "offset": 10 }));
+        let translate =
+            |query: &str| gql2sql_with_options(parse_query(query)?, &variables, None, &options);
+        let (statement, params, _tags, _is_mutation) = translate(
+            r#"query Apps($name: String, $limit: Int, $offset: Int) {
+                App(filter: { field: "name", operator: "eq", value: $name }, limit: $limit, offset: $offset) {
+                    id
                 }
-            "#,
+            }"#,
         )?;
-        let (statement, params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({ "id_getU7BBKiUwTgwiWMcgUYA4CById": "piWkMrFFXgdQBBkzf84MD" })),
-            None,
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let (reordered, reordered_params, _tags, _is_mutation) = translate(
+            r#"query Apps($name: String, $limit: Int, $offset: Int) {
+                App(offset: $offset, limit: $limit, filter: { field: "name", operator: "eq", value: $name }) {
+                    id
+                }
+            }"#,
         )?;
-        assert_snapshot!(statement.to_string());
-        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        assert_eq!(reordered.to_string(), statement.to_string());
+        assert_eq!(reordered_params, params);
         Ok(())
     }
+
     #[test]
-    fn group_by_query() -> Result<(), anyhow::Error> {
+    fn query_aggregate_aliases() -> Result<(), anyhow::Error> {
         let gqlast = parse_query(
-            r#"
-                query BrevityQuery($groupBy: [String]) {
-                    Event(filter: { field: "xVAFwi3LkLnRYqtkV3e9A_id", operator: "eq", value: "ge3xraXEcwPTF6hJxLXC7" }, groupBy: $groupBy) @meta(table: "LC4PdkWrXEq6PnJNF98RE", aggregate: true) {
-                        value {
-                          W3htYNGnCaJp4MAp6p6c9_id @relation(table: "AQfNfkgxq4iLcAhkdNAWf", fields: ["id"], references: ["W3htYNGnCaJp4MAp6p6c9_id"], single: true) {
-                            id
-                            name: QJ3MwMUiXqrkPwb88eW8g
-                          }
-                          t473xCb8nhWCxX7Ag7k6q_id @relation(table: "fTgjFRxYgaj3qHriEdQi3", fields: ["id"], references: ["t473xCb8nhWCxX7Ag7k6q_id"], single: true) {
-                            id
-                            title: tcGyWe4CLwhpTJp4krApd
-                          }
-                        }
-                        count
+            r#"query Signups {
+                stats: Signup_aggregate {
+                    total: count
+                    newest: max {
+                        created: createdAt
                     }
                 }
-            "#,
-        )?;
-        let (statement, params, _tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!({ "groupBy": ["W3htYNGnCaJp4MAp6p6c9_id", "t473xCb8nhWCxX7Ag7k6q_id"] })),
-            None,
+                byPlan: Signup_aggregate(groupBy: ["plan"]) {
+                    group: value {
+                        tier: plan
+                    }
+                    signups: count
+                }
+            }"#,
         )?;
-        assert_snapshot!(statement.to_string());
-        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
         Ok(())
     }
+
     #[test]
-    fn nested_playground() -> Result<(), anyhow::Error> {
-        let gqlast = parse_query(
-            r#"
-            query BrevityDBQuery(
-  $playbook_id: String!
-  $template_BahPd_id_order: [boardcolumn_Order]
-  $playbook_LFc9r_id_order: [boardcolumn_Order]
-  $playbook_playbook_id_order: [playbookstandard_Order]
-  $boardrow_row_id_filter: boardcell_Filter
-  $boardrow_row_id_distinct: boardcell_Distinct
-  $workflows_Kdda9_id_order: [approvalworkflow_cqaw9_Order]
-) {
-  playbook: getplaybookById(id: $playbook_id)
-    @meta(table: "playbook", single: true) {
-    __typename
-    id
-    name
-    created_at
-    updated_at
-    folder_UEiw4_id {
-      id
-    }
-    workspace_VTGmA_id {
-      id
-    }
-    ownercolumn_3hJaT_id
-      @relation(
-        table: "boardcolumn"
-        fields: ["id"]
-        single: true
-        references: ["ownercolumn_3hJaT_id"]
-      ) {
-      __typename
-      id
-      created_at
-      name_CwYar
-      type_Hbagk
-      updated_at
-      order_WUkJE
-      width_xJ846
-      board_3jDDw_id {
-        id
-      }
-      required_4jaR4
-      isdefault_KCmRr
-      temporary_4NyhY
-      playbook_LFc9r_id {
-        id
-      }
-      template_BahPd_id {
-        id
-      }
-    }
-    playbook_template_id
-      @relation(
-        table: "template"
-        fields: ["id"]
-        single: true
-        references: ["playbook_template_id"]
-      ) {
-      __typename
-      id
-      title
-      created_at
-      updated_at
-      organization_organization_id {
-        id
-      }
-      defaultdescriptioncolumn_kMT8n_id {
-        id
-      }
-      template_BahPd_id(order: $template_BahPd_id_order)
-        @relation(
-          table: "boardcolumn"
-          fields: ["template_BahPd_id"]
-          references: ["id"]
-        ) {
-        __typename
-        id
-        created_at
-        name_CwYar
-        type_Hbagk
-        updated_at
-        order_WUkJE
-        width_xJ846
-        board_3jDDw_id {
-          id
-        }
-        required_4jaR4
-        isdefault_KCmRr
-        temporary_4NyhY
-        playbook_LFc9r_id {
-          id
-        }
-        template_BahPd_id {
-          id
-        }
-        column_Xdjyz_id
-          @relation(
-            table: "boardcolumnoptions_mrX6T"
-            fields: ["column_Xdjyz_id"]
-            references: ["id"]
-          ) {
-          __typename
-          id
-          created_at
-          name_bFeAf
-          updated_at
-          column_Xdjyz_id {
-            id
-          }
-        }
-      }
-    }
-    statuscolumn_fFigH_id
-      @relation(
-        table: "boardcolumn"
-        fields: ["id"]
-        single: true
-        references: ["statuscolumn_fFigH_id"]
-      ) {
-      __typename
-      id
-      created_at
-      name_CwYar
-      type_Hbagk
-      updated_at
-      order_WUkJE
-      width_xJ846
-      board_3jDDw_id {
-        id
-      }
-      required_4jaR4
-      isdefault_KCmRr
-      temporary_4NyhY
-      playbook_LFc9r_id {
-        id
-      }
-      template_BahPd_id {
-        id
-      }
-    }
-    duedatecolumn_Qajep_id
-      @relation(
-        table: "boardcolumn"
-        fields: ["id"]
-        single: true
-        references: ["duedatecolumn_Qajep_id"]
-      ) {
-      __typename
-      id
-      created_at
-      name_CwYar
-      type_Hbagk
-      updated_at
-      order_WUkJE
-      width_xJ846
-      board_3jDDw_id {
-        id
-      }
-      required_4jaR4
-      isdefault_KCmRr
-      temporary_4NyhY
-      playbook_LFc9r_id {
-        id
-      }
-      template_BahPd_id {
-        id
-      }
+    fn query_group_by_validation() -> Result<(), anyhow::Error> {
+        let translate = |query: &str| gql2sql(parse_query(query)?, &None, None);
+        let (statement, _params, _tags, _is_mutation) = translate(
+            r#"query {
+                Signup_aggregate(groupBy: ["ownerId"]) {
+                    value {
+                        ownerId @relation(table: "User", fields: ["uid"], references: ["ownerId"], single: true) {
+                            name
+                        }
+                    }
+                    count
+                }
+            }"#,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let error = translate(
+            r#"query { Signup_aggregate(groupBy: ["plan"]) { value { plan } region count } }"#,
+        )
+        .expect_err("ungrouped field should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "region is neither grouped nor aggregated, select it in value and groupBy at 1:62"
+        );
+        let error =
+            translate(r#"query { Signup_aggregate(groupBy: ["a\"b"]) { value { plan } count } }"#)
+                .expect_err("column should be rejected");
+        assert_eq!(error.to_string(), r#"Invalid groupBy column: a"b"#);
+        let error = translate(r#"query { Signup_aggregate(groupBy: [1]) { count } }"#)
+            .expect_err("number should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "groupBy must be a list of column names or buckets, got 1"
+        );
+        let error = translate(
+            r#"query { Signup_aggregate(groupBy: ["plan", "plan"]) { value { plan } count } }"#,
+        )
+        .expect_err("repeated column should be rejected");
+        assert_eq!(error.to_string(), "groupBy lists plan more than once");
+        let error = translate(
+            r#"query {
+                Signup_aggregate(groupBy: ["ownerId"]) {
+                    value { ownerId @relation(table: "User", fields: ["id"], references: ["ownerId"]) { name } }
+                    count
+                }
+            }"#,
+        )
+        .expect_err("many relation should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "groupBy value ownerId must be a single relation at 3:29"
+        );
+        Ok(())
     }
-    descriptioncolumn_nNkVP_id
-      @relation(
-        table: "boardcolumn"
-        fields: ["id"]
-        single: true
-        references: ["descriptioncolumn_nNkVP_id"]
-      ) {
-      __typename
-      id
-      created_at
-      name_CwYar
-      type_Hbagk
-      updated_at
-      order_WUkJE
-      width_xJ846
-      board_3jDDw_id {
-        id
-      }
-      required_4jaR4
-      isdefault_KCmRr
-      temporary_4NyhY
-      playbook_LFc9r_id {
-        id
-      }
-      template_BahPd_id
-        @relation(
-          table: "template"
-          fields: ["id"]
-          single: true
-          references: ["template_BahPd_id"]
-        ) {
-        __typename
-        id
-        title
-        created_at
-        updated_at
-        organization_organization_id {
-          id
-        }
-        defaultdescriptioncolumn_kMT8n_id {
-          id
-        }
-      }
+
+    #[test]
+    fn query_group_by_rollup() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Sales($sets: [[String]]) {
+                byRegion: Sale_aggregate(groupBy: { rollup: ["region", "product"] }, order: { region: ASC }) {
+                    value {
+                        region
+                        product
+                        level: grouping
+                    }
+                    sum {
+                        amount
+                    }
+                }
+                byMonth: Sale_aggregate(groupBy: { cube: ["region", { field: "soldAt", bucket: "month" }] }) {
+                    value {
+                        region
+                        soldAt
+                        grouping
+                    }
+                    count
+                }
+                bySet: Sale_aggregate(groupBy: { groupingSets: $sets }) {
+                    value {
+                        region
+                        product
+                    }
+                    count
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(json!({ "sets": [["region"], ["product"], []] })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
     }
-    playbook_id
-      @relation(
-        table: "folderitem"
-        fields: ["playbook_id"]
-        single: true
-        references: ["id"]
-      ) {
-      __typename
-      id
-      board_id {
-        id
-      }
-      folder_id {
-        id
-      }
-      created_at
-      updated_at
-      playbook_id {
-        id
-      }
-      okr_ByTYz_id {
-        id
-      }
-      dashboard_zzwnp_id {
-        id
-      }
+
+    #[test]
+    fn query_literal_escaping() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query {
+                User(filter: { field: "name", operator: "eq", value: "it's", logicalOperator: "OR", children: [
+                    { field: "name", operator: "eq", value: "''" },
+                    { field: "name", operator: "eq", value: "a\\b" },
+                    { field: "name", operator: "eq", value: "a\\'b" },
+                    { field: "name", operator: "eq", value: "a\\\\" }
+                ] }) {
+                    id
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(statement.to_string());
+        Ok(())
     }
-    playbook_LFc9r_id(order: $playbook_LFc9r_id_order)
-      @relation(
-        table: "boardcolumn"
-        fields: ["playbook_LFc9r_id"]
-        references: ["id"]
-      ) {
-      __typename
-      id
-      created_at
-      name_CwYar
-      type_Hbagk
-      updated_at
-      order_WUkJE
-      width_xJ846
-      board_3jDDw_id {
-        id
-      }
-      required_4jaR4
-      isdefault_KCmRr
-      temporary_4NyhY
-      playbook_LFc9r_id {
-        id
-      }
-      template_BahPd_id
-        @relation(
-          table: "template"
-          fields: ["id"]
-          single: true
-          references: ["template_BahPd_id"]
-        ) {
-        __typename
-        id
-        title
-        created_at
-        updated_at
-        organization_organization_id {
-          id
-        }
-        defaultdescriptioncolumn_kMT8n_id {
-          id
-        }
-      }
-      column_Xdjyz_id
-        @relation(
-          table: "boardcolumnoptions_mrX6T"
-          fields: ["column_Xdjyz_id"]
-          references: ["id"]
-        ) {
-        __typename
-        id
-        created_at
-        name_bFeAf
-        updated_at
-        column_Xdjyz_id {
-          id
-        }
-      }
+
+    #[test]
+    fn query_binary_values() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"mutation Upload($content: Bytes!, $magic: Bytes!) {
+                insert(data: { id: "f1", content: $content, magic: $magic }) @meta(table: "File", insert: true) {
+                    id
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            gqlast,
+            &Some(
+                json!({ "content": { "base64": "3q2+7w==" }, "magic": { "hex": "\\x89504E47" } }),
+            ),
+            None,
+        )?;
+        assert_snapshot!(statement.to_string());
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        let literal = get_value(
+            &GqlValue::Binary(vec![0, 255].into()),
+            &mut IndexMap::new(),
+            &mut IndexMap::new(),
+        )?;
+        assert_eq!(literal.to_string(), "decode('00ff', 'hex')");
+        let error = gql2sql(
+            parse_query("query Files($content: Bytes) { File { id } }")?,
+            &Some(json!({ "content": { "base64": "not base64!" } })),
+            None,
+        )
+        .expect_err("invalid base64 should be rejected");
+        assert_eq!(
+            error.to_string(),
+            "variable $content is not valid binary: Invalid symbol 32, offset 3."
+        );
+        let error = gql2sql(
+            parse_query("query Files($content: Bytes) { File { id } }")?,
+            &Some(json!({ "content": "3q2+7w==" })),
+            None,
+        )
+        .expect_err("bytes must be encoded");
+        assert_eq!(
+            error.to_string(),
+            r#"variable $content of type Bytes must be { base64: ".." } or { hex: ".." }"#
+        );
+        // only variables declared as Bytes are bytes, other objects are JSON
+        let (statement, params, _tags, _is_mutation) = gql2sql(
+            parse_query(
+                r#"mutation Save($meta: JSON!) {
+                    insert(data: { id: "f1", meta: $meta }) @meta(table: "File", insert: true) {
+                        id
+                    }
+                }"#,
+            )?,
+            &Some(json!({ "meta": { "hex": "ff" } })),
+            None,
+        )?;
+        assert!(!statement.to_string().contains("bytea"));
+        assert!(params.is_none());
+        Ok(())
     }
-    playbook_playbook_id(order: $playbook_playbook_id_order)
-      @relation(
-        table: "playbookstandard"
-        fields: ["playbook_playbook_id"]
-        references: ["id"]
-      ) {
-      __typename
-      id
-      created_at
-      updated_at
-      row_DBUfb_id
-        @relation(
-          table: "boardrow"
-          fields: ["id"]
-          single: true
-          references: ["row_DBUfb_id"]
-        ) {
-        __typename
-        id
-        created_at
-        updated_at
-        board_mkGmp_id {
-          id
-        }
-        playbook_t7raV_id {
-          id
-        }
-        template_xdeiM_id {
-          id
+
+    #[test]
+    fn query_enum_mapping() -> Result<(), anyhow::Error> {
+        let options = Options {
+            enums: HashMap::from([(
+                "PostStatus".to_string(),
+                EnumMapping {
+                    sql_type: "post_status".to_string(),
+                    labels: HashMap::from([
+                        ("DRAFT".to_string(), "draft".to_string()),
+                        ("PUBLISHED".to_string(), "published".to_string()),
+                    ]),
+                },
+            )]),
+            ..Options::default()
+        };
+        let gqlast = parse_query(
+            r#"query Posts($status: PostStatus!, $statuses: [PostStatus!], $title: String) {
+                Post(filter: {
+                    field: "status",
+                    operator: "neq",
+                    value: DRAFT,
+                    children: [
+                        { field: "status", operator: "eq", value: $status },
+                        { field: "status", operator: "in", value: $statuses },
+                        { field: "title", operator: "eq", value: $title }
+                    ]
+                }) {
+                    id
+                    status
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) = gql2sql_with_options(
+            gqlast,
+            &Some(
+                json!({ "status": "PUBLISHED", "statuses": ["DRAFT", "PUBLISHED"], "title": "Hello" }),
+            ),
+            None,
+            &options,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_snapshot!(serde_json::to_string_pretty(&params)?);
+        Ok(())
+    }
+
+    struct Mask;
+
+    impl DirectiveHandler for Mask {
+        fn name(&self) -> &str {
+            "mask"
         }
-        defaultdescriptionvalue_tHbN3_id {
-          id
+
+        fn project(&self, context: &DirectiveContext, expr: Expr) -> AnyResult<Expr> {
+            let keep = context
+                .arguments
+                .get("keep")
+                .map_or(0, |keep| keep.as_u64().unwrap_or_default());
+            Ok(get_function_call(
+                "concat",
+                vec![
+                    get_function_call(
+                        "left",
+                        vec![expr, Expr::Value(Value::Number(keep.to_string(), false))],
+                    ),
+                    Expr::Value(Value::SingleQuotedString("****".to_string())),
+                ],
+            ))
         }
-        boardrow_row_id(
-          filter: $boardrow_row_id_filter
-          distinct: $boardrow_row_id_distinct
-        )
-          @relation(
-            table: "boardcell"
-            fields: ["boardrow_row_id"]
-            references: ["id"]
-          ) {
-          __typename
-          id
-          created_at
-          updated_at
-          boardrow_row_id {
-            id
-          }
-          datevalue_hd3CD
-          status_HVqrA_id {
-            id
-          }
-          textvalue_ahacc
-          numberfield_YfkE7
-          playbook_EqwVY_id {
-            id
-          }
-          selectvalue_q66xK
-          uservalue_faXth_id {
-            id
-          }
-          parentboard_BWMCH_id {
-            id
-          }
-          boardcolumn_column_id {
-            id
-          }
-          multiselectvalue_bHY6V
+    }
+
+    struct Tenant;
+
+    impl DirectiveHandler for Tenant {
+        fn name(&self) -> &str {
+            "tenant"
         }
-      }
-      status_d9CPd_id {
-        id
-      }
-      duedate_cRCB8_id {
-        id
-      }
-      ownercell_TyVaR_id {
-        id
-      }
-      playbook_playbook_id {
-        id
-      }
-      playbookrow_VJ6Vw_id
-        @relation(
-          table: "approvalworkflow_cqaw9"
-          fields: ["id"]
-          single: true
-          references: ["playbookrow_VJ6Vw_id"]
-        ) {
-        __typename
-        id
-        created_at
-        name_HKxd6
-        updated_at
-        workflows_Kdda9_id {
-          id
+
+        fn filter(&self, context: &DirectiveContext) -> AnyResult<Option<Expr>> {
+            let Some(JsonValue::String(org)) = context.arguments.get("org") else {
+                return Err(anyhow!("@tenant needs an org"));
+            };
+            Ok(Some(Expr::BinaryOp {
+                left: Box::new(Expr::CompoundIdentifier(vec![
+                    Ident::with_quote(QUOTE_CHAR, context.table),
+                    Ident::with_quote(QUOTE_CHAR, "orgId"),
+                ])),
+                op: BinaryOperator::Eq,
+                right: Box::new(Expr::Value(Value::SingleQuotedString(org.clone()))),
+            }))
         }
-      }
     }
-    workflows_Kdda9_id(order: $workflows_Kdda9_id_order)
-      @relation(
-        table: "approvalworkflow_cqaw9"
-        fields: ["workflows_Kdda9_id"]
-        references: ["id"]
-      ) {
-      __typename
-      id
-      created_at
-      name_HKxd6
-      updated_at
-      workflows_Kdda9_id {
-        id
-      }
+
+    struct Audit;
+
+    impl DirectiveHandler for Audit {
+        fn name(&self) -> &str {
+            "audit"
+        }
+
+        fn ctes(&self, context: &DirectiveContext) -> AnyResult<Vec<Cte>> {
+            let sql = format!(
+                "WITH audit AS (INSERT INTO \"Audit\" (\"table\") VALUES ('{}') RETURNING 1) SELECT 1",
+                context.table
+            );
+            let statement = sqlparser::parser::Parser::parse_sql(
+                &sqlparser::dialect::PostgreSqlDialect {},
+                &sql,
+            )?
+            .remove(0);
+            let Statement::Query(query) = statement else {
+                return Err(anyhow!("not a query"));
+            };
+            Ok(query.with.map(|with| with.cte_tables).unwrap_or_default())
+        }
     }
-  }
-}
-            "#,
+
+    #[test]
+    fn query_directive_handlers() -> Result<(), anyhow::Error> {
+        let mut options = Options::default();
+        options.directives.register(Mask);
+        options.directives.register(Tenant);
+        options.directives.register(Audit);
+        let gqlast = parse_query(
+            r#"query Apps($org: String!) {
+                App(filter: { field: "name", operator: "eq", value: "Home" }) @tenant(org: $org) @audit {
+                    id
+                    secret @mask(keep: 4)
+                    Page @relation(table: "Page", field: ["appId"], references: ["id"]) @tenant(org: $org) {
+                        id
+                    }
+                }
+            }"#,
         )?;
-        let (statement, params, tags, _is_mutation) = gql2sql(
-            gqlast,
-            &Some(json!(
-            {
-              "playbook_id": "PMxiGmJ4eyndrdp3J3Li6",
-              "template_BahPd_id_order": [
-                {
-                  "id": "ASC",
-                  "field": "created_at",
-                  "direction": "ASC"
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_with_options(gqlast, &Some(json!({ "org": "acme" })), None, &options)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_directive_filters() -> Result<(), anyhow::Error> {
+        let mut options = Options::default();
+        options.directives.register(Tenant);
+        let variables = Some(json!({ "org": "acme" }));
+        let translate = |query: &str| {
+            gql2sql_with_options(parse_query(query)?, &variables, None, &options)
+                .map(|(statement, _params, _tags, _is_mutation)| statement)
+        };
+        let update = translate(
+            r#"mutation Rename($org: String!) {
+                update(id: "1", set: { name: "Home" }) @meta(table: "App", update: true) @tenant(org: $org) {
+                    id
                 }
-              ],
-              "playbook_LFc9r_id_order": [
-                {
-                  "id": "ASC",
-                  "field": "created_at",
-                  "direction": "ASC"
+            }"#,
+        )?;
+        assert_snapshot!(pretty_sql(&update.to_string()));
+        let delete = translate(
+            r#"mutation Remove($org: String!) {
+                delete(id: "1") @meta(table: "App", delete: true) @tenant(org: $org) {
+                    id
                 }
-              ],
-              "playbook_playbook_id_order": [
-                {
-                  "id": "ASC",
-                  "field": "created_at",
-                  "direction": "ASC"
+            }"#,
+        )?;
+        assert_snapshot!(pretty_sql(&delete.to_string()));
+        let claim = translate(
+            r#"mutation Claim($org: String!) {
+                jobs: Job(set: { status: "running" }, order: { createdAt: ASC }) @meta(claim: true, limit: 5) @tenant(org: $org) {
+                    id
                 }
-              ],
-              "boardrow_row_id_filter": {
-                "id": "filter_YipDb8gGjkbHRpLfbGBNt",
-                "field": "playbook_EqwVY_id",
-                "value": null,
-                "children": [
-                  {
-                    "id": "filter_L6NRaeg8JXzdDFdtFePdc",
-                    "field": "playbook_EqwVY_id",
-                    "value": "PMxiGmJ4eyndrdp3J3Li6",
-                    "children": [],
-                    "operator": "eq",
-                    "logicalOperator": "AND"
-                  }
-                ],
-                "operator": "null",
-                "logicalOperator": "OR"
-              },
-              "boardrow_row_id_distinct": {
-                "on": [
-                  "boardrow_row_id",
-                  "boardcolumn_column_id"
-                ],
-                "order": [
-                  {
-                    "id": "ASC",
-                    "field": "created_at",
-                    "direction": "DESC"
-                  }
-                ]
-              },
-              "workflows_Kdda9_id_order": [
-                {
-                  "id": "ASC",
-                  "field": "created_at",
-                  "direction": "ASC"
+            }"#,
+        )?;
+        assert_snapshot!(pretty_sql(&claim.to_string()));
+        let error = translate(
+            r#"mutation Create($org: String!) {
+                insert(data: { id: "1", name: "Home" }) @meta(table: "App", insert: true) @tenant(org: $org) {
+                    id
                 }
-              ]
+            }"#,
+        )
+        .expect_err("a filter cannot narrow an insert");
+        assert_eq!(
+            error.to_string(),
+            "Directive filters cannot apply to the insert into App"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_allow_list() -> Result<(), anyhow::Error> {
+        let allowed = r#"query GetApp { App(id: "1") { id } }"#;
+        assert_eq!(
+            get_document_hash("{ __typename }"),
+            "7f56e67dd21ab3f30d1ff8b7bed08893f0a0db86449836189b361dd1e56ddb4b"
+        );
+        let allow_list = AllowList::new([get_document_hash(allowed).to_uppercase()])?;
+        assert_eq!(allow_list, AllowList::from_documents([allowed, allowed]));
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql_checked(allowed, &None, None, &Options::default(), &allow_list)?;
+        assert_snapshot!(statement.to_string());
+        let other = r#"query GetApp { App(id: "2") { id } }"#;
+        let error = gql2sql_checked(other, &None, None, &Options::default(), &allow_list)
+            .expect_err("the document is not on the list");
+        assert_eq!(
+            error.downcast_ref::<NotAllowed>(),
+            Some(&NotAllowed {
+                hash: get_document_hash(other)
+            })
+        );
+        assert!(AllowList::new(["abc"]).is_err());
+        let allow_list: AllowList = serde_json::from_value(json!([get_document_hash(other)]))?;
+        assert!(allow_list.allows(other) && !allow_list.allows(allowed));
+        assert!(constant_time_eq(b"hash", b"hash") && !constant_time_eq(b"hash", b"hasH"));
+        Ok(())
+    }
+
+    #[test]
+    fn query_directive_variables() -> Result<(), anyhow::Error> {
+        let query = r#"query Items($schema: String!, $single: Boolean = true, $label: String, $version: Int) {
+            item: Item(id: "1") @meta(table: "Component", schema: $schema, single: $single) {
+                id
+                label @static(value: $label)
+                version @static(value: $version)
             }
-                        )),
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "schema": "app", "label": "Draft", "version": 3 })),
             None,
         )?;
+        assert_snapshot!(statement.to_string());
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "schema": "app", "label": "", "version": 3 })),
+            None,
+        )?;
+        assert!(statement.to_string().contains("'' AS \"label\""));
+        let error = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "schema": "app", "single": "yes" })),
+            None,
+        )
+        .expect_err("single must be a boolean");
+        assert_eq!(
+            error.to_string(),
+            "@meta argument \"single\" must be a boolean, got $single = \"yes\" at 2:33"
+        );
+        let error = gql2sql(
+            parse_query(r#"query Items($table: String!) { Item @meta(table: $table) { id } }"#)?,
+            &Some(json!({ "table": "Component" })),
+            None,
+        )
+        .expect_err("tables are not taken from variables");
+        assert_eq!(
+            error.to_string(),
+            "@meta argument \"table\" must be a non-empty string at 1:37"
+        );
+        Ok(())
+    }
 
-        println!("query: {statement}");
-        println!("vars: {}", serde_json::to_string_pretty(&params)?);
-        println!("tags: {}", serde_json::to_string_pretty(&tags)?);
-        // assert_snapshot!(statement.to_string());
-        // assert_snapshot!();
+    #[test]
+    fn mutation_delete_all() -> Result<(), anyhow::Error> {
+        let query = r#"mutation ClearSessions($allowAll: Boolean) {
+            delete_Session(allowAll: $allowAll) {
+                id
+            }
+        }"#;
+        let error = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "allowAll": false })),
+            None,
+        )
+        .expect_err("there is no filter");
+        assert_eq!(
+            error.downcast_ref::<UnfilteredDelete>(),
+            Some(&UnfilteredDelete {
+                table: "Session".to_string()
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "refusing to delete from Session without filter, pass allowAll: true to delete every row"
+        );
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "allowAll": true })),
+            None,
+        )?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        let (statement, _params, _tags, _is_mutation) = gql2sql(
+            parse_query(r#"mutation { delete_Session(id: "s1", allowAll: true) { id } }"#)?,
+            &None,
+            None,
+        )?;
+        assert!(statement.to_string().contains(r#"WHERE "id" = 's1'"#));
+        Ok(())
+    }
+
+    #[test]
+    fn query_dry_run() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Apps($name: String) {
+                App(filter: { field: "name", operator: "eq", value: $name }) @meta(schema: "app") {
+                    id
+                    name
+                    components @relation(table: "Component", field: ["appId"], references: ["id"]) {
+                        id
+                    }
+                }
+            }"#,
+        )?;
+        let result = dry_run(
+            gqlast,
+            &Some(json!({ "name": "Home" })),
+            None,
+            &Options::default(),
+        )?;
+        assert_snapshot!(serde_json::to_string_pretty(&result)?);
+        let gqlast =
+            parse_query(r#"mutation { update_App(id: "1", set: { name: "Away" }) { id } }"#)?;
+        let result = dry_run(gqlast, &None, None, &Options::default())?;
+        assert_eq!(
+            result.tables,
+            vec![TableColumns {
+                schema: None,
+                table: "App".to_string(),
+                columns: vec!["name".to_string(), "*".to_string(), "id".to_string()],
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn query_referenced_objects() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query {
+                Post(id: "p1") @meta(schema: "blog") {
+                    id
+                    author @relation(table: "User", schema: "auth", field: ["id"], references: ["authorId"], single: true) {
+                        email
+                    }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(gqlast, &None, None)?;
+        assert_snapshot!(serde_json::to_string_pretty(&get_referenced_objects(
+            &statement
+        ))?);
+        Ok(())
+    }
+
+    #[test]
+    fn query_order_by_static() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query GetComponents($kind: String!) {
+                components: Component(
+                    filter: { field: "kind", operator: "eq", value: $kind },
+                    order: [{ kind: ASC }, { title: DESC }]
+                ) {
+                   id
+                   title @column(name: "name")
+                   kind @static(value: "page")
+                }
+            }"#,
+        )?;
+        let (statement, params, _tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "kind": "page" })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_eq!(params.map(|p| p.len()), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn query_sample() -> Result<(), anyhow::Error> {
+        let options = Options {
+            tenant: Some(TenantOptions {
+                columns: HashMap::from([("Event".to_string(), "org_id".to_string())]),
+                value: json!("org-1"),
+            }),
+            ..Options::default()
+        };
+        let query = r#"query($percent: Float!) {
+            Event(
+                sample: { percent: $percent, method: BERNOULLI, seed: 42 },
+                filter: { field: "kind", operator: "eq", value: "click" },
+                first: 100
+            ) @meta(schema: "analytics") {
+                id
+                kind
+            }
+        }"#;
+        let pretty = Options {
+            pretty: true,
+            ..Options::default()
+        };
+        let (statement, _params, _tags, _is_mutation) = gql2sql_with_options(
+            parse_query(query)?,
+            &Some(json!({ "percent": 2.5 })),
+            None,
+            &options,
+        )?;
+        assert_snapshot!(to_sql(&statement, &pretty));
+        assert_eq!(
+            get_referenced_objects(&statement).tables,
+            vec![TableColumns {
+                schema: Some("analytics".to_string()),
+                table: "Event".to_string(),
+                columns: vec!["id".to_string(), "kind".to_string(), "org_id".to_string()],
+            }]
+        );
+        let error = gql2sql_with_options(
+            parse_query(query)?,
+            &Some(json!({ "percent": 150 })),
+            None,
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "sample percent must be a number from 0 to 100"
+        );
+        let (statement, _params, _tags, _is_mutation) = gql2sql_with_options(
+            parse_query(
+                r#"query {
+                    Event {
+                        id
+                        user(sample: { percent: 10 }) @relation(table: "User", field: ["id"], references: ["userId"], single: true) {
+                            name
+                        }
+                    }
+                }"#,
+            )?,
+            &None,
+            None,
+            &Options {
+                flatten_single_joins: true,
+                ..Options::default()
+            },
+        )?;
+        // the sample of the relation samples the table it is joined to
+        assert_snapshot!(to_sql(&statement, &pretty));
+        Ok(())
+    }
+
+    #[test]
+    fn mutation_claim() -> Result<(), anyhow::Error> {
+        let query = r#"mutation ClaimJobs($queue: String!, $batch: Int!, $worker: String!) {
+            jobs: Job(
+                filter: { field: "queue", operator: "eq", value: $queue },
+                order: { createdAt: ASC },
+                set: { status: "running", worker: $worker }
+            ) @meta(claim: true, limit: $batch) {
+                id
+                payload
+            }
+        }"#;
+        let (statement, params, _tags, is_mutation) = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "queue": "email", "batch": 10, "worker": "w1" })),
+            None,
+        )?;
+        assert!(is_mutation);
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        assert_eq!(
+            params.map(|p| p.into_iter().map(|p| p.value).collect::<Vec<_>>()),
+            Some(vec![json!("email"), json!("w1")])
+        );
+        let error = gql2sql(
+            parse_query(query)?,
+            &Some(json!({ "queue": "email", "batch": 0, "worker": "w1" })),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "@meta argument \"limit\" must be a positive integer, got $batch = 0 at 6:15"
+        );
+        let (statement, _params, _tags, _is_mutation) = gql2sql_with_options(
+            parse_query(query)?,
+            &Some(json!({ "queue": "email", "batch": 10, "worker": "w1" })),
+            None,
+            &Options {
+                table_key_columns: HashMap::from([(
+                    "Job".to_string(),
+                    vec!["queue".to_string(), "seq".to_string()],
+                )]),
+                ..Options::default()
+            },
+        )?;
+        // rows are claimed by the key columns of the table
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_empty_results() -> Result<(), anyhow::Error> {
+        let gqlast = parse_query(
+            r#"query Author($id: String!) {
+                author: User_one(id: $id) {
+                    name
+                    profile @relation(table: "Profile", field: ["userId"], references: ["id"], single: true) {
+                        bio
+                    }
+                    postsByState: Post(groupBy: ["published"]) @relation(table: "Post", field: ["authorId"], references: ["id"], aggregate: true) {
+                        count
+                    }
+                }
+            }"#,
+        )?;
+        let (statement, _params, _tags, _is_mutation) =
+            gql2sql(gqlast, &Some(json!({ "id": "u3" })), None)?;
+        assert_snapshot!(pretty_sql(&statement.to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn query_schema_relation_tags() -> Result<(), anyhow::Error> {
+        let query = r#"query Author {
+            users(filter: { field: "id", operator: "eq", value: "u1" }) @meta(table: "users", schema: "auth", single: true) {
+                id
+                latest: posts(filter: { field: "id", operator: "eq", value: "p1" }) @relation(table: "posts", schema: "blog", fields: ["authorId"], references: ["id"]) {
+                    id
+                }
+            }
+        }"#;
+        let (_statement, _params, tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        assert_snapshot!(serde_json::to_string_pretty(&tags)?);
+        Ok(())
+    }
+
+    #[test]
+    fn pretty_sql_only_changes_whitespace() -> Result<(), anyhow::Error> {
+        let query = r#"query {
+            App(filter: { field: "name", operator: "eq", value: "it's  (here)" }) {
+                id
+                Page @relation(table: "Page", field: ["appId"], references: ["id"]) {
+                    id
+                }
+            }
+        }"#;
+        let (statement, _params, _tags, _is_mutation) = gql2sql(parse_query(query)?, &None, None)?;
+        let sql = statement.to_string();
+        let pretty = to_sql(
+            &statement,
+            &Options {
+                pretty: true,
+                ..Options::default()
+            },
+        );
+        let squash = |sql: &str| sql.split_whitespace().collect::<String>();
+        assert_eq!(squash(&pretty), squash(&sql));
+        assert!(pretty.contains("'it''s  (here)'"));
+        assert_snapshot!(pretty);
         Ok(())
     }
 }
@@ -0,0 +1,334 @@
+use crate::binary::get_binary_variables;
+use crate::budget::{check_memory, start_budget};
+use crate::consts::QUOTE_CHAR;
+use crate::dates::get_string_variables;
+use crate::directives::add_ctes;
+use crate::enums::declare_enums;
+use crate::ir::{find_reference, join, split};
+use crate::sample::count_samples;
+use crate::variables::resolve_directive_variables;
+use crate::{
+    dedupe_joins, flatten_single_joins, get_directive_ctes, get_operation, normalize_identifiers,
+    prune_columns, push_down_predicates, shorten_aliases, translate, CacheTag, Diagnostic, Dialect,
+    Options, Param, PlanQuery,
+};
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::types::ExecutableDocument;
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{
+    Expr, Ident, ObjectName, Query, Statement, Value, Visit, VisitMut, Visitor, VisitorMut,
+};
+use std::borrow::Cow;
+use std::ops::ControlFlow;
+
+type JsonValue = serde_json::Value;
+
+/// A translated operation before rendering. Its selects are split out of the statement into
+/// `queries`, where tools read and rewrite their tables, joins, filters and projections before
+/// `render` puts them back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryPlan {
+    /// The statement, with each select replaced by `TABLE #<index>` of its entry in `queries`
+    pub statement: Statement,
+    /// The selects of the statement, each after the ones it runs
+    #[serde(default)]
+    pub queries: Vec<PlanQuery>,
+    pub params: Option<Vec<Param>>,
+    pub tags: Option<Vec<CacheTag>>,
+    pub is_mutation: bool,
+    /// Problems that did not stop the translation, such as groupBy keys missing from `value`
+    #[serde(default)]
+    pub warnings: Vec<Diagnostic>,
+}
+
+#[derive(Default)]
+struct Tables {
+    ctes: Vec<String>,
+    tables: Vec<ObjectName>,
+}
+
+impl Visitor for Tables {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let Some(with) = &query.with {
+            self.ctes.extend(
+                with.cte_tables
+                    .iter()
+                    .map(|cte| cte.alias.name.value.clone()),
+            );
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_relation(&mut self, relation: &ObjectName) -> ControlFlow<()> {
+        let is_cte = matches!(relation.0.as_slice(), [name] if self.ctes.contains(&name.value));
+        if !is_cte && !self.tables.contains(relation) {
+            self.tables.push(relation.clone());
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+// sqlparser writes a quote that follows a backslash without doubling it, which would end the
+// string or identifier early in Postgres
+struct Literals;
+
+impl Literals {
+    fn idents<'a>(idents: impl IntoIterator<Item = &'a Ident>) -> ControlFlow<String> {
+        match idents
+            .into_iter()
+            .find(|ident| ident.quote_style == Some(QUOTE_CHAR) && ident.value.contains(QUOTE_CHAR))
+        {
+            Some(ident) => ControlFlow::Break(ident.value.clone()),
+            None => ControlFlow::Continue(()),
+        }
+    }
+}
+
+impl VisitorMut for Literals {
+    type Break = String;
+
+    fn pre_visit_relation(&mut self, relation: &mut ObjectName) -> ControlFlow<String> {
+        Self::idents(&relation.0)
+    }
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<String> {
+        match expr {
+            Expr::Value(Value::SingleQuotedString(s)) if s.contains('\\') => {
+                *expr = Expr::Value(Value::EscapedStringLiteral(std::mem::take(s)));
+            }
+            Expr::Identifier(ident) => return Self::idents([&*ident]),
+            Expr::CompoundIdentifier(idents) => return Self::idents(idents.iter()),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+// `$n::type` placeholders, renumbered by their old number
+struct Renumber(Vec<usize>);
+
+impl VisitorMut for Renumber {
+    type Break = ();
+
+    fn pre_visit_expr(&mut self, expr: &mut Expr) -> ControlFlow<()> {
+        if let Expr::Value(Value::Placeholder(placeholder)) = expr {
+            let renumbered = placeholder
+                .strip_prefix('$')
+                .and_then(|rest| rest.split_once("::"))
+                .and_then(|(n, sql_type)| {
+                    let n = n.parse::<usize>().ok()?.checked_sub(1)?;
+                    Some(format!("${}::{sql_type}", self.0.get(n)?))
+                });
+            if let Some(renumbered) = renumbered {
+                *placeholder = renumbered;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+impl QueryPlan {
+    /// Runs `f` on the whole statement, with the selects put back, and splits them out again.
+    pub(crate) fn edit<T>(&mut self, f: impl FnOnce(&mut Statement) -> T) -> T {
+        if self.queries.is_empty() {
+            return f(&mut self.statement);
+        }
+        let queries = std::mem::take(&mut self.queries);
+        join(&mut self.statement, queries);
+        let result = f(&mut self.statement);
+        self.queries = split(&mut self.statement);
+        result
+    }
+
+    /// The whole statement, with the selects put back.
+    pub(crate) fn get_statement(&self) -> Cow<'_, Statement> {
+        if self.queries.is_empty() {
+            Cow::Borrowed(&self.statement)
+        } else {
+            let mut statement = self.statement.clone();
+            join(&mut statement, self.queries.clone());
+            Cow::Owned(statement)
+        }
+    }
+
+    /// Numbers the parameters by name instead of by first use, so operations that only differ
+    /// in the order of their arguments bind the same parameter sequence.
+    pub fn sort_params(&mut self) {
+        let Some(params) = &mut self.params else {
+            return;
+        };
+        let mut order = (0..params.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| params[*a].name.cmp(&params[*b].name));
+        let mut numbers = vec![0; order.len()];
+        for (new, old) in order.iter().enumerate() {
+            numbers[*old] = new + 1;
+        }
+        let mut unsorted = std::mem::take(params)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        *params = order.iter().filter_map(|i| unsorted[*i].take()).collect();
+        let _ = self.edit(|statement| VisitMut::visit(statement, &mut Renumber(numbers)));
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[derive(Default)]
+struct Joins(usize);
+
+#[cfg(feature = "tracing")]
+impl Visitor for Joins {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let sqlparser::ast::SetExpr::Select(select) = query.body.as_ref() {
+            self.0 += select
+                .from
+                .iter()
+                .map(|table| table.joins.len())
+                .sum::<usize>();
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+impl QueryPlan {
+    /// The tables the plan reads or writes, in order of first appearance.
+    #[must_use]
+    pub fn tables(&self) -> Vec<ObjectName> {
+        let mut tables = Tables::default();
+        let _ = Visit::visit(self.get_statement().as_ref(), &mut tables);
+        tables.tables
+    }
+}
+
+/// Translates an operation into a `QueryPlan`.
+pub fn plan(
+    ast: ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+    options: &Options,
+) -> AnyResult<QueryPlan> {
+    let mut plan = build_plan(ast, variables, operation_name, options)?;
+    plan.queries = split(&mut plan.statement);
+    Ok(plan)
+}
+
+/// `plan` without splitting the selects out, for rendering right away.
+pub(crate) fn build_plan(
+    mut ast: ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+    options: &Options,
+) -> AnyResult<QueryPlan> {
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!(
+        "gql2sql::plan",
+        joins = tracing::field::Empty,
+        params = tracing::field::Empty,
+        tags = tracing::field::Empty,
+    )
+    .entered();
+    let _budget = start_budget(options.memory_budget)?;
+    resolve_directive_variables(&mut ast, variables, operation_name.as_deref())?;
+    let enums = if options.enums.is_empty() {
+        None
+    } else {
+        Some(declare_enums(
+            &mut ast,
+            variables,
+            operation_name.as_deref(),
+            &options.enums,
+        )?)
+    };
+    let variables = enums.as_ref().map_or(variables, |(variables, _)| variables);
+    let ctes = get_directive_ctes(&ast, variables, operation_name.as_deref(), options)?;
+    let definitions = &get_operation(&ast, operation_name.as_deref())?.variable_definitions;
+    let binary = get_binary_variables(definitions);
+    let strings = get_string_variables(definitions);
+    let mut warnings = vec![];
+    let (statement, params, tags, is_mutation) =
+        translate(ast, variables, operation_name, options, &mut warnings)?;
+    check_memory()?;
+    let mut plan = QueryPlan {
+        statement,
+        queries: vec![],
+        params,
+        tags,
+        is_mutation,
+        warnings,
+    };
+    add_ctes(&mut plan.statement, ctes)?;
+    if let Some((_, declared)) = &enums {
+        plan.apply_enums(declared, &options.enums)?;
+    }
+    plan.apply_binary(&binary);
+    if let ControlFlow::Break(ident) = VisitMut::visit(&mut plan.statement, &mut Literals) {
+        return Err(anyhow!(
+            "identifier {} must not contain a double quote",
+            ident
+        ));
+    }
+    dedupe_joins(&mut plan.statement);
+    shorten_aliases(&mut plan.statement);
+    options.date_policy.check()?;
+    plan.apply_date_policy(&options.date_policy, &strings);
+    if let Some(tenant) = &options.tenant {
+        plan.isolate_tenant(tenant)?;
+    }
+    push_down_predicates(&mut plan.statement);
+    // after isolation, so tenant conditions move into the join with the rest of the filter
+    if options.flatten_single_joins {
+        let samples = count_samples(&plan.statement);
+        flatten_single_joins(&mut plan.statement);
+        if count_samples(&plan.statement) != samples {
+            return Err(anyhow!("flattening single joins would drop a table sample"));
+        }
+    }
+    if options.sort_params {
+        plan.sort_params();
+    }
+    check_memory()?;
+    #[cfg(feature = "tracing")]
+    {
+        let mut joins = Joins::default();
+        let _ = Visit::visit(&plan.statement, &mut joins);
+        span.record("joins", joins.0);
+        span.record("params", plan.params.as_ref().map_or(0, Vec::len));
+        span.record("tags", plan.tags.as_ref().map_or(0, Vec::len));
+    }
+    Ok(plan)
+}
+
+/// Produces the final statement of a plan for `dialect`, putting its selects back, applying
+/// the identifier strategy of `options` and narrowing `SELECT *` subqueries to the columns the
+/// statement reads. Fails for a reference to a query the plan does not have, and for a statement
+/// the dialect cannot run.
+pub fn render(
+    plan: QueryPlan,
+    dialect: Dialect,
+    options: &Options,
+) -> AnyResult<(Statement, Option<Vec<Param>>, Option<Vec<CacheTag>>, bool)> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("gql2sql::render").entered();
+    let QueryPlan {
+        mut statement,
+        queries,
+        params,
+        tags,
+        is_mutation,
+        ..
+    } = plan;
+    join(&mut statement, queries);
+    if let Some(reference) = find_reference(&statement) {
+        return Err(anyhow!("the plan has no query for {}", reference));
+    }
+    dialect.check(&statement)?;
+    normalize_identifiers(&mut statement, options.identifiers);
+    // after normalizing, so the narrowed columns are spelled as the references to them
+    prune_columns(&mut statement);
+    Ok((statement, params, tags, is_mutation))
+}
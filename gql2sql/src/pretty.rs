@@ -0,0 +1,226 @@
+// a hand-rolled scanner rather than the sqlparser tokenizer, whose Display does not re-escape
+// string literals; only whitespace outside literals and quoted identifiers is ever changed
+enum Piece<'a> {
+    Word(&'a str),
+    Open,
+    Close,
+    Space,
+    Text(&'a str),
+}
+
+fn scan_quoted(sql: &str, start: usize, quote: u8, backslash: bool) -> usize {
+    let bytes = sql.as_bytes();
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' if backslash => i += 2,
+            c if c == quote => {
+                if bytes.get(i + 1) == Some(&quote) {
+                    i += 2;
+                } else {
+                    return i + 1;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn scan_dollar(sql: &str, start: usize) -> Option<usize> {
+    let rest = &sql[start + 1..];
+    let tag_len = rest.find('$')?;
+    let tag = &rest[..tag_len];
+    if !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        || tag.starts_with(|c: char| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let delimiter = &sql[start..start + tag_len + 2];
+    let body = start + delimiter.len();
+    let end = sql[body..].find(delimiter)?;
+    Some(body + end + delimiter.len())
+}
+
+fn scan(sql: &str) -> Vec<Piece<'_>> {
+    let bytes = sql.as_bytes();
+    let mut pieces = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let end = match c {
+            b'(' => {
+                pieces.push(Piece::Open);
+                i += 1;
+                continue;
+            }
+            b')' => {
+                pieces.push(Piece::Close);
+                i += 1;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => {
+                while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                    i += 1;
+                }
+                pieces.push(Piece::Space);
+                continue;
+            }
+            b'\'' => {
+                let escaped =
+                    matches!(pieces.last(), Some(Piece::Word(w)) if w.eq_ignore_ascii_case("e"));
+                scan_quoted(sql, i, b'\'', escaped)
+            }
+            b'"' => scan_quoted(sql, i, b'"', false),
+            b'$' => scan_dollar(sql, i).unwrap_or(i + 1),
+            c if c.is_ascii_alphanumeric() || c == b'_' => {
+                let mut end = i;
+                while end < bytes.len()
+                    && (bytes[end].is_ascii_alphanumeric() || bytes[end] == b'_')
+                {
+                    end += 1;
+                }
+                pieces.push(Piece::Word(&sql[i..end]));
+                i = end;
+                continue;
+            }
+            _ => i + sql[i..].chars().next().map_or(1, char::len_utf8),
+        };
+        pieces.push(Piece::Text(&sql[i..end]));
+        i = end;
+    }
+    pieces
+}
+
+fn next_word<'a>(pieces: &[Piece<'a>]) -> Option<&'a str> {
+    pieces.iter().find_map(|piece| match piece {
+        Piece::Space => None,
+        Piece::Word(word) => Some(Some(*word)),
+        _ => Some(None),
+    })?
+}
+
+fn is(word: Option<&str>, keywords: &[&str]) -> bool {
+    word.is_some_and(|word| keywords.iter().any(|k| word.eq_ignore_ascii_case(k)))
+}
+
+// whether `word` starts a new clause of the enclosing query
+fn starts_clause(word: &str, previous: Option<&str>, next: Option<&str>) -> bool {
+    let word = Some(word);
+    const CLAUSES: &[&str] = &[
+        "WHERE",
+        "HAVING",
+        "LIMIT",
+        "OFFSET",
+        "RETURNING",
+        "VALUES",
+        "UNION",
+        "EXCEPT",
+        "INTERSECT",
+        "ORDER",
+        "FOR",
+    ];
+    if is(word, CLAUSES) {
+        return true;
+    }
+    if is(word, &["SET"]) {
+        return !is(previous, &["UPDATE"]);
+    }
+    if is(word, &["FROM"]) {
+        return !is(previous, &["DISTINCT", "DELETE"]);
+    }
+    if is(word, &["GROUP"]) {
+        return !is(previous, &["WITHIN"]);
+    }
+    if is(word, &["LEFT", "RIGHT", "INNER", "FULL", "CROSS"]) {
+        return is(next, &["JOIN", "OUTER"]);
+    }
+    if is(word, &["JOIN"]) {
+        return !is(
+            previous,
+            &["LEFT", "RIGHT", "INNER", "FULL", "CROSS", "OUTER"],
+        );
+    }
+    if is(word, &["ON"]) {
+        return is(next, &["CONFLICT"]);
+    }
+    if is(word, &["SELECT"]) {
+        return previous.is_none();
+    }
+    false
+}
+
+/// Indents rendered SQL: every subquery on its own lines and each clause of a query
+/// (`FROM`, `WHERE`, joins, ...) on a new line. Only whitespace is changed.
+pub fn pretty_sql(sql: &str) -> String {
+    let pieces = scan(sql);
+    let mut output = String::with_capacity(sql.len() * 2);
+    // true for the parentheses around a subquery
+    let mut parens: Vec<bool> = vec![];
+    let mut depth = 0;
+    let mut space = false;
+    let mut line_start = true;
+    let mut previous = None;
+    let newline = |output: &mut String, depth: usize| {
+        output.push('\n');
+        output.push_str(&"  ".repeat(depth));
+    };
+    for (i, piece) in pieces.iter().enumerate() {
+        let mut word = None;
+        match piece {
+            Piece::Space => {
+                space = true;
+                continue;
+            }
+            Piece::Open => {
+                if space && !line_start {
+                    output.push(' ');
+                }
+                output.push('(');
+                let subquery = is(
+                    next_word(&pieces[i + 1..]),
+                    &["SELECT", "WITH", "INSERT", "UPDATE", "DELETE"],
+                );
+                parens.push(subquery);
+                line_start = subquery;
+                if subquery {
+                    depth += 1;
+                    newline(&mut output, depth);
+                }
+            }
+            Piece::Close => {
+                if parens.pop() == Some(true) {
+                    depth -= 1;
+                    newline(&mut output, depth);
+                }
+                output.push(')');
+                line_start = false;
+            }
+            Piece::Word(w) => {
+                let clause_level = parens.last() != Some(&false);
+                if clause_level
+                    && !line_start
+                    && !output.is_empty()
+                    && starts_clause(w, previous, next_word(&pieces[i + 1..]))
+                {
+                    newline(&mut output, depth);
+                } else if space && !line_start {
+                    output.push(' ');
+                }
+                output.push_str(w);
+                word = Some(*w);
+                line_start = false;
+            }
+            Piece::Text(text) => {
+                if space && !line_start {
+                    output.push(' ');
+                }
+                output.push_str(text);
+                line_start = false;
+            }
+        }
+        previous = word;
+        space = false;
+    }
+    output
+}
@@ -0,0 +1,324 @@
+use crate::consts::QUOTE_CHAR;
+use sqlparser::ast::{
+    Expr, FunctionArg, FunctionArgExpr, FunctionArguments, GroupByExpr, Ident, ObjectName, Query,
+    Select, SelectItem, SetExpr, TableFactor, Value, Visit, VisitMut, Visitor, VisitorMut,
+};
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+
+// a relation in a FROM clause, with its output columns when they can be read off the query
+struct Item {
+    // the position of the relation in the FROM clause, which keys its usage
+    index: usize,
+    alias: String,
+    columns: Option<Vec<String>>,
+}
+
+struct Scope {
+    id: usize,
+    items: Vec<Item>,
+}
+
+fn get_select(query: &Query) -> Option<&Select> {
+    match query.body.as_ref() {
+        SetExpr::Select(select) => Some(select),
+        _ => None,
+    }
+}
+
+fn get_select_mut(query: &mut Query) -> Option<&mut Select> {
+    match query.body.as_mut() {
+        SetExpr::Select(select) => Some(select),
+        _ => None,
+    }
+}
+
+fn get_relations(select: &Select) -> impl Iterator<Item = &TableFactor> {
+    select.from.iter().flat_map(|table| {
+        std::iter::once(&table.relation).chain(table.joins.iter().map(|join| &join.relation))
+    })
+}
+
+fn get_relations_mut(select: &mut Select) -> impl Iterator<Item = &mut TableFactor> {
+    select.from.iter_mut().flat_map(|table| {
+        std::iter::once(&mut table.relation)
+            .chain(table.joins.iter_mut().map(|join| &mut join.relation))
+    })
+}
+
+/// A derived table that only reads every column of a single table, `(SELECT * FROM t WHERE ..)`,
+/// so its `*` can be narrowed to the columns used outside of it.
+fn is_prunable(relation: &TableFactor) -> bool {
+    let TableFactor::Derived {
+        subquery,
+        alias: Some(_),
+        ..
+    } = relation
+    else {
+        return false;
+    };
+    let Some(select) = get_select(subquery) else {
+        return false;
+    };
+    subquery.with.is_none()
+        && matches!(select.projection.as_slice(), [SelectItem::Wildcard(_)])
+        && select.distinct.is_none()
+        && select.having.is_none()
+        && matches!(&select.group_by, GroupByExpr::Expressions(e) if e.is_empty())
+        && matches!(select.from.as_slice(), [table] if table.joins.is_empty()
+            && matches!(table.relation, TableFactor::Table { .. }))
+}
+
+fn get_output_columns(query: &Query) -> Option<Vec<String>> {
+    let select = get_select(query)?;
+    select
+        .projection
+        .iter()
+        .map(|item| match item {
+            SelectItem::ExprWithAlias { alias, .. } => Some(alias.value.clone()),
+            SelectItem::UnnamedExpr(Expr::Identifier(ident)) => Some(ident.value.clone()),
+            SelectItem::UnnamedExpr(Expr::CompoundIdentifier(idents)) => {
+                idents.last().map(|ident| ident.value.clone())
+            }
+            SelectItem::UnnamedExpr(Expr::Function(function)) => {
+                function.name.0.last().map(|ident| ident.value.clone())
+            }
+            SelectItem::UnnamedExpr(_) => Some("?column?".to_string()),
+            SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..) => None,
+        })
+        .collect()
+}
+
+fn get_item(index: usize, relation: &TableFactor) -> Option<Item> {
+    match relation {
+        TableFactor::Table { name, alias, .. } => Some(Item {
+            index,
+            alias: alias
+                .as_ref()
+                .map(|alias| alias.name.value.clone())
+                .or_else(|| name.0.last().map(|ident| ident.value.clone()))?,
+            columns: None,
+        }),
+        TableFactor::Derived {
+            subquery, alias, ..
+        } => {
+            let alias = alias.as_ref()?;
+            let columns = if alias.columns.is_empty() {
+                get_output_columns(subquery)
+            } else {
+                Some(alias.columns.iter().map(|c| c.value.clone()).collect())
+            };
+            Some(Item {
+                index,
+                alias: alias.name.value.clone(),
+                columns,
+            })
+        }
+        _ => None,
+    }
+}
+
+// unquoted words other than plain lowercase names, such as DEFAULT, are SQL rather than columns
+fn is_column(ident: &Ident) -> bool {
+    ident.quote_style == Some(QUOTE_CHAR)
+        || (ident.quote_style.is_none()
+            && ident
+                .value
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'))
+}
+
+/// Finds, for every prunable derived table, the columns its enclosing queries use. Queries are
+/// numbered in visiting order, so the rewrite can find them again by the query and the position
+/// of the relation in its FROM clause.
+#[derive(Default)]
+struct Usage {
+    next: usize,
+    scopes: Vec<Scope>,
+    columns: HashMap<(usize, usize), Vec<Ident>>,
+    // derived tables used as a whole row, or by names that could not be resolved
+    whole: HashSet<(usize, usize)>,
+}
+
+impl Usage {
+    fn find_alias(&self, alias: &str) -> Option<(usize, usize)> {
+        self.scopes.iter().rev().find_map(|scope| {
+            scope
+                .items
+                .iter()
+                .find(|item| item.alias == alias)
+                .map(|item| (scope.id, item.index))
+        })
+    }
+
+    fn add_column(&mut self, key: (usize, usize), column: &Ident) {
+        let columns = self.columns.entry(key).or_default();
+        if !columns.iter().any(|c| c.value == column.value) {
+            columns.push(column.clone());
+        }
+    }
+
+    fn qualified(&mut self, idents: &[Ident]) {
+        let [.., qualifier, column] = idents else {
+            return;
+        };
+        if let Some(key) = self.find_alias(&qualifier.value) {
+            self.add_column(key, column);
+        }
+    }
+
+    fn whole_row(&mut self, alias: &ObjectName) {
+        if let Some(alias) = alias.0.last() {
+            if let Some(key) = self.find_alias(&alias.value) {
+                self.whole.insert(key);
+            }
+        }
+    }
+
+    // an unqualified name is a column of the innermost relation that has it, a whole row, or,
+    // when a query reads a single relation of unknown columns, a column of that relation
+    fn unqualified(&mut self, ident: &Ident) {
+        for scope in self.scopes.iter().rev() {
+            if scope.items.is_empty() {
+                continue;
+            }
+            let known = scope.items.iter().any(|item| {
+                item.columns
+                    .as_ref()
+                    .is_some_and(|columns| columns.contains(&ident.value))
+            });
+            if known {
+                return;
+            }
+            if let Some(item) = scope.items.iter().find(|item| item.alias == ident.value) {
+                self.whole.insert((scope.id, item.index));
+                return;
+            }
+            let mut unknown = scope.items.iter().filter(|item| item.columns.is_none());
+            match (unknown.next(), unknown.next()) {
+                (None, _) => continue,
+                (Some(item), None) if is_column(ident) => {
+                    let key = (scope.id, item.index);
+                    self.add_column(key, ident);
+                }
+                _ => {
+                    self.whole.extend(
+                        scope
+                            .items
+                            .iter()
+                            .filter(|item| item.columns.is_none())
+                            .map(|item| (scope.id, item.index)),
+                    );
+                }
+            }
+            return;
+        }
+    }
+}
+
+impl Visitor for Usage {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        let id = self.next;
+        self.next += 1;
+        let Some(select) = get_select(query) else {
+            self.scopes.push(Scope { id, items: vec![] });
+            return ControlFlow::Continue(());
+        };
+        let items = get_relations(select)
+            .enumerate()
+            .filter_map(|(index, relation)| get_item(index, relation))
+            .collect::<Vec<_>>();
+        if select
+            .projection
+            .iter()
+            .any(|item| matches!(item, SelectItem::Wildcard(_)))
+        {
+            self.whole.extend(items.iter().map(|item| (id, item.index)));
+        }
+        self.scopes.push(Scope { id, items });
+        for item in &select.projection {
+            if let SelectItem::QualifiedWildcard(alias, _) = item {
+                self.whole_row(alias);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.scopes.pop();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::Identifier(ident) => self.unqualified(ident),
+            // group by keys and whole rows are also written as quoted strings
+            Expr::Value(Value::DoubleQuotedString(name)) => {
+                self.unqualified(&Ident::with_quote(QUOTE_CHAR, name));
+            }
+            Expr::CompoundIdentifier(idents) => self.qualified(idents),
+            Expr::Function(function) => {
+                if let FunctionArguments::List(list) = &function.args {
+                    for arg in &list.args {
+                        let (FunctionArg::Named { arg, .. } | FunctionArg::Unnamed(arg)) = arg;
+                        if let FunctionArgExpr::QualifiedWildcard(alias) = arg {
+                            self.whole_row(alias);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+struct Prune {
+    next: usize,
+    usage: Usage,
+}
+
+impl VisitorMut for Prune {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        let id = self.next;
+        self.next += 1;
+        let Some(select) = get_select_mut(query) else {
+            return ControlFlow::Continue(());
+        };
+        for (index, relation) in get_relations_mut(select).enumerate() {
+            if !is_prunable(relation) {
+                continue;
+            }
+            let TableFactor::Derived { subquery, .. } = relation else {
+                continue;
+            };
+            let key = (id, index);
+            if self.usage.whole.contains(&key) {
+                continue;
+            }
+            let Some(columns) = self.usage.columns.get(&key) else {
+                continue;
+            };
+            if let Some(select) = get_select_mut(subquery) {
+                select.projection = columns
+                    .iter()
+                    .map(|column| SelectItem::UnnamedExpr(Expr::Identifier(column.clone())))
+                    .collect();
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Narrows `SELECT *` in derived tables over a single table to the columns the rest of the
+/// statement reads from them. Derived tables that are used as a whole row, or whose columns
+/// cannot all be told apart from those of other relations, keep `*`.
+pub(crate) fn prune_columns<T: Visit + VisitMut>(node: &mut T) {
+    let mut usage = Usage::default();
+    let _ = Visit::visit(node, &mut usage);
+    let _ = VisitMut::visit(node, &mut Prune { next: 0, usage });
+}
@@ -0,0 +1,200 @@
+use crate::consts::QUOTE_CHAR;
+use sqlparser::ast::{
+    BinaryOperator, Expr, Ident, Query, SetExpr, TableFactor, Value, VisitMut, VisitorMut,
+};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+// column -> the constant it equals
+type Constants = HashMap<String, Expr>;
+
+// literals and bound parameters, which are the same for every row
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::Nested(expr) | Expr::Cast { expr, .. } => is_constant(expr),
+        Expr::Value(Value::DoubleQuotedString(_)) => false,
+        Expr::Value(_) => true,
+        _ => false,
+    }
+}
+
+fn get_conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            let mut conjuncts = get_conjuncts(left);
+            conjuncts.extend(get_conjuncts(right));
+            conjuncts
+        }
+        Expr::Nested(nested) => match nested.as_ref() {
+            and @ Expr::BinaryOp {
+                op: BinaryOperator::And,
+                ..
+            } => get_conjuncts(and),
+            _ => vec![expr],
+        },
+        expr => vec![expr],
+    }
+}
+
+// the equalities of a condition, either way round
+fn get_equalities(expr: &Expr) -> Vec<(&Expr, &Expr)> {
+    get_conjuncts(expr)
+        .into_iter()
+        .filter_map(|conjunct| match conjunct {
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Eq,
+                right,
+            } => Some((left.as_ref(), right.as_ref())),
+            _ => None,
+        })
+        .flat_map(|(left, right)| [(left, right), (right, left)])
+        .collect()
+}
+
+// the table of a query that reads a single one, so bare names in its condition are its columns
+fn get_table(query: &Query) -> Option<&[Ident]> {
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        return None;
+    };
+    let [table] = select.from.as_slice() else {
+        return None;
+    };
+    match &table.relation {
+        TableFactor::Table {
+            name, alias: None, ..
+        } if table.joins.is_empty() => Some(&name.0),
+        _ => None,
+    }
+}
+
+fn get_column<'a>(expr: &'a Expr, table: &[Ident]) -> Option<&'a str> {
+    match expr {
+        Expr::Identifier(ident) if ident.quote_style == Some(QUOTE_CHAR) => Some(&ident.value),
+        Expr::CompoundIdentifier(idents) => match idents.split_last() {
+            Some((column, qualifier)) if qualifier == table => Some(&column.value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Pushdown {
+    // per query, the columns of its derived tables known to equal a constant
+    scopes: Vec<HashMap<String, Constants>>,
+}
+
+impl Pushdown {
+    fn find(&self, qualifier: &str, column: &str) -> Option<&Expr> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(qualifier))
+            .and_then(|constants| constants.get(column))
+    }
+
+    // adds the constants of the parent rows to the join keys of a query over a single table,
+    // returning every column the query is now filtered to a constant on
+    fn push(&self, query: &mut Query) -> Constants {
+        let mut constants = Constants::new();
+        let mut pushed = vec![];
+        let Some(table) = get_table(query) else {
+            return constants;
+        };
+        let SetExpr::Select(select) = query.body.as_ref() else {
+            return constants;
+        };
+        let Some(selection) = &select.selection else {
+            return constants;
+        };
+        let equalities = get_equalities(selection);
+        for (left, right) in &equalities {
+            if let Some(column) = get_column(left, table).filter(|_| is_constant(right)) {
+                constants.insert(column.to_string(), (*right).clone());
+            }
+        }
+        for (left, right) in &equalities {
+            let Some(column) = get_column(left, table) else {
+                continue;
+            };
+            let Expr::CompoundIdentifier(parent) = right else {
+                continue;
+            };
+            let [qualifier, parent_column] = parent.as_slice() else {
+                continue;
+            };
+            if constants.contains_key(column) {
+                continue;
+            }
+            if let Some(constant) = self.find(&qualifier.value, &parent_column.value) {
+                constants.insert(column.to_string(), constant.clone());
+                pushed.push(Expr::BinaryOp {
+                    left: Box::new((*left).clone()),
+                    op: BinaryOperator::Eq,
+                    right: Box::new(constant.clone()),
+                });
+            }
+        }
+        if let SetExpr::Select(select) = query.body.as_mut() {
+            for condition in pushed {
+                select.selection = Some(match select.selection.take() {
+                    Some(selection) => Expr::BinaryOp {
+                        left: Box::new(selection),
+                        op: BinaryOperator::And,
+                        right: Box::new(condition),
+                    },
+                    None => condition,
+                });
+            }
+        }
+        constants
+    }
+}
+
+impl VisitorMut for Pushdown {
+    type Break = ();
+
+    // the derived tables of a query are narrowed before their own queries are visited, so the
+    // joins nested in them see the constants of every table above
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<()> {
+        let mut scope = HashMap::new();
+        if let SetExpr::Select(select) = query.body.as_mut() {
+            let relations = select.from.iter_mut().flat_map(|table| {
+                std::iter::once(&mut table.relation)
+                    .chain(table.joins.iter_mut().map(|join| &mut join.relation))
+            });
+            for relation in relations {
+                if let TableFactor::Derived {
+                    subquery,
+                    alias: Some(alias),
+                    ..
+                } = relation
+                {
+                    let constants = self.push(subquery);
+                    if !constants.is_empty() {
+                        scope.insert(alias.name.value.clone(), constants);
+                    }
+                }
+            }
+        }
+        self.scopes.push(scope);
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &mut Query) -> ControlFlow<()> {
+        self.scopes.pop();
+        ControlFlow::Continue(())
+    }
+}
+
+/// Repeats constant filters of parent rows on the join keys of their children: a parent
+/// filtered on `"branch" = $1` whose child is joined on `"Child"."branch" = "base"."branch"`
+/// also filters the child on `"Child"."branch" = $1`, so it is narrowed before the join.
+pub(crate) fn push_down_predicates<T: VisitMut>(node: &mut T) {
+    let _ = VisitMut::visit(node, &mut Pushdown::default());
+}
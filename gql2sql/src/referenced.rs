@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+use sqlparser::ast::{
+    Delete, Expr, FromTable, Ident, ObjectName, Query, SelectItem, SetExpr, Statement, TableFactor,
+    TableWithJoins, Visit, Visitor,
+};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+/// A table the statement reads or writes, with the columns it references. `*` stands for a
+/// `RETURNING *`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableColumns {
+    /// Set when the statement qualifies the table
+    pub schema: Option<String>,
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+/// The schemas, tables and columns a statement references, for authorization checks, choosing
+/// row level security policies or mapping writes to the caches they invalidate.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferencedObjects {
+    pub schemas: Vec<String>,
+    /// In order of first reference
+    pub tables: Vec<TableColumns>,
+}
+
+// the schema and name of a table, which names with more parts can't be
+fn get_name(name: &ObjectName) -> Option<(Option<String>, String)> {
+    match name.0.as_slice() {
+        [table] => Some((None, table.value.clone())),
+        [schema, table] => Some((Some(schema.value.clone()), table.value.clone())),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Columns {
+    ctes: Vec<String>,
+    tables: Vec<TableColumns>,
+    // the tables of each query or write being visited, by the names columns are qualified with
+    scopes: Vec<HashMap<String, usize>>,
+}
+
+impl Columns {
+    fn get_table(&mut self, name: &ObjectName) -> Option<usize> {
+        if matches!(name.0.as_slice(), [name] if self.ctes.contains(&name.value)) {
+            return None;
+        }
+        let (schema, name) = get_name(name)?;
+        Some(
+            self.tables
+                .iter()
+                .position(|table| table.schema == schema && table.table == name)
+                .unwrap_or_else(|| {
+                    self.tables.push(TableColumns {
+                        schema,
+                        table: name,
+                        columns: vec![],
+                    });
+                    self.tables.len() - 1
+                }),
+        )
+    }
+
+    fn push_scope(&mut self, from: &[TableWithJoins]) {
+        let mut scope = HashMap::new();
+        let relations = from.iter().flat_map(|table| {
+            std::iter::once(&table.relation).chain(table.joins.iter().map(|join| &join.relation))
+        });
+        for relation in relations {
+            if let TableFactor::Table { name, alias, .. } = relation {
+                if let (Some(table), Some(last)) = (self.get_table(name), name.0.last()) {
+                    let qualifier = alias
+                        .as_ref()
+                        .map_or_else(|| last.value.clone(), |alias| alias.name.value.clone());
+                    scope.insert(qualifier, table);
+                }
+            }
+        }
+        self.scopes.push(scope);
+    }
+
+    fn add_column(&mut self, table: usize, column: &str) {
+        let columns = &mut self.tables[table].columns;
+        if !columns.iter().any(|c| c == column) {
+            columns.push(column.to_string());
+        }
+    }
+
+    // a column without a qualifier belongs to the only table of its query
+    fn add_ident(&mut self, ident: &Ident) {
+        let Some(scope) = self.scopes.last() else {
+            return;
+        };
+        if let (1, Some(table)) = (scope.len(), scope.values().next().copied()) {
+            self.add_column(table, &ident.value);
+        }
+    }
+
+    // a qualified column belongs to the nearest table with that name or alias
+    fn add_qualified(&mut self, qualifier: &Ident, column: &Ident) {
+        let table = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&qualifier.value).copied());
+        if let Some(table) = table {
+            self.add_column(table, &column.value);
+        }
+    }
+
+    fn add_wildcard(&mut self, returning: Option<&Vec<SelectItem>>) {
+        let has_wildcard = returning.is_some_and(|items| {
+            items
+                .iter()
+                .any(|item| matches!(item, SelectItem::Wildcard(_)))
+        });
+        let tables = self.scopes.last().map(|scope| scope.values().copied());
+        if let (true, Some(tables)) = (has_wildcard, tables) {
+            for table in tables.collect::<Vec<_>>() {
+                self.add_column(table, "*");
+            }
+        }
+    }
+}
+
+impl Visitor for Columns {
+    type Break = ();
+
+    fn pre_visit_query(&mut self, query: &Query) -> ControlFlow<()> {
+        if let Some(with) = &query.with {
+            self.ctes.extend(
+                with.cte_tables
+                    .iter()
+                    .map(|cte| cte.alias.name.value.clone()),
+            );
+        }
+        match query.body.as_ref() {
+            SetExpr::Select(select) => self.push_scope(&select.from),
+            _ => self.scopes.push(HashMap::new()),
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, _query: &Query) -> ControlFlow<()> {
+        self.scopes.pop();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, statement: &Statement) -> ControlFlow<()> {
+        match statement {
+            Statement::Insert(insert) => {
+                let mut scope = HashMap::new();
+                if let Some(table) = self.get_table(&insert.table_name) {
+                    for column in &insert.columns {
+                        self.add_column(table, &column.value);
+                    }
+                    if let Some(last) = insert.table_name.0.last() {
+                        scope.insert(last.value.clone(), table);
+                    }
+                }
+                self.scopes.push(scope);
+                self.add_wildcard(insert.returning.as_ref());
+            }
+            Statement::Update {
+                table,
+                assignments,
+                returning,
+                ..
+            } => {
+                self.push_scope(std::slice::from_ref(table));
+                for assignment in assignments {
+                    if let Some(column) = assignment.id.last() {
+                        self.add_ident(column);
+                    }
+                }
+                self.add_wildcard(returning.as_ref());
+            }
+            Statement::Delete(Delete {
+                from: FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from),
+                returning,
+                ..
+            }) => {
+                self.push_scope(from);
+                self.add_wildcard(returning.as_ref());
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_statement(&mut self, statement: &Statement) -> ControlFlow<()> {
+        if matches!(
+            statement,
+            Statement::Insert(_) | Statement::Update { .. } | Statement::Delete(_)
+        ) {
+            self.scopes.pop();
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_expr(&mut self, expr: &Expr) -> ControlFlow<()> {
+        match expr {
+            Expr::Identifier(ident) => self.add_ident(ident),
+            Expr::CompoundIdentifier(idents) => {
+                if let [.., qualifier, column] = idents.as_slice() {
+                    self.add_qualified(qualifier, column);
+                }
+            }
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// The schemas, tables and columns of a statement, as written in it.
+#[must_use]
+pub fn get_referenced_objects(statement: &Statement) -> ReferencedObjects {
+    let mut columns = Columns::default();
+    let _ = Visit::visit(statement, &mut columns);
+    let mut schemas = vec![];
+    for schema in columns
+        .tables
+        .iter()
+        .filter_map(|table| table.schema.as_ref())
+    {
+        if !schemas.contains(schema) {
+            schemas.push(schema.clone());
+        }
+    }
+    ReferencedObjects {
+        schemas,
+        tables: columns.tables,
+    }
+}
@@ -0,0 +1,200 @@
+use async_graphql_parser::{
+    types::{Directive, Field, Selection},
+    Pos, Positioned,
+};
+use async_graphql_value::{Name, Value as GqlValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Lists every foreign key as `{schema, table, columns, foreignSchema, foreignTable, foreignColumns}`
+/// in a single jsonb `data` column, ready for `RelationRegistry::from_foreign_keys`.
+pub const FOREIGN_KEYS_QUERY: &str = r#"SELECT coalesce(jsonb_agg(fk), '[]') AS "data" FROM (
+  SELECT kcu.table_schema AS "schema", kcu.table_name AS "table",
+    jsonb_agg(kcu.column_name ORDER BY kcu.ordinal_position) AS "columns",
+    ref.table_schema AS "foreignSchema", ref.table_name AS "foreignTable",
+    jsonb_agg(ref.column_name ORDER BY kcu.ordinal_position) AS "foreignColumns"
+  FROM information_schema.referential_constraints rc
+  JOIN information_schema.key_column_usage kcu
+    ON kcu.constraint_schema = rc.constraint_schema AND kcu.constraint_name = rc.constraint_name
+  JOIN information_schema.key_column_usage ref
+    ON ref.constraint_schema = rc.unique_constraint_schema
+    AND ref.constraint_name = rc.unique_constraint_name
+    AND ref.ordinal_position = kcu.position_in_unique_constraint
+  GROUP BY rc.constraint_schema, rc.constraint_name, kcu.table_schema, kcu.table_name,
+    ref.table_schema, ref.table_name
+) AS fk"#;
+
+/// A foreign key from `table.columns` to `foreign_table.foreign_columns`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForeignKey {
+    pub schema: Option<String>,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub foreign_schema: Option<String>,
+    pub foreign_table: String,
+    pub foreign_columns: Vec<String>,
+}
+
+/// The arguments of the `@relation` directive a field would otherwise need.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Relation {
+    pub table: String,
+    pub schema: Option<String>,
+    /// Columns of the related table
+    pub fields: Vec<String>,
+    /// Columns of the parent table
+    pub references: Vec<String>,
+    pub single: bool,
+}
+
+/// Relations by table and GraphQL field name, consulted for fields without a `@relation` directive.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RelationRegistry {
+    tables: HashMap<String, HashMap<String, Relation>>,
+}
+
+impl RelationRegistry {
+    /// Both sides of every foreign key become a relation: the referencing table gets a single
+    /// field named after the referenced table and the referenced table a list field named after
+    /// the referencing one. When a name is taken the first column is appended, e.g. `User_createdBy`.
+    pub fn from_foreign_keys(foreign_keys: impl IntoIterator<Item = ForeignKey>) -> Self {
+        let mut registry = Self::default();
+        for fk in foreign_keys {
+            let column = fk.columns.first().cloned().unwrap_or_default();
+            registry.insert_unique(
+                &fk.table,
+                &fk.foreign_table,
+                &column,
+                Relation {
+                    table: fk.foreign_table.clone(),
+                    schema: fk.foreign_schema.clone(),
+                    fields: fk.foreign_columns.clone(),
+                    references: fk.columns.clone(),
+                    single: true,
+                },
+            );
+            registry.insert_unique(
+                &fk.foreign_table,
+                &fk.table,
+                &column,
+                Relation {
+                    table: fk.table.clone(),
+                    schema: fk.schema.clone(),
+                    fields: fk.columns,
+                    references: fk.foreign_columns,
+                    single: false,
+                },
+            );
+        }
+        registry
+    }
+
+    fn insert_unique(&mut self, table: &str, name: &str, column: &str, relation: Relation) {
+        let fields = self.tables.entry(table.to_string()).or_default();
+        let name = if fields.contains_key(name) {
+            format!("{name}_{column}")
+        } else {
+            name.to_string()
+        };
+        fields.entry(name).or_insert(relation);
+    }
+
+    pub fn insert(
+        &mut self,
+        table: impl Into<String>,
+        field: impl Into<String>,
+        relation: Relation,
+    ) {
+        self.tables
+            .entry(table.into())
+            .or_default()
+            .insert(field.into(), relation);
+    }
+
+    pub fn get(&self, table: &str, field: &str) -> Option<&Relation> {
+        self.tables.get(table).and_then(|fields| fields.get(field))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+}
+
+fn get_list(values: &[String]) -> GqlValue {
+    GqlValue::List(values.iter().cloned().map(GqlValue::String).collect())
+}
+
+pub(crate) fn get_directive(relation: &Relation, pos: Pos) -> Positioned<Directive> {
+    let argument = |name: &str, value| {
+        (
+            Positioned::new(Name::new(name), pos),
+            Positioned::new(value, pos),
+        )
+    };
+    let mut arguments = vec![argument("table", GqlValue::String(relation.table.clone()))];
+    if let Some(schema) = relation.schema.as_ref().filter(|s| s.as_str() != "public") {
+        arguments.push(argument("schema", GqlValue::String(schema.clone())));
+    }
+    arguments.push(argument("field", get_list(&relation.fields)));
+    arguments.push(argument("references", get_list(&relation.references)));
+    arguments.push(argument("single", GqlValue::Boolean(relation.single)));
+    Positioned::new(
+        Directive {
+            name: Positioned::new(Name::new("relation"), pos),
+            arguments,
+        },
+        pos,
+    )
+}
+
+fn get_directive_table(field: &Field) -> Option<&str> {
+    field
+        .directives
+        .iter()
+        .filter(|d| d.node.name.node.as_str() == "relation")
+        .flat_map(|d| d.node.arguments.iter())
+        .find_map(|(name, value)| match (name.node.as_str(), &value.node) {
+            ("table", GqlValue::String(table)) => Some(table.as_str()),
+            _ => None,
+        })
+}
+
+/// Adds a `@relation` directive from the registry to every nested field of `table` that has none.
+pub(crate) fn apply_relations(
+    items: &mut [Positioned<Selection>],
+    table: &str,
+    registry: &RelationRegistry,
+) {
+    for selection in items {
+        match &mut selection.node {
+            Selection::Field(field) => {
+                let pos = field.pos;
+                let field = &mut field.node;
+                if field.selection_set.node.items.is_empty() {
+                    continue;
+                }
+                let child = match get_directive_table(field) {
+                    Some(child) => Some(child.to_string()),
+                    None => {
+                        let name = field.name.node.as_str();
+                        let name = name.strip_suffix("_aggregate").unwrap_or(name);
+                        registry.get(table, name).map(|relation| {
+                            field.directives.push(get_directive(relation, pos));
+                            relation.table.clone()
+                        })
+                    }
+                };
+                if let Some(child) = child {
+                    apply_relations(&mut field.selection_set.node.items, &child, registry);
+                }
+            }
+            Selection::InlineFragment(fragment) => {
+                apply_relations(&mut fragment.node.selection_set.node.items, table, registry);
+            }
+            Selection::FragmentSpread(_) => {}
+        }
+    }
+}
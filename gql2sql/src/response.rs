@@ -0,0 +1,161 @@
+use crate::consts::{AFFECTED_ROWS, TYPENAME};
+use crate::{flatten_variables, get_operation, has_skip, parse_mutation_meta, parse_query_meta};
+use anyhow::Result as AnyResult;
+use async_graphql_parser::{
+    types::{ExecutableDocument, Field, OperationType, Selection},
+    Positioned,
+};
+use async_graphql_value::{
+    indexmap::{IndexMap, IndexSet},
+    Name, Value as GqlValue,
+};
+use serde_json::{json, Map, Value as JsonValue};
+
+// the table a nested relation reads from, which the translator also reports as its __typename
+fn get_relation_typename(field: &Field) -> Option<&str> {
+    let Some(directive) = field
+        .directives
+        .iter()
+        .find(|d| d.node.name.node.as_str() == "relation")
+    else {
+        return Some(field.name.node.as_str());
+    };
+    let mut typename = Some(field.name.node.as_str());
+    for (name, value) in &directive.node.arguments {
+        match (name.node.as_str(), &value.node) {
+            ("table" | "function", GqlValue::String(table)) => typename = Some(table.as_str()),
+            ("aggregate", GqlValue::Boolean(true)) => return None,
+            _ => {}
+        }
+    }
+    typename
+}
+
+fn shape_value(
+    value: &mut JsonValue,
+    items: &[Positioned<Selection>],
+    typename: Option<&str>,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) {
+    match value {
+        JsonValue::Array(list) => {
+            for item in list {
+                shape_value(item, items, typename, sql_vars);
+            }
+        }
+        JsonValue::Object(map) => shape_object(map, items, typename, sql_vars),
+        _ => {}
+    }
+}
+
+fn shape_object(
+    map: &mut Map<String, JsonValue>,
+    items: &[Positioned<Selection>],
+    typename: Option<&str>,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) {
+    for selection in items {
+        match &selection.node {
+            Selection::Field(field) => {
+                let field = &field.node;
+                let key = field
+                    .alias
+                    .as_ref()
+                    .map_or(field.name.node.as_str(), |alias| alias.node.as_str());
+                if has_skip(field, sql_vars) {
+                    map.remove(key);
+                    continue;
+                }
+                let value = map.entry(key).or_insert(JsonValue::Null);
+                if field.name.node == TYPENAME {
+                    if let (JsonValue::Null, Some(typename)) = (&value, typename) {
+                        *value = JsonValue::String(typename.to_string());
+                    }
+                } else if !field.selection_set.node.items.is_empty() {
+                    shape_value(
+                        value,
+                        &field.selection_set.node.items,
+                        get_relation_typename(field),
+                        sql_vars,
+                    );
+                }
+            }
+            Selection::InlineFragment(fragment) => {
+                let fragment = &fragment.node;
+                let typename = fragment
+                    .type_condition
+                    .as_ref()
+                    .map_or(typename, |condition| Some(condition.node.on.node.as_str()));
+                shape_object(map, &fragment.selection_set.node.items, typename, sql_vars);
+            }
+            // fragment spreads are rejected by the translator
+            Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
+/// Turns the `data` column returned by a generated statement into a spec-compliant GraphQL response.
+pub fn shape_response(
+    ast: &ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<&str>,
+    data: Option<JsonValue>,
+    errors: Vec<String>,
+) -> AnyResult<JsonValue> {
+    let operation = get_operation(ast, operation_name)?;
+    // only the arguments of @skip and @include are read, which are not bytes
+    let (_, sql_vars) = flatten_variables(
+        variables,
+        operation.variable_definitions.clone(),
+        &IndexSet::new(),
+    );
+    let data = match data {
+        Some(JsonValue::Object(mut map)) => {
+            for selection in &operation.selection_set.node.items {
+                let Selection::Field(field) = &selection.node else {
+                    continue;
+                };
+                let field = &field.node;
+                let (typename, key, is_plain) = match operation.ty {
+                    OperationType::Mutation => {
+                        let (name, key, ..) = parse_mutation_meta(field)?;
+                        (name, key, true)
+                    }
+                    _ => {
+                        let (name, key, is_aggregate, _, is_count, is_exists, _) =
+                            parse_query_meta(field)?;
+                        (name, key, !(is_aggregate || is_count || is_exists))
+                    }
+                };
+                if has_skip(field, &sql_vars) {
+                    map.remove(key);
+                    continue;
+                }
+                let mut items = field.selection_set.node.items.clone();
+                if operation.ty == OperationType::Mutation {
+                    // the count is returned next to the rows, which may be left out
+                    let (counts, rows): (Vec<_>, Vec<_>) = items.into_iter().partition(|item| {
+                        matches!(&item.node, Selection::Field(f) if f.node.name.node == AFFECTED_ROWS)
+                    });
+                    if rows.is_empty() && !counts.is_empty() {
+                        continue;
+                    }
+                    items = rows;
+                }
+                let value = map.entry(key).or_insert(JsonValue::Null);
+                shape_value(value, &items, is_plain.then_some(typename), &sql_vars);
+            }
+            JsonValue::Object(map)
+        }
+        Some(data) => data,
+        None => JsonValue::Null,
+    };
+    let mut response = json!({ "data": data });
+    if !errors.is_empty() {
+        response["errors"] = errors
+            .into_iter()
+            .map(|message| json!({ "message": message }))
+            .collect();
+    }
+    Ok(response)
+}
@@ -0,0 +1,190 @@
+use crate::consts::{SAMPLE_METHODS, TABLESAMPLE};
+use crate::{find_string_or_variable, get_function_call};
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_value::{indexmap::IndexMap, Name, Number, Value as GqlValue};
+use sqlparser::ast::{
+    Expr, FunctionArg, FunctionArgExpr, FunctionArguments, Query, SetExpr, Statement, TableFactor,
+    Value, Visit, VisitMut, Visitor, VisitorMut,
+};
+use std::ops::ControlFlow;
+
+type JsonValue = serde_json::Value;
+
+const REPEATABLE: &str = "REPEATABLE";
+
+// a number, or a variable holding one
+fn get_number(value: &GqlValue, sql_vars: &IndexMap<Name, JsonValue>) -> Option<Number> {
+    match value {
+        GqlValue::Number(number) => Some(number.clone()),
+        GqlValue::Variable(name) => match sql_vars.get(name) {
+            Some(JsonValue::Number(number)) => Some(number.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The `sample` argument of a field, `{percent, method, seed}`, as a call of the sampling method
+/// with the percent and seed. `method` is `SYSTEM`, which reads whole pages at random, unless it
+/// is `BERNOULLI`, which reads every page and keeps each row at random.
+pub(crate) fn get_sample(
+    value: &GqlValue,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<Option<Expr>> {
+    let sample = match value {
+        GqlValue::Null => return Ok(None),
+        GqlValue::Object(sample) => sample,
+        _ => return Err(anyhow!("Invalid value for sample")),
+    };
+    let mut percent = None;
+    let mut method = SAMPLE_METHODS[0];
+    let mut seed = None;
+    for (key, value) in sample {
+        match key.as_str() {
+            "percent" => {
+                percent = get_number(value, sql_vars)
+                    .filter(|p| p.as_f64().is_some_and(|p| (0.0..=100.0).contains(&p)));
+                if percent.is_none() {
+                    return Err(anyhow!("sample percent must be a number from 0 to 100"));
+                }
+            }
+            "method" => {
+                let name = find_string_or_variable(value, sql_vars).unwrap_or_default();
+                method = SAMPLE_METHODS
+                    .into_iter()
+                    .find(|m| *m == name)
+                    .ok_or_else(|| anyhow!("Invalid value for sample method: {}", name))?;
+            }
+            "seed" => {
+                seed = get_number(value, sql_vars);
+                if seed.is_none() && !matches!(value, GqlValue::Variable(_) | GqlValue::Null) {
+                    return Err(anyhow!("sample seed must be a number"));
+                }
+            }
+            _ => return Err(anyhow!("Invalid argument for sample: {}", key)),
+        }
+    }
+    let percent = percent.ok_or_else(|| anyhow!("sample needs a percent"))?;
+    let args = std::iter::once(percent)
+        .chain(seed)
+        .map(|n| Expr::Value(Value::Number(n.to_string(), false)))
+        .collect();
+    Ok(Some(get_function_call(method, args)))
+}
+
+/// Samples the table of a filter query. sqlparser has no `TABLESAMPLE` clause, so the sample is
+/// kept as the hint of the table, which the passes over the plan carry with the table and
+/// `to_sql` writes as the clause.
+pub(crate) fn set_sample(query: &mut Query, sample: Expr) {
+    let SetExpr::Select(select) = query.body.as_mut() else {
+        return;
+    };
+    match select.from.first_mut().map(|table| &mut table.relation) {
+        Some(TableFactor::Table { with_hints, .. }) => *with_hints = vec![sample],
+        // the query that sorts the rows of a distinct query
+        Some(TableFactor::Derived { subquery, .. }) => set_sample(subquery, sample),
+        _ => {}
+    }
+}
+
+// `TABLESAMPLE method (percent) REPEATABLE (seed)` for the hint `set_sample` added
+fn get_clause(hint: &Expr) -> Option<String> {
+    let Expr::Function(sample) = hint else {
+        return None;
+    };
+    let FunctionArguments::List(list) = &sample.args else {
+        return None;
+    };
+    let args = list
+        .args
+        .iter()
+        .filter_map(|arg| match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(expr)) => Some(expr.to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let [percent, seed @ ..] = args.as_slice() else {
+        return None;
+    };
+    let mut clause = format!("{TABLESAMPLE} {} ({percent})", sample.name);
+    if let Some(seed) = seed.first() {
+        clause = format!("{clause} {REPEATABLE} ({seed})");
+    }
+    Some(clause)
+}
+
+// replaces the sample hints with numbered placeholders, collecting their clauses
+struct Samples {
+    marker: String,
+    clauses: Vec<String>,
+}
+
+impl VisitorMut for Samples {
+    type Break = ();
+
+    fn pre_visit_table_factor(&mut self, table_factor: &mut TableFactor) -> ControlFlow<()> {
+        if let TableFactor::Table { with_hints, .. } = table_factor {
+            if let Some(clause) = with_hints.first().and_then(get_clause) {
+                *with_hints = vec![Expr::Value(Value::Placeholder(format!(
+                    "{}{}",
+                    self.marker,
+                    self.clauses.len()
+                )))];
+                self.clauses.push(clause);
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+// the tables with a sample
+struct Count(usize);
+
+impl Visitor for Count {
+    type Break = ();
+
+    fn pre_visit_table_factor(&mut self, table_factor: &TableFactor) -> ControlFlow<()> {
+        if let TableFactor::Table { with_hints, .. } = table_factor {
+            if with_hints.first().and_then(get_clause).is_some() {
+                self.0 += 1;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// The number of tables of a statement with a sample.
+pub(crate) fn count_samples(statement: &Statement) -> usize {
+    let mut count = Count(0);
+    let _ = Visit::visit(statement, &mut count);
+    count.0
+}
+
+/// Writes a statement with the samples of its tables as `TABLESAMPLE` clauses. sqlparser writes
+/// hints as `WITH (..)` after the table and its alias, where Postgres expects the clause, so each
+/// sample becomes a placeholder hint no other part of the statement spells, which is replaced.
+pub(crate) fn write_samples(statement: &Statement) -> String {
+    let sql = statement.to_string();
+    if count_samples(statement) == 0 {
+        return sql;
+    }
+    let mut marker = TABLESAMPLE.to_string();
+    while sql.contains(&marker) {
+        marker.push('_');
+    }
+    let mut samples = Samples {
+        marker,
+        clauses: vec![],
+    };
+    let mut statement = statement.clone();
+    let _ = VisitMut::visit(&mut statement, &mut samples);
+    let mut sql = statement.to_string();
+    for (i, clause) in samples.clauses.iter().enumerate() {
+        sql = sql.replacen(
+            &format!(" WITH ({}{i})", samples.marker),
+            &format!(" {clause}"),
+            1,
+        );
+    }
+    sql
+}
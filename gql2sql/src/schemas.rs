@@ -0,0 +1,181 @@
+use crate::find_string_or_variable;
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::types::{Directive, Field, Selection};
+use async_graphql_parser::Positioned;
+use async_graphql_value::{indexmap::IndexMap, Name, Value as GqlValue};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+type JsonValue = serde_json::Value;
+
+/// Finds the schema of a table whose `@meta` or `@relation` names none, e.g. the schema of the
+/// tenant of the request. Maps and closures of a table name are resolvers.
+pub trait SchemaResolver: Send + Sync {
+    fn schema(&self, table: &str) -> Option<String>;
+}
+
+impl SchemaResolver for HashMap<String, String> {
+    fn schema(&self, table: &str) -> Option<String> {
+        self.get(table).cloned()
+    }
+}
+
+impl<F> SchemaResolver for F
+where
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    fn schema(&self, table: &str) -> Option<String> {
+        self(table)
+    }
+}
+
+/// The resolver of `Options::table_schemas`. Options deserialized from JSON hold a map of
+/// table names to schemas; other resolvers are set in code with `TableSchemas::new`.
+#[derive(Clone)]
+pub struct TableSchemas(Arc<dyn SchemaResolver>);
+
+impl TableSchemas {
+    pub fn new(resolver: impl SchemaResolver + 'static) -> Self {
+        Self(Arc::new(resolver))
+    }
+
+    #[must_use]
+    pub fn get(&self, table: &str) -> Option<String> {
+        self.0.schema(table)
+    }
+}
+
+impl Default for TableSchemas {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+impl From<HashMap<String, String>> for TableSchemas {
+    fn from(schemas: HashMap<String, String>) -> Self {
+        Self::new(schemas)
+    }
+}
+
+impl fmt::Debug for TableSchemas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TableSchemas")
+    }
+}
+
+impl<'de> Deserialize<'de> for TableSchemas {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::<String, String>::deserialize(deserializer).map(Self::from)
+    }
+}
+
+// resolves a `schema: $variable` argument, or adds the schema of `table` when there is none
+fn apply_schema(
+    directive: &mut Positioned<Directive>,
+    table: &str,
+    schemas: &TableSchemas,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<()> {
+    let pos = directive.pos;
+    let arguments = &mut directive.node.arguments;
+    match arguments
+        .iter_mut()
+        .find(|(name, _)| name.node.as_str() == "schema")
+    {
+        Some((_, value)) => {
+            let schema = find_string_or_variable(&value.node, sql_vars).ok_or_else(|| {
+                anyhow!(
+                    "@{} argument \"schema\" must be a string or string variable at {}",
+                    directive.node.name.node,
+                    pos
+                )
+            })?;
+            value.node = GqlValue::String(schema);
+        }
+        None => {
+            if let Some(schema) = schemas.get(table) {
+                arguments.push((
+                    Positioned::new(Name::new("schema"), pos),
+                    Positioned::new(GqlValue::String(schema), pos),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_relation_table(directives: &[Positioned<Directive>]) -> Option<String> {
+    directives
+        .iter()
+        .filter(|d| d.node.name.node.as_str() == "relation")
+        .flat_map(|d| d.node.arguments.iter())
+        .find_map(|(name, value)| match (name.node.as_str(), &value.node) {
+            ("table", GqlValue::String(table)) => Some(table.clone()),
+            _ => None,
+        })
+}
+
+fn apply_nested_schemas(
+    items: &mut [Positioned<Selection>],
+    schemas: &TableSchemas,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<()> {
+    for selection in items {
+        let (directives, items) = match &mut selection.node {
+            Selection::Field(field) => (
+                &mut field.node.directives,
+                &mut field.node.selection_set.node.items,
+            ),
+            Selection::InlineFragment(fragment) => (
+                &mut fragment.node.directives,
+                &mut fragment.node.selection_set.node.items,
+            ),
+            Selection::FragmentSpread(_) => continue,
+        };
+        if let Some(table) = get_relation_table(directives) {
+            for directive in directives
+                .iter_mut()
+                .filter(|d| d.node.name.node.as_str() == "relation")
+            {
+                apply_schema(directive, &table, schemas, sql_vars)?;
+            }
+        }
+        apply_nested_schemas(items, schemas, sql_vars)?;
+    }
+    Ok(())
+}
+
+/// Gives the root field of `table` and its relations the schema `schemas` resolves for their table
+/// unless their `@meta` or `@relation` names one, and resolves `schema: $variable` arguments.
+pub(crate) fn apply_schemas(
+    field: &mut Field,
+    table: &str,
+    schemas: &TableSchemas,
+    sql_vars: &IndexMap<Name, JsonValue>,
+) -> AnyResult<()> {
+    let pos = field.name.pos;
+    match field
+        .directives
+        .iter_mut()
+        .find(|d| d.node.name.node.as_str() == "meta")
+    {
+        Some(meta) => apply_schema(meta, table, schemas, sql_vars)?,
+        None => {
+            if let Some(schema) = schemas.get(table) {
+                field.directives.push(Positioned::new(
+                    Directive {
+                        name: Positioned::new(Name::new("meta"), pos),
+                        arguments: vec![(
+                            Positioned::new(Name::new("schema"), pos),
+                            Positioned::new(GqlValue::String(schema), pos),
+                        )],
+                    },
+                    pos,
+                ));
+            }
+        }
+    }
+    apply_nested_schemas(&mut field.selection_set.node.items, schemas, sql_vars)
+}
@@ -0,0 +1,186 @@
+use crate::{
+    count_operation_nodes, get_operation, get_operation_mut, get_timeouts, gql2sql_with_options,
+    CacheTag, Options, Param,
+};
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::types::{ExecutableDocument, OperationType, Selection};
+use serde::Deserialize;
+use sqlparser::ast::{Expr, Ident, ObjectName, Statement, Value};
+use std::collections::BTreeMap;
+
+type JsonValue = serde_json::Value;
+
+/// Settings applied with `SET LOCAL` to operations of at least `min_nodes` nodes, counted as
+/// for `Options::node_budget`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LocalSettings {
+    pub min_nodes: usize,
+    /// `statement_timeout`, in milliseconds
+    pub statement_timeout_ms: Option<u64>,
+    /// `work_mem`, e.g. `64MB`
+    pub work_mem: Option<String>,
+    /// Any other setting, like the plan hint `enable_nestloop: off`
+    pub values: BTreeMap<String, String>,
+}
+
+fn set_local(name: &str, value: Expr) -> AnyResult<Statement> {
+    // the name is written into the statement as is
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        return Err(anyhow!("Invalid setting name: {}", name));
+    }
+    Ok(Statement::SetVariable {
+        local: true,
+        hivevar: false,
+        variable: ObjectName(name.split('.').map(Ident::new).collect()),
+        value: vec![value],
+    })
+}
+
+/// The `SET LOCAL` statements of the `Options::local_settings` entry with the highest
+/// `min_nodes` the operation reaches, to run in its transaction before the statement.
+pub fn get_local_settings(
+    ast: &ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<&str>,
+    options: &Options,
+) -> AnyResult<Vec<Statement>> {
+    if options.local_settings.is_empty() {
+        return Ok(vec![]);
+    }
+    let nodes = count_operation_nodes(get_operation(ast, operation_name)?, variables);
+    let Some(settings) = options
+        .local_settings
+        .iter()
+        .filter(|settings| settings.min_nodes <= nodes)
+        .max_by_key(|settings| settings.min_nodes)
+    else {
+        return Ok(vec![]);
+    };
+    let mut statements = vec![];
+    if let Some(timeout) = settings.statement_timeout_ms {
+        statements.push(set_local(
+            "statement_timeout",
+            Expr::Value(Value::Number(timeout.to_string(), false)),
+        )?);
+    }
+    if let Some(work_mem) = &settings.work_mem {
+        statements.push(set_local(
+            "work_mem",
+            Expr::Value(Value::SingleQuotedString(work_mem.clone())),
+        )?);
+    }
+    for (name, value) in &settings.values {
+        statements.push(set_local(
+            name,
+            Expr::Value(Value::SingleQuotedString(value.clone())),
+        )?);
+    }
+    Ok(statements)
+}
+
+/// `gql2sql_with_options`, with the statement after its `get_local_settings`.
+pub fn gql2sql_statements(
+    ast: ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+    options: &Options,
+) -> AnyResult<(
+    Vec<Statement>,
+    Option<Vec<Param>>,
+    Option<Vec<CacheTag>>,
+    bool,
+)> {
+    let mut statements = get_local_settings(&ast, variables, operation_name.as_deref(), options)?;
+    let (statement, params, tags, is_mutation) =
+        gql2sql_with_options(ast, variables, operation_name, options)?;
+    statements.push(statement);
+    Ok((statements, params, tags, is_mutation))
+}
+
+/// A root field of a query compiled on its own by `gql2sql_roots`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootStatement {
+    /// The response key of the field, the one key of the statement's `data`
+    pub key: String,
+    /// The `SET LOCAL` statements to run before `statement` in its transaction
+    pub settings: Vec<Statement>,
+    pub statement: Statement,
+    pub params: Option<Vec<Param>>,
+    pub tags: Option<Vec<CacheTag>>,
+    /// From `@meta(maxMs:)`, the `statement_timeout` of `settings`
+    pub timeout_ms: Option<u64>,
+}
+
+/// Compiles each root field of a query as a statement of its own, to run in a transaction of
+/// its own so a slow field times out without failing the others. The `statement_timeout` of a
+/// field is its `@meta(maxMs:)`, or else that of its `get_local_settings`. The root fields of a
+/// mutation commit together, so mutations fail.
+pub fn gql2sql_roots(
+    ast: ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<String>,
+    options: &Options,
+) -> AnyResult<Vec<RootStatement>> {
+    let operation = get_operation(&ast, operation_name.as_deref())?;
+    if operation.ty == OperationType::Mutation {
+        return Err(anyhow!(
+            "the root fields of a mutation run in one statement, they cannot be split"
+        ));
+    }
+    let timeouts = get_timeouts(&ast, operation_name.as_deref())?.unwrap_or_default();
+    let mut roots = vec![];
+    for index in 0..operation.selection_set.node.items.len() {
+        let mut root = ast.clone();
+        let items = &mut get_operation_mut(&mut root, operation_name.as_deref())
+            .ok_or_else(|| anyhow!("No operation found in the document"))?
+            .selection_set
+            .node
+            .items;
+        let selection = items.swap_remove(index);
+        let Selection::Field(field) = &selection.node else {
+            return Err(anyhow!(
+                "splitting root fields needs them selected directly, not through fragments at {}",
+                selection.pos
+            ));
+        };
+        let key = field
+            .node
+            .alias
+            .as_ref()
+            .unwrap_or(&field.node.name)
+            .node
+            .to_string();
+        *items = vec![selection];
+        let timeout_ms = timeouts.get(&key).copied();
+        let mut settings =
+            get_local_settings(&root, variables, operation_name.as_deref(), options)?;
+        if let Some(timeout) = timeout_ms {
+            settings.retain(|setting| {
+                !matches!(setting, Statement::SetVariable { variable, .. } if variable.to_string() == "statement_timeout")
+            });
+            settings.insert(
+                0,
+                set_local(
+                    "statement_timeout",
+                    Expr::Value(Value::Number(timeout.to_string(), false)),
+                )?,
+            );
+        }
+        let (statement, params, tags, _) =
+            gql2sql_with_options(root, variables, operation_name.clone(), options)?;
+        roots.push(RootStatement {
+            key,
+            settings,
+            statement,
+            params,
+            tags,
+            timeout_ms,
+        });
+    }
+    Ok(roots)
+}
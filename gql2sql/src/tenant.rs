@@ -0,0 +1,243 @@
+use crate::consts::QUOTE_CHAR;
+use crate::{value_to_type, Param, QueryPlan};
+use anyhow::{anyhow, Result as AnyResult};
+use serde::Deserialize;
+use sqlparser::ast::{
+    Assignment, BinaryOperator, Delete, Expr, FromTable, Ident, Insert, ObjectName, OnConflict,
+    OnConflictAction, OnInsert, Query, SelectItem, SetExpr, Statement, TableFactor, TableWithJoins,
+    Value, VisitMut, VisitorMut,
+};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+type JsonValue = serde_json::Value;
+
+/// Restricts every listed table to the rows of a single tenant.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TenantOptions {
+    /// Tenant column by table name, e.g. `{"Project": "org_id"}`. A schema-qualified name,
+    /// e.g. `{"auth.users": "org_id"}`, leaves tables of the same name in other schemas alone.
+    pub columns: HashMap<String, String>,
+    /// The tenant id, bound as an extra parameter
+    pub value: JsonValue,
+}
+
+struct Isolation<'a> {
+    columns: &'a HashMap<String, String>,
+    placeholder: Expr,
+}
+
+impl Isolation<'_> {
+    // the schema-qualified name first, then the table name
+    fn column(&self, name: &ObjectName) -> Option<&String> {
+        let qualified = name
+            .0
+            .iter()
+            .map(|ident| ident.value.as_str())
+            .collect::<Vec<_>>()
+            .join(".");
+        self.columns
+            .get(&qualified)
+            .or_else(|| self.columns.get(&name.0.last()?.value))
+    }
+
+    fn condition(&self, qualifier: &[Ident], name: &ObjectName) -> Option<Expr> {
+        let column = self.column(name)?;
+        let mut idents = qualifier.to_vec();
+        idents.push(Ident::with_quote(QUOTE_CHAR, column));
+        Some(Expr::BinaryOp {
+            left: Box::new(Expr::CompoundIdentifier(idents)),
+            op: BinaryOperator::Eq,
+            right: Box::new(self.placeholder.clone()),
+        })
+    }
+
+    fn relation(&self, relation: &TableFactor) -> Option<Expr> {
+        let TableFactor::Table { name, alias, .. } = relation else {
+            return None;
+        };
+        match alias {
+            Some(alias) => self.condition(std::slice::from_ref(&alias.name), name),
+            None => self.condition(&name.0, name),
+        }
+    }
+
+    fn tables(&self, tables: &[TableWithJoins], selection: &mut Option<Expr>) {
+        for table in tables {
+            let relations = std::iter::once(&table.relation)
+                .chain(table.joins.iter().map(|join| &join.relation));
+            for condition in relations.filter_map(|relation| self.relation(relation)) {
+                and(selection, condition);
+            }
+        }
+    }
+
+    // a row must not be moved to another tenant
+    fn assignments(&self, assignments: &mut [Assignment], column: &str) {
+        for assignment in assignments {
+            if assignment
+                .id
+                .last()
+                .is_some_and(|ident| ident.value == column)
+            {
+                assignment.value = self.placeholder.clone();
+            }
+        }
+    }
+
+    // inserted rows belong to the tenant, whatever they set the tenant column to
+    fn insert(&self, insert: &mut Insert) -> ControlFlow<String> {
+        let Some(column) = self.column(&insert.table_name) else {
+            return ControlFlow::Continue(());
+        };
+        let index = insert
+            .columns
+            .iter()
+            .position(|ident| ident.value == *column);
+        if index.is_none() {
+            insert.columns.push(Ident::with_quote(QUOTE_CHAR, column));
+        }
+        if let Some(source) = &mut insert.source {
+            self.source(source.body.as_mut(), index)?;
+        }
+        if let Some(OnInsert::OnConflict(OnConflict {
+            action: OnConflictAction::DoUpdate(update),
+            ..
+        })) = &mut insert.on
+        {
+            // an upsert must not take over a row of another tenant
+            let table = insert.table_name.0.last().cloned();
+            if let Some(condition) = table
+                .and_then(|table| self.condition(std::slice::from_ref(&table), &insert.table_name))
+            {
+                and(&mut update.selection, condition);
+            }
+            self.assignments(&mut update.assignments, column);
+        }
+        ControlFlow::Continue(())
+    }
+
+    // sets the tenant column of every row of an insert source, at `index` or appended
+    fn source(&self, source: &mut SetExpr, index: Option<usize>) -> ControlFlow<String> {
+        let rows: Vec<&mut Vec<Expr>> = match source {
+            SetExpr::Values(values) => values.rows.iter_mut().collect(),
+            SetExpr::Select(select) => {
+                let is_wildcard = select.projection.iter().any(|item| {
+                    matches!(
+                        item,
+                        SelectItem::Wildcard(_) | SelectItem::QualifiedWildcard(..)
+                    )
+                });
+                if is_wildcard {
+                    return ControlFlow::Break(
+                        "an insert of all the columns of a query".to_string(),
+                    );
+                }
+                let item = SelectItem::UnnamedExpr(self.placeholder.clone());
+                match index {
+                    Some(index) if index < select.projection.len() => {
+                        select.projection[index] = item;
+                    }
+                    _ => select.projection.push(item),
+                }
+                return ControlFlow::Continue(());
+            }
+            SetExpr::Query(query) => return self.source(query.body.as_mut(), index),
+            SetExpr::SetOperation { left, right, .. } => {
+                self.source(left, index)?;
+                return self.source(right, index);
+            }
+            _ => return ControlFlow::Break(format!("the insert source {source}")),
+        };
+        for row in rows {
+            match index {
+                Some(index) if index < row.len() => row[index] = self.placeholder.clone(),
+                _ => row.push(self.placeholder.clone()),
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+// the existing condition is parenthesized so an OR in it cannot swallow the tenant check
+fn and(selection: &mut Option<Expr>, condition: Expr) {
+    *selection = Some(match selection.take() {
+        Some(existing) => Expr::BinaryOp {
+            left: Box::new(Expr::Nested(Box::new(existing))),
+            op: BinaryOperator::And,
+            right: Box::new(condition),
+        },
+        None => condition,
+    });
+}
+
+impl VisitorMut for Isolation<'_> {
+    type Break = String;
+
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<String> {
+        if let SetExpr::Select(select) = query.body.as_mut() {
+            self.tables(&select.from, &mut select.selection);
+        }
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_statement(&mut self, statement: &mut Statement) -> ControlFlow<String> {
+        match statement {
+            Statement::Update {
+                table,
+                assignments,
+                from,
+                selection,
+                ..
+            } => {
+                self.tables(std::slice::from_ref(table), selection);
+                if let Some(from) = from {
+                    self.tables(std::slice::from_ref(from), selection);
+                }
+                if let TableFactor::Table { name, .. } = &table.relation {
+                    if let Some(column) = self.column(name) {
+                        self.assignments(assignments, column);
+                    }
+                }
+            }
+            Statement::Delete(Delete {
+                from: FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from),
+                selection,
+                ..
+            }) => self.tables(from, selection),
+            Statement::Insert(insert) => return self.insert(insert),
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+impl QueryPlan {
+    /// Adds `column = tenant` for every table of `tenant.columns` the plan reads or writes, and
+    /// sets the column to the tenant in the rows it inserts or updates, binding the tenant id as
+    /// a new parameter. Fails for an insert whose rows cannot be given the tenant column.
+    pub fn isolate_tenant(&mut self, tenant: &TenantOptions) -> AnyResult<()> {
+        if tenant.columns.is_empty() {
+            return Ok(());
+        }
+        let params = self.params.get_or_insert_with(Vec::new);
+        let sql_type = value_to_type(&tenant.value);
+        params.push(Param {
+            name: "tenant".to_string(),
+            value: tenant.value.clone(),
+            sql_type: sql_type.to_string(),
+        });
+        let mut isolation = Isolation {
+            columns: &tenant.columns,
+            placeholder: Expr::Value(Value::Placeholder(format!("${}::{sql_type}", params.len()))),
+        };
+        match self.edit(|statement| statement.visit(&mut isolation)) {
+            ControlFlow::Break(source) => Err(anyhow!(
+                "tenant isolation cannot set the tenant column of {}",
+                source
+            )),
+            ControlFlow::Continue(()) => Ok(()),
+        }
+    }
+}
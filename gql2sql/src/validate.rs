@@ -0,0 +1,105 @@
+use crate::variables::check_declared;
+use crate::{get_operation, parse_mutation_meta, parse_query_meta};
+use async_graphql_parser::{
+    types::{ExecutableDocument, OperationType, Selection},
+    Pos, Positioned,
+};
+use serde::{Deserialize, Serialize};
+
+type JsonValue = serde_json::Value;
+
+/// A problem found without generating SQL, pointing at a 1-based line and column when known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(message: impl Into<String>, pos: Option<Pos>) -> Self {
+        Self {
+            message: message.into(),
+            line: pos.map(|pos| pos.line),
+            column: pos.map(|pos| pos.column),
+        }
+    }
+}
+
+/// Parses a document, returning its syntax errors.
+pub fn parse_check(query: &str) -> Vec<Diagnostic> {
+    match async_graphql_parser::parse_query(query) {
+        Ok(_) => vec![],
+        Err(error) => vec![Diagnostic::new(error.to_string(), error.positions().next())],
+    }
+}
+
+fn check_fragments(items: &[Positioned<Selection>], diagnostics: &mut Vec<Diagnostic>) {
+    for selection in items {
+        match &selection.node {
+            Selection::Field(field) => {
+                check_fragments(&field.node.selection_set.node.items, diagnostics);
+            }
+            Selection::InlineFragment(fragment) => {
+                check_fragments(&fragment.node.selection_set.node.items, diagnostics);
+            }
+            Selection::FragmentSpread(_) => diagnostics.push(Diagnostic::new(
+                "Fragment spread is not supported",
+                Some(selection.pos),
+            )),
+        }
+    }
+}
+
+/// Checks an operation against the rules the translator enforces up front: the operation
+/// exists, required variables are provided, used variables are declared, root fields have
+/// valid `@meta` and no fragment spreads are used.
+pub fn validate(
+    ast: &ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<&str>,
+) -> Vec<Diagnostic> {
+    let operation = match get_operation(ast, operation_name) {
+        Ok(operation) => operation,
+        Err(error) => return vec![Diagnostic::new(error.to_string(), None)],
+    };
+    let mut diagnostics = vec![];
+    for definition in &operation.variable_definitions {
+        let definition = &definition.node;
+        let name = definition.name.node.as_str();
+        let provided = variables
+            .as_ref()
+            .and_then(|variables| variables.get(name))
+            .is_some_and(|value| !value.is_null());
+        if !definition.var_type.node.nullable && definition.default_value.is_none() && !provided {
+            diagnostics.push(Diagnostic::new(
+                format!(
+                    "Variable \"${}\" of required type \"{}\" was not provided",
+                    name, definition.var_type.node
+                ),
+                Some(definition.name.pos),
+            ));
+        }
+    }
+    if let Err(error) = check_declared(operation) {
+        diagnostics.push(Diagnostic::new(error.to_string(), None));
+    }
+    for selection in &operation.selection_set.node.items {
+        let Selection::Field(field) = &selection.node else {
+            diagnostics.push(Diagnostic::new(
+                "Fragment not supported",
+                Some(selection.pos),
+            ));
+            continue;
+        };
+        let meta = match operation.ty {
+            OperationType::Mutation => parse_mutation_meta(&field.node).map(|_| ()),
+            _ => parse_query_meta(&field.node).map(|_| ()),
+        };
+        if let Err(error) = meta {
+            diagnostics.push(Diagnostic::new(error.to_string(), Some(field.pos)));
+        }
+        check_fragments(&field.node.selection_set.node.items, &mut diagnostics);
+    }
+    diagnostics
+}
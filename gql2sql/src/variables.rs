@@ -0,0 +1,284 @@
+use crate::get_operation_mut;
+use anyhow::{anyhow, Result as AnyResult};
+use async_graphql_parser::{
+    types::{Directive, ExecutableDocument, OperationDefinition, Selection},
+    Positioned,
+};
+use async_graphql_value::{Name, Value as GqlValue};
+use std::fmt;
+
+type JsonValue = serde_json::Value;
+
+/// A variable that is used but not declared by the operation, or required and not provided.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownVariable {
+    pub variable: String,
+    /// Response path of the field using it, e.g. `User.posts`
+    pub field: String,
+}
+
+impl fmt::Display for UnknownVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown variable ${} used in {}",
+            self.variable, self.field
+        )
+    }
+}
+
+impl std::error::Error for UnknownVariable {}
+
+struct Known<'a> {
+    operation: &'a OperationDefinition,
+    /// The provided variables, or `None` to only look for undeclared ones
+    variables: Option<Option<&'a serde_json::Map<String, JsonValue>>>,
+}
+
+impl Known<'_> {
+    // an optional variable that was not provided stays null, as before
+    fn contains(&self, name: &Name) -> bool {
+        let Some(definition) = self
+            .operation
+            .variable_definitions
+            .iter()
+            .find(|d| d.node.name.node == *name)
+        else {
+            return false;
+        };
+        let Some(variables) = self.variables else {
+            return true;
+        };
+        let definition = &definition.node;
+        definition.var_type.node.nullable
+            || definition.default_value.is_some()
+            || variables.is_some_and(|variables| variables.contains_key(name.as_str()))
+    }
+
+    fn value<'v>(&self, value: &'v GqlValue) -> Option<&'v Name> {
+        match value {
+            GqlValue::Variable(name) => (!self.contains(name)).then_some(name),
+            GqlValue::List(list) => list.iter().find_map(|v| self.value(v)),
+            GqlValue::Object(object) => object.values().find_map(|v| self.value(v)),
+            _ => None,
+        }
+    }
+
+    fn directives<'v>(&self, directives: &'v [Positioned<Directive>]) -> Option<&'v Name> {
+        directives
+            .iter()
+            .flat_map(|d| d.node.arguments.iter())
+            .find_map(|(_, value)| self.value(&value.node))
+    }
+
+    fn items(&self, items: &[Positioned<Selection>], path: &str) -> Result<(), UnknownVariable> {
+        for selection in items {
+            match &selection.node {
+                Selection::Field(field) => {
+                    let field = &field.node;
+                    let key = field.alias.as_ref().unwrap_or(&field.name).node.as_str();
+                    let path = if path.is_empty() {
+                        key.to_string()
+                    } else {
+                        format!("{path}.{key}")
+                    };
+                    let unknown = field
+                        .arguments
+                        .iter()
+                        .find_map(|(_, value)| self.value(&value.node))
+                        .or_else(|| self.directives(&field.directives));
+                    if let Some(variable) = unknown {
+                        return Err(UnknownVariable {
+                            variable: variable.to_string(),
+                            field: path,
+                        });
+                    }
+                    self.items(&field.selection_set.node.items, &path)?;
+                }
+                Selection::InlineFragment(fragment) => {
+                    let fragment = &fragment.node;
+                    if let Some(variable) = self.directives(&fragment.directives) {
+                        return Err(UnknownVariable {
+                            variable: variable.to_string(),
+                            field: path.to_string(),
+                        });
+                    }
+                    self.items(&fragment.selection_set.node.items, path)?;
+                }
+                Selection::FragmentSpread(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the first variable the operation uses without declaring it, or requires without it
+/// being provided.
+pub(crate) fn check_variables(
+    operation: &OperationDefinition,
+    variables: &Option<JsonValue>,
+) -> Result<(), UnknownVariable> {
+    let known = Known {
+        operation,
+        variables: Some(variables.as_ref().and_then(JsonValue::as_object)),
+    };
+    known.items(&operation.selection_set.node.items, "")
+}
+
+/// Finds the first variable the operation uses without declaring it.
+pub(crate) fn check_declared(operation: &OperationDefinition) -> Result<(), UnknownVariable> {
+    let known = Known {
+        operation,
+        variables: None,
+    };
+    known.items(&operation.selection_set.node.items, "")
+}
+
+#[derive(Clone, Copy)]
+enum Literal {
+    Boolean,
+    Scalar,
+    Count,
+}
+
+impl Literal {
+    // the arguments that are read as literals, and what their variables must hold; `schema` is
+    // resolved by `apply_schemas`, and `table` and `function` are never taken from variables, so
+    // a client cannot pick what the operation reads
+    fn of(directive: &str, argument: &str) -> Option<Self> {
+        match (directive, argument) {
+            (
+                "meta",
+                "aggregate" | "single" | "count" | "exists" | "insert" | "update" | "delete"
+                | "claim",
+            )
+            | ("relation", "single" | "aggregate" | "many") => Some(Self::Boolean),
+            ("static", "value") => Some(Self::Scalar),
+            ("meta", "limit") => Some(Self::Count),
+            _ => None,
+        }
+    }
+
+    fn get_value(self, value: &JsonValue) -> Option<GqlValue> {
+        match (self, value) {
+            (Self::Scalar, JsonValue::String(s)) => Some(GqlValue::String(s.clone())),
+            (Self::Boolean | Self::Scalar, JsonValue::Bool(b)) => Some(GqlValue::Boolean(*b)),
+            (Self::Scalar, JsonValue::Number(n)) => Some(GqlValue::Number(n.clone())),
+            (Self::Count, JsonValue::Number(n)) if n.as_u64().is_some_and(|n| n > 0) => {
+                Some(GqlValue::Number(n.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn expected(self) -> &'static str {
+        match self {
+            Self::Boolean => "a boolean",
+            Self::Scalar => "a string, number or boolean",
+            Self::Count => "a positive integer",
+        }
+    }
+}
+
+struct Resolve<'a> {
+    variables: Option<&'a serde_json::Map<String, JsonValue>>,
+    // the defaults of the variables that are not provided
+    defaults: serde_json::Map<String, JsonValue>,
+}
+
+impl Resolve<'_> {
+    fn directive(&self, directive: &mut Positioned<Directive>) -> AnyResult<()> {
+        let pos = directive.pos;
+        let name = directive.node.name.node.to_string();
+        for (argument, value) in &mut directive.node.arguments {
+            let GqlValue::Variable(variable) = &value.node else {
+                continue;
+            };
+            let Some(literal) = Literal::of(&name, argument.node.as_str()) else {
+                continue;
+            };
+            let provided = self
+                .variables
+                .and_then(|variables| variables.get(variable.as_str()))
+                .or_else(|| self.defaults.get(variable.as_str()))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "@{} argument \"{}\" uses ${}, which is not provided at {}",
+                        name,
+                        argument.node,
+                        variable,
+                        pos
+                    )
+                })?;
+            value.node = literal.get_value(provided).ok_or_else(|| {
+                anyhow!(
+                    "@{} argument \"{}\" must be {}, got ${} = {} at {}",
+                    name,
+                    argument.node,
+                    literal.expected(),
+                    variable,
+                    provided,
+                    pos
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn items(&self, items: &mut [Positioned<Selection>]) -> AnyResult<()> {
+        for selection in items {
+            let (directives, items) = match &mut selection.node {
+                Selection::Field(field) => (
+                    &mut field.node.directives,
+                    &mut field.node.selection_set.node.items,
+                ),
+                Selection::InlineFragment(fragment) => (
+                    &mut fragment.node.directives,
+                    &mut fragment.node.selection_set.node.items,
+                ),
+                Selection::FragmentSpread(_) => continue,
+            };
+            for directive in directives {
+                self.directive(directive)?;
+            }
+            self.items(items)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the variables of the directive arguments that are read as literals, like
+/// `@meta(single:)` and `@static(value:)`, with their values, so they shape the query the same
+/// way literals do. Values of the wrong type are an error, as are variables that are
+/// neither provided nor defaulted.
+pub(crate) fn resolve_directive_variables(
+    ast: &mut ExecutableDocument,
+    variables: &Option<JsonValue>,
+    operation_name: Option<&str>,
+) -> AnyResult<()> {
+    let Some(operation) = get_operation_mut(ast, operation_name) else {
+        return Ok(());
+    };
+    let variables = variables.as_ref().and_then(JsonValue::as_object);
+    let mut defaults = serde_json::Map::new();
+    for definition in &operation.variable_definitions {
+        let definition = &definition.node;
+        if let Some(default) = &definition.default_value {
+            if !variables.is_some_and(|values| values.contains_key(definition.name.node.as_str())) {
+                defaults.insert(
+                    definition.name.node.to_string(),
+                    default.node.clone().into_json()?,
+                );
+            }
+        }
+    }
+    let resolve = Resolve {
+        variables,
+        defaults,
+    };
+    resolve.items(&mut operation.selection_set.node.items)?;
+    for fragment in ast.fragments.values_mut() {
+        resolve.items(&mut fragment.node.selection_set.node.items)?;
+    }
+    Ok(())
+}
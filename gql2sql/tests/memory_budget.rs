@@ -0,0 +1,39 @@
+//! `Options::memory_budget`, counted by `BudgetAllocator` as the global allocator of the test.
+//!
+//! Run with `cargo test -p gql2sql --features memory-budget --test memory_budget`.
+
+use gql2sql::{gql2sql_with_options, parse, BudgetAllocator, Options};
+use serde_json::json;
+
+#[global_allocator]
+static ALLOC: BudgetAllocator = BudgetAllocator::system();
+
+#[test]
+fn memory_budget() -> anyhow::Result<()> {
+    let fields = (0..200)
+        .map(|i| format!("f{i}: Post(filter: {{ field: \"id\", operator: \"eq\", value: $id }}) {{ id title }}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let query = format!("query Posts($id: String!) {{ {fields} }}");
+    let variables = Some(json!({ "id": "p1" }));
+    let generous = Options {
+        memory_budget: Some(64 << 20),
+        ..Options::default()
+    };
+    gql2sql_with_options(parse(&query)?, &variables, None, &generous)?;
+    let tight = Options {
+        memory_budget: Some(64 << 10),
+        ..Options::default()
+    };
+    let error = gql2sql_with_options(parse(&query)?, &variables, None, &tight)
+        .expect_err("the translation holds more than 64KB");
+    assert!(
+        error
+            .to_string()
+            .ends_with("exceeds the memory budget of 65536"),
+        "{error}"
+    );
+    // the budget ends with the translation
+    gql2sql_with_options(parse(&query)?, &variables, None, &Options::default())?;
+    Ok(())
+}
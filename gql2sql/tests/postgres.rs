@@ -0,0 +1,197 @@
+//! Round-trip tests: every `tests/postgres/cases/<name>.graphql` is translated, run against
+//! `tests/postgres/schema.sql` and its `data` column compared with `<name>.json`: as is, with
+//! single relations flattened to plain joins, and with `standard_conforming_strings` off, where
+//! backslashes in plain string literals are escapes.
+//!
+//! The SQL of every insta snapshot in `src/snapshots` is also prepared against the tables of
+//! `tests/postgres/snapshots.sql`, so Postgres parses and type checks what the snapshot suites
+//! only compare as text. The statements after a `-- snapshot: <name>` line of that file only
+//! run for the snapshot they name, for tests that assume other column types.
+//!
+//! Run with `cargo test -p gql2sql --features postgres-tests --test postgres`. A Postgres
+//! container is started unless `GQL2SQL_TEST_DATABASE_URL` points at a server to use instead.
+//! The round trip creates and drops the `gql2sql_test` schema of that database; the snapshot
+//! tables, and any schema they are qualified with, only exist in a transaction that is rolled
+//! back.
+
+use anyhow::Context;
+use gql2sql::{gql2sql_with_options, parse, Options};
+use postgres::{types::Type, Client, NoTls};
+use pretty_assertions::assert_eq;
+use serde_json::Value;
+use std::{collections::HashMap, fs, path::Path};
+use testcontainers_modules::{
+    postgres::Postgres,
+    testcontainers::{runners::SyncRunner, Container},
+};
+
+const URL_VAR: &str = "GQL2SQL_TEST_DATABASE_URL";
+const SCHEMA: &str = "gql2sql_test";
+
+// the container is dropped, and stopped, with the connection
+fn connect() -> anyhow::Result<(Client, Option<Container<Postgres>>)> {
+    if let Ok(url) = std::env::var(URL_VAR) {
+        return Ok((Client::connect(&url, NoTls)?, None));
+    }
+    let container = Postgres::default().start()?;
+    let url = format!(
+        "postgres://postgres:postgres@{}:{}/postgres",
+        container.get_host()?,
+        container.get_host_port_ipv4(5432)?
+    );
+    Ok((Client::connect(&url, NoTls)?, Some(container)))
+}
+
+// parameters are sent as text and converted by the `$n::type` casts of the statement
+fn to_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        value => Some(value.to_string()),
+    }
+}
+
+fn run_case(
+    client: &mut Client,
+    path: &Path,
+    options: &Options,
+    settings: &str,
+) -> anyhow::Result<()> {
+    let query = fs::read_to_string(path)?;
+    let expected: Value = serde_json::from_str(&fs::read_to_string(path.with_extension("json"))?)?;
+    let variables = Some(expected["variables"].clone());
+    let (statement, params, _tags, _is_mutation) =
+        gql2sql_with_options(parse(&query)?, &variables, None, options)?;
+    let values = params
+        .unwrap_or_default()
+        .iter()
+        .map(|param| to_text(&param.value))
+        .collect::<Vec<_>>();
+    let typed = values
+        .iter()
+        .map(|value| (value as &(dyn postgres::types::ToSql + Sync), Type::TEXT))
+        .collect::<Vec<_>>();
+    // each case sees the seed data, whatever the mutations of the others
+    let mut transaction = client.transaction()?;
+    transaction.batch_execute(settings)?;
+    let rows = transaction
+        .query_typed(&statement.to_string(), &typed)
+        .with_context(|| path.display().to_string())?;
+    let data: Value = rows
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("{} returned no rows", path.display()))?
+        .try_get("data")?;
+    transaction.rollback()?;
+    assert_eq!(data, expected["data"], "{}", path.display());
+    Ok(())
+}
+
+#[test]
+fn round_trip() -> anyhow::Result<()> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/postgres");
+    let (mut client, _container) = connect()?;
+    client.batch_execute(&format!(
+        "DROP SCHEMA IF EXISTS {SCHEMA} CASCADE; CREATE SCHEMA {SCHEMA}; SET search_path TO {SCHEMA};"
+    ))?;
+    client.batch_execute(&fs::read_to_string(dir.join("schema.sql"))?)?;
+    let mut cases = fs::read_dir(dir.join("cases"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    cases.retain(|path| path.extension().is_some_and(|ext| ext == "graphql"));
+    cases.sort();
+    assert!(!cases.is_empty());
+    let flattened = Options {
+        flatten_single_joins: true,
+        ..Options::default()
+    };
+    for path in cases {
+        run_case(&mut client, &path, &Options::default(), "")?;
+        run_case(&mut client, &path, &flattened, "")?;
+        run_case(
+            &mut client,
+            &path,
+            &Options::default(),
+            "SET LOCAL standard_conforming_strings = off",
+        )?;
+    }
+    client.batch_execute(&format!("DROP SCHEMA {SCHEMA} CASCADE;"))?;
+    Ok(())
+}
+
+// the SQL after the header of an insta snapshot, if the snapshot is of a statement
+fn snapshot_sql(snapshot: &str) -> Option<&str> {
+    let body = snapshot.splitn(3, "---\n").nth(2)?.trim();
+    let first = body.split_whitespace().next()?;
+    ["SELECT", "WITH", "INSERT", "UPDATE", "DELETE", "SET", "/*"]
+        .contains(&first)
+        .then_some(body)
+}
+
+// the statements of the schema for every snapshot, and those for the snapshots they name
+fn snapshot_schema(schema: &str) -> (&str, HashMap<&str, &str>) {
+    let mut sections = schema.split("\n-- snapshot: ");
+    let shared = sections.next().unwrap_or_default();
+    let named = sections
+        .filter_map(|section| section.split_once('\n'))
+        .map(|(name, statements)| (name.trim(), statements))
+        .collect();
+    (shared, named)
+}
+
+// settings are run, statements prepared: parsed and type checked without binding parameters
+fn check_snapshot(client: &mut postgres::Transaction, sql: &str) -> anyhow::Result<()> {
+    for statement in sql.split(";\n") {
+        if statement.starts_with("SET ") {
+            client.batch_execute(statement)?;
+        } else {
+            client.prepare(statement)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn snapshots() -> anyhow::Result<()> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let (mut client, _container) = connect()?;
+    let mut transaction = client.transaction()?;
+    transaction.batch_execute(
+        "CREATE SCHEMA gql2sql_snapshots; SET LOCAL search_path TO gql2sql_snapshots;",
+    )?;
+    let schema = fs::read_to_string(dir.join("tests/postgres/snapshots.sql"))?;
+    let (shared, mut named) = snapshot_schema(&schema);
+    transaction.batch_execute(shared)?;
+    let mut snapshots = fs::read_dir(dir.join("src/snapshots"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    snapshots.retain(|path| path.extension().is_some_and(|ext| ext == "snap"));
+    snapshots.sort();
+    let mut checked = 0;
+    let mut failures = vec![];
+    for path in snapshots {
+        let snapshot = fs::read_to_string(&path)?;
+        let Some(sql) = snapshot_sql(&snapshot) else {
+            continue;
+        };
+        checked += 1;
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.strip_prefix("gql2sql__tests__"))
+            .unwrap_or_default();
+        // a failed statement aborts the savepoint, not the snapshots after it
+        let mut savepoint = transaction.transaction()?;
+        if let Some(statements) = named.remove(name) {
+            savepoint.batch_execute(statements)?;
+        }
+        if let Err(error) = check_snapshot(&mut savepoint, sql) {
+            failures.push(format!("{}: {error:#}", path.display()));
+        }
+        savepoint.rollback()?;
+    }
+    transaction.rollback()?;
+    assert!(checked > 0);
+    assert!(named.is_empty(), "no snapshot named {:?}", named.keys());
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+    Ok(())
+}
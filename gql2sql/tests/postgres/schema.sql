@@ -0,0 +1,58 @@
+CREATE TABLE "User" (
+  "id" text PRIMARY KEY,
+  "name" text NOT NULL,
+  "age" integer
+);
+
+CREATE TABLE "Post" (
+  "id" text PRIMARY KEY,
+  "title" text NOT NULL,
+  "published" boolean NOT NULL DEFAULT false,
+  "authorId" text NOT NULL REFERENCES "User" ("id")
+);
+
+CREATE TABLE "Tag" (
+  "id" text PRIMARY KEY,
+  "name" text NOT NULL
+);
+
+CREATE TABLE "Profile" (
+  "userId" text PRIMARY KEY REFERENCES "User" ("id"),
+  "bio" text NOT NULL
+);
+
+CREATE TABLE "_PostToTag" (
+  "A" text NOT NULL REFERENCES "Post" ("id"),
+  "B" text NOT NULL REFERENCES "Tag" ("id")
+);
+
+CREATE UNIQUE INDEX "_PostToTag_AB_unique" ON "_PostToTag" ("A", "B");
+
+INSERT INTO "User" VALUES ('u1', 'Ada', 36), ('u2', 'Grace', 45), ('u3', 'Linus', NULL);
+
+INSERT INTO "Post" VALUES
+  ('p1', 'Notes on the engine', true, 'u1'),
+  ('p2', 'Draft', false, 'u1'),
+  ('p3', 'Compilers', true, 'u2');
+
+INSERT INTO "Profile" VALUES ('u1', 'Mathematician');
+
+INSERT INTO "Tag" VALUES ('t1', 'math'), ('t2', 'history');
+
+INSERT INTO "_PostToTag" VALUES ('p1', 't1'), ('p1', 't2'), ('p3', 't1');
+
+CREATE TABLE "File" (
+  "id" text PRIMARY KEY,
+  "name" text NOT NULL,
+  "content" bytea NOT NULL
+);
+
+INSERT INTO "File" VALUES ('f1', 'empty', '\x'), ('f2', 'magic', '\x89504e47');
+
+CREATE TABLE "Note" (
+  "id" text PRIMARY KEY,
+  "due" text NOT NULL,
+  "at" text
+);
+
+INSERT INTO "Note" VALUES ('n1', '2024-01-01', '09:30:00'), ('n2', 'someday', NULL);
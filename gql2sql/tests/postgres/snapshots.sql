@@ -0,0 +1,711 @@
+-- The tables, types and functions the SQL snapshots of src/snapshots reference. Columns are
+-- text unless a snapshot compares, aggregates or casts them as something else.
+
+CREATE TYPE "post_status" AS ENUM ('draft', 'published');
+
+-- stands in for pgvector, which the server may not have
+CREATE DOMAIN "vector" AS float8[];
+
+CREATE FUNCTION "vector_distance"(float8[], float8[]) RETURNS float8
+  LANGUAGE sql AS 'SELECT 0::float8';
+
+CREATE OPERATOR <-> (LEFTARG = float8[], RIGHTARG = float8[], FUNCTION = "vector_distance");
+
+CREATE FUNCTION "search_apps"(text, numeric) RETURNS TABLE ("id" text, "name" text)
+  LANGUAGE sql AS 'SELECT NULL::text, NULL::text';
+
+CREATE FUNCTION "app_owners"(text) RETURNS TABLE ("id" text, "appId" text)
+  LANGUAGE sql AS 'SELECT NULL::text, NULL::text';
+
+CREATE SCHEMA IF NOT EXISTS "analytics";
+
+CREATE TABLE "analytics"."Event" (
+  "id" text,
+  "kind" text,
+  "org_id" text
+);
+
+CREATE TABLE "AQfNfkgxq4iLcAhkdNAWf" (
+  "QJ3MwMUiXqrkPwb88eW8g" text,
+  "W3htYNGnCaJp4MAp6p6c9_id" text,
+  "id" text
+);
+
+CREATE TABLE "App" (
+  "analytics" text,
+  "branch" text,
+  "createdAt" text,
+  "customCSS" text,
+  "customDomain" text,
+  "description" text,
+  "favicon" text,
+  "id" text,
+  "metadata" jsonb,
+  "name" text,
+  "orgId" text,
+  "ownerId" text,
+  "secret" text,
+  "settings" jsonb,
+  "slug" text,
+  "theme" text
+);
+
+CREATE TABLE "Audit" (
+  "table" text
+);
+
+CREATE TABLE "Comment" (
+  "approved" boolean,
+  "body" text,
+  "id" text,
+  "points" integer,
+  "postId" text
+);
+
+CREATE TABLE "Component" (
+  "appId" text,
+  "branch" text,
+  "createdAt" text,
+  "id" text,
+  "kind" text,
+  "name" text,
+  "order" numeric,
+  "pageId" text,
+  "parentId" text,
+  "status" text,
+  "tags" jsonb,
+  "title" text,
+  "version" integer,
+  "orderKey" text
+);
+
+CREATE TABLE "ComponentMeta" (
+  "branch" text,
+  "componentId" text,
+  "id" text,
+  "title" text
+);
+
+CREATE TABLE "Connection" (
+  "appId" text,
+  "branch" text,
+  "id" text,
+  "kind" text,
+  "name" text,
+  "prodUrl" text
+);
+
+CREATE TABLE "Device" (
+  "uuid" text
+);
+
+CREATE TABLE "Element" (
+  "branch" text,
+  "componentId" text,
+  "componentParentId" text,
+  "conditions" text,
+  "id" text,
+  "kind" text,
+  "name" text,
+  "order" text,
+  "props" text,
+  "source" text,
+  "styles" text
+);
+
+CREATE TABLE "Endpoint" (
+  "branch" text,
+  "connectionId" text,
+  "id" text,
+  "method" text,
+  "name" text,
+  "path" text,
+  "responseSchemaId" text
+);
+
+CREATE TABLE "Event" (
+  "branch" text,
+  "closesAt" timetz,
+  "componentMetaId" text,
+  "createdAt" timestamptz,
+  "day" date,
+  "endsAt" timestamptz,
+  "help" text,
+  "id" text,
+  "label" text,
+  "name" text,
+  "opensAt" time,
+  "seenAt" timestamptz,
+  "startsAt" timestamptz,
+  "type" text,
+  "updatedAt" timestamptz,
+  "userId" text
+);
+
+CREATE TABLE "File" (
+  "content" text,
+  "id" text,
+  "magic" text,
+  "name" text,
+  "size" text
+);
+
+CREATE TABLE "Fjjm3XAhyDmbhzymrrkRT" (
+  "XF4f6Qrhk86AX6dFWjYDt" numeric,
+  "id" text
+);
+
+CREATE TABLE "H33iDwNVqqMxAnVEgPaTh" (
+  "FwpKpCegQH4EkzbjbNqVn" text,
+  "Gb8jAGqGDbYqfeqDDxKUF_id" text,
+  "HYWfawTyxPNUf9a4DAH79" text,
+  "LJDX6neXAYeXt9aVWxTRk" text,
+  "MiyNcUJzKGJgQ9BERD8fr_id" text,
+  "TbFeY8XVMaYnkQjDPWMkb_id" text,
+  "ayipLT8iKHNTdhmiVqmxq" text,
+  "d8GJJg9DjNehPAeJcpTjM" text,
+  "egeyQ33H3z4EqzcRVFchV" text,
+  "id" text,
+  "q6pJYTjmbprTNRdqG9Jrw" text,
+  "r7xwAFrckDaVLwPzUAADB" text,
+  "zFjEBPkLYmEAxLHrt3N4B" text
+);
+
+CREATE TABLE "Header" (
+  "branch" text,
+  "dynamic" text,
+  "id" text,
+  "key" text,
+  "parentConnectionId" text,
+  "parentEndpointId" text,
+  "value" text
+);
+
+CREATE TABLE "Job" (
+  "createdAt" text,
+  "id" text,
+  "payload" text,
+  "queue" text,
+  "status" text,
+  "worker" text,
+  "orgId" text,
+  "seq" integer
+);
+
+CREATE TABLE "LC4PdkWrXEq6PnJNF98RE" (
+  "W3htYNGnCaJp4MAp6p6c9_id" text,
+  "t473xCb8nhWCxX7Ag7k6q_id" text,
+  "xVAFwi3LkLnRYqtkV3e9A_id" text
+);
+
+CREATE TABLE "Launch" (
+  "createdAt" timestamptz,
+  "id" text,
+  "updatedAt" timestamptz
+);
+
+CREATE TABLE "Layout" (
+  "appId" text,
+  "branch" text,
+  "id" text,
+  "kind" text,
+  "name" text,
+  "props" text,
+  "source" text,
+  "styles" text
+);
+
+CREATE TABLE "MdYg7jdht8ByhnKdfXBAb" (
+  "H6hp6JGhzgPTYmLYwLk8P" text,
+  "id" text
+);
+
+CREATE TABLE "Member" (
+  "id" text,
+  "teamId" text
+);
+
+CREATE TABLE "Membership" (
+  "orgId" text,
+  "role" text,
+  "userId" text
+);
+
+CREATE TABLE "Mr3R877DKbWTNWRzmEjxE" (
+  "id" text
+);
+
+CREATE TABLE "N8Ag4Vgad4rYwcRmMJhGR" (
+  "id" text,
+  "value" numeric
+);
+
+CREATE TABLE "Order" (
+  "customer" text,
+  "customerId" text,
+  "id" text,
+  "total" numeric
+);
+
+CREATE TABLE "Org" (
+  "name" text,
+  "slug" text
+);
+
+CREATE TABLE "Page" (
+  "appId" text,
+  "draft" boolean,
+  "id" text,
+  "layout" text,
+  "name" text,
+  "orgId" text,
+  "ownerId" text,
+  "title" text
+);
+
+CREATE TABLE "PageMeta" (
+  "branch" text,
+  "componentId" text,
+  "description" text,
+  "id" text,
+  "loader" text,
+  "maxAge" text,
+  "path" text,
+  "protection" text,
+  "sMaxAge" text,
+  "socialImage" text,
+  "staleWhileRevalidate" text,
+  "title" text,
+  "urlParams" text
+);
+
+CREATE TABLE "Plugin" (
+  "appId" text,
+  "branch" text,
+  "instanceId" text,
+  "kind" text
+);
+
+CREATE TABLE "Post" (
+  "archived" boolean,
+  "authorId" text,
+  "createdAt" text,
+  "data" jsonb,
+  "flags" jsonb,
+  "history" jsonb,
+  "id" text,
+  "labels" text[],
+  "meta" jsonb,
+  "published" boolean,
+  "settings" jsonb,
+  "status" post_status,
+  "tags" text[],
+  "title" text,
+  "views" integer
+);
+
+CREATE TABLE "Profile" (
+  "bio" text,
+  "userId" text,
+  "title" text
+);
+
+CREATE TABLE "Project" (
+  "id" text,
+  "name" text,
+  "orgId" text,
+  "org_id" text,
+  "ownerId" text,
+  "public" boolean,
+  "status" text
+);
+
+CREATE TABLE "Project_7a6b5c4d3e2f1a0b9c8d7e6f" (
+  "id" text,
+  "workspaceId" text
+);
+
+CREATE TABLE "Prop" (
+  "componentId" text,
+  "hidden" boolean
+);
+
+CREATE TABLE "Record" (
+  "age" text,
+  "id" text,
+  "name" text
+);
+
+CREATE TABLE "Row" (
+  "email" text,
+  "id" text,
+  "parentId" text
+);
+
+CREATE TABLE "Sale" (
+  "amount" numeric,
+  "product" text,
+  "region" text,
+  "soldAt" timestamptz
+);
+
+CREATE TABLE "Schema" (
+  "appId" text,
+  "branch" text,
+  "id" text,
+  "mutationConnectionId" text,
+  "schema" text
+);
+
+CREATE TABLE "Search" (
+  "branch" text,
+  "dynamic" text,
+  "endpointId" text,
+  "id" text,
+  "key" text,
+  "value" text
+);
+
+CREATE TABLE "Settings" (
+  "ownerId" text,
+  "theme" text
+);
+
+CREATE TABLE "Signup" (
+  "createdAt" timestamptz,
+  "ownerId" text,
+  "plan" text,
+  "region" text
+);
+
+CREATE TABLE "Source" (
+  "branch" text,
+  "componentId" text,
+  "description" text,
+  "id" text,
+  "instanceTemplate" text,
+  "name" text,
+  "outputType" text,
+  "provider" text,
+  "source" text,
+  "sourceProp" text,
+  "template" text,
+  "utilityId" text
+);
+
+CREATE TABLE "StatusChange" (
+  "createdAt" text,
+  "memberId" text,
+  "state" text
+);
+
+CREATE TABLE "Step" (
+  "branch" text,
+  "data" text,
+  "filter" text,
+  "id" text,
+  "kind" text,
+  "kindId" text,
+  "order" numeric,
+  "parentId" text,
+  "template_BahPd_id" text,
+  "workflowId" text
+);
+
+CREATE TABLE "Stuff" (
+  "componentId" text,
+  "id" text
+);
+
+CREATE TABLE "Style" (
+  "appId" text,
+  "branch" text,
+  "id" text,
+  "isDefault" text,
+  "kind" text,
+  "name" text,
+  "styles" text
+);
+
+CREATE TABLE "Task" (
+  "archived" boolean,
+  "done" boolean,
+  "due_date_9KpQ1" text,
+  "estimate" integer,
+  "id" text,
+  "name" text,
+  "org_id" text,
+  "ownerId" text,
+  "owner_id_Lm3" text,
+  "projectId" text,
+  "status" text,
+  "status_x7Rt2" text
+);
+
+CREATE TABLE "Task_0f1e2d3c4b5a69788796a5b4" (
+  "id" text,
+  "projectId" text
+);
+
+CREATE TABLE "Team" (
+  "name" text,
+  "orgSlug" text
+);
+
+CREATE TABLE "Template" (
+  "id" text,
+  "layout" text,
+  "name" text,
+  "org_id" text
+);
+
+CREATE TABLE "U7BBKiUwTgwiWMcgUYA4C" (
+  "BtaHL8fRtKFw8gDJULFYp" text,
+  "id" text
+);
+
+CREATE TABLE "UcwtYEtmmpXagcpcRiYKC" (
+  "created_at" text,
+  "id" text,
+  "updated_at" text,
+  "xb8nemrkchVQgxkXkCPhE" text
+);
+
+CREATE TABLE "User" (
+  "age" text,
+  "createdAt" text,
+  "created_at" text,
+  "display_name_Vb8" text,
+  "gnHezR9MdBFH9kCthN3aB" text,
+  "id" text,
+  "name" text,
+  "ownerId" text,
+  "uid" text,
+  "email" text,
+  "profile_image_url" text,
+  "updated_at" timestamptz
+);
+
+CREATE TABLE "Utility" (
+  "branch" text,
+  "data" text,
+  "id" text,
+  "kind" text,
+  "kindId" text,
+  "name" text
+);
+
+CREATE TABLE "WFqGH6dk8MpxfpHXh7awi" (
+  "MHPB9NP84gr3eXBmBfbxh_id" text,
+  "QYtpTcmJCe6zfCHWwpNjR" text,
+  "a8heQgUMyFync44JACwKA" text,
+  "id" text,
+  "updated_at" text,
+  "ynWfqMzGjjVQYzbKx4rMX" text
+);
+
+CREATE TABLE "Workflow" (
+  "appId" text,
+  "args" text,
+  "branch" text,
+  "id" text,
+  "name" text
+);
+
+CREATE TABLE "Workspace_2f9c1d7e4b8a6035e1d2c3b4" (
+  "id" text
+);
+
+CREATE TABLE "_H33iDwNVqqMxAnVEgPaThToMr3R877DKbWTNWRzmEjxE" (
+  "A" text,
+  "B" text
+);
+
+CREATE TABLE "_UserTowrHJEgwMUmdJ3eWtPLPk8" (
+  "A" text,
+  "B" text
+);
+
+CREATE TABLE "fHWzqqUgtLRk88TzxQwfy" (
+  "hx8if7CyVtb4jrN4DXgie_id" text,
+  "id" text,
+  "created_at" timestamptz,
+  "updated_at" timestamptz
+);
+
+CREATE TABLE "fTgjFRxYgaj3qHriEdQi3" (
+  "id" text,
+  "t473xCb8nhWCxX7Ag7k6q_id" text,
+  "tcGyWe4CLwhpTJp4krApd" text
+);
+
+CREATE TABLE "iYrk3kyTqaDQrLgjDaE9n" (
+  "eT86hgrpFB49r7N6AXz63" text,
+  "id" text
+);
+
+CREATE TABLE "jddN4iWUQgbFHi8edGyMd" (
+  "id" text,
+  "Y4MGwWgpeRU6kCAqbyDgz" text,
+  "created_at" timestamptz,
+  "updated_at" timestamptz
+);
+
+CREATE TABLE "launch_pad" (
+  "id" text,
+  "pad_number" text
+);
+
+CREATE TABLE "launch_site" (
+  "created_at" text,
+  "id" text,
+  "launch_site_id" text,
+  "name" text
+);
+
+CREATE TABLE "mission" (
+  "missionName" text
+);
+
+CREATE TABLE "wrHJEgwMUmdJ3eWtPLPk8" (
+  "id" text
+);
+
+CREATE SCHEMA IF NOT EXISTS "Blog";
+
+CREATE TABLE "Blog"."Comment" (
+  "authorId" text,
+  "postId" text
+);
+
+CREATE TABLE "Blog"."Post" (
+  "authorId" text,
+  "createdAt" text,
+  "id" text
+);
+
+CREATE TABLE "Blog"."Tag" (
+  "id" text
+);
+
+CREATE TABLE "Blog"."_PostToTag" (
+  "A" text,
+  "B" text
+);
+
+CREATE TABLE "Blog"."comment" (
+  "body" text,
+  "postId" text
+);
+
+CREATE SCHEMA IF NOT EXISTS "app";
+
+CREATE TABLE "app"."Component" (
+  "id" text
+);
+
+CREATE SCHEMA IF NOT EXISTS "auth";
+
+CREATE TABLE "auth"."Hero" (
+  "name" text,
+  "number_of_movies" integer,
+  "secret_identity" text,
+  "updated_at" timestamptz
+);
+
+CREATE TABLE "auth"."User" (
+  "id" text,
+  "name" text,
+  "org_id" text
+);
+
+CREATE TABLE "auth"."Villain" (
+  "id" text,
+  "name" text
+);
+
+CREATE TABLE "auth"."sessions" (
+  "expires" text,
+  "sessionToken" text,
+  "userId" text
+);
+
+CREATE TABLE "auth"."users" (
+  "email" text,
+  "emailVerified" text,
+  "id" text,
+  "image" text,
+  "name" text
+);
+
+CREATE TABLE "auth"."verification_tokens" (
+  "expires" text,
+  "identifier" text,
+  "token" text
+);
+
+CREATE SCHEMA IF NOT EXISTS "billing";
+
+CREATE TABLE "billing"."Plan" (
+  "id" text
+);
+
+CREATE SCHEMA IF NOT EXISTS "blog";
+
+CREATE TABLE "blog"."comment" (
+  "body" text,
+  "postid" text
+);
+
+CREATE TABLE "blog"."post" (
+  "authorid" text,
+  "createdat" text,
+  "id" text
+);
+
+-- public always exists and may already have a "User"
+CREATE TABLE IF NOT EXISTS "public"."User" (
+  "id" text
+);
+
+CREATE SCHEMA IF NOT EXISTS "tenant_42";
+
+CREATE TABLE "tenant_42"."App" (
+  "id" text
+);
+
+CREATE TABLE "tenant_42"."Component" (
+  "appId" text,
+  "id" text,
+  "ownerId" text
+);
+
+
+CREATE TABLE "Session" (
+  "id" text
+);
+
+CREATE TABLE "Hero" (
+  "id" text
+);
+
+CREATE TABLE "Setting" (
+  "id" text,
+  "appId" text
+);
+
+CREATE TABLE "_PostToTag" (
+  "A" text,
+  "B" text
+);
+
+CREATE TABLE "Document" (
+  "id" text,
+  "embedding" vector
+);
+
+-- snapshot: query_filter_type_hint
+ALTER TABLE "Post" ALTER COLUMN "id" TYPE integer USING NULL;
+
+-- snapshot: query_format_date-2
+ALTER TABLE "Launch" ALTER COLUMN "createdAt" TYPE timestamp;
@@ -0,0 +1,284 @@
+//! Property tests: random documents over a small schema, with random variables, must either
+//! translate to SQL that parses again or fail with an error, never panic.
+
+use gql2sql::{
+    gql2sql, parse,
+    sqlparser::{dialect::PostgreSqlDialect, parser::Parser, tokenizer::Tokenizer},
+};
+use proptest::prelude::*;
+use serde_json::{json, Value};
+
+const TABLES: &[&str] = &["User", "Post", "Tag"];
+const COLUMNS: &[&str] = &["id", "name", "age", "title", "authorId", "createdAt"];
+const OPERATORS: &[&str] = &[
+    "eq", "neq", "lt", "lte", "gt", "gte", "like", "ilike", "in", "not_in", "null", "not_null",
+];
+const VARIABLES: &[&str] = &["a", "b", "c"];
+
+fn json_value() -> impl Strategy<Value = Value> {
+    let leaf = prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        any::<f64>()
+            .prop_filter("finite", |f| f.is_finite())
+            .prop_map(Value::from),
+        "[a-zA-Z0-9 '\"\\\\%_:-]{0,8}".prop_map(Value::from),
+        Just(json!("2024-01-02T03:04:05Z")),
+    ];
+    leaf.prop_recursive(2, 8, 3, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..3).prop_map(Value::from),
+            prop::collection::vec((prop::sample::select(COLUMNS), inner), 0..3).prop_map(
+                |entries| {
+                    Value::Object(
+                        entries
+                            .into_iter()
+                            .map(|(k, v)| (k.to_string(), v))
+                            .collect(),
+                    )
+                }
+            ),
+        ]
+    })
+}
+
+// a GraphQL input value, written as source text
+fn literal() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        Just("null".to_string()),
+        any::<bool>().prop_map(|b| b.to_string()),
+        any::<i64>().prop_map(|i| i.to_string()),
+        any::<f64>()
+            .prop_filter("finite", |f| f.is_finite())
+            .prop_map(|f| format!("{f:?}")),
+        "[a-zA-Z0-9 '\"\\\\%_:-]{0,8}".prop_map(|s| Value::from(s).to_string()),
+        prop::sample::select(VARIABLES).prop_map(|v| format!("${v}")),
+        Just("ASC".to_string()),
+    ];
+    leaf.prop_recursive(2, 8, 3, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..3).prop_map(|l| format!("[{}]", l.join(", "))),
+            prop::collection::vec((prop::sample::select(COLUMNS), inner), 0..3).prop_map(
+                |entries| {
+                    let fields = entries
+                        .into_iter()
+                        .map(|(k, v)| format!("{k}: {v}"))
+                        .collect::<Vec<_>>();
+                    format!("{{ {} }}", fields.join(", "))
+                }
+            ),
+        ]
+    })
+}
+
+// mostly real columns, sometimes any string or a variable
+fn field() -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => prop::sample::select(COLUMNS).prop_map(|c| format!("\"{c}\"")),
+        1 => "[a-zA-Z '\"\\\\;-]{0,6}".prop_map(|s| Value::from(s).to_string()),
+        1 => prop::sample::select(VARIABLES).prop_map(|v| format!("${v}")),
+    ]
+}
+
+fn operator() -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => prop::sample::select(OPERATORS).prop_map(|o| format!("\"{o}\"")),
+        1 => prop::sample::select(&["@>", "<@", "~", "~*", "!~", "&&", "||"][..])
+            .prop_map(|o| format!("\"{o}\"")),
+        // not operators, so they must be rejected rather than written into the SQL
+        1 => "[a-z' ;]{1,6}".prop_map(|s| Value::from(s).to_string()),
+    ]
+}
+
+fn filter() -> impl Strategy<Value = String> {
+    let leaf = (field(), operator(), literal()).prop_map(|(field, operator, value)| {
+        format!("{{ field: {field}, operator: {operator}, value: {value} }}")
+    });
+    leaf.prop_recursive(2, 6, 2, |inner| {
+        (
+            field(),
+            operator(),
+            literal(),
+            prop::sample::select(&["AND", "OR"][..]),
+            prop::collection::vec(inner, 1..3),
+        )
+            .prop_map(|(field, operator, value, logical, children)| {
+                format!(
+                    r#"{{ field: {field}, operator: {operator}, value: {value}, logicalOperator: "{logical}", children: [{}] }}"#,
+                    children.join(", ")
+                )
+            })
+    })
+}
+
+fn arguments() -> impl Strategy<Value = String> {
+    (
+        prop::option::of(filter()),
+        prop::option::of((
+            prop::sample::select(COLUMNS),
+            prop::sample::select(&["ASC", "DESC"][..]),
+        )),
+        prop::option::of(literal()),
+        prop::option::of(literal()),
+        prop::option::of(literal()),
+    )
+        .prop_map(|(filter, order, first, offset, id)| {
+            let mut args = vec![];
+            if let Some(filter) = filter {
+                args.push(format!("filter: {filter}"));
+            }
+            if let Some((column, dir)) = order {
+                args.push(format!("order: {{ {column}: {dir} }}"));
+            }
+            if let Some(first) = first {
+                args.push(format!("first: {first}"));
+            }
+            if let Some(offset) = offset {
+                args.push(format!("offset: {offset}"));
+            }
+            if let Some(id) = id {
+                args.push(format!("id: {id}"));
+            }
+            if args.is_empty() {
+                String::new()
+            } else {
+                format!("({})", args.join(", "))
+            }
+        })
+}
+
+fn selection() -> impl Strategy<Value = String> {
+    let column = (
+        prop::option::of("[a-z]{1,4}"),
+        prop::sample::select(COLUMNS),
+    )
+        .prop_map(|(alias, column)| match alias {
+            Some(alias) => format!("{alias}_: {column}"),
+            None => column.to_string(),
+        });
+    let columns = prop::collection::vec(prop_oneof![column, Just("__typename".to_string())], 1..4)
+        .prop_map(|columns| columns.join(" "));
+    columns.prop_recursive(2, 6, 2, |inner| {
+        (
+            inner.clone(),
+            "[a-z]{1,4}",
+            arguments(),
+            prop::sample::select(TABLES),
+            prop::sample::select(COLUMNS),
+            prop::sample::select(COLUMNS),
+            any::<bool>(),
+            inner,
+        )
+            .prop_map(
+                |(columns, name, args, table, field, reference, single, children)| {
+                    format!(
+                        r#"{columns} {name}{args} @relation(table: "{table}", field: ["{field}"], references: ["{reference}"], single: {single}) {{ {children} }}"#
+                    )
+                },
+            )
+    })
+}
+
+fn query() -> impl Strategy<Value = String> {
+    let root = (
+        prop::sample::select(TABLES),
+        prop::sample::select(&["", "_one", "_count", "_exists"][..]),
+        arguments(),
+        selection(),
+    )
+        .prop_map(|(table, suffix, args, selection)| {
+            let selection = if ["_count", "_exists"].contains(&suffix) {
+                String::new()
+            } else {
+                format!("{{ {selection} }}")
+            };
+            format!("{table}{suffix}{args} {selection}")
+        });
+    prop::collection::vec(root, 1..3).prop_map(|roots| {
+        format!(
+            "query Q($a: JSON, $b: JSON, $c: JSON) {{ {} }}",
+            roots.join(" ")
+        )
+    })
+}
+
+fn mutation() -> impl Strategy<Value = String> {
+    let root = (
+        prop::sample::select(TABLES),
+        prop::sample::select(&["insert", "update", "delete"][..]),
+        prop::option::of(filter()),
+        literal(),
+        selection(),
+    )
+        .prop_map(|(table, kind, filter, value, selection)| {
+            let args = match (kind, filter) {
+                ("insert", _) => format!("(data: {value})"),
+                ("update", Some(filter)) => format!("(filter: {filter}, set: {value})"),
+                ("update", None) => format!("(set: {value})"),
+                (_, Some(filter)) => format!("(filter: {filter})"),
+                (_, None) => String::new(),
+            };
+            format!("{kind}_{table}{args} {{ {selection} }}")
+        });
+    prop::collection::vec(root, 1..3).prop_map(|roots| {
+        format!(
+            "mutation M($a: JSON, $b: JSON, $c: JSON) {{ {} }}",
+            roots.join(" ")
+        )
+    })
+}
+
+fn variables() -> impl Strategy<Value = Option<Value>> {
+    prop::option::of(
+        prop::collection::vec((prop::sample::select(VARIABLES), json_value()), 0..3).prop_map(
+            |entries| {
+                Value::Object(
+                    entries
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v))
+                        .collect(),
+                )
+            },
+        ),
+    )
+}
+
+fn check(document: &str, variables: &Option<Value>) -> Result<(), TestCaseError> {
+    let Ok(ast) = parse(document) else {
+        return Ok(());
+    };
+    if let Ok((statement, _params, _tags, _is_mutation)) = gql2sql(ast, variables, None) {
+        let sql = statement.to_string();
+        let dialect = PostgreSqlDialect {};
+        // sqlparser cannot parse a DELETE inside WITH, which Postgres accepts
+        let result = if sql.contains("AS (DELETE") {
+            Tokenizer::new(&dialect, &sql)
+                .tokenize()
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        } else {
+            Parser::parse_sql(&dialect, &sql)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        };
+        if let Err(e) = result {
+            return Err(TestCaseError::fail(format!("{e}: {sql}")));
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn queries_translate_or_fail(document in query(), variables in variables()) {
+        check(&document, &variables)?;
+    }
+
+    #[test]
+    fn mutations_translate_or_fail(document in mutation(), variables in variables()) {
+        check(&document, &variables)?;
+    }
+}
@@ -51,7 +51,55 @@ const _lib = await prepare(opts, {
     result: "pointer",
     nonblocking: false,
   },
+  translate: {
+    parameters: ["pointer", "usize"],
+    result: "pointer",
+    nonblocking: false,
+  },
 })
+/**
+ * A cache tag of the result, formatted in `tags` as `type:{table}:{key}:{value}`.
+ */
+export type CacheTag = {
+  table: string
+  key: string | undefined | null
+  value: string | undefined | null
+}
+/**
+ * The translated statement, or `error` when the query could not be translated.
+ */
+export type GqlResult = {
+  sql: string
+  params: Array<any> | undefined | null
+  paramTypes: Array<string> | undefined | null
+  paramNames: Array<string> | undefined | null
+  tags: Array<string> | undefined | null
+  cacheTags: Array<CacheTag> | undefined | null
+  isMutation: boolean
+  referencedObjects: ReferencedObjects | undefined | null
+  error: string | undefined | null
+}
+export type Input = {
+  query: string
+  variables: any | undefined | null
+  operationName: string | undefined | null
+}
+/**
+ * The schemas, tables and columns the statement references, for authorization checks.
+ */
+export type ReferencedObjects = {
+  schemas: Array<string>
+  tables: Array<TableColumns>
+}
+/**
+ * A table the statement reads or writes, with the columns it references; `*` stands for a
+ * `RETURNING *`.
+ */
+export type TableColumns = {
+  schema: string | undefined | null
+  table: string
+  columns: Array<string>
+}
 
 export function gql2sql(a0: string) {
   const a0_buf = encode(a0)
@@ -60,3 +108,10 @@ export function gql2sql(a0: string) {
   const result = readPointer(rawResult)
   return decode(result)
 }
+export function translate(a0: Input) {
+  const a0_buf = encode(JSON.stringify(a0))
+  const a0_ptr = Deno.UnsafePointer.of(a0_buf)
+  let rawResult = _lib.symbols.translate(a0_ptr, a0_buf.byteLength)
+  const result = readPointer(rawResult)
+  return JSON.parse(decode(result)) as GqlResult
+}
@@ -1,11 +1,130 @@
-use async_graphql_parser::parse_query;
+// deno_bindgen generates the FFI wrappers that take raw pointers
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use deno_bindgen::deno_bindgen;
-use gql2sql::gql2sql as gql2sql_rs;
+use gql2sql::{get_referenced_objects, gql2sql as gql2sql_rs, parse, to_sql, Options};
+use serde_json::Value;
 
 #[deno_bindgen]
 pub fn gql2sql(code: &str) -> String {
-    let gqlast = parse_query(code).expect("Failed to parse query");
+    let gqlast = parse(code).expect("Failed to parse query");
     let (statement, _params, _tags, _is_mutation) =
         gql2sql_rs(gqlast, &None, None).expect("Failed to convert query");
-    statement.to_string()
+    to_sql(&statement, &Options::default())
+}
+
+#[deno_bindgen]
+#[serde(rename_all = "camelCase")]
+pub struct Input {
+    query: String,
+    variables: Option<Value>,
+    operation_name: Option<String>,
+}
+
+/// A cache tag of the result, formatted in `tags` as `type:{table}:{key}:{value}`.
+#[deno_bindgen]
+pub struct CacheTag {
+    table: String,
+    key: Option<String>,
+    value: Option<String>,
+}
+
+impl From<gql2sql::CacheTag> for CacheTag {
+    fn from(tag: gql2sql::CacheTag) -> Self {
+        CacheTag {
+            table: tag.table,
+            key: tag.key,
+            value: tag.value,
+        }
+    }
+}
+
+/// A table the statement reads or writes, with the columns it references; `*` stands for a
+/// `RETURNING *`.
+#[deno_bindgen]
+pub struct TableColumns {
+    schema: Option<String>,
+    table: String,
+    columns: Vec<String>,
+}
+
+impl From<gql2sql::TableColumns> for TableColumns {
+    fn from(table: gql2sql::TableColumns) -> Self {
+        TableColumns {
+            schema: table.schema,
+            table: table.table,
+            columns: table.columns,
+        }
+    }
+}
+
+/// The schemas, tables and columns the statement references, for authorization checks.
+#[deno_bindgen]
+pub struct ReferencedObjects {
+    schemas: Vec<String>,
+    tables: Vec<TableColumns>,
+}
+
+impl From<gql2sql::ReferencedObjects> for ReferencedObjects {
+    fn from(objects: gql2sql::ReferencedObjects) -> Self {
+        ReferencedObjects {
+            schemas: objects.schemas,
+            tables: objects.tables.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// The translated statement, or `error` when the query could not be translated.
+#[deno_bindgen]
+#[serde(rename_all = "camelCase")]
+pub struct GqlResult {
+    sql: String,
+    params: Option<Vec<Value>>,
+    param_types: Option<Vec<String>>,
+    param_names: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    cache_tags: Option<Vec<CacheTag>>,
+    is_mutation: bool,
+    referenced_objects: Option<ReferencedObjects>,
+    error: Option<String>,
+}
+
+fn run(input: Input) -> anyhow::Result<GqlResult> {
+    let ast = parse(&input.query)?;
+    let (statement, params, tags, is_mutation) =
+        gql2sql_rs(ast, &input.variables, input.operation_name)?;
+    let param_types = params
+        .as_ref()
+        .map(|p| p.iter().map(|p| p.sql_type.clone()).collect());
+    let param_names = params
+        .as_ref()
+        .map(|p| p.iter().map(|p| p.name.clone()).collect());
+    Ok(GqlResult {
+        sql: to_sql(&statement, &Options::default()),
+        params: params.map(|p| p.into_iter().map(|p| p.value).collect()),
+        param_types,
+        param_names,
+        tags: tags
+            .as_ref()
+            .map(|t| t.iter().map(ToString::to_string).collect()),
+        cache_tags: tags.map(|tags| tags.into_iter().map(CacheTag::from).collect()),
+        is_mutation,
+        referenced_objects: Some(get_referenced_objects(&statement).into()),
+        error: None,
+    })
+}
+
+#[deno_bindgen]
+pub fn translate(input: Input) -> GqlResult {
+    run(input).unwrap_or_else(|error| GqlResult {
+        sql: String::new(),
+        params: None,
+        param_types: None,
+        param_names: None,
+        tags: None,
+        cache_tags: None,
+        is_mutation: false,
+        referenced_objects: None,
+        error: Some(error.to_string()),
+    })
 }
@@ -1,5 +1,5 @@
 import { assertEquals } from "https://deno.land/std@0.173.0/testing/asserts.ts";
-import { gql2sql } from "../bindings/bindings.ts";
+import { gql2sql, translate } from "../bindings/bindings.ts";
 
 Deno.test("sync function from native code", () => {
   const fixture = `
@@ -32,3 +32,25 @@ Deno.test("sync function from native code", () => {
     `SELECT json_build_object('App', (SELECT coalesce(json_agg(to_json((SELECT "root" FROM (SELECT "base"."id", "components") AS "root"))), '[]') AS "root" FROM (SELECT * FROM "App" WHERE "id" = '345810043118026832' ORDER BY "name" ASC) AS "base" LEFT JOIN LATERAL (SELECT coalesce(json_agg(to_json((SELECT "root" FROM (SELECT "base.Component"."id", "pageMeta", "elements") AS "root"))), '[]') AS "components" FROM (SELECT * FROM "Component" WHERE "Component"."appId" = "base"."id") AS "base.Component" LEFT JOIN LATERAL (SELECT to_json((SELECT "root" FROM (SELECT "base.Component.PageMeta"."id", "base.Component.PageMeta"."path") AS "root")) AS "pageMeta" FROM (SELECT * FROM "PageMeta" WHERE "PageMeta"."componentId" = "base.Component"."id" LIMIT 1) AS "base.Component.PageMeta") AS "root.PageMeta" ON ('true') LEFT JOIN LATERAL (SELECT coalesce(json_agg(to_json((SELECT "root" FROM (SELECT "base.Component.Element"."id", "base.Component.Element"."name") AS "root"))), '[]') AS "elements" FROM (SELECT * FROM "Element" WHERE "Element"."componentParentId" = "base.Component"."id" ORDER BY "order" ASC) AS "base.Component.Element") AS "root.Element" ON ('true')) AS "root.Component" ON ('true')), 'Component_aggregate', (SELECT json_build_object('count', COUNT(*), 'min', json_build_object('createdAt', MIN("createdAt"))) AS "root" FROM (SELECT * FROM "Component" WHERE "appId" = '345810043118026832') AS "base")) AS "data"`
   );
 });
+
+Deno.test("translate with variables", () => {
+  const result = translate({
+    query: `query App($id: String!) { App(id: $id) { id } }`,
+    variables: { id: "345810043118026832" },
+    operationName: "App",
+  });
+  assertEquals(result.error, null);
+  assertEquals(result.params, ["345810043118026832"]);
+  assertEquals(result.paramNames, ["id"]);
+  assertEquals(result.cacheTags, [{ table: "App", key: null, value: null }]);
+  assertEquals(result.isMutation, false);
+  assertEquals(result.referencedObjects, {
+    schemas: [],
+    tables: [{ schema: null, table: "App", columns: ["id"] }],
+  });
+});
+
+Deno.test("translate reports errors", () => {
+  const result = translate({ query: "query {", variables: null, operationName: null });
+  assertEquals(typeof result.error, "string");
+});
@@ -1,6 +1,6 @@
 import test from 'ava'
 
-import { gql2Sql } from '../index'
+import { AllowList, documentHash, explain, gql2Sql, parseCheck, translate, translateAsync, translateChecked, validate } from '../index'
 
 const gql = String.raw
 
@@ -242,3 +242,49 @@ test('complex query', (t) => {
   )
   t.snapshot(JSON.parse(result))
 })
+
+test('typed and async api match the string api', async (t) => {
+  const query = gql`
+    query App($id: String!) {
+      App(filter: { field: "id", operator: "eq", value: $id }) {
+        id
+        name
+      }
+    }
+  `
+  const variables = { id: '345810043118026832' }
+  const expected = JSON.parse(gql2Sql(JSON.stringify({ query, variables })))
+  for (const result of [translate({ query, variables }), await translateAsync({ query, variables })]) {
+    t.is(result.sql, expected.sql)
+    t.deepEqual(result.params, expected.params)
+    t.deepEqual(result.tags, expected.tags)
+    t.is(result.isMutation, expected.isMutation)
+    t.deepEqual(result.referencedObjects.tables[0].columns, expected.referencedObjects.tables[0].columns)
+  }
+})
+
+test('parseCheck and validate report diagnostics', (t) => {
+  t.deepEqual(parseCheck('query { App { id } }'), [])
+  t.is(parseCheck('query { App { id }')[0].line, 1)
+  const query = 'query App($id: String!) { App(id: $id) { id } }'
+  t.deepEqual(validate(query, { id: '1' }), [])
+  t.is(validate(query, {})[0].message, 'Variable "$id" of required type "String!" was not provided')
+})
+
+test('translateChecked only compiles documents on the allow list', (t) => {
+  const query = 'query App { App(id: "1") { id } }'
+  const allowList = new AllowList([documentHash(query)])
+  t.true(allowList.allows(query))
+  t.is(translateChecked({ query }, allowList).sql, translate({ query }).sql)
+  const other = 'query App { App(id: "2") { id } }'
+  t.throws(() => translateChecked({ query: other }, AllowList.fromDocuments([query])), {
+    message: `Document ${documentHash(other)} is not on the allow list`,
+  })
+})
+
+test('explain lists the tables and columns of a query', (t) => {
+  const result = explain({ query: 'query App($id: String!) { App(id: $id) { id name } }', variables: { id: '1' } })
+  t.deepEqual(result.tables, [{ table: 'App', columns: ['id', 'name'] }])
+  t.deepEqual(result.params, [{ name: 'id', value: '1', type: 'text' }])
+  t.false(result.isMutation)
+})
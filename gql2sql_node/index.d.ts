@@ -4,3 +4,112 @@
 /* auto-generated by NAPI-RS */
 
 export declare function gql2Sql(args: string): string
+/** The arguments of `gql2sql` as a plain object, skipping the JSON round trip. */
+export interface TranslateInput {
+  query?: string
+  document?: any
+  variables?: any
+  operationName?: string
+  options?: any
+}
+export interface Translation {
+  sql: string
+  params?: Array<any>
+  paramTypes?: Array<string>
+  paramNames?: Array<string>
+  tags?: Array<string>
+  cacheTags?: Array<CacheTag>
+  isMutation: boolean
+  timeouts?: Record<string, number>
+  /** Directives of the operation itself with their arguments, e.g. `{ cached: { ttl: 60 } }` */
+  directives?: Record<string, any>
+  cachePolicy?: CachePolicy
+  /** `SET LOCAL` statements to run before `sql` in its transaction */
+  settings?: Array<string>
+  referencedObjects: ReferencedObjects
+  /** Each root field of a query compiled on its own, with the `splitRoots` option */
+  roots?: Array<RootTranslation>
+}
+/** A root field of a query compiled on its own, to run in a transaction of its own. */
+export interface RootTranslation {
+  /** The response key of the field */
+  key: string
+  sql: string
+  params?: Array<any>
+  paramTypes?: Array<string>
+  paramNames?: Array<string>
+  /** `SET LOCAL` statements to run before `sql`, with the `statement_timeout` of `timeoutMs` */
+  settings: Array<string>
+  /** From `@meta(maxMs:)` */
+  timeoutMs?: number
+}
+/** From `@cached(ttl:, staleWhileRevalidate:)`, in seconds. */
+export interface CachePolicy {
+  ttl: number
+  staleWhileRevalidate?: number
+  /** The matching `Cache-Control` header value */
+  cacheControl: string
+}
+/** A cache tag of the result, formatted in `tags` as `type:{table}:{key}:{value}`. */
+export interface CacheTag {
+  table: string
+  key?: string
+  value?: string
+}
+export declare function translate(input: TranslateInput): Translation
+/** The SHA-256 of the query text as hex, as used by persisted queries. */
+export declare function documentHash(query: string): string
+/** `translate` for a query that must be on the allow list, which is checked before parsing. */
+export declare function translateChecked(input: TranslateInput, allowList: AllowList): Translation
+/** Translates on the libuv thread pool so large documents don't block the event loop. */
+export declare function translateAsync(input: TranslateInput): Promise<Translation>
+/** A parameter of `explain`, in binding order. */
+export interface ExplainParam {
+  name: string
+  value: any
+  type: string
+}
+/**
+ * A table the statement reads or writes, with the columns it references; `*` stands for a
+ * `RETURNING *`.
+ */
+export interface TableColumns {
+  /** Set when the statement qualifies the table */
+  schema?: string
+  table: string
+  columns: Array<string>
+}
+/** The schemas, tables and columns the statement references, for authorization checks. */
+export interface ReferencedObjects {
+  schemas: Array<string>
+  tables: Array<TableColumns>
+}
+export interface Explanation {
+  sql: string
+  params: Array<ExplainParam>
+  tags: Array<CacheTag>
+  isMutation: boolean
+  /** The size of the operation and its variables, as the `nodeBudget` option counts it */
+  complexity: number
+  tables: Array<TableColumns>
+  warnings: Array<Diagnostic>
+}
+/** Compiles without running, for checking persisted documents in CI. */
+export declare function explain(input: TranslateInput): Explanation
+export declare function shape(args: string): string
+/** A problem found by `parseCheck` or `validate`, or a warning of `explain`. */
+export interface Diagnostic {
+  message: string
+  line?: number
+  column?: number
+}
+/** Parses a query without translating it; an empty list means it is well formed. */
+export declare function parseCheck(query: string): Array<Diagnostic>
+/** Parses and validates a query without generating SQL; an empty list means it can be translated. */
+export declare function validate(query: string, variables?: any | undefined | null, operationName?: string | undefined | null): Array<Diagnostic>
+/** The approved documents, by the hashes `documentHash` computes. */
+export declare class AllowList {
+  constructor(hashes: Array<string>)
+  static fromDocuments(documents: Array<string>): AllowList
+  allows(query: string): boolean
+}
@@ -284,6 +284,15 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { gql2Sql } = nativeBinding
+const { gql2Sql, translate, AllowList, documentHash, translateChecked, translateAsync, explain, shape, parseCheck, validate } = nativeBinding
 
 module.exports.gql2Sql = gql2Sql
+module.exports.translate = translate
+module.exports.AllowList = AllowList
+module.exports.documentHash = documentHash
+module.exports.translateChecked = translateChecked
+module.exports.translateAsync = translateAsync
+module.exports.explain = explain
+module.exports.shape = shape
+module.exports.parseCheck = parseCheck
+module.exports.validate = validate
@@ -1,39 +1,543 @@
-use async_graphql_parser::parse_query;
-use gql2sql::gql2sql as gql2sql_rs;
+use gql2sql::{
+  dry_run, get_cache_policy, get_local_settings, get_operation_directives, get_operation_name,
+  get_referenced_objects, get_timeouts, gql2sql_roots, gql2sql_with_options, parse, parse_js_ast,
+  shape_response, to_commented_sql, Options,
+};
+use indexmap::IndexMap;
+use napi::{bindgen_prelude::AsyncTask, Env, Task};
 use napi_derive::napi;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 
 #[derive(Deserialize)]
 pub struct Args {
+  #[serde(default)]
   pub query: String,
+  pub document: Option<Value>,
   pub variables: Option<Value>,
   pub operation_name: Option<String>,
+  pub options: Option<Options>,
 }
 
 #[derive(Serialize)]
 pub struct GqlResult {
   pub sql: String,
   pub params: Option<Vec<Value>>,
+  #[serde(rename = "paramTypes")]
+  pub param_types: Option<Vec<String>>,
+  #[serde(rename = "paramNames")]
+  pub param_names: Option<Vec<String>>,
   pub tags: Option<Vec<String>>,
+  #[serde(rename = "cacheTags")]
+  pub cache_tags: Option<Vec<gql2sql::CacheTag>>,
   #[serde(rename = "isMutation")]
   pub is_mutation: bool,
+  pub timeouts: Option<IndexMap<String, u64>>,
+  pub directives: Option<IndexMap<String, Value>>,
+  #[serde(rename = "cachePolicy")]
+  pub cache_policy: Option<gql2sql::CachePolicy>,
+  pub settings: Option<Vec<String>>,
+  #[serde(rename = "referencedObjects")]
+  pub referenced_objects: gql2sql::ReferencedObjects,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub roots: Option<Vec<RootResult>>,
+}
+
+#[derive(Serialize)]
+pub struct RootResult {
+  pub key: String,
+  pub sql: String,
+  pub params: Option<Vec<Value>>,
+  #[serde(rename = "paramTypes")]
+  pub param_types: Option<Vec<String>>,
+  #[serde(rename = "paramNames")]
+  pub param_names: Option<Vec<String>>,
+  pub settings: Vec<String>,
+  #[serde(rename = "timeoutMs")]
+  pub timeout_ms: Option<u64>,
+}
+
+fn translate_args(
+  args: Args,
+  allow_list: Option<&gql2sql::AllowList>,
+) -> anyhow::Result<GqlResult> {
+  let Args {
+    query,
+    document,
+    variables,
+    operation_name,
+    options,
+  } = args;
+  if let Some(allow_list) = allow_list {
+    if document.is_some() {
+      return Err(anyhow::anyhow!(
+        "the allow list checks the query text, pass query instead of document"
+      ));
+    }
+    allow_list.check(&query)?;
+  }
+  let ast = match document {
+    Some(document) => parse_js_ast(&document)?,
+    None => parse(&query)?,
+  };
+  let timeouts = get_timeouts(&ast, operation_name.as_deref())?;
+  let directives = get_operation_directives(&ast, &variables, operation_name.as_deref())?;
+  let cache_policy = get_cache_policy(&ast, &variables, operation_name.as_deref())?;
+  let options = options.unwrap_or_default();
+  let settings = get_local_settings(&ast, &variables, operation_name.as_deref(), &options)?;
+  let name = get_operation_name(&ast, operation_name.as_deref());
+  let roots = if options.split_roots {
+    let roots = gql2sql_roots(ast.clone(), &variables, operation_name.clone(), &options)?;
+    Some(
+      roots
+        .into_iter()
+        .map(|root| RootResult {
+          key: root.key,
+          sql: to_commented_sql(
+            &root.statement,
+            name.as_deref(),
+            &variables,
+            root.tags.as_deref(),
+            &options,
+          ),
+          param_types: root
+            .params
+            .as_ref()
+            .map(|p| p.iter().map(|p| p.sql_type.clone()).collect()),
+          param_names: root
+            .params
+            .as_ref()
+            .map(|p| p.iter().map(|p| p.name.clone()).collect()),
+          params: root
+            .params
+            .map(|p| p.into_iter().map(|p| p.value).collect()),
+          settings: root.settings.iter().map(ToString::to_string).collect(),
+          timeout_ms: root.timeout_ms,
+        })
+        .collect(),
+    )
+  } else {
+    None
+  };
+  let (sql, params, tags, is_mutation) =
+    gql2sql_with_options(ast, &variables, operation_name, &options)?;
+  let param_types = params
+    .as_ref()
+    .map(|p| p.iter().map(|p| p.sql_type.clone()).collect());
+  let param_names = params
+    .as_ref()
+    .map(|p| p.iter().map(|p| p.name.clone()).collect());
+  Ok(GqlResult {
+    sql: to_commented_sql(&sql, name.as_deref(), &variables, tags.as_deref(), &options),
+    params: params.map(|p| p.into_iter().map(|p| p.value).collect()),
+    param_types,
+    param_names,
+    tags: tags
+      .as_ref()
+      .map(|t| t.iter().map(ToString::to_string).collect()),
+    cache_tags: tags,
+    is_mutation,
+    timeouts,
+    directives,
+    cache_policy,
+    settings: (!settings.is_empty()).then(|| settings.iter().map(ToString::to_string).collect()),
+    referenced_objects: get_referenced_objects(&sql),
+    roots,
+  })
 }
 
 #[napi]
 pub fn gql2sql(args: String) -> anyhow::Result<String> {
+  let result = translate_args(serde_json::from_str(&args)?, None)?;
+  serde_json::to_string(&result).map_err(|e| anyhow::anyhow!(e))
+}
+
+/// The arguments of `gql2sql` as a plain object, skipping the JSON round trip.
+#[napi(object)]
+pub struct TranslateInput {
+  pub query: Option<String>,
+  pub document: Option<Value>,
+  pub variables: Option<Value>,
+  pub operation_name: Option<String>,
+  pub options: Option<Value>,
+}
+
+impl TryFrom<TranslateInput> for Args {
+  type Error = anyhow::Error;
+
+  fn try_from(input: TranslateInput) -> anyhow::Result<Self> {
+    Ok(Args {
+      query: input.query.unwrap_or_default(),
+      document: input.document,
+      variables: input.variables,
+      operation_name: input.operation_name,
+      options: input.options.map(serde_json::from_value).transpose()?,
+    })
+  }
+}
+
+#[napi(object)]
+pub struct Translation {
+  pub sql: String,
+  pub params: Option<Vec<Value>>,
+  pub param_types: Option<Vec<String>>,
+  pub param_names: Option<Vec<String>>,
+  pub tags: Option<Vec<String>>,
+  pub cache_tags: Option<Vec<CacheTag>>,
+  pub is_mutation: bool,
+  pub timeouts: Option<HashMap<String, i64>>,
+  /// Directives of the operation itself with their arguments, e.g. `{ cached: { ttl: 60 } }`
+  pub directives: Option<HashMap<String, Value>>,
+  pub cache_policy: Option<CachePolicy>,
+  /// `SET LOCAL` statements to run before `sql` in its transaction
+  pub settings: Option<Vec<String>>,
+  pub referenced_objects: ReferencedObjects,
+  /// Each root field of a query compiled on its own, with the `splitRoots` option
+  pub roots: Option<Vec<RootTranslation>>,
+}
+
+/// A root field of a query compiled on its own, to run in a transaction of its own.
+#[napi(object)]
+pub struct RootTranslation {
+  /// The response key of the field
+  pub key: String,
+  pub sql: String,
+  pub params: Option<Vec<Value>>,
+  pub param_types: Option<Vec<String>>,
+  pub param_names: Option<Vec<String>>,
+  /// `SET LOCAL` statements to run before `sql`, with the `statement_timeout` of `timeoutMs`
+  pub settings: Vec<String>,
+  /// From `@meta(maxMs:)`
+  pub timeout_ms: Option<i64>,
+}
+
+impl From<RootResult> for RootTranslation {
+  fn from(root: RootResult) -> Self {
+    RootTranslation {
+      key: root.key,
+      sql: root.sql,
+      params: root.params,
+      param_types: root.param_types,
+      param_names: root.param_names,
+      settings: root.settings,
+      timeout_ms: root
+        .timeout_ms
+        .map(|timeout| i64::try_from(timeout).unwrap_or(i64::MAX)),
+    }
+  }
+}
+
+impl From<GqlResult> for Translation {
+  fn from(result: GqlResult) -> Self {
+    Translation {
+      sql: result.sql,
+      params: result.params,
+      param_types: result.param_types,
+      param_names: result.param_names,
+      tags: result.tags,
+      cache_tags: result
+        .cache_tags
+        .map(|tags| tags.into_iter().map(CacheTag::from).collect()),
+      is_mutation: result.is_mutation,
+      timeouts: result.timeouts.map(|timeouts| {
+        timeouts
+          .into_iter()
+          .map(|(name, timeout)| (name, i64::try_from(timeout).unwrap_or(i64::MAX)))
+          .collect()
+      }),
+      directives: result
+        .directives
+        .map(|directives| directives.into_iter().collect()),
+      cache_policy: result.cache_policy.map(CachePolicy::from),
+      settings: result.settings,
+      referenced_objects: result.referenced_objects.into(),
+      roots: result
+        .roots
+        .map(|roots| roots.into_iter().map(RootTranslation::from).collect()),
+    }
+  }
+}
+
+/// From `@cached(ttl:, staleWhileRevalidate:)`, in seconds.
+#[napi(object)]
+pub struct CachePolicy {
+  pub ttl: i64,
+  pub stale_while_revalidate: Option<i64>,
+  /// The matching `Cache-Control` header value
+  pub cache_control: String,
+}
+
+impl From<gql2sql::CachePolicy> for CachePolicy {
+  fn from(policy: gql2sql::CachePolicy) -> Self {
+    let seconds = |s: u64| i64::try_from(s).unwrap_or(i64::MAX);
+    CachePolicy {
+      ttl: seconds(policy.ttl),
+      stale_while_revalidate: policy.stale_while_revalidate.map(seconds),
+      cache_control: policy.cache_control(),
+    }
+  }
+}
+
+/// A cache tag of the result, formatted in `tags` as `type:{table}:{key}:{value}`.
+#[napi(object)]
+pub struct CacheTag {
+  pub table: String,
+  pub key: Option<String>,
+  pub value: Option<String>,
+}
+
+impl From<gql2sql::CacheTag> for CacheTag {
+  fn from(tag: gql2sql::CacheTag) -> Self {
+    CacheTag {
+      table: tag.table,
+      key: tag.key,
+      value: tag.value,
+    }
+  }
+}
+
+#[napi]
+pub fn translate(input: TranslateInput) -> anyhow::Result<Translation> {
+  Ok(translate_args(input.try_into()?, None)?.into())
+}
+
+/// The approved documents, by the hashes `documentHash` computes.
+#[napi(js_name = "AllowList")]
+pub struct AllowList {
+  inner: gql2sql::AllowList,
+}
+
+#[napi]
+impl AllowList {
+  #[napi(constructor)]
+  pub fn new(hashes: Vec<String>) -> anyhow::Result<Self> {
+    Ok(AllowList {
+      inner: gql2sql::AllowList::new(hashes)?,
+    })
+  }
+
+  #[napi(factory)]
+  pub fn from_documents(documents: Vec<String>) -> Self {
+    AllowList {
+      inner: gql2sql::AllowList::from_documents(documents),
+    }
+  }
+
+  #[napi]
+  pub fn allows(&self, query: String) -> bool {
+    self.inner.allows(&query)
+  }
+}
+
+/// The SHA-256 of the query text as hex, as used by persisted queries.
+#[napi]
+pub fn document_hash(query: String) -> String {
+  gql2sql::get_document_hash(&query)
+}
+
+/// `translate` for a query that must be on the allow list, which is checked before parsing.
+#[napi]
+pub fn translate_checked(
+  input: TranslateInput,
+  allow_list: &AllowList,
+) -> anyhow::Result<Translation> {
+  Ok(translate_args(input.try_into()?, Some(&allow_list.inner))?.into())
+}
+
+pub struct TranslateTask {
+  args: Option<Args>,
+}
+
+impl Task for TranslateTask {
+  type Output = GqlResult;
+  type JsValue = Translation;
+
+  fn compute(&mut self) -> napi::Result<GqlResult> {
+    let args = self
+      .args
+      .take()
+      .ok_or_else(|| napi::Error::from_reason("translation already ran"))?;
+    Ok(translate_args(args, None)?)
+  }
+
+  fn resolve(&mut self, _env: Env, output: GqlResult) -> napi::Result<Translation> {
+    Ok(output.into())
+  }
+}
+
+/// Translates on the libuv thread pool so large documents don't block the event loop.
+#[napi]
+pub fn translate_async(input: TranslateInput) -> anyhow::Result<AsyncTask<TranslateTask>> {
+  Ok(AsyncTask::new(TranslateTask {
+    args: Some(input.try_into()?),
+  }))
+}
+
+/// A parameter of `explain`, in binding order.
+#[napi(object)]
+pub struct ExplainParam {
+  pub name: String,
+  pub value: Value,
+  #[napi(js_name = "type")]
+  pub sql_type: String,
+}
+
+/// A table the statement reads or writes, with the columns it references; `*` stands for a
+/// `RETURNING *`.
+#[napi(object)]
+pub struct TableColumns {
+  /// Set when the statement qualifies the table
+  pub schema: Option<String>,
+  pub table: String,
+  pub columns: Vec<String>,
+}
+
+impl From<gql2sql::TableColumns> for TableColumns {
+  fn from(table: gql2sql::TableColumns) -> Self {
+    TableColumns {
+      schema: table.schema,
+      table: table.table,
+      columns: table.columns,
+    }
+  }
+}
+
+/// The schemas, tables and columns the statement references, for authorization checks.
+#[napi(object)]
+pub struct ReferencedObjects {
+  pub schemas: Vec<String>,
+  pub tables: Vec<TableColumns>,
+}
+
+impl From<gql2sql::ReferencedObjects> for ReferencedObjects {
+  fn from(objects: gql2sql::ReferencedObjects) -> Self {
+    ReferencedObjects {
+      schemas: objects.schemas,
+      tables: objects.tables.into_iter().map(Into::into).collect(),
+    }
+  }
+}
+
+#[napi(object)]
+pub struct Explanation {
+  pub sql: String,
+  pub params: Vec<ExplainParam>,
+  pub tags: Vec<CacheTag>,
+  pub is_mutation: bool,
+  /// The size of the operation and its variables, as the `nodeBudget` option counts it
+  pub complexity: u32,
+  pub tables: Vec<TableColumns>,
+  pub warnings: Vec<Diagnostic>,
+}
+
+/// Compiles without running, for checking persisted documents in CI.
+#[napi]
+pub fn explain(input: TranslateInput) -> anyhow::Result<Explanation> {
   let Args {
     query,
+    document,
+    variables,
+    operation_name,
+    options,
+  } = input.try_into()?;
+  let ast = match document {
+    Some(document) => parse_js_ast(&document)?,
+    None => parse(&query)?,
+  };
+  let result = dry_run(
+    ast,
+    &variables,
+    operation_name,
+    &options.unwrap_or_default(),
+  )?;
+  Ok(Explanation {
+    sql: result.sql,
+    params: result
+      .params
+      .into_iter()
+      .map(|param| ExplainParam {
+        name: param.name,
+        value: param.value,
+        sql_type: param.sql_type,
+      })
+      .collect(),
+    tags: result.tags.into_iter().map(CacheTag::from).collect(),
+    is_mutation: result.is_mutation,
+    complexity: u32::try_from(result.complexity).unwrap_or(u32::MAX),
+    tables: result.tables.into_iter().map(Into::into).collect(),
+    warnings: result.warnings.into_iter().map(Into::into).collect(),
+  })
+}
+
+#[derive(Deserialize)]
+pub struct ShapeArgs {
+  #[serde(default)]
+  pub query: String,
+  pub document: Option<Value>,
+  pub variables: Option<Value>,
+  pub operation_name: Option<String>,
+  pub data: Option<Value>,
+  #[serde(default)]
+  pub errors: Vec<String>,
+}
+
+#[napi]
+pub fn shape(args: String) -> anyhow::Result<String> {
+  let ShapeArgs {
+    query,
+    document,
     variables,
     operation_name,
+    data,
+    errors,
   } = serde_json::from_str(&args)?;
-  let ast = parse_query(query)?;
-  let (sql, params, tags, is_mutation) = gql2sql_rs(ast, &variables, operation_name)?;
-  let result = GqlResult {
-    sql: sql.to_string(),
-    params,
-    tags,
-    is_mutation,
+  let ast = match document {
+    Some(document) => parse_js_ast(&document)?,
+    None => parse(&query)?,
   };
-  serde_json::to_string(&result).map_err(|e| anyhow::anyhow!(e))
+  let response = shape_response(&ast, &variables, operation_name.as_deref(), data, errors)?;
+  serde_json::to_string(&response).map_err(|e| anyhow::anyhow!(e))
+}
+
+/// A problem found by `parseCheck` or `validate`, or a warning of `explain`.
+#[napi(object)]
+pub struct Diagnostic {
+  pub message: String,
+  pub line: Option<u32>,
+  pub column: Option<u32>,
+}
+
+impl From<gql2sql::Diagnostic> for Diagnostic {
+  fn from(diagnostic: gql2sql::Diagnostic) -> Self {
+    let position = |n: Option<usize>| n.and_then(|n| u32::try_from(n).ok());
+    Diagnostic {
+      message: diagnostic.message,
+      line: position(diagnostic.line),
+      column: position(diagnostic.column),
+    }
+  }
+}
+
+/// Parses a query without translating it; an empty list means it is well formed.
+#[napi]
+pub fn parse_check(query: String) -> Vec<Diagnostic> {
+  gql2sql::parse_check(&query)
+    .into_iter()
+    .map(Into::into)
+    .collect()
+}
+
+/// Parses and validates a query without generating SQL; an empty list means it can be translated.
+#[napi]
+pub fn validate(
+  query: String,
+  variables: Option<Value>,
+  operation_name: Option<String>,
+) -> Vec<Diagnostic> {
+  let diagnostics = match parse(&query) {
+    Ok(ast) => gql2sql::validate(&ast, &variables, operation_name.as_deref()),
+    Err(_) => gql2sql::parse_check(&query),
+  };
+  diagnostics.into_iter().map(Into::into).collect()
 }
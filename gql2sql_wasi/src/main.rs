@@ -0,0 +1,176 @@
+use gql2sql::{
+    get_cache_policy, get_local_settings, get_operation_directives, get_operation_name,
+    get_referenced_objects, get_timeouts, gql2sql_roots, gql2sql_with_options, parse, parse_js_ast,
+    to_commented_sql, CachePolicy, CacheTag, Options, Param, ReferencedObjects,
+};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+
+// Same request shape as the string API of the node binding so hosts can share payloads
+#[derive(Deserialize)]
+struct Args {
+    #[serde(default)]
+    query: String,
+    document: Option<Value>,
+    variables: Option<Value>,
+    operation_name: Option<String>,
+    options: Option<Options>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GqlResult {
+    sql: String,
+    params: Option<Vec<Value>>,
+    param_types: Option<Vec<String>>,
+    param_names: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    cache_tags: Option<Vec<CacheTag>>,
+    is_mutation: bool,
+    timeouts: Option<IndexMap<String, u64>>,
+    directives: Option<IndexMap<String, Value>>,
+    cache_policy: Option<CachePolicy>,
+    settings: Option<Vec<String>>,
+    referenced_objects: ReferencedObjects,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roots: Option<Vec<RootResult>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RootResult {
+    key: String,
+    sql: String,
+    params: Option<Vec<Value>>,
+    param_types: Option<Vec<String>>,
+    param_names: Option<Vec<String>>,
+    settings: Vec<String>,
+    timeout_ms: Option<u64>,
+}
+
+fn to_param(value: Value, sql_type: &str) -> anyhow::Result<Value> {
+    Ok(match value {
+        Value::Object(obj) => Value::String(serde_json::to_string(&obj)?),
+        Value::Array(list) if sql_type.ends_with("[]") => Value::Array(list),
+        Value::Array(list) => Value::String(serde_json::to_string(&list)?),
+        other => other,
+    })
+}
+
+// the values of `params` as bound, with their types and names
+type BoundParams = (Option<Vec<Value>>, Option<Vec<String>>, Option<Vec<String>>);
+
+fn bind_params(params: Option<Vec<Param>>) -> anyhow::Result<BoundParams> {
+    let param_types = params
+        .as_ref()
+        .map(|p| p.iter().map(|p| p.sql_type.clone()).collect());
+    let param_names = params
+        .as_ref()
+        .map(|p| p.iter().map(|p| p.name.clone()).collect());
+    let params = params
+        .map(|p| {
+            p.into_iter()
+                .map(|p| to_param(p.value, &p.sql_type))
+                .collect::<anyhow::Result<_>>()
+        })
+        .transpose()?;
+    Ok((params, param_types, param_names))
+}
+
+fn run(input: &str) -> anyhow::Result<GqlResult> {
+    let Args {
+        query,
+        document,
+        variables,
+        operation_name,
+        options,
+    } = serde_json::from_str(input)?;
+    let ast = match document {
+        Some(document) => parse_js_ast(&document)?,
+        None => parse(&query)?,
+    };
+    let timeouts = get_timeouts(&ast, operation_name.as_deref())?;
+    let directives = get_operation_directives(&ast, &variables, operation_name.as_deref())?;
+    let cache_policy = get_cache_policy(&ast, &variables, operation_name.as_deref())?;
+    let options = options.unwrap_or_default();
+    let settings = get_local_settings(&ast, &variables, operation_name.as_deref(), &options)?;
+    let name = get_operation_name(&ast, operation_name.as_deref());
+    let roots = if options.split_roots {
+        let roots = gql2sql_roots(ast.clone(), &variables, operation_name.clone(), &options)?;
+        let mut results = vec![];
+        for root in roots {
+            let sql = to_commented_sql(
+                &root.statement,
+                name.as_deref(),
+                &variables,
+                root.tags.as_deref(),
+                &options,
+            );
+            let (params, param_types, param_names) = bind_params(root.params)?;
+            results.push(RootResult {
+                key: root.key,
+                sql,
+                params,
+                param_types,
+                param_names,
+                settings: root.settings.iter().map(ToString::to_string).collect(),
+                timeout_ms: root.timeout_ms,
+            });
+        }
+        Some(results)
+    } else {
+        None
+    };
+    let (sql, params, tags, is_mutation) =
+        gql2sql_with_options(ast, &variables, operation_name, &options)?;
+    let (params, param_types, param_names) = bind_params(params)?;
+    Ok(GqlResult {
+        sql: to_commented_sql(&sql, name.as_deref(), &variables, tags.as_deref(), &options),
+        params,
+        param_types,
+        param_names,
+        tags: tags
+            .as_ref()
+            .map(|t| t.iter().map(ToString::to_string).collect()),
+        cache_tags: tags,
+        is_mutation,
+        timeouts,
+        directives,
+        cache_policy,
+        settings: (!settings.is_empty())
+            .then(|| settings.iter().map(ToString::to_string).collect()),
+        referenced_objects: get_referenced_objects(&sql),
+        roots,
+    })
+}
+
+/// Reads one JSON request from stdin and writes the translated query (or `{"error": ...}`) to stdout.
+fn main() -> ExitCode {
+    let mut input = String::new();
+    let (output, code) = match io::stdin()
+        .read_to_string(&mut input)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| run(&input))
+        .and_then(|result| Ok(serde_json::to_string(&result)?))
+    {
+        Ok(output) => (output, ExitCode::SUCCESS),
+        Err(e) => (
+            serde_json::json!({ "error": e.to_string() }).to_string(),
+            ExitCode::FAILURE,
+        ),
+    };
+    let mut stdout = io::stdout().lock();
+    // WAGI/CGI hosts (e.g. Spin) set REQUEST_METHOD and expect a header block before the body
+    if std::env::var_os("REQUEST_METHOD").is_some()
+        && write!(stdout, "content-type: application/json\n\n").is_err()
+    {
+        return ExitCode::FAILURE;
+    }
+    if writeln!(stdout, "{}", output).is_err() {
+        return ExitCode::FAILURE;
+    }
+    code
+}